@@ -2,17 +2,20 @@ use std::cell::RefCell;
 use std::rc::Rc;
 
 use crate::emulator::apu::synth::{Noise, Pulse, Triangle, DMC};
-use crate::emulator::apu::APU;
+use crate::emulator::apu::{Channel, APU};
+use crate::emulator::io::font;
 
 pub struct APUDebug {
     apu: Rc<RefCell<APU>>,
     dummy_noise: Noise,
+    labels: [Option<char>; 5],
 }
 
 impl APUDebug {
     pub const WAVEFORM_WIDTH: usize = 256;
     pub const WAVEFORM_HEIGHT: usize = 160;
     const WAVEFORM_SCALE: usize = 64;
+    const CHANNEL_HEIGHT: usize = 32;
 
     pub fn new(apu: Rc<RefCell<APU>>) -> APUDebug {
         // We're going to pull values out of a real Noise component to get an authentic looking
@@ -20,12 +23,23 @@ impl APUDebug {
         // Hack the timer so we only have to clock it once to change values.
         let mut dummy_noise = Noise::new();
         dummy_noise.timer.set_period(1);
-        dummy_noise.length = 1;
-        dummy_noise.enabled = true;
+        dummy_noise.length.set_enabled(true);
+        dummy_noise.length.reload(1);
+        dummy_noise.length.commit();
         dummy_noise.envelope.set_volume(1);
         dummy_noise.envelope.constant_volume = true;
 
-        APUDebug { apu, dummy_noise }
+        APUDebug {
+            apu,
+            dummy_noise,
+            labels: [None; 5],
+        }
+    }
+
+    // Label a channel's waveform, e.g. with the key which controls it.
+    pub fn set_channel_label(&mut self, channel: Channel, label: char) {
+        let ix = Channel::ALL.iter().position(|&c| c == channel).unwrap();
+        self.labels[ix] = Some(label);
     }
 
     pub fn do_render<F>(&mut self, render_waveforms: F)
@@ -47,6 +61,44 @@ impl APUDebug {
         APUDebug::draw_triangle_wave(buffer, &apu.triangle, 0, 64);
         APUDebug::draw_noise(buffer, &apu.noise, dummy_noise, 0, 96);
         APUDebug::draw_dmc(buffer, &apu.dmc, 0, 128);
+
+        for (ix, &channel) in Channel::ALL.iter().enumerate() {
+            let y = ix * APUDebug::CHANNEL_HEIGHT;
+            if !apu.is_channel_audible(channel) {
+                APUDebug::dim_channel(buffer, y);
+            }
+
+            let colour = if apu.is_channel_solo(channel) {
+                [0x00, 0xFF, 0x00]
+            } else if apu.is_channel_audible(channel) {
+                [0xFF, 0xFF, 0xFF]
+            } else {
+                [0x60, 0x60, 0x60]
+            };
+            if let Some(label) = self.labels[ix] {
+                font::draw_char(buffer, APUDebug::WAVEFORM_WIDTH, 2, y + 1, label, colour);
+            }
+
+            APUDebug::draw_volume(buffer, apu.channel_volume(channel), y + 30);
+        }
+    }
+
+    fn dim_channel(buffer: &mut [u8], y: usize) {
+        let start = y * APUDebug::WAVEFORM_WIDTH * 3;
+        let end = (y + APUDebug::CHANNEL_HEIGHT) * APUDebug::WAVEFORM_WIDTH * 3;
+        for byte in buffer[start..end].iter_mut() {
+            *byte /= 4;
+        }
+    }
+
+    // Draw a bar showing the channel volume, where unity is half the available width.
+    fn draw_volume(buffer: &mut [u8], volume: f32, y: usize) {
+        let width =
+            ((volume / APU::MAX_CHANNEL_VOLUME) * (APUDebug::WAVEFORM_WIDTH as f32)) as usize;
+        for dx in 0..width.min(APUDebug::WAVEFORM_WIDTH) {
+            let ix = (y * APUDebug::WAVEFORM_WIDTH + dx) * 3;
+            buffer[ix..ix + 3].copy_from_slice(&[0x40, 0x40, 0x40]);
+        }
     }
 
     fn draw_pulse_wave(buffer: &mut [u8], pulse: &Pulse, x: usize, y: usize) {
@@ -54,7 +106,7 @@ impl APUDebug {
         let amplitude = pulse.envelope.volume();
         let seq = Pulse::SEQUENCES[pulse.sequence as usize];
 
-        if period <= 8 || pulse.length == 0 {
+        if period <= 8 || pulse.length.value() == 0 {
             APUDebug::draw_silence(buffer, x, y);
             return;
         }
@@ -85,7 +137,7 @@ impl APUDebug {
 
     fn draw_triangle_wave(buffer: &mut [u8], triangle: &Triangle, x: usize, y: usize) {
         let period = triangle.timer.period();
-        if period == 0 || triangle.length == 0 || triangle.linear == 0 || !triangle.enabled {
+        if period == 0 || triangle.length.value() == 0 || triangle.linear == 0 {
             APUDebug::draw_silence(buffer, x, y);
             return;
         }
@@ -101,7 +153,7 @@ impl APUDebug {
     fn draw_noise(buffer: &mut [u8], noise: &Noise, dummy_noise: &mut Noise, x: usize, y: usize) {
         let period = noise.timer.period();
 
-        if period == 0 || noise.length == 0 || noise.envelope.volume() == 0 {
+        if period == 0 || noise.length.value() == 0 || noise.envelope.volume() == 0 {
             APUDebug::draw_silence(buffer, x, y);
             return;
         }
@@ -0,0 +1,159 @@
+// A record of writes to the audio registers, timestamped in CPU cycles.
+// Enough to replay the music elsewhere, e.g. by exporting it to VGM.
+// Only the 2A03's registers are logged. There's no expansion audio emulated, so writes to
+// expansion chips' registers aren't logged.
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct RegisterWrite {
+    pub cycle: u64,
+    pub address: u16,
+    pub value: u8,
+}
+
+// A run of DMC sample data, which has to be in the player's memory by the time it's played.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SampleBlock {
+    pub cycle: u64,
+    pub address: u16,
+    pub data: Vec<u8>,
+}
+
+// DMC samples start in $C000-$FFFF, and wrap around to $8000.
+const SAMPLE_MEMORY_START: u16 = 0x8000;
+const SAMPLE_MEMORY_SIZE: usize = 0x8000;
+
+pub struct RegisterLog {
+    start_cycle: u64,
+    end_cycle: u64,
+    writes: Vec<RegisterWrite>,
+    sample_blocks: Vec<SampleBlock>,
+
+    // The sample data the player will have been given so far, so only changes are recorded,
+    // e.g. after a mapper switches in another bank of samples.
+    sample_memory: Vec<Option<u8>>,
+}
+
+impl RegisterLog {
+    pub fn new(start_cycle: u64) -> RegisterLog {
+        RegisterLog {
+            start_cycle,
+            end_cycle: start_cycle,
+            writes: vec![],
+            sample_blocks: vec![],
+            sample_memory: vec![None; SAMPLE_MEMORY_SIZE],
+        }
+    }
+
+    pub fn record(&mut self, cycle: u64, address: u16, value: u8) {
+        self.writes.push(RegisterWrite {
+            cycle: cycle - self.start_cycle,
+            address,
+            value,
+        });
+        self.end_cycle = self.end_cycle.max(cycle);
+    }
+
+    // Records sample data as it's read, as (address, byte) pairs. Any bytes which differ from
+    // what was recorded before are kept as blocks to load at this cycle.
+    pub fn record_sample_data(&mut self, cycle: u64, data: &[(u16, u8)]) {
+        let first_block = self.sample_blocks.len();
+        for &(address, byte) in data {
+            if address < SAMPLE_MEMORY_START {
+                continue;
+            }
+            let ix = (address - SAMPLE_MEMORY_START) as usize;
+            if self.sample_memory[ix] == Some(byte) {
+                continue;
+            }
+            self.sample_memory[ix] = Some(byte);
+
+            match self.sample_blocks[first_block..].last_mut() {
+                Some(block) if block.address as usize + block.data.len() == address as usize => {
+                    block.data.push(byte)
+                }
+                _ => self.sample_blocks.push(SampleBlock {
+                    cycle: cycle - self.start_cycle,
+                    address,
+                    data: vec![byte],
+                }),
+            }
+        }
+        self.end_cycle = self.end_cycle.max(cycle);
+    }
+
+    // Marks the end of the log, so it includes any silence after the last write.
+    pub fn finish(&mut self, cycle: u64) {
+        self.end_cycle = self.end_cycle.max(cycle);
+    }
+
+    // Writes in the order they happened, with cycles counted from the start of the log.
+    pub fn writes(&self) -> &[RegisterWrite] {
+        &self.writes
+    }
+
+    pub fn duration_cycles(&self) -> u64 {
+        self.end_cycle - self.start_cycle
+    }
+
+    // Sample data in the order it was recorded, with cycles counted from the start of the log.
+    pub fn sample_blocks(&self) -> &[SampleBlock] {
+        &self.sample_blocks
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::emulator::apu::log::{RegisterLog, RegisterWrite, SampleBlock};
+
+    #[test]
+    fn test_record_relative_to_start() {
+        let mut log = RegisterLog::new(1000);
+        log.record(1010, 0x4000, 0x3F);
+        log.record(1020, 0x4015, 0x01);
+        log.finish(1500);
+
+        assert_eq!(
+            log.writes(),
+            &[
+                RegisterWrite {
+                    cycle: 10,
+                    address: 0x4000,
+                    value: 0x3F
+                },
+                RegisterWrite {
+                    cycle: 20,
+                    address: 0x4015,
+                    value: 0x01
+                },
+            ]
+        );
+        assert_eq!(log.duration_cycles(), 500);
+    }
+
+    #[test]
+    fn test_sample_blocks() {
+        let mut log = RegisterLog::new(100);
+        log.record_sample_data(110, &[(0xC000, 0x01), (0xC001, 0x02), (0xC002, 0x03)]);
+        // Only the bytes which changed are recorded again, e.g. after a bank switch.
+        log.record_sample_data(120, &[(0xC000, 0x01), (0xC001, 0x05), (0xC002, 0x06)]);
+        log.record_sample_data(130, &[(0xC000, 0x07), (0xC001, 0x05), (0xC002, 0x08)]);
+        log.record_sample_data(140, &[(0xFFFF, 0x09), (0x8000, 0x0A)]);
+
+        let block = |cycle, address, data: &[u8]| SampleBlock {
+            cycle,
+            address,
+            data: data.to_vec(),
+        };
+        assert_eq!(
+            log.sample_blocks(),
+            &[
+                block(10, 0xC000, &[0x01, 0x02, 0x03]),
+                block(20, 0xC001, &[0x05, 0x06]),
+                block(30, 0xC000, &[0x07]),
+                block(30, 0xC002, &[0x08]),
+                block(40, 0xFFFF, &[0x09]),
+                block(40, 0x8000, &[0x0A]),
+            ]
+        );
+    }
+}
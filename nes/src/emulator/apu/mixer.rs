@@ -0,0 +1,284 @@
+use serde::{Deserialize, Serialize};
+
+// The 2A03's own channels, which are all the emulator has, since no mapper implements expansion
+// audio yet. When one does (VRC6, FDS, ...), this enum, Channel::ALL and the mute/solo/volume and
+// panning arrays in Mixer will all need to grow to cover its channels, or they'll be mixed in
+// with no way to control them.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, Serialize, Deserialize)]
+pub enum Channel {
+    Pulse1,
+    Pulse2,
+    Triangle,
+    Noise,
+    DMC,
+}
+
+impl Channel {
+    // Every channel, in the order the mixer and its controls index them.
+    pub const ALL: [Channel; 5] = [
+        Channel::Pulse1,
+        Channel::Pulse2,
+        Channel::Triangle,
+        Channel::Noise,
+        Channel::DMC,
+    ];
+
+    fn index(self) -> usize {
+        match self {
+            Channel::Pulse1 => 0,
+            Channel::Pulse2 => 1,
+            Channel::Triangle => 2,
+            Channel::Noise => 3,
+            Channel::DMC => 4,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct ChannelControl {
+    muted: bool,
+    solo: bool,
+    volume: f32,
+}
+
+// The APU mixes its channels non-linearly.
+// Rather than evaluating the formulas for every sample we precompute the two lookup tables
+// described at https://wiki.nesdev.com/w/index.php/APU_Mixer
+pub struct Mixer {
+    pulse_table: [f32; 31],
+    tnd_table: [f32; 203],
+
+    controls: [ChannelControl; 5],
+
+    // Effective gain of each channel after applying mute, solo and volume.
+    gains: [f32; 5],
+    unity: bool,
+
+    // Stereo position of each channel, from -1.0 (left) to 1.0 (right).
+    pan: [f32; 5],
+    left_gains: [f32; 5],
+    right_gains: [f32; 5],
+}
+
+impl Mixer {
+    pub const MAX_VOLUME: f32 = 2.0;
+
+    // Pulse 1 and pulse 2 are spread well to the left and right, as they're commonly presented,
+    // with the triangle centred and the noise and DMC only slightly off centre.
+    pub const FAMICOM_PAN: [f32; 5] = [-0.75, 0.75, 0.0, 0.25, -0.25];
+
+    pub fn new() -> Mixer {
+        let mut pulse_table = [0.0; 31];
+        for (n, entry) in pulse_table.iter_mut().enumerate().skip(1) {
+            *entry = 95.52 / (8128.0 / (n as f32) + 100.0);
+        }
+
+        let mut tnd_table = [0.0; 203];
+        for (n, entry) in tnd_table.iter_mut().enumerate().skip(1) {
+            *entry = 163.67 / (24329.0 / (n as f32) + 100.0);
+        }
+
+        Mixer {
+            pulse_table,
+            tnd_table,
+            controls: [ChannelControl {
+                muted: false,
+                solo: false,
+                volume: 1.0,
+            }; 5],
+            gains: [1.0; 5],
+            unity: true,
+            pan: [0.0; 5],
+            left_gains: [1.0; 5],
+            right_gains: [1.0; 5],
+        }
+    }
+
+    pub fn is_muted(&self, channel: Channel) -> bool {
+        self.controls[channel.index()].muted
+    }
+
+    pub fn set_muted(&mut self, channel: Channel, muted: bool) {
+        self.controls[channel.index()].muted = muted;
+        self.update_gains();
+    }
+
+    pub fn is_solo(&self, channel: Channel) -> bool {
+        self.controls[channel.index()].solo
+    }
+
+    // While any channel is soloed, only soloed channels are heard.
+    pub fn set_solo(&mut self, channel: Channel, solo: bool) {
+        self.controls[channel.index()].solo = solo;
+        self.update_gains();
+    }
+
+    pub fn volume(&self, channel: Channel) -> f32 {
+        self.controls[channel.index()].volume
+    }
+
+    pub fn set_volume(&mut self, channel: Channel, volume: f32) {
+        self.controls[channel.index()].volume = volume.clamp(0.0, Mixer::MAX_VOLUME);
+        self.update_gains();
+    }
+
+    // Whether the channel currently contributes to the output at all.
+    pub fn is_audible(&self, channel: Channel) -> bool {
+        self.gains[channel.index()] > 0.0
+    }
+
+    pub fn pan(&self, channel: Channel) -> f32 {
+        self.pan[channel.index()]
+    }
+
+    pub fn set_pan(&mut self, channel: Channel, pan: f32) {
+        self.pan[channel.index()] = pan.clamp(-1.0, 1.0);
+        self.update_gains();
+    }
+
+    fn update_gains(&mut self) {
+        let any_solo = self.controls.iter().any(|c| c.solo);
+        for (gain, control) in self.gains.iter_mut().zip(self.controls.iter()) {
+            *gain = if control.muted || (any_solo && !control.solo) {
+                0.0
+            } else {
+                control.volume
+            };
+        }
+        self.unity = self.gains.iter().all(|&g| g == 1.0);
+
+        // A centred channel plays at full volume on both sides, panning attenuates the far side.
+        for (ix, (&gain, &pan)) in self.gains.iter().zip(self.pan.iter()).enumerate() {
+            self.left_gains[ix] = gain * (1.0 - pan).min(1.0);
+            self.right_gains[ix] = gain * (1.0 + pan).min(1.0);
+        }
+    }
+
+    // Inputs are the raw 4-bit (7-bit for DMC) channel outputs.
+    // Output is in the range 0.0 - 1.0 (higher if channels are boosted above unity volume).
+    pub fn mix(&self, pulse_1: u8, pulse_2: u8, triangle: u8, noise: u8, dmc: u8) -> f32 {
+        if self.unity {
+            let pulse_ix = (pulse_1 + pulse_2) as usize;
+            let tnd_ix = 3 * (triangle as usize) + 2 * (noise as usize) + (dmc as usize);
+            return self.pulse_table[pulse_ix] + self.tnd_table[tnd_ix];
+        }
+
+        self.mix_scaled(&self.gains, [pulse_1, pulse_2, triangle, noise, dmc])
+    }
+
+    // As mix, but each side hears the channels scaled according to their pan positions.
+    pub fn mix_stereo(
+        &self,
+        pulse_1: u8,
+        pulse_2: u8,
+        triangle: u8,
+        noise: u8,
+        dmc: u8,
+    ) -> (f32, f32) {
+        let levels = [pulse_1, pulse_2, triangle, noise, dmc];
+        (
+            self.mix_scaled(&self.left_gains, levels),
+            self.mix_scaled(&self.right_gains, levels),
+        )
+    }
+
+    // The output of a single channel as if it were the only one playing.
+    // Ignores mute, solo, volume and pan.
+    pub fn mix_isolated(&self, channel: Channel, level: u8) -> f32 {
+        let level = level as usize;
+        match channel {
+            Channel::Pulse1 | Channel::Pulse2 => self.pulse_table[level],
+            Channel::Triangle => self.tnd_table[3 * level],
+            Channel::Noise => self.tnd_table[2 * level],
+            Channel::DMC => self.tnd_table[level],
+        }
+    }
+
+    fn mix_scaled(&self, gains: &[f32; 5], levels: [u8; 5]) -> f32 {
+        // Scaled channel levels fall between table entries, so interpolate.
+        let mut scaled = [0.0; 5];
+        for (ix, level) in scaled.iter_mut().enumerate() {
+            *level = (levels[ix] as f32) * gains[ix];
+        }
+        let pulse_ix = scaled[0] + scaled[1];
+        let tnd_ix = 3.0 * scaled[2] + 2.0 * scaled[3] + scaled[4];
+        Mixer::lookup(&self.pulse_table, pulse_ix) + Mixer::lookup(&self.tnd_table, tnd_ix)
+    }
+
+    fn lookup(table: &[f32], ix: f32) -> f32 {
+        let last = table.len() - 1;
+        let base = (ix.floor() as usize).min(last - 1);
+        let frac = ix - (base as f32);
+        table[base] + (table[base + 1] - table[base]) * frac
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::emulator::apu::mixer::{Channel, Mixer};
+
+    #[test]
+    fn test_unity_matches_table() {
+        let mixer = Mixer::new();
+        let mut scaled = Mixer::new();
+        scaled.set_volume(Channel::Noise, 0.5);
+        scaled.set_volume(Channel::Noise, 1.0);
+
+        assert_eq!(mixer.mix(3, 7, 15, 2, 64), scaled.mix(3, 7, 15, 2, 64));
+    }
+
+    #[test]
+    fn test_mute() {
+        let mut mixer = Mixer::new();
+        mixer.set_muted(Channel::Pulse1, true);
+
+        assert_eq!(mixer.mix(15, 0, 0, 0, 0), 0.0);
+        assert_eq!(mixer.mix(15, 4, 0, 0, 0), Mixer::new().mix(0, 4, 0, 0, 0));
+        assert!(!mixer.is_audible(Channel::Pulse1));
+        assert!(mixer.is_audible(Channel::Pulse2));
+    }
+
+    #[test]
+    fn test_solo() {
+        let mut mixer = Mixer::new();
+        mixer.set_solo(Channel::Triangle, true);
+        mixer.set_solo(Channel::DMC, true);
+
+        let unmixed = Mixer::new();
+        assert_eq!(mixer.mix(15, 15, 9, 12, 30), unmixed.mix(0, 0, 9, 0, 30));
+
+        mixer.set_solo(Channel::Triangle, false);
+        mixer.set_solo(Channel::DMC, false);
+        assert_eq!(mixer.mix(15, 15, 9, 12, 30), unmixed.mix(15, 15, 9, 12, 30));
+    }
+
+    #[test]
+    fn test_volume() {
+        let mut mixer = Mixer::new();
+        mixer.set_volume(Channel::Pulse2, 0.5);
+        assert_eq!(mixer.mix(0, 8, 0, 0, 0), Mixer::new().mix(0, 4, 0, 0, 0));
+
+        // Halfway between two table entries.
+        let unmixed = Mixer::new();
+        let expected = (unmixed.mix(0, 3, 0, 0, 0) + unmixed.mix(0, 4, 0, 0, 0)) / 2.0;
+        assert!((mixer.mix(0, 7, 0, 0, 0) - expected).abs() < 1e-6);
+
+        mixer.set_volume(Channel::Pulse2, 10.0);
+        assert_eq!(mixer.volume(Channel::Pulse2), Mixer::MAX_VOLUME);
+    }
+
+    #[test]
+    fn test_pan() {
+        let mut mixer = Mixer::new();
+        let mono = mixer.mix(8, 0, 0, 0, 0);
+        assert_eq!(mixer.mix_stereo(8, 0, 0, 0, 0), (mono, mono));
+
+        mixer.set_pan(Channel::Pulse1, -1.0);
+        assert_eq!(mixer.mix_stereo(8, 0, 0, 0, 0), (mono, 0.0));
+
+        mixer.set_pan(Channel::Pulse1, 0.5);
+        let (left, right) = mixer.mix_stereo(8, 0, 0, 0, 0);
+        assert!((left - Mixer::new().mix(4, 0, 0, 0, 0)).abs() < 1e-6);
+        assert_eq!(right, mono);
+    }
+}
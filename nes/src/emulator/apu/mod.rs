@@ -1,4 +1,6 @@
 pub mod debug;
+pub mod log;
+mod mixer;
 mod synth;
 
 use std::cell::RefCell;
@@ -7,16 +9,37 @@ use std::rc::Rc;
 use crate::emulator::clock::Ticker;
 use crate::emulator::memory::{Reader, Writer};
 
+use self::log::RegisterLog;
+pub use self::mixer::Channel;
+use self::mixer::Mixer;
 use self::synth::{Noise, Pulse, Sweep, Triangle, DMC};
 
 pub trait AudioOut {
     fn emit(&mut self, sample: f32);
+
+    // Only called when the APU is in stereo mode.
+    // Outputs which can't handle stereo just mix it back down.
+    fn emit_stereo(&mut self, left: f32, right: f32) {
+        self.emit((left + right) / 2.0);
+    }
+
+    // Only called when the APU has channel output enabled.
+    // Receives each channel's isolated output, in the order of Channel::ALL, alongside the mix.
+    fn emit_channels(&mut self, _levels: &[f32; 5]) {}
 }
 
 impl<A: AudioOut> AudioOut for Rc<RefCell<A>> {
     fn emit(&mut self, sample: f32) {
         self.borrow_mut().emit(sample);
     }
+
+    fn emit_stereo(&mut self, left: f32, right: f32) {
+        self.borrow_mut().emit_stereo(left, right);
+    }
+
+    fn emit_channels(&mut self, levels: &[f32; 5]) {
+        self.borrow_mut().emit_channels(levels);
+    }
 }
 
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
@@ -30,13 +53,47 @@ const LENGTH_COUNTER_LOOKUP: [u8; 0x20] = [
     192, 24, 72, 26, 16, 28, 32, 30,
 ];
 
+// Frame sequencer step timings, measured in CPU cycles since the sequencer was last reset.
+// See https://wiki.nesdev.com/w/index.php/APU_Frame_Counter
+const STEP_1: u64 = 7457;
+const STEP_2: u64 = 14913;
+const STEP_3: u64 = 22371;
+const FOUR_STEP_IRQ: u64 = 29828;
+const FOUR_STEP_4: u64 = 29829;
+const FOUR_STEP_LENGTH: u64 = 29830;
+const FIVE_STEP_5: u64 = 37281;
+const FIVE_STEP_LENGTH: u64 = 37282;
+
+// At power-on the frame sequencer is already part way through, as if $00 had been written to
+// $4017 around 10 cycles before the first instruction.
+const POWER_ON_CYCLES: u64 = 11;
+
 pub struct APU {
     output: Box<dyn AudioOut>,
+    // A second output receiving the mix, e.g. for recording video.
+    recording_output: Option<Box<dyn AudioOut>>,
+    mixer: Mixer,
+    stereo: bool,
+    channel_output: bool,
+
+    // The APU is clocked once per CPU cycle.
+    // Pulse and noise timers, and the output sample, only advance on every other cycle.
+    odd_cycle: bool,
+    cycles: u64,
+
+    // The last value written to each register, and the log of writes if enabled.
+    registers: [u8; 0x18],
+    register_log: Option<RegisterLog>,
 
     sequence_mode: SequenceMode,
     cycle_counter: u64,
     irq_flag: bool,
-    irq_enabled: bool,
+    irq_inhibit: bool,
+
+    // Writes to $4017 take effect 3 or 4 CPU cycles later, depending on whether the write landed
+    // on an even or odd CPU cycle.
+    frame_counter_write: Option<u8>,
+    frame_counter_delay: u8,
 
     pulse_1: Pulse,
     pulse_2: Pulse,
@@ -46,14 +103,29 @@ pub struct APU {
 }
 
 impl APU {
+    pub const MAX_CHANNEL_VOLUME: f32 = Mixer::MAX_VOLUME;
+
     pub fn new(output: Box<dyn AudioOut>, prg_rom: Box<dyn Reader>) -> APU {
         APU {
             output,
+            recording_output: None,
+            mixer: Mixer::new(),
+            stereo: false,
+            channel_output: false,
+
+            odd_cycle: false,
+            cycles: 0,
+
+            registers: [0; 0x18],
+            register_log: None,
 
             sequence_mode: SequenceMode::FourStep,
-            cycle_counter: 0,
+            cycle_counter: POWER_ON_CYCLES,
             irq_flag: false,
-            irq_enabled: true,
+            irq_inhibit: false,
+
+            frame_counter_write: None,
+            frame_counter_delay: 0,
 
             pulse_1: Pulse::new(Sweep::new(false)),
             pulse_2: Pulse::new(Sweep::new(true)),
@@ -63,10 +135,143 @@ impl APU {
         }
     }
 
+    // Returns the channels and frame counter to their power-on state, keeping the outputs, mixer
+    // settings and register log.
+    pub fn power_on(&mut self, prg_rom: Box<dyn Reader>) {
+        self.odd_cycle = false;
+        self.cycles = 0;
+        self.registers = [0; 0x18];
+
+        self.sequence_mode = SequenceMode::FourStep;
+        self.cycle_counter = POWER_ON_CYCLES;
+        self.irq_flag = false;
+        self.irq_inhibit = false;
+
+        self.frame_counter_write = None;
+        self.frame_counter_delay = 0;
+
+        self.pulse_1 = Pulse::new(Sweep::new(false));
+        self.pulse_2 = Pulse::new(Sweep::new(true));
+        self.triangle = Triangle::new();
+        self.noise = Noise::new();
+        self.dmc = DMC::new(prg_rom);
+    }
+
     pub fn irq_triggered(&self) -> bool {
         self.irq_flag || self.dmc.irq_flag
     }
 
+    pub fn is_channel_muted(&self, channel: Channel) -> bool {
+        self.mixer.is_muted(channel)
+    }
+
+    pub fn set_channel_muted(&mut self, channel: Channel, muted: bool) {
+        self.mixer.set_muted(channel, muted);
+    }
+
+    pub fn is_channel_solo(&self, channel: Channel) -> bool {
+        self.mixer.is_solo(channel)
+    }
+
+    pub fn set_channel_solo(&mut self, channel: Channel, solo: bool) {
+        self.mixer.set_solo(channel, solo);
+    }
+
+    pub fn channel_volume(&self, channel: Channel) -> f32 {
+        self.mixer.volume(channel)
+    }
+
+    // Volume is a linear scale on the channel's output, from 0.0 up to MAX_CHANNEL_VOLUME.
+    pub fn set_channel_volume(&mut self, channel: Channel, volume: f32) {
+        self.mixer.set_volume(channel, volume);
+    }
+
+    pub fn is_channel_audible(&self, channel: Channel) -> bool {
+        self.mixer.is_audible(channel)
+    }
+
+    pub fn is_stereo(&self) -> bool {
+        self.stereo
+    }
+
+    // In stereo mode each channel is panned according to its pan position.
+    pub fn set_stereo(&mut self, stereo: bool) {
+        self.stereo = stereo;
+    }
+
+    pub fn is_channel_output(&self) -> bool {
+        self.channel_output
+    }
+
+    // Whether to additionally emit each channel's isolated output, e.g. for recording.
+    pub fn set_channel_output(&mut self, enabled: bool) {
+        self.channel_output = enabled;
+    }
+
+    pub fn set_recording_output(&mut self, output: Option<Box<dyn AudioOut>>) {
+        self.recording_output = output;
+    }
+
+    pub fn channel_pan(&self, channel: Channel) -> f32 {
+        self.mixer.pan(channel)
+    }
+
+    // Pan ranges from -1.0 (hard left) to 1.0 (hard right).
+    pub fn set_channel_pan(&mut self, channel: Channel, pan: f32) {
+        self.mixer.set_pan(channel, pan);
+    }
+
+    // Centres every channel.
+    pub fn reset_panning(&mut self) {
+        for &channel in Channel::ALL.iter() {
+            self.mixer.set_pan(channel, 0.0);
+        }
+    }
+
+    pub fn set_famicom_panning(&mut self) {
+        for (&channel, &pan) in Channel::ALL.iter().zip(Mixer::FAMICOM_PAN.iter()) {
+            self.mixer.set_pan(channel, pan);
+        }
+    }
+
+    pub fn is_logging_registers(&self) -> bool {
+        self.register_log.is_some()
+    }
+
+    // Start logging register writes.
+    // The log opens with the current register values, so playback starts from the same state.
+    pub fn start_register_log(&mut self) {
+        let mut log = RegisterLog::new(self.cycles);
+        log.record(self.cycles, 0x4015, self.registers[0x15]);
+        log.record(self.cycles, 0x4017, self.registers[0x17]);
+        for address in 0x4000..=0x4013 {
+            if address != 0x4009 && address != 0x400D {
+                log.record(
+                    self.cycles,
+                    address,
+                    self.registers[(address - 0x4000) as usize],
+                );
+            }
+        }
+
+        self.register_log = Some(log);
+        self.log_dmc_sample();
+    }
+
+    pub fn stop_register_log(&mut self) -> Option<RegisterLog> {
+        let mut log = self.register_log.take()?;
+        log.finish(self.cycles);
+        Some(log)
+    }
+
+    // Keep a copy of the sample data, which is needed to play the log back. It's read as a sample
+    // is set up, and again byte by byte as it's played, in case a mapper switches banks.
+    fn log_dmc_sample(&mut self) {
+        if let Some(ref mut log) = self.register_log {
+            log.record_sample_data(self.cycles, &self.dmc.sample_data());
+        }
+    }
+
     fn clock_linear_and_envelope(&mut self) {
         self.pulse_1.envelope.clock();
         self.pulse_2.envelope.clock();
@@ -80,68 +285,147 @@ impl APU {
         self.triangle.clock_length();
         self.noise.clock_length();
     }
-}
 
-impl Ticker for APU {
-    fn tick(&mut self) -> u32 {
-        self.cycle_counter += 1;
+    fn commit_length_counters(&mut self) {
+        self.pulse_1.length.commit();
+        self.pulse_2.length.commit();
+        self.triangle.length.commit();
+        self.noise.length.commit();
+    }
+
+    fn set_frame_irq(&mut self) {
+        if !self.irq_inhibit {
+            self.irq_flag = true;
+        }
+    }
+
+    fn clock_frame_counter_write(&mut self) {
+        let byte = match self.frame_counter_write {
+            None => return,
+            Some(byte) => byte,
+        };
+
+        self.frame_counter_delay -= 1;
+        if self.frame_counter_delay != 0 {
+            return;
+        }
+
+        self.frame_counter_write = None;
+        self.cycle_counter = 0;
+        self.sequence_mode = if byte & 0x80 == 0 {
+            SequenceMode::FourStep
+        } else {
+            SequenceMode::FiveStep
+        };
+
+        // Entering 5-step mode immediately clocks all units.
+        if self.sequence_mode == SequenceMode::FiveStep {
+            self.clock_linear_and_envelope();
+            self.clock_length_counters();
+        }
+    }
+
+    fn clock_frame_sequencer(&mut self) {
         match self.sequence_mode {
             SequenceMode::FourStep => match self.cycle_counter {
-                3729 => self.clock_linear_and_envelope(),
-                7457 => {
+                STEP_1 | STEP_3 => self.clock_linear_and_envelope(),
+                STEP_2 => {
                     self.clock_linear_and_envelope();
                     self.clock_length_counters();
                 }
-                11186 => self.clock_linear_and_envelope(),
-                14915 => {
+                FOUR_STEP_IRQ => self.set_frame_irq(),
+                FOUR_STEP_4 => {
+                    self.set_frame_irq();
                     self.clock_linear_and_envelope();
                     self.clock_length_counters();
+                }
+                FOUR_STEP_LENGTH => {
+                    self.set_frame_irq();
                     self.cycle_counter = 0;
-                    self.irq_flag = self.irq_enabled;
                 }
                 _ => (),
             },
             SequenceMode::FiveStep => match self.cycle_counter {
-                3729 => self.clock_linear_and_envelope(),
-                7457 => {
-                    self.clock_linear_and_envelope();
-                    self.clock_length_counters();
-                }
-                11186 => self.clock_linear_and_envelope(),
-                18641 => {
+                STEP_1 | STEP_3 => self.clock_linear_and_envelope(),
+                STEP_2 | FIVE_STEP_5 => {
                     self.clock_linear_and_envelope();
                     self.clock_length_counters();
-                    self.cycle_counter = 0;
                 }
+                FIVE_STEP_LENGTH => self.cycle_counter = 0,
                 _ => (),
             },
         };
+        self.cycle_counter += 1;
+    }
+}
+
+impl Ticker for APU {
+    fn tick(&mut self) -> u32 {
+        self.cycles += 1;
+        self.clock_frame_counter_write();
+        self.clock_frame_sequencer();
+        self.commit_length_counters();
+
+        // Triangle and DMC clock at the CPU rate, the other components at half of that.
+        self.triangle.clock();
+        self.dmc.clock();
+        if let Some(fetch) = self.dmc.take_fetch() {
+            if let Some(ref mut log) = self.register_log {
+                log.record_sample_data(self.cycles, &[fetch]);
+            }
+        }
+
+        self.odd_cycle = !self.odd_cycle;
+        if self.odd_cycle {
+            return 1;
+        }
 
         self.pulse_1.clock();
         self.pulse_2.clock();
         self.noise.clock();
-        // Triangle and DMC clock twice as fast as the other components.
-        self.triangle.clock();
-        self.triangle.clock();
-        self.dmc.clock();
-        self.dmc.clock();
 
-        // Mixer.
-        let p1 = self.pulse_1.volume() as f32;
-        let p2 = self.pulse_2.volume() as f32;
-        let t = self.triangle.volume() as f32;
-        let n = self.noise.volume() as f32;
-        let dmc = self.dmc.volume as f32;
+        let (pulse_1, pulse_2) = (self.pulse_1.volume(), self.pulse_2.volume());
+        let (triangle, noise) = (self.triangle.volume(), self.noise.volume());
+        let dmc = self.dmc.volume;
+        if self.channel_output {
+            let levels = [
+                self.mixer.mix_isolated(Channel::Pulse1, pulse_1),
+                self.mixer.mix_isolated(Channel::Pulse2, pulse_2),
+                self.mixer.mix_isolated(Channel::Triangle, triangle),
+                self.mixer.mix_isolated(Channel::Noise, noise),
+                self.mixer.mix_isolated(Channel::DMC, dmc),
+            ];
+            self.output.emit_channels(&levels);
+        }
 
-        let pulse_out = 0.00752 * (p1 + p2);
-        let tnd_out = (0.00851 * t) + (0.00494 * n) + (0.00335 * dmc);
-        self.output.emit(pulse_out + tnd_out);
+        if self.stereo {
+            let (left, right) = self
+                .mixer
+                .mix_stereo(pulse_1, pulse_2, triangle, noise, dmc);
+            self.output.emit_stereo(left, right);
+            if let Some(output) = self.recording_output.as_mut() {
+                output.emit_stereo(left, right);
+            }
+        } else {
+            let sample = self.mixer.mix(pulse_1, pulse_2, triangle, noise, dmc);
+            self.output.emit(sample);
+            if let Some(output) = self.recording_output.as_mut() {
+                output.emit(sample);
+            }
+        }
         1
     }
 }
 
 impl Writer for APU {
     fn write(&mut self, address: u16, byte: u8) {
+        if let 0x4000..=0x4017 = address {
+            self.registers[(address - 0x4000) as usize] = byte;
+        }
+        if let Some(ref mut log) = self.register_log {
+            log.record(self.cycles, address, byte);
+        }
+
         match address {
             0x4000 => {
                 write_first_pulse_register(&mut self.pulse_1, byte);
@@ -169,7 +453,7 @@ impl Writer for APU {
             }
             0x4008 => {
                 self.triangle.linear_reload_value = byte & 0x7F;
-                self.triangle.halt_length = (byte & 0x80) != 0;
+                self.triangle.length.set_halt((byte & 0x80) != 0);
                 self.triangle.control_flag = (byte & 0x80) != 0;
             }
             0x400A => {
@@ -177,14 +461,16 @@ impl Writer for APU {
                 self.triangle.timer.set_period(new_period);
             }
             0x400B => {
-                self.triangle.length = LENGTH_COUNTER_LOOKUP[(byte >> 3) as usize];
+                self.triangle
+                    .length
+                    .reload(LENGTH_COUNTER_LOOKUP[(byte >> 3) as usize]);
                 let new_period =
                     (self.triangle.timer.period() & 0x00FF) | (((byte & 0x7) as u16) << 8);
                 self.triangle.timer.set_period(new_period);
                 self.triangle.linear_reload_flag = true;
             }
             0x400C => {
-                self.noise.halt_length = (byte & 0x20) != 0;
+                self.noise.length.set_halt((byte & 0x20) != 0);
                 self.noise.envelope.loop_flag = (byte & 0x20) != 0;
                 self.noise.envelope.constant_volume = (byte & 0x10) != 0;
                 self.noise.envelope.set_volume(byte & 0x0F);
@@ -197,24 +483,32 @@ impl Writer for APU {
                     .set_period(Noise::PERIOD_LOOKUP[(byte & 0x0F) as usize]);
             }
             0x400F => {
-                self.noise.length = LENGTH_COUNTER_LOOKUP[(byte >> 3) as usize];
+                self.noise
+                    .length
+                    .reload(LENGTH_COUNTER_LOOKUP[(byte >> 3) as usize]);
                 self.noise.envelope.restart();
             }
             0x4010 => {
                 self.dmc.irq_enabled = byte & 0x80 != 0;
+                if !self.dmc.irq_enabled {
+                    self.dmc.irq_flag = false;
+                }
                 self.dmc.loop_flag = byte & 0x40 != 0;
+                // The rates are in CPU cycles, and the divider counts from its period down to 0.
                 self.dmc
                     .timer
-                    .set_period(DMC::PERIOD_LOOKUP[(byte & 0x0F) as usize]);
+                    .set_period(DMC::PERIOD_LOOKUP[(byte & 0x0F) as usize] - 1);
             }
             0x4011 => {
                 self.dmc.volume = byte & 0x7F;
             }
             0x4012 => {
                 self.dmc.sample_addr = 0xC000 | ((byte as u16) << 6);
+                self.log_dmc_sample();
             }
             0x4013 => {
                 self.dmc.sample_len = ((byte as u16) << 4) + 1;
+                self.log_dmc_sample();
             }
             0x4015 => {
                 self.dmc.irq_flag = false;
@@ -227,47 +521,21 @@ impl Writer for APU {
                     self.dmc.enabled = false;
                     self.dmc.bytes_remaining = 0;
                 }
-                if (byte >> 3) & 0x1 != 0 {
-                    self.noise.enabled = true;
-                } else {
-                    self.noise.enabled = false;
-                    self.noise.length = 0;
-                }
-                if (byte >> 2) & 0x1 != 0 {
-                    self.triangle.enabled = true;
-                } else {
-                    self.triangle.enabled = false;
-                    self.triangle.length = 0;
-                }
-                if (byte >> 1) & 0x1 != 0 {
-                    self.pulse_2.enabled = true;
-                } else {
-                    self.pulse_2.enabled = false;
-                    self.pulse_2.length = 0;
-                }
-                if byte & 0x1 != 0 {
-                    self.pulse_1.enabled = true;
-                } else {
-                    self.pulse_1.enabled = false;
-                    self.pulse_1.length = 0;
-                }
+                self.noise.length.set_enabled((byte >> 3) & 0x1 != 0);
+                self.triangle.length.set_enabled((byte >> 2) & 0x1 != 0);
+                self.pulse_2.length.set_enabled((byte >> 1) & 0x1 != 0);
+                self.pulse_1.length.set_enabled(byte & 0x1 != 0);
+                self.log_dmc_sample();
             }
             0x4017 => {
-                self.sequence_mode = if byte & 0x80 == 0 {
-                    SequenceMode::FourStep
-                } else {
-                    SequenceMode::FiveStep
-                };
-
-                // IRQ inhibit.
-                if byte & 0x70 != 0 {
-                    self.irq_enabled = false;
+                // IRQ inhibit takes effect immediately, the rest of the write is delayed.
+                self.irq_inhibit = byte & 0x40 != 0;
+                if self.irq_inhibit {
                     self.irq_flag = false;
-                } else {
-                    self.irq_enabled = true;
                 }
 
-                self.cycle_counter = 0;
+                self.frame_counter_write = Some(byte);
+                self.frame_counter_delay = if self.odd_cycle { 4 } else { 3 };
             }
             _ => (),
         }
@@ -279,28 +547,29 @@ impl Reader for APU {
         match address {
             0x4015 => {
                 let mut status = 0;
-                if self.pulse_1.length != 0 {
+                if self.pulse_1.length.value() != 0 {
                     status |= 1
                 };
-                if self.pulse_2.length != 0 {
+                if self.pulse_2.length.value() != 0 {
                     status |= 1 << 1
                 };
-                if self.triangle.length != 0 {
+                if self.triangle.length.value() != 0 {
                     status |= 1 << 2
                 };
-                if self.noise.length != 0 {
+                if self.noise.length.value() != 0 {
                     status |= 1 << 3
                 };
                 if self.dmc.bytes_remaining != 0 {
                     status |= 1 << 4
                 };
-                if self.dmc.irq_flag {
-                    status |= 1 << 5
-                };
                 if self.irq_flag {
                     status |= 1 << 6
                 };
+                if self.dmc.irq_flag {
+                    status |= 1 << 7
+                };
 
+                // Reading the status clears the frame interrupt flag, but not the DMC one.
                 self.irq_flag = false;
                 status
             }
@@ -313,7 +582,7 @@ fn write_first_pulse_register(pulse: &mut Pulse, byte: u8) {
     pulse.sequence = byte >> 6;
     // These 2 flags share the same bit.
     pulse.envelope.loop_flag = (byte & 0x20) != 0;
-    pulse.halt_length = (byte & 0x20) != 0;
+    pulse.length.set_halt((byte & 0x20) != 0);
     pulse.envelope.constant_volume = (byte & 0x10) != 0;
     pulse.envelope.set_volume(byte & 0x0F);
     pulse.envelope.restart();
@@ -332,8 +601,170 @@ fn write_second_pulse_register(pulse: &mut Pulse, byte: u8) {
 }
 
 fn write_third_pulse_register(pulse: &mut Pulse, byte: u8) {
-    pulse.length = LENGTH_COUNTER_LOOKUP[(byte >> 3) as usize];
+    pulse
+        .length
+        .reload(LENGTH_COUNTER_LOOKUP[(byte >> 3) as usize]);
     let new_period = (pulse.timer.period() & 0x00FF) | (((byte & 0x7) as u16) << 8);
     pulse.timer.set_period(new_period);
     pulse.restart();
 }
+
+#[cfg(test)]
+mod test {
+    use crate::emulator::apu::APU;
+    use crate::emulator::clock::Ticker;
+    use crate::emulator::io::nop::DummyAudio;
+    use crate::emulator::memory::{Memory, Reader, Writer};
+
+    fn new_apu() -> APU {
+        APU::new(Box::new(DummyAudio {}), Box::new(Memory::new_ram(0x10000)))
+    }
+
+    fn run_for(apu: &mut APU, cycles: u64) {
+        for _ in 0..cycles {
+            apu.tick();
+        }
+    }
+
+    #[test]
+    fn test_four_step_irq_timing() {
+        let mut apu = new_apu();
+        apu.write(0x4017, 0x00);
+
+        // 3 cycles of write delay, then the IRQ is raised on step 4.
+        run_for(&mut apu, 3 + 29827);
+        assert!(!apu.irq_triggered());
+        run_for(&mut apu, 1);
+        assert!(apu.irq_triggered());
+
+        // Reading $4015 reports and clears the flag.
+        assert_eq!(apu.read(0x4015) & 0x40, 0x40);
+        assert!(!apu.irq_triggered());
+
+        // But it's set again on the following 2 cycles.
+        run_for(&mut apu, 1);
+        assert!(apu.irq_triggered());
+    }
+
+    #[test]
+    fn test_irq_inhibit() {
+        let mut apu = new_apu();
+        apu.write(0x4017, 0x00);
+        run_for(&mut apu, 30_000);
+        assert!(apu.irq_triggered());
+
+        // Setting the inhibit flag clears the IRQ immediately and stops it firing again.
+        apu.write(0x4017, 0x40);
+        assert!(!apu.irq_triggered());
+        run_for(&mut apu, 60_000);
+        assert!(!apu.irq_triggered());
+    }
+
+    #[test]
+    fn test_register_log() {
+        let mut apu = new_apu();
+        apu.write(0x4000, 0x3F);
+        run_for(&mut apu, 100);
+
+        apu.start_register_log();
+        run_for(&mut apu, 10);
+        apu.write(0x4002, 0xFD);
+        run_for(&mut apu, 5);
+        let log = apu.stop_register_log().unwrap();
+        assert!(!apu.is_logging_registers());
+
+        // The log opens with the existing register state, at cycle 0.
+        let writes = log.writes();
+        assert_eq!(writes[0].address, 0x4015);
+        assert!(writes
+            .iter()
+            .any(|w| w.cycle == 0 && w.address == 0x4000 && w.value == 0x3F));
+
+        let last = writes.last().unwrap();
+        assert_eq!((last.cycle, last.address, last.value), (10, 0x4002, 0xFD));
+        assert_eq!(log.duration_cycles(), 15);
+    }
+
+    #[test]
+    fn test_five_step_mode_has_no_irq() {
+        let mut apu = new_apu();
+        apu.write(0x4017, 0x80);
+        run_for(&mut apu, 80_000);
+        assert!(!apu.irq_triggered());
+    }
+
+    #[test]
+    fn test_write_delay_depends_on_parity() {
+        let mut apu = new_apu();
+
+        // Even cycle: 3 cycles delay.
+        apu.write(0x4015, 0x01);
+        apu.write(0x4003, 0x18);
+        run_for(&mut apu, 1);
+        apu.tick();
+        apu.write(0x4017, 0x80);
+        run_for(&mut apu, 2);
+        assert_eq!(apu.pulse_1.length.value(), 2);
+        run_for(&mut apu, 1);
+        assert_eq!(apu.pulse_1.length.value(), 1);
+
+        // Odd cycle: 4 cycles delay.
+        apu.write(0x4017, 0x80);
+        run_for(&mut apu, 3);
+        assert_eq!(apu.pulse_1.length.value(), 1);
+        run_for(&mut apu, 1);
+        assert_eq!(apu.pulse_1.length.value(), 0);
+    }
+
+    #[test]
+    fn test_length_counter_ignores_writes_while_disabled() {
+        let mut apu = new_apu();
+        apu.write(0x4003, 0x18);
+        apu.tick();
+        assert_eq!(apu.read(0x4015) & 0x01, 0x00);
+
+        apu.write(0x4015, 0x01);
+        apu.write(0x4003, 0x18);
+        apu.tick();
+        assert_eq!(apu.read(0x4015) & 0x01, 0x01);
+
+        // Disabling the channel clears the counter.
+        apu.write(0x4015, 0x00);
+        assert_eq!(apu.read(0x4015) & 0x01, 0x00);
+    }
+
+    #[test]
+    fn test_length_reload_during_clock() {
+        let mut apu = new_apu();
+        apu.write(0x4015, 0x01);
+        apu.write(0x4017, 0x00);
+        apu.write(0x4003, 0x18);
+        run_for(&mut apu, 3 + 14912);
+        assert_eq!(apu.pulse_1.length.value(), 2);
+
+        // A reload on the same cycle as the clock of a non-zero counter is ignored.
+        apu.write(0x4003, 0xF8);
+        apu.tick();
+        assert_eq!(apu.pulse_1.length.value(), 1);
+
+        // Otherwise it's applied as normal.
+        apu.write(0x4003, 0xF8);
+        apu.tick();
+        assert_eq!(apu.pulse_1.length.value(), 30);
+    }
+
+    #[test]
+    fn test_halt_during_clock() {
+        let mut apu = new_apu();
+        apu.write(0x4015, 0x01);
+        apu.write(0x4017, 0x00);
+        apu.write(0x4003, 0x18);
+        run_for(&mut apu, 3 + 14912);
+
+        // Halting on the same cycle as the clock doesn't affect that clock.
+        apu.write(0x4000, 0x20);
+        apu.tick();
+        assert_eq!(apu.pulse_1.length.value(), 1);
+        assert!(apu.pulse_1.length.is_halted());
+    }
+}
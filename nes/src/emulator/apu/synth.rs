@@ -96,6 +96,87 @@ impl Envelope {
     }
 }
 
+// Length counters are shared by every channel except the DMC.
+// Writes to the halt flag and reload value are latched and only applied after the frame sequencer
+// has had a chance to clock the counter on the same CPU cycle.  This reproduces the hardware race
+// where a reload is ignored if it coincides with a clock of a non-zero counter, and a halt flag
+// change does not affect a clock which happens on the same cycle.
+pub struct LengthCounter {
+    enabled: bool,
+    counter: u8,
+    halt: bool,
+    pending_halt: Option<bool>,
+    pending_reload: Option<u8>,
+    clocked_while_non_zero: bool,
+}
+
+impl LengthCounter {
+    pub fn new() -> LengthCounter {
+        LengthCounter {
+            enabled: false,
+            counter: 0,
+            halt: false,
+            pending_halt: None,
+            pending_reload: None,
+            clocked_while_non_zero: false,
+        }
+    }
+
+    pub fn clock(&mut self) {
+        if self.counter != 0 {
+            self.clocked_while_non_zero = true;
+            if !self.halt {
+                self.counter -= 1;
+            }
+        }
+    }
+
+    // Apply any register writes which were latched during this cycle.
+    pub fn commit(&mut self) {
+        if let Some(value) = self.pending_reload.take() {
+            if self.enabled && !self.clocked_while_non_zero {
+                self.counter = value;
+            }
+        }
+
+        if let Some(halt) = self.pending_halt.take() {
+            self.halt = halt;
+        }
+
+        self.clocked_while_non_zero = false;
+    }
+
+    pub fn reload(&mut self, value: u8) {
+        if self.enabled {
+            self.pending_reload = Some(value);
+        }
+    }
+
+    pub fn set_halt(&mut self, halt: bool) {
+        self.pending_halt = Some(halt);
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.counter = 0;
+            self.pending_reload = None;
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.halt
+    }
+
+    pub fn value(&self) -> u8 {
+        self.counter
+    }
+}
+
 pub struct Sweep {
     twos_complement: bool,
     pub enabled: bool,
@@ -154,10 +235,8 @@ impl Sweep {
 }
 
 pub struct Pulse {
-    pub enabled: bool,
     pub timer: Divider,
-    pub length: u8,
-    pub halt_length: bool,
+    pub length: LengthCounter,
     pub sequence: u8,
     sequence_ix: u8,
     pub envelope: Envelope,
@@ -174,10 +253,8 @@ impl Pulse {
 
     pub fn new(sweep: Sweep) -> Pulse {
         Pulse {
-            enabled: false,
             timer: Divider::new(0),
-            length: 0,
-            halt_length: false,
+            length: LengthCounter::new(),
             sequence: 0,
             envelope: Envelope::new(),
             sweep,
@@ -198,24 +275,18 @@ impl Pulse {
     }
 
     pub fn clock_length(&mut self) {
-        if !self.halt_length {
-            self.length = self.length.saturating_sub(1);
-        }
+        self.length.clock();
 
         let new_period = self.sweep.get_updated_period(self.timer.period());
         self.timer.set_period(new_period);
     }
 
     pub fn volume(&self) -> u8 {
-        if !self.enabled {
-            return 0;
-        }
-
         if self.timer.counter() < 8 {
             return 0;
         }
 
-        if self.length == 0 {
+        if self.length.value() == 0 {
             return 0;
         }
 
@@ -237,11 +308,9 @@ impl Pulse {
 }
 
 pub struct Triangle {
-    pub enabled: bool,
     pub timer: Divider,
     pub linear: u8,
-    pub length: u8,
-    pub halt_length: bool,
+    pub length: LengthCounter,
     pub linear_reload_flag: bool,
     pub linear_reload_value: u8,
     pub control_flag: bool,
@@ -256,11 +325,9 @@ impl Triangle {
 
     pub fn new() -> Triangle {
         Triangle {
-            enabled: false,
             timer: Divider::new(0),
             linear: 0,
-            length: 0,
-            halt_length: false,
+            length: LengthCounter::new(),
             linear_reload_flag: false,
             linear_reload_value: 0,
             control_flag: false,
@@ -287,17 +354,11 @@ impl Triangle {
     }
 
     pub fn clock_length(&mut self) {
-        if !self.halt_length {
-            self.length = self.length.saturating_sub(1);
-        }
+        self.length.clock();
     }
 
     pub fn volume(&self) -> u8 {
-        if !self.enabled {
-            return 0;
-        }
-
-        if self.linear == 0 || self.length == 0 {
+        if self.linear == 0 || self.length.value() == 0 {
             return 0;
         }
 
@@ -306,11 +367,9 @@ impl Triangle {
 }
 
 pub struct Noise {
-    pub enabled: bool,
     pub envelope: Envelope,
     shift_register: u16,
-    pub length: u8,
-    pub halt_length: bool,
+    pub length: LengthCounter,
     pub mode: bool,
     pub timer: Divider,
 }
@@ -322,11 +381,9 @@ impl Noise {
 
     pub fn new() -> Noise {
         Noise {
-            enabled: false,
             envelope: Envelope::new(),
             shift_register: 1,
-            length: 0,
-            halt_length: false,
+            length: LengthCounter::new(),
             mode: false,
             timer: Divider::new(0),
         }
@@ -347,21 +404,15 @@ impl Noise {
     }
 
     pub fn clock_length(&mut self) {
-        if !self.halt_length {
-            self.length = self.length.saturating_sub(1);
-        }
+        self.length.clock();
     }
 
     pub fn volume(&self) -> u8 {
-        if !self.enabled {
-            return 0;
-        }
-
         if self.shift_register & 0x1 != 0 {
             return 0;
         }
 
-        if self.length == 0 {
+        if self.length.value() == 0 {
             return 0;
         }
 
@@ -389,6 +440,9 @@ pub struct DMC {
 
     shift_register: u8,
     bits_remaining: u8,
+
+    // The address and byte last read by the memory reader, until taken.
+    fetch: Option<(u16, u8)>,
 }
 
 impl DMC {
@@ -415,14 +469,18 @@ impl DMC {
 
             shift_register: 0,
             bits_remaining: 0,
+
+            fetch: None,
         }
     }
 
     pub fn clock(&mut self) {
         if self.timer.clock() {
-            self.clock_memory_reader();
             self.clock_output_unit();
         }
+
+        // The sample buffer is refilled as soon as it's emptied, rather than on the timer.
+        self.clock_memory_reader();
     }
 
     pub fn restart_sample(&mut self) {
@@ -430,11 +488,30 @@ impl DMC {
         self.current_addr = self.sample_addr;
     }
 
+    // Each (address, byte) of the configured sample, without affecting playback.
+    pub fn sample_data(&mut self) -> Vec<(u16, u8)> {
+        let mut addr = self.sample_addr;
+        let mut data = Vec::with_capacity(self.sample_len as usize);
+        for _ in 0..self.sample_len {
+            data.push((addr, self.prg_rom.read(addr)));
+            addr = addr.wrapping_add(1);
+            if addr == 0 {
+                addr = 0x8000;
+            }
+        }
+        data
+    }
+
+    pub fn take_fetch(&mut self) -> Option<(u16, u8)> {
+        self.fetch.take()
+    }
+
     fn clock_memory_reader(&mut self) {
         if self.sample_buffer.is_none() && self.bytes_remaining != 0 {
             // TODO: Stall the CPU by 4 (or 2) clocks.
             let byte = self.prg_rom.read(self.current_addr);
             self.sample_buffer = Some(byte);
+            self.fetch = Some((self.current_addr, byte));
             self.current_addr = self.current_addr.wrapping_add(1);
             if self.current_addr == 0 {
                 self.current_addr = 0x8000
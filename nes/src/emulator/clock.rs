@@ -85,7 +85,11 @@ struct TickNode {
 impl Ord for TickNode {
     fn cmp(&self, other: &TickNode) -> Ordering {
         // Flip the ordering here to create a min-heap.
-        other.next_tick_cycle.cmp(&self.next_tick_cycle)
+        // Ties go to whichever ticker was managed first, so they always run in the same order.
+        other
+            .next_tick_cycle
+            .cmp(&self.next_tick_cycle)
+            .then_with(|| other.ticker_ix.cmp(&self.ticker_ix))
     }
 }
 
@@ -133,6 +137,27 @@ mod test {
         assert_eq!(ticker.borrow().value, 3);
     }
 
+    #[test]
+    fn test_tie_order() {
+        let mut clock = Clock::new();
+        let tickers: Vec<_> = (0..3)
+            .map(|_| Rc::new(RefCell::new(DummyTicker::new())))
+            .collect();
+        for ticker in tickers.iter() {
+            clock.manage(ScaledTicker::new(Box::new(ticker.clone()), 2));
+        }
+
+        for round in 0..10 {
+            for (ix, ticker) in tickers.iter().enumerate() {
+                clock.tick();
+                assert_eq!(ticker.borrow().value, round + 1);
+                for later in tickers[ix + 1..].iter() {
+                    assert_eq!(later.borrow().value, round);
+                }
+            }
+        }
+    }
+
     #[test]
     fn test_scaled_ticker() {
         let mut clock = Clock::new();
@@ -1,10 +1,12 @@
 use std::collections::HashMap;
 
-use crate::emulator::io::event::{Event, EventHandler, Key};
-use crate::emulator::memory::{Reader, Writer};
+use serde::{Deserialize, Serialize};
+
+use crate::emulator::input::{InputDevice, Port};
+use crate::emulator::io::event::{Event, EventHandler, Key, PadAxis, PadButton};
 use crate::emulator::state::{ControllerState, SaveState};
 
-#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, Serialize, Deserialize)]
 pub enum Button {
     Start,
     Select,
@@ -14,15 +16,125 @@ pub enum Button {
     Down,
     Left,
     Right,
+    // Press A or B repeatedly while held.
+    TurboA,
+    TurboB,
 }
 
 pub type KeyMap = HashMap<Key, Button>;
 
 pub type KeyState = HashMap<Button, bool>;
 
+pub type PadMap = HashMap<PadButton, Button>;
+
+// A recording of a controller's buttons, one byte per frame as returned by buttons().
+pub type Macro = Vec<u8>;
+
+// Macros, each replayed by pressing its key.
+pub type MacroMap = HashMap<Key, Macro>;
+
+// Turbo rates to choose from, in presses per second at 60 frames per second.
+pub const TURBO_RATES: [u8; 3] = [30, 20, 15];
+
+fn default_turbo_rate() -> u8 {
+    TURBO_RATES[0]
+}
+
+// The NES's B and A are left and right of each other, like X and A, or A and B.
+pub fn default_padmap() -> PadMap {
+    [
+        (PadButton::A, Button::B),
+        (PadButton::X, Button::B),
+        (PadButton::B, Button::A),
+        (PadButton::Y, Button::A),
+        (PadButton::Back, Button::Select),
+        (PadButton::Start, Button::Start),
+        (PadButton::DPadUp, Button::Up),
+        (PadButton::DPadDown, Button::Down),
+        (PadButton::DPadLeft, Button::Left),
+        (PadButton::DPadRight, Button::Right),
+    ]
+    .iter()
+    .cloned()
+    .collect()
+}
+
+// The keys and gamepad buttons for one controller.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ControllerBindings {
+    pub keys: KeyMap,
+    pub pad: PadMap,
+    #[serde(default = "default_turbo_rate")]
+    pub turbo_rate: u8,
+    #[serde(default)]
+    pub macros: MacroMap,
+}
+
+// Bindings for both controllers, supplied by the frontend.
+// Players 1 and 2 use the first and second gamepads.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Bindings {
+    pub players: [ControllerBindings; 2],
+}
+
+impl Bindings {
+    // Player 1 on Z/X/A/S and the arrows, and player 2 only on a gamepad.
+    pub fn new() -> Bindings {
+        let keys = [
+            (Key::Z, Button::A),
+            (Key::X, Button::B),
+            (Key::A, Button::Start),
+            (Key::S, Button::Select),
+            (Key::Up, Button::Up),
+            (Key::Down, Button::Down),
+            (Key::Left, Button::Left),
+            (Key::Right, Button::Right),
+        ];
+        Bindings {
+            players: [
+                ControllerBindings {
+                    keys: keys.iter().cloned().collect(),
+                    pad: default_padmap(),
+                    turbo_rate: default_turbo_rate(),
+                    macros: MacroMap::new(),
+                },
+                ControllerBindings {
+                    keys: KeyMap::new(),
+                    pad: default_padmap(),
+                    turbo_rate: default_turbo_rate(),
+                    macros: MacroMap::new(),
+                },
+            ],
+        }
+    }
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// How far the stick must be pushed from the centre to press a direction.
+pub const DEFAULT_DEADZONE: i16 = 8000;
+
 pub struct Controller {
     keymap: KeyMap,
     keystate: KeyState,
+    // The gamepad this is played with, if any, and the buttons and stick position on it.
+    pad: Option<(u8, PadMap)>,
+    padstate: KeyState,
+    stick: (i16, i16),
+    deadzone: i16,
+    // The current frame, and how many frames each turbo press and release takes.
+    frame: u64,
+    turbo_period: u64,
+    macros: MacroMap,
+    // The macro being replayed and how far through it is, and the one being recorded.
+    playing: Option<(Macro, usize)>,
+    recording: Option<Macro>,
+    // Buttons set by a movie being played or recorded, in place of anything held.
+    movie_input: Option<u8>,
     strobe_ix: u8,
     register: u8,
 }
@@ -43,10 +155,164 @@ impl Controller {
         Controller {
             keymap,
             keystate: HashMap::new(),
+            pad: None,
+            padstate: HashMap::new(),
+            stick: (0, 0),
+            deadzone: DEFAULT_DEADZONE,
+            frame: 0,
+            turbo_period: 60 / default_turbo_rate() as u64,
+            macros: MacroMap::new(),
+            playing: None,
+            recording: None,
+            movie_input: None,
             strobe_ix: 0,
             register: 0,
         }
     }
+
+    pub fn set_keymap(&mut self, keymap: KeyMap) {
+        self.keymap = keymap;
+        self.keystate.clear();
+    }
+
+    // Also plays with the gamepad for the given player.
+    pub fn set_pad(&mut self, player: u8, padmap: PadMap) {
+        self.pad = Some((player, padmap));
+        self.padstate.clear();
+        self.stick = (0, 0);
+    }
+
+    pub fn set_deadzone(&mut self, deadzone: i16) {
+        self.deadzone = deadzone;
+    }
+
+    // Rates above 30 can't be kept in time with the frames, so are slowed to 30.
+    pub fn set_turbo_rate(&mut self, rate: u8) {
+        self.turbo_period = (60 / rate.max(1) as u64).max(2);
+    }
+
+    // The rate turbo buttons press at, in presses per second.
+    pub fn turbo_rate(&self) -> u8 {
+        (60 / self.turbo_period) as u8
+    }
+
+    pub fn set_macros(&mut self, macros: MacroMap) {
+        self.macros = macros;
+    }
+
+    pub fn play(&mut self, recording: Macro) {
+        self.playing = if recording.is_empty() {
+            None
+        } else {
+            Some((recording, 0))
+        };
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    // Records the buttons pressed each frame, until stopped.
+    pub fn start_recording(&mut self) {
+        self.recording = Some(Macro::new());
+    }
+
+    pub fn stop_recording(&mut self) -> Option<Macro> {
+        self.recording.take()
+    }
+
+    // Overrides the buttons, as returned by buttons(), or releases them with None.
+    pub fn set_movie_input(&mut self, buttons: Option<u8>) {
+        self.movie_input = buttons;
+    }
+
+    // Whether a button is pressed, including by turbo buttons, macros and movies.
+    pub fn is_pressed(&self, button: Button) -> bool {
+        match (self.movie_input, Controller::bit(button)) {
+            (Some(buttons), Some(bit)) => buttons & bit != 0,
+            (Some(_), None) => false,
+            _ => self.is_live_pressed(button),
+        }
+    }
+
+    // Whether a button is pressed by the player, ignoring any movie.
+    fn is_live_pressed(&self, button: Button) -> bool {
+        let turbo = match button {
+            Button::A => self.is_held(Button::TurboA),
+            Button::B => self.is_held(Button::TurboB),
+            _ => false,
+        };
+        let turbo = turbo && self.frame % self.turbo_period < self.turbo_period / 2;
+
+        let playing = match (&self.playing, Controller::bit(button)) {
+            (Some((recording, ix)), Some(bit)) => recording[*ix] & bit != 0,
+            _ => false,
+        };
+
+        turbo || playing || self.is_held(button)
+    }
+
+    // Whether a button is held down, by a key or on the gamepad.
+    fn is_held(&self, button: Button) -> bool {
+        let stick = match button {
+            Button::Up => self.stick.1 < -self.deadzone,
+            Button::Down => self.stick.1 > self.deadzone,
+            Button::Left => self.stick.0 < -self.deadzone,
+            Button::Right => self.stick.0 > self.deadzone,
+            _ => false,
+        };
+        stick
+            || *self.keystate.get(&button).unwrap_or(&false)
+            || *self.padstate.get(&button).unwrap_or(&false)
+    }
+
+    // The buttons currently held, one bit each in the order they're read.
+    pub fn buttons(&self) -> u8 {
+        Controller::STROBE_ORDER
+            .iter()
+            .enumerate()
+            .filter(|&(_, &button)| self.is_pressed(button))
+            .fold(0, |byte, (ix, _)| byte | (1 << ix))
+    }
+
+    // The buttons the player is pressing, even while a movie has overridden them.
+    pub fn live_buttons(&self) -> u8 {
+        Controller::STROBE_ORDER
+            .iter()
+            .enumerate()
+            .filter(|&(_, &button)| self.is_live_pressed(button))
+            .fold(0, |byte, (ix, _)| byte | (1 << ix))
+    }
+
+    // The bit for a button in buttons(), if it's one of the NES's buttons.
+    fn bit(button: Button) -> Option<u8> {
+        Controller::STROBE_ORDER
+            .iter()
+            .position(|&b| b == button)
+            .map(|ix| 1 << ix)
+    }
+
+    fn handle_pad_event(&mut self, event: Event) {
+        let (player, padmap) = match &self.pad {
+            Some(pad) => pad,
+            None => return,
+        };
+        match event {
+            Event::PadDown(p, pad_button) | Event::PadUp(p, pad_button) if p == *player => {
+                if let Some(&button) = padmap.get(&pad_button) {
+                    let pressed = matches!(event, Event::PadDown(..));
+                    self.padstate.insert(button, pressed);
+                }
+            }
+            Event::PadAxis(p, PadAxis::LeftX, value) if p == *player => self.stick.0 = value,
+            Event::PadAxis(p, PadAxis::LeftY, value) if p == *player => self.stick.1 = value,
+            Event::PadDisconnected(p) if p == *player => {
+                self.padstate.clear();
+                self.stick = (0, 0);
+            }
+            _ => (),
+        }
+    }
 }
 
 impl EventHandler for Controller {
@@ -56,35 +322,52 @@ impl EventHandler for Controller {
                 if let Some(button) = self.keymap.get(&key) {
                     self.keystate.insert(*button, true);
                 }
+                if let Some(recording) = self.macros.get(&key) {
+                    self.play(recording.clone());
+                }
             }
             Event::KeyUp(key) => {
                 if let Some(button) = self.keymap.get(&key) {
                     self.keystate.insert(*button, false);
                 }
             }
+            _ => self.handle_pad_event(event),
         }
     }
 }
 
-impl Reader for Controller {
-    fn read(&mut self, _address: u16) -> u8 {
+impl InputDevice for Controller {
+    fn strobe(&mut self, byte: u8) {
+        // Controller is only responsible for the bit 0.
+        self.register = byte & 1;
+    }
+
+    fn read(&mut self, _port: Port) -> u8 {
         // If strobe bit is 1, constantly reset state.
         if self.register & 1 != 0 {
             self.strobe_ix = 0;
         }
         let button = Controller::STROBE_ORDER[self.strobe_ix as usize];
-        let is_pressed = *self.keystate.get(&button).unwrap_or(&false);
-        let byte = if is_pressed { 1 } else { 0 };
+        let byte = if self.is_pressed(button) { 1 } else { 0 };
         self.strobe_ix += 1;
         self.strobe_ix %= 8;
         byte
     }
-}
 
-impl Writer for Controller {
-    fn write(&mut self, _address: u16, byte: u8) {
-        // Controller is only responsible for the bit 0.
-        self.register = byte & 1;
+    fn frame(&mut self, frame: u64) {
+        if frame == self.frame {
+            return;
+        }
+
+        let buttons = self.buttons();
+        if let Some(recording) = &mut self.recording {
+            recording.push(buttons);
+        }
+        self.playing = match self.playing.take() {
+            Some((recording, ix)) if ix + 1 < recording.len() => Some((recording, ix + 1)),
+            _ => None,
+        };
+        self.frame = frame;
     }
 }
 
@@ -101,3 +384,101 @@ impl<'de> SaveState<'de, ControllerState> for Controller {
         self.register = state.register;
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::emulator::controller::{default_padmap, Button, Controller, KeyMap};
+    use crate::emulator::input::InputDevice;
+    use crate::emulator::io::event::{Event, EventHandler, Key, PadAxis, PadButton};
+
+    #[test]
+    fn test_pad_buttons() {
+        let mut controller = Controller::new(KeyMap::new());
+        controller.set_pad(1, default_padmap());
+
+        controller.handle_event(Event::PadDown(1, PadButton::B));
+        controller.handle_event(Event::PadDown(0, PadButton::Start));
+        assert!(controller.is_pressed(Button::A));
+        assert!(!controller.is_pressed(Button::Start));
+
+        controller.handle_event(Event::PadUp(1, PadButton::B));
+        assert!(!controller.is_pressed(Button::A));
+    }
+
+    #[test]
+    fn test_pad_stick() {
+        let mut controller = Controller::new(KeyMap::new());
+        controller.set_pad(0, default_padmap());
+
+        // Within the deadzone.
+        controller.handle_event(Event::PadAxis(0, PadAxis::LeftX, -4000));
+        assert_eq!(controller.buttons(), 0);
+
+        controller.handle_event(Event::PadAxis(0, PadAxis::LeftX, -20000));
+        controller.handle_event(Event::PadAxis(0, PadAxis::LeftY, 32767));
+        assert_eq!(controller.buttons(), 0b0110_0000);
+
+        controller.handle_event(Event::PadDisconnected(0));
+        assert_eq!(controller.buttons(), 0);
+    }
+
+    #[test]
+    fn test_turbo() {
+        let keymap = [(Key::Z, Button::TurboA)].iter().cloned().collect();
+        let mut controller = Controller::new(keymap);
+        controller.set_turbo_rate(20);
+        controller.handle_event(Event::KeyDown(Key::Z));
+
+        let presses: Vec<bool> = (1..=6)
+            .map(|frame| {
+                controller.frame(frame);
+                controller.is_pressed(Button::A)
+            })
+            .collect();
+        assert_eq!(presses, vec![false, false, true, false, false, true]);
+        assert!(!controller.is_pressed(Button::B));
+    }
+
+    #[test]
+    fn test_macro() {
+        let keymap = [(Key::Z, Button::A), (Key::X, Button::B)]
+            .iter()
+            .cloned()
+            .collect();
+        let mut controller = Controller::new(keymap);
+
+        controller.start_recording();
+        controller.handle_event(Event::KeyDown(Key::Z));
+        controller.frame(1);
+        controller.handle_event(Event::KeyDown(Key::X));
+        controller.frame(2);
+        controller.handle_event(Event::KeyUp(Key::Z));
+        controller.handle_event(Event::KeyUp(Key::X));
+        controller.frame(3);
+        let recording = controller.stop_recording().unwrap();
+        assert_eq!(recording, vec![0b01, 0b11, 0b00]);
+
+        controller.set_macros([(Key::M, recording)].iter().cloned().collect());
+        controller.handle_event(Event::KeyDown(Key::M));
+        assert_eq!(controller.buttons(), 0b01);
+        controller.frame(4);
+        assert_eq!(controller.buttons(), 0b11);
+        controller.frame(5);
+        controller.frame(6);
+        assert_eq!(controller.buttons(), 0b00);
+    }
+
+    #[test]
+    fn test_movie_input() {
+        let keymap = [(Key::Z, Button::A)].iter().cloned().collect();
+        let mut controller = Controller::new(keymap);
+        controller.handle_event(Event::KeyDown(Key::Z));
+
+        controller.set_movie_input(Some(0b1000_0000));
+        assert_eq!(controller.buttons(), 0b1000_0000);
+        assert_eq!(controller.live_buttons(), 0b01);
+
+        controller.set_movie_input(None);
+        assert_eq!(controller.buttons(), 0b01);
+    }
+}
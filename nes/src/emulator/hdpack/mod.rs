@@ -0,0 +1,678 @@
+// Support for high-definition graphics packs in Mesen's format.
+//
+// A pack is a directory containing a "hires.txt" file, which lists replacement images for tiles,
+// identified by their pattern data and palette, and optionally conditions on when to use them.
+// See https://www.mesen.ca/docs/hdpacks.html
+//
+// Only the tile, background and condition rules are supported.
+// Other rules, such as audio replacements, are ignored.
+
+mod render;
+
+use std::collections::HashMap;
+use std::fs;
+use std::fs::File;
+use std::path::Path;
+
+use crate::emulator::memory::Reader;
+use crate::emulator::ppu::{PixelSource, TileSource};
+
+pub use self::render::HDRenderer;
+
+pub const MAX_SCALE: usize = 4;
+
+// An RGBA image.
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pixels: Vec<[u8; 4]>,
+}
+
+impl Image {
+    pub fn new(width: usize, height: usize, pixels: Vec<[u8; 4]>) -> Image {
+        Image {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Image, String> {
+        let file = File::open(path).map_err(|e| e.to_string())?;
+        let mut decoder = png::Decoder::new(file);
+        decoder.set_transformations(png::Transformations::EXPAND);
+        let (info, mut reader) = decoder.read_info().map_err(|e| e.to_string())?;
+
+        let mut data = vec![0; info.buffer_size()];
+        reader.next_frame(&mut data).map_err(|e| e.to_string())?;
+
+        let channels = match info.color_type {
+            png::ColorType::Grayscale => 1,
+            png::ColorType::GrayscaleAlpha => 2,
+            png::ColorType::RGB => 3,
+            png::ColorType::RGBA => 4,
+            png::ColorType::Indexed => return Err(String::from("Unexpected indexed image")),
+        };
+        let sample_bytes = match info.bit_depth {
+            png::BitDepth::Sixteen => 2,
+            _ => 1,
+        };
+
+        let (width, height) = (info.width as usize, info.height as usize);
+        let mut pixels = Vec::with_capacity(width * height);
+        for row in data.chunks(info.line_size).take(height) {
+            for pixel in row.chunks(channels * sample_bytes).take(width) {
+                // Just take the high byte of 16 bit samples.
+                let sample = |ix: usize| pixel[ix * sample_bytes];
+                pixels.push(match channels {
+                    1 => [sample(0), sample(0), sample(0), 0xFF],
+                    2 => [sample(0), sample(0), sample(0), sample(1)],
+                    3 => [sample(0), sample(1), sample(2), 0xFF],
+                    _ => [sample(0), sample(1), sample(2), sample(3)],
+                });
+            }
+        }
+
+        Ok(Image::new(width, height, pixels))
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> Option<[u8; 4]> {
+        if x < self.width && y < self.height {
+            Some(self.pixels[y * self.width + x])
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+struct TileKey {
+    chr: [u8; 16],
+    palette: [u8; 4],
+}
+
+impl TileKey {
+    fn matches(&self, tile: &TileSource) -> bool {
+        self.chr == tile.chr && self.palette == tile.palette
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Operator {
+    Equal,
+    NotEqual,
+    Greater,
+    Less,
+    GreaterOrEqual,
+    LessOrEqual,
+}
+
+impl Operator {
+    fn apply(self, a: u8, b: u8) -> bool {
+        match self {
+            Operator::Equal => a == b,
+            Operator::NotEqual => a != b,
+            Operator::Greater => a > b,
+            Operator::Less => a < b,
+            Operator::GreaterOrEqual => a >= b,
+            Operator::LessOrEqual => a <= b,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Condition {
+    // Positions are in screen pixels, "nearby" ones relative to the top left of the tile.
+    TileAtPosition {
+        x: i32,
+        y: i32,
+        tile: TileKey,
+    },
+    SpriteAtPosition {
+        x: i32,
+        y: i32,
+        tile: TileKey,
+    },
+    TileNearby {
+        x: i32,
+        y: i32,
+        tile: TileKey,
+    },
+    SpriteNearby {
+        x: i32,
+        y: i32,
+        tile: TileKey,
+    },
+
+    // Only CPU RAM can be checked.
+    MemoryCheck {
+        address: u16,
+        operator: Operator,
+        other: u16,
+        mask: u8,
+    },
+    MemoryCheckConstant {
+        address: u16,
+        operator: Operator,
+        value: u8,
+        mask: u8,
+    },
+
+    // True when frame % divisor >= minimum.
+    FrameRange {
+        divisor: u64,
+        minimum: u64,
+    },
+
+    HorizontalMirror,
+    VerticalMirror,
+    BackgroundPriority,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct ConditionRef {
+    index: usize,
+    negate: bool,
+}
+
+// The replacement image for a tile, drawn at 8 * scale pixels square.
+#[derive(Clone, Debug, PartialEq)]
+struct Replacement {
+    image: usize,
+    x: usize,
+    y: usize,
+    brightness: f32,
+    conditions: Vec<ConditionRef>,
+}
+
+// An image drawn behind everything, in place of the backdrop colour.
+#[derive(Clone, Debug, PartialEq)]
+struct Background {
+    image: usize,
+    brightness: f32,
+    conditions: Vec<ConditionRef>,
+}
+
+// What conditions are evaluated against.
+struct Context<'a> {
+    sources: &'a [Option<PixelSource>],
+    memory: &'a mut dyn Reader,
+    frame: u64,
+}
+
+impl<'a> Context<'a> {
+    fn source(&self, x: i32, y: i32) -> Option<&PixelSource> {
+        if !(0..256).contains(&x) || !(0..240).contains(&y) {
+            return None;
+        }
+        self.sources[(y * 256 + x) as usize].as_ref()
+    }
+
+    fn read(&mut self, address: u16) -> u8 {
+        if address < 0x2000 {
+            self.memory.read(address & 0x07FF)
+        } else {
+            0
+        }
+    }
+}
+
+// A tile being drawn, and the screen position of its top left corner.
+struct DrawnTile<'a> {
+    tile: &'a TileSource,
+    sprite: bool,
+    x: i32,
+    y: i32,
+}
+
+pub struct HDPack {
+    scale: usize,
+    images: Vec<Image>,
+    conditions: Vec<Condition>,
+    tiles: HashMap<TileKey, Vec<Replacement>>,
+
+    // Tiles to use with any palette, if there's no exact match.
+    default_tiles: HashMap<[u8; 16], Vec<Replacement>>,
+
+    backgrounds: Vec<Background>,
+}
+
+impl HDPack {
+    // CHR ROM is needed to look up tiles given by index, which is how packs for games without
+    // CHR RAM identify them.
+    pub fn load<P: AsRef<Path>>(dir: P, chr_rom: &[u8]) -> Result<HDPack, String> {
+        let dir = dir.as_ref();
+        let text = fs::read_to_string(dir.join("hires.txt"))
+            .map_err(|e| format!("Couldn't read hires.txt: {}", e))?;
+        HDPack::parse(&text, chr_rom, &mut |name| {
+            Image::load(dir.join(name)).map_err(|e| format!("Couldn't load {}: {}", name, e))
+        })
+    }
+
+    pub fn parse(
+        text: &str,
+        chr_rom: &[u8],
+        load_image: &mut dyn FnMut(&str) -> Result<Image, String>,
+    ) -> Result<HDPack, String> {
+        let mut pack = HDPack {
+            scale: 1,
+            images: vec![],
+            conditions: vec![
+                Condition::HorizontalMirror,
+                Condition::VerticalMirror,
+                Condition::BackgroundPriority,
+            ],
+            tiles: HashMap::new(),
+            default_tiles: HashMap::new(),
+            backgrounds: vec![],
+        };
+
+        let mut condition_names: HashMap<String, usize> = HashMap::new();
+        condition_names.insert(String::from("hmirror"), 0);
+        condition_names.insert(String::from("vmirror"), 1);
+        condition_names.insert(String::from("bgpriority"), 2);
+
+        for (ix, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            pack.parse_line(line, chr_rom, &mut condition_names, load_image)
+                .map_err(|e| format!("hires.txt line {}: {}", ix + 1, e))?;
+        }
+
+        // Rules with conditions take precedence over those without.
+        for replacements in pack
+            .tiles
+            .values_mut()
+            .chain(pack.default_tiles.values_mut())
+        {
+            replacements.sort_by_key(|r| r.conditions.is_empty());
+        }
+
+        Ok(pack)
+    }
+
+    pub fn scale(&self) -> usize {
+        self.scale
+    }
+
+    fn parse_line(
+        &mut self,
+        line: &str,
+        chr_rom: &[u8],
+        condition_names: &mut HashMap<String, usize>,
+        load_image: &mut dyn FnMut(&str) -> Result<Image, String>,
+    ) -> Result<(), String> {
+        // Rules may be prefixed by conditions, e.g. "[a&!b]<tile>...".
+        let (conditions, line) = if line.starts_with('[') {
+            let end = line.find(']').ok_or("Unterminated condition list")?;
+            let conditions = line[1..end]
+                .split('&')
+                .map(|name| {
+                    let (name, negate) = match name.strip_prefix('!') {
+                        Some(name) => (name, true),
+                        None => (name, false),
+                    };
+                    match condition_names.get(name.trim()) {
+                        None => Err(format!("Unknown condition: {}", name)),
+                        Some(&index) => Ok(ConditionRef { index, negate }),
+                    }
+                })
+                .collect::<Result<Vec<ConditionRef>, String>>()?;
+            (conditions, &line[end + 1..])
+        } else {
+            (vec![], line)
+        };
+
+        if !line.starts_with('<') {
+            return Err(format!("Expected a tag: {}", line));
+        }
+        let end = line.find('>').ok_or("Unterminated tag")?;
+        let tag = &line[1..end];
+        let args: Vec<&str> = line[end + 1..].split(',').map(|s| s.trim()).collect();
+
+        match tag {
+            "scale" => {
+                let scale = parse_decimal(args[0])?;
+                if scale < 1 || scale > MAX_SCALE as i64 {
+                    return Err(format!("Unsupported scale: {}", scale));
+                }
+                self.scale = scale as usize;
+            }
+            "img" => {
+                let image = load_image(args[0])?;
+                self.images.push(image);
+            }
+            "condition" => {
+                let condition = parse_condition(&args, chr_rom)?;
+                self.conditions.push(condition);
+                condition_names.insert(String::from(args[0]), self.conditions.len() - 1);
+            }
+            "tile" => {
+                expect_args(&args, 7)?;
+                let tile = parse_tile_key(args[1], args[2], chr_rom)?;
+                let replacement = Replacement {
+                    image: self.parse_image_index(args[0])?,
+                    x: parse_decimal(args[3])? as usize,
+                    y: parse_decimal(args[4])? as usize,
+                    brightness: parse_float(args[5])?,
+                    conditions,
+                };
+
+                if args[6] == "Y" {
+                    self.default_tiles
+                        .entry(tile.chr)
+                        .or_default()
+                        .push(replacement.clone());
+                }
+                self.tiles.entry(tile).or_default().push(replacement);
+            }
+            "background" => {
+                expect_args(&args, 2)?;
+                let image = load_image(args[0])?;
+                self.images.push(image);
+                self.backgrounds.push(Background {
+                    image: self.images.len() - 1,
+                    brightness: parse_float(args[1])?,
+                    conditions,
+                });
+            }
+            _ => (),
+        }
+
+        Ok(())
+    }
+
+    fn parse_image_index(&self, s: &str) -> Result<usize, String> {
+        let index = parse_decimal(s)? as usize;
+        if index >= self.images.len() {
+            return Err(format!("No such image: {}", index));
+        }
+        Ok(index)
+    }
+
+    fn replacement(&self, drawn: &DrawnTile, context: &mut Context) -> Option<&Replacement> {
+        let key = TileKey {
+            chr: drawn.tile.chr,
+            palette: drawn.tile.palette,
+        };
+
+        let exact = self.tiles.get(&key).into_iter().flatten();
+        let default = self.default_tiles.get(&key.chr).into_iter().flatten();
+        exact.chain(default).find(|replacement| {
+            replacement
+                .conditions
+                .iter()
+                .all(|&c| self.check_condition(c, Some(drawn), context))
+        })
+    }
+
+    fn background(&self, context: &mut Context) -> Option<&Background> {
+        self.backgrounds.iter().find(|background| {
+            background
+                .conditions
+                .iter()
+                .all(|&c| self.check_condition(c, None, context))
+        })
+    }
+
+    fn check_condition(
+        &self,
+        condition: ConditionRef,
+        drawn: Option<&DrawnTile>,
+        context: &mut Context,
+    ) -> bool {
+        let result = match self.conditions[condition.index] {
+            Condition::TileAtPosition { x, y, tile } => context
+                .source(x, y)
+                .and_then(|s| s.background.as_ref())
+                .is_some_and(|t| tile.matches(t)),
+            Condition::SpriteAtPosition { x, y, tile } => context
+                .source(x, y)
+                .and_then(|s| s.sprite.as_ref())
+                .is_some_and(|t| tile.matches(t)),
+            Condition::TileNearby { x, y, tile } => drawn.is_some_and(|d| {
+                context
+                    .source(d.x + x, d.y + y)
+                    .and_then(|s| s.background.as_ref())
+                    .is_some_and(|t| tile.matches(t))
+            }),
+            Condition::SpriteNearby { x, y, tile } => drawn.is_some_and(|d| {
+                context
+                    .source(d.x + x, d.y + y)
+                    .and_then(|s| s.sprite.as_ref())
+                    .is_some_and(|t| tile.matches(t))
+            }),
+            Condition::MemoryCheck {
+                address,
+                operator,
+                other,
+                mask,
+            } => {
+                let a = context.read(address) & mask;
+                let b = context.read(other) & mask;
+                operator.apply(a, b)
+            }
+            Condition::MemoryCheckConstant {
+                address,
+                operator,
+                value,
+                mask,
+            } => operator.apply(context.read(address) & mask, value),
+            Condition::FrameRange { divisor, minimum } => {
+                divisor != 0 && context.frame % divisor >= minimum
+            }
+            Condition::HorizontalMirror => drawn.is_some_and(|d| d.tile.h_flip),
+            Condition::VerticalMirror => drawn.is_some_and(|d| d.tile.v_flip),
+            Condition::BackgroundPriority => {
+                drawn.is_some_and(|d| d.sprite && d.tile.behind_background)
+            }
+        };
+        result != condition.negate
+    }
+}
+
+fn expect_args(args: &[&str], count: usize) -> Result<(), String> {
+    if args.len() < count {
+        return Err(format!("Expected {} arguments, got {}", count, args.len()));
+    }
+    Ok(())
+}
+
+fn parse_decimal(s: &str) -> Result<i64, String> {
+    s.parse::<i64>()
+        .map_err(|_| format!("Invalid number: {}", s))
+}
+
+fn parse_float(s: &str) -> Result<f32, String> {
+    s.parse::<f32>()
+        .map_err(|_| format!("Invalid number: {}", s))
+}
+
+fn parse_hex(s: &str) -> Result<u32, String> {
+    u32::from_str_radix(s, 16).map_err(|_| format!("Invalid hex number: {}", s))
+}
+
+// Tiles are either 32 hex digits of pattern data, or an index into CHR ROM.
+// Palettes are 8 hex digits, colour 0 first.
+fn parse_tile_key(tile: &str, palette: &str, chr_rom: &[u8]) -> Result<TileKey, String> {
+    let mut chr = [0; 16];
+    if tile.len() == 32 {
+        for (ix, byte) in chr.iter_mut().enumerate() {
+            *byte = parse_hex(&tile[ix * 2..ix * 2 + 2])? as u8;
+        }
+    } else {
+        let start = parse_decimal(tile)? as usize * 16;
+        if start + 16 > chr_rom.len() {
+            return Err(format!("Tile {} is outside CHR ROM", tile));
+        }
+        chr.copy_from_slice(&chr_rom[start..start + 16]);
+    }
+
+    if palette.len() != 8 {
+        return Err(format!("Invalid palette: {}", palette));
+    }
+
+    Ok(TileKey {
+        chr,
+        palette: parse_hex(palette)?.to_be_bytes(),
+    })
+}
+
+fn parse_operator(s: &str) -> Result<Operator, String> {
+    match s {
+        "==" => Ok(Operator::Equal),
+        "!=" => Ok(Operator::NotEqual),
+        ">" => Ok(Operator::Greater),
+        "<" => Ok(Operator::Less),
+        ">=" => Ok(Operator::GreaterOrEqual),
+        "<=" => Ok(Operator::LessOrEqual),
+        _ => Err(format!("Unknown operator: {}", s)),
+    }
+}
+
+// Arguments are the name, the type, and then the type's parameters.
+fn parse_condition(args: &[&str], chr_rom: &[u8]) -> Result<Condition, String> {
+    expect_args(args, 2)?;
+    let kind = args[1];
+    let position = |args: &[&str]| -> Result<(i32, i32, TileKey), String> {
+        expect_args(args, 6)?;
+        Ok((
+            parse_decimal(args[2])? as i32,
+            parse_decimal(args[3])? as i32,
+            parse_tile_key(args[4], args[5], chr_rom)?,
+        ))
+    };
+    let mask = |arg: Option<&&str>| match arg {
+        None => Ok(0xFF),
+        Some(s) => parse_hex(s).map(|m| m as u8),
+    };
+
+    match kind {
+        "tileAtPosition" => {
+            let (x, y, tile) = position(args)?;
+            Ok(Condition::TileAtPosition { x, y, tile })
+        }
+        "spriteAtPosition" => {
+            let (x, y, tile) = position(args)?;
+            Ok(Condition::SpriteAtPosition { x, y, tile })
+        }
+        "tileNearby" => {
+            let (x, y, tile) = position(args)?;
+            Ok(Condition::TileNearby { x, y, tile })
+        }
+        "spriteNearby" => {
+            let (x, y, tile) = position(args)?;
+            Ok(Condition::SpriteNearby { x, y, tile })
+        }
+        "memoryCheck" => {
+            expect_args(args, 5)?;
+            Ok(Condition::MemoryCheck {
+                address: parse_hex(args[2])? as u16,
+                operator: parse_operator(args[3])?,
+                other: parse_hex(args[4])? as u16,
+                mask: mask(args.get(5))?,
+            })
+        }
+        "memoryCheckConstant" => {
+            expect_args(args, 5)?;
+            Ok(Condition::MemoryCheckConstant {
+                address: parse_hex(args[2])? as u16,
+                operator: parse_operator(args[3])?,
+                value: parse_hex(args[4])? as u8,
+                mask: mask(args.get(5))?,
+            })
+        }
+        "frameRange" => {
+            expect_args(args, 4)?;
+            Ok(Condition::FrameRange {
+                divisor: parse_decimal(args[2])? as u64,
+                minimum: parse_decimal(args[3])? as u64,
+            })
+        }
+        _ => Err(format!("Unsupported condition type: {}", kind)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::emulator::hdpack::{Condition, HDPack, Image, Operator};
+
+    fn solid_image(width: usize, height: usize, colour: [u8; 4]) -> Image {
+        Image::new(width, height, vec![colour; width * height])
+    }
+
+    fn parse(text: &str, chr_rom: &[u8]) -> Result<HDPack, String> {
+        HDPack::parse(text, chr_rom, &mut |_| {
+            Ok(solid_image(32, 32, [0xFF, 0, 0, 0xFF]))
+        })
+    }
+
+    #[test]
+    fn test_parse_tiles() {
+        let mut chr_rom = vec![0; 32];
+        chr_rom[16] = 0xAB;
+
+        let pack = parse(
+            "<ver>106\n\
+             <scale>2\n\
+             <img>tiles.png\n\
+             <tile>0,1,0F162730,0,0,1,N\n\
+             <tile>0,000102030405060708090A0B0C0D0E0F,0F000000,16,0,0.5,Y\n",
+            &chr_rom,
+        )
+        .unwrap();
+
+        assert_eq!(pack.scale(), 2);
+        assert_eq!(pack.tiles.len(), 2);
+        assert_eq!(pack.default_tiles.len(), 1);
+
+        let (key, replacements) = pack
+            .tiles
+            .iter()
+            .find(|(key, _)| key.chr[0] == 0xAB)
+            .unwrap();
+        assert_eq!(key.palette, [0x0F, 0x16, 0x27, 0x30]);
+        assert_eq!(replacements[0].brightness, 1.0);
+    }
+
+    #[test]
+    fn test_parse_conditions() {
+        let pack = parse(
+            "<img>tiles.png\n\
+             <condition>lives,memoryCheckConstant,75A,>=,3\n\
+             <condition>blink,frameRange,60,30\n\
+             [lives&!blink&hmirror]<tile>0,000102030405060708090A0B0C0D0E0F,0F000000,0,0,1,N\n",
+            &[],
+        )
+        .unwrap();
+
+        assert_eq!(
+            pack.conditions[3],
+            Condition::MemoryCheckConstant {
+                address: 0x75A,
+                operator: Operator::GreaterOrEqual,
+                value: 3,
+                mask: 0xFF,
+            }
+        );
+
+        let replacement = &pack.tiles.values().next().unwrap()[0];
+        let conditions: Vec<(usize, bool)> = replacement
+            .conditions
+            .iter()
+            .map(|c| (c.index, c.negate))
+            .collect();
+        assert_eq!(conditions, vec![(3, false), (4, true), (0, false)]);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(parse("[missing]<tile>0,0,0F000000,0,0,1,N", &[]).is_err());
+        assert!(parse("<scale>8", &[]).is_err());
+        assert!(parse("<tile>0,0,0F000000,0,0,1,N", &[]).is_err());
+        assert!(parse("<img>a.png\n<tile>0,0,0F000000,0,0,1,N", &[]).is_err());
+    }
+}
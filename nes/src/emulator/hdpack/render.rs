@@ -0,0 +1,383 @@
+use crate::emulator::hdpack::{Context, DrawnTile, HDPack, Replacement};
+use crate::emulator::io::palette::Palette;
+use crate::emulator::memory::Reader;
+use crate::emulator::ppu::{PixelSource, TileSource};
+
+// Draws frames with a pack's graphics in place of the original tiles.
+// The PPU must have tile output enabled so each pixel's source is known.
+//
+// Pixels are collected over a frame, and composited at the end of it, so conditions can look at
+// anywhere on screen.
+pub struct HDRenderer {
+    pack: HDPack,
+    memory: Box<dyn Reader>,
+    palette: Palette,
+    frame: u64,
+
+    sources: Vec<Option<PixelSource>>,
+    output: Vec<u8>,
+}
+
+// The last tile looked up, which is usually the same for several pixels in a row.
+struct Lookup<'a> {
+    tile: TileSource,
+    x: i32,
+    y: i32,
+    replacement: Option<&'a Replacement>,
+}
+
+impl HDRenderer {
+    pub fn new(pack: HDPack, memory: Box<dyn Reader>) -> HDRenderer {
+        let scale = pack.scale();
+        HDRenderer {
+            pack,
+            memory,
+            palette: Palette::new(),
+            frame: 0,
+            sources: vec![None; 256 * 240],
+            output: vec![0; 256 * 240 * 3 * scale * scale],
+        }
+    }
+
+    pub fn scale(&self) -> usize {
+        self.pack.scale()
+    }
+
+    pub fn width(&self) -> usize {
+        256 * self.scale()
+    }
+
+    pub fn height(&self) -> usize {
+        240 * self.scale()
+    }
+
+    // The last complete frame, as RGB.
+    pub fn do_render<F: FnOnce(&[u8])>(&self, render: F) {
+        render(&self.output);
+    }
+
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+    }
+
+    pub fn set_source(&mut self, x: usize, y: usize, source: &PixelSource) {
+        self.sources[y * 256 + x] = Some(*source);
+    }
+
+    // Takes the frame as colour indices, for pixels which didn't come from a tile.
+    pub fn finish_frame(&mut self, colours: &[u16]) {
+        let HDRenderer {
+            pack,
+            memory,
+            palette,
+            frame,
+            sources,
+            output,
+        } = self;
+
+        let mut context = Context {
+            sources,
+            memory: memory.as_mut(),
+            frame: *frame,
+        };
+        let scale = pack.scale();
+        let width = 256 * scale;
+        let background = pack.background(&mut context);
+
+        let mut last_background: Option<Lookup> = None;
+        let mut last_sprite: Option<Lookup> = None;
+        let mut block = vec![[0.0f32; 3]; scale * scale];
+
+        for y in 0..240 {
+            for x in 0..256 {
+                let colour = colours[y * 256 + x];
+                let emphasis = colour & 0x1C0;
+
+                match context.sources[y * 256 + x] {
+                    None => {
+                        let rgb = to_float(palette.convert(colour));
+                        block.iter_mut().for_each(|p| *p = rgb);
+                    }
+                    Some(ref source) => {
+                        // Start with the backdrop.
+                        let backdrop = to_float(palette.convert(source.backdrop as u16 | emphasis));
+                        for (ix, p) in block.iter_mut().enumerate() {
+                            *p = background
+                                .and_then(|b| {
+                                    let image = &pack.images[b.image];
+                                    let px = x * scale + ix % scale;
+                                    let py = y * scale + ix / scale;
+                                    image.pixel(px, py).map(|c| (c, b.brightness))
+                                })
+                                .map_or(backdrop, |(c, brightness)| blend(backdrop, c, brightness));
+                        }
+
+                        let source = *source;
+                        let bg_opaque = source.background.is_some_and(|t| t.colour != 0);
+
+                        if let Some(tile) = source.background {
+                            let lookup =
+                                lookup(pack, &mut context, &mut last_background, tile, false, x, y);
+                            draw_tile(pack, palette, &mut block, &tile, lookup, emphasis);
+                        }
+
+                        if let Some(tile) = source.sprite {
+                            if !(tile.behind_background && bg_opaque) {
+                                let lookup =
+                                    lookup(pack, &mut context, &mut last_sprite, tile, true, x, y);
+                                draw_tile(pack, palette, &mut block, &tile, lookup, emphasis);
+                            }
+                        }
+                    }
+                }
+
+                for (ix, p) in block.iter().enumerate() {
+                    let ox = x * scale + ix % scale;
+                    let oy = y * scale + ix / scale;
+                    let offset = (oy * width + ox) * 3;
+                    output[offset] = p[0] as u8;
+                    output[offset + 1] = p[1] as u8;
+                    output[offset + 2] = p[2] as u8;
+                }
+            }
+        }
+
+        // Sources are only emitted while tile output is enabled, so don't keep stale ones.
+        sources.iter_mut().for_each(|s| *s = None);
+        *frame += 1;
+    }
+}
+
+// Finds the replacement for the tile a pixel is from, reusing the last lookup if it's the same
+// tile in the same place.
+fn lookup<'a>(
+    pack: &'a HDPack,
+    context: &mut Context,
+    last: &mut Option<Lookup<'a>>,
+    tile: TileSource,
+    sprite: bool,
+    x: usize,
+    y: usize,
+) -> Option<&'a Replacement> {
+    // The screen position of the tile's top left corner.
+    let column = if tile.h_flip { 7 - tile.x } else { tile.x };
+    let row = if tile.v_flip { 7 - tile.y } else { tile.y };
+    let tile_x = x as i32 - column as i32;
+    let tile_y = y as i32 - row as i32;
+
+    if let Some(ref l) = last {
+        if l.x == tile_x
+            && l.y == tile_y
+            && l.tile.chr == tile.chr
+            && l.tile.palette == tile.palette
+            && l.tile.h_flip == tile.h_flip
+            && l.tile.v_flip == tile.v_flip
+            && l.tile.behind_background == tile.behind_background
+        {
+            return l.replacement;
+        }
+    }
+
+    let drawn = DrawnTile {
+        tile: &tile,
+        sprite,
+        x: tile_x,
+        y: tile_y,
+    };
+    let replacement = pack.replacement(&drawn, context);
+    *last = Some(Lookup {
+        tile,
+        x: tile_x,
+        y: tile_y,
+        replacement,
+    });
+    replacement
+}
+
+fn draw_tile(
+    pack: &HDPack,
+    palette: &Palette,
+    block: &mut [[f32; 3]],
+    tile: &TileSource,
+    replacement: Option<&Replacement>,
+    emphasis: u16,
+) {
+    let scale = pack.scale();
+    match replacement {
+        Some(replacement) => {
+            let image = &pack.images[replacement.image];
+            for (ix, p) in block.iter_mut().enumerate() {
+                // Sub-pixels are flipped along with the tile.
+                let sx = if tile.h_flip {
+                    scale - 1 - ix % scale
+                } else {
+                    ix % scale
+                };
+                let sy = if tile.v_flip {
+                    scale - 1 - ix / scale
+                } else {
+                    ix / scale
+                };
+                let px = replacement.x + tile.x as usize * scale + sx;
+                let py = replacement.y + tile.y as usize * scale + sy;
+                if let Some(c) = image.pixel(px, py) {
+                    *p = blend(*p, c, replacement.brightness);
+                }
+            }
+        }
+        None if tile.colour != 0 => {
+            let index = tile.palette[tile.colour as usize] as u16 | emphasis;
+            let rgb = to_float(palette.convert(index));
+            block.iter_mut().for_each(|p| *p = rgb);
+        }
+        None => (),
+    }
+}
+
+fn to_float((r, g, b): (u8, u8, u8)) -> [f32; 3] {
+    [r as f32, g as f32, b as f32]
+}
+
+// Draws an RGBA pixel over another.
+fn blend(under: [f32; 3], over: [u8; 4], brightness: f32) -> [f32; 3] {
+    let alpha = over[3] as f32 / 255.0;
+    let mut result = [0.0; 3];
+    for (ix, c) in result.iter_mut().enumerate() {
+        let over = (over[ix] as f32 * brightness).min(255.0);
+        *c = under[ix] * (1.0 - alpha) + over * alpha;
+    }
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use crate::emulator::hdpack::{HDPack, HDRenderer, Image};
+    use crate::emulator::io::palette;
+    use crate::emulator::memory::{Memory, Writer};
+    use crate::emulator::ppu::{PixelSource, TileSource};
+
+    const CHR: &str = "000102030405060708090A0B0C0D0E0F";
+
+    fn tile(colour: u8, x: u8, y: u8) -> TileSource {
+        let mut chr = [0; 16];
+        for (ix, byte) in chr.iter_mut().enumerate() {
+            *byte = ix as u8;
+        }
+        TileSource {
+            chr,
+            palette: [0x0F, 0x16, 0x27, 0x30],
+            colour,
+            x,
+            y,
+            h_flip: false,
+            v_flip: false,
+            behind_background: false,
+        }
+    }
+
+    fn renderer(rules: &str) -> HDRenderer {
+        // A 16x16 image, where each pixel's red value is its x and green its y.
+        let mut pixels = vec![];
+        for y in 0..16 {
+            for x in 0..16 {
+                pixels.push([x as u8, y as u8, 0, 0xFF]);
+            }
+        }
+        let text = format!("<scale>2\n<img>tile.png\n{}", rules);
+        let pack =
+            HDPack::parse(&text, &[], &mut |_| Ok(Image::new(16, 16, pixels.clone()))).unwrap();
+        HDRenderer::new(pack, Box::new(Memory::new_ram(0x800)))
+    }
+
+    fn pixel(renderer: &HDRenderer, x: usize, y: usize) -> [u8; 3] {
+        let mut result = [0; 3];
+        renderer.do_render(|data| {
+            let offset = (y * renderer.width() + x) * 3;
+            result.copy_from_slice(&data[offset..offset + 3]);
+        });
+        result
+    }
+
+    #[test]
+    fn test_replace_tile() {
+        let mut renderer = renderer(&format!("<tile>0,{},0F162730,0,0,1,N", CHR));
+        assert_eq!((renderer.width(), renderer.height()), (512, 480));
+
+        // Pixel (2, 3) of a tile drawn at (10, 20).
+        let source = PixelSource {
+            backdrop: 0x0F,
+            background: Some(tile(1, 2, 3)),
+            sprite: None,
+        };
+        renderer.set_source(12, 23, &source);
+        renderer.finish_frame(&[0x16; 256 * 240]);
+
+        assert_eq!(pixel(&renderer, 24, 46), [4, 6, 0]);
+        assert_eq!(pixel(&renderer, 25, 47), [5, 7, 0]);
+    }
+
+    #[test]
+    fn test_flipped_sprite() {
+        let mut renderer = renderer(&format!("<tile>0,{},0F162730,0,0,1,N", CHR));
+
+        let mut sprite = tile(1, 0, 0);
+        sprite.h_flip = true;
+        let source = PixelSource {
+            backdrop: 0x0F,
+            background: None,
+            sprite: Some(sprite),
+        };
+        renderer.set_source(7, 0, &source);
+        renderer.finish_frame(&[0; 256 * 240]);
+
+        assert_eq!(pixel(&renderer, 14, 0), [1, 0, 0]);
+        assert_eq!(pixel(&renderer, 15, 0), [0, 0, 0]);
+    }
+
+    #[test]
+    fn test_unmatched_tile() {
+        let mut renderer = renderer(&format!("<tile>0,{},0F000000,0,0,1,N", CHR));
+
+        // Drawn with the tile's palette, as there's no replacement.
+        let source = PixelSource {
+            backdrop: 0x0F,
+            background: Some(tile(2, 0, 0)),
+            sprite: None,
+        };
+        renderer.set_source(0, 0, &source);
+
+        // Pixels without a source are drawn in the emitted colour.
+        let mut colours = vec![0; 256 * 240];
+        colours[1] = 0x30;
+        renderer.finish_frame(&colours);
+
+        let (r, g, b) = palette::convert_index(0x27);
+        assert_eq!(pixel(&renderer, 1, 1), [r, g, b]);
+        let (r, g, b) = palette::convert_index(0x30);
+        assert_eq!(pixel(&renderer, 2, 0), [r, g, b]);
+    }
+
+    #[test]
+    fn test_memory_condition() {
+        let mut memory = Memory::new_ram(0x800);
+        memory.write(0x10, 1);
+        let rules = format!(
+            "<condition>flag,memoryCheckConstant,10,==,1\n\
+             [!flag]<tile>0,{},0F162730,0,0,1,N\n\
+             [flag]<tile>0,{},0F162730,8,8,1,N\n",
+            CHR, CHR
+        );
+        let mut renderer = renderer(&rules);
+        renderer.memory = Box::new(memory);
+
+        let source = PixelSource {
+            backdrop: 0x0F,
+            background: Some(tile(1, 0, 0)),
+            sprite: None,
+        };
+        renderer.set_source(0, 0, &source);
+        renderer.finish_frame(&[0; 256 * 240]);
+
+        assert_eq!(pixel(&renderer, 0, 0), [8, 8, 0]);
+    }
+}
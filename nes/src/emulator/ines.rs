@@ -62,6 +62,13 @@ impl ROM {
         }
     }
 
+    // The raw CHR ROM, which is empty if the cartridge uses CHR RAM.
+    pub fn chr_rom(&self) -> &[u8] {
+        let start = (16 + self.prg_rom_size_bytes()) as usize;
+        let end = start + self.chr_rom_size_bytes() as usize;
+        &self.data[start..end]
+    }
+
     pub fn chr_rom_size_bytes(&self) -> u32 {
         (self.data[5] as u32) * 8192
     }
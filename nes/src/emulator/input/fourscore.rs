@@ -0,0 +1,154 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::emulator::controller::Controller;
+use crate::emulator::input::{InputDevice, Port};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum FourScoreMode {
+    // The NES Four Score, plugged into both ports. Each port reports 24 bits: its first
+    // controller, then its second, then a signature identifying the adapter.
+    NES,
+    // The Famicom 4 player adapter, on the expansion port. Players 3 and 4 are reported alongside
+    // players 1 and 2, on D1 of each port.
+    Famicom,
+}
+
+// Signatures after the 2 controllers on each port, in the order the bits are read.
+const SIGNATURES: [u32; 2] = [0b0000_1000, 0b0000_0100];
+
+// Four controllers shared between the two ports, plugged into both of them.
+pub struct FourScore {
+    mode: FourScoreMode,
+    controllers: [Rc<RefCell<Controller>>; 4],
+    strobe: bool,
+
+    // Bits still to be read from each port, on D0 and D1.
+    reports: [[u32; 2]; 2],
+}
+
+impl FourScore {
+    // Players 1 and 3 are on port 1, and 2 and 4 on port 2.
+    pub fn new(mode: FourScoreMode, controllers: [Rc<RefCell<Controller>>; 4]) -> FourScore {
+        FourScore {
+            mode,
+            controllers,
+            strobe: false,
+            reports: [[0; 2]; 2],
+        }
+    }
+
+    pub fn mode(&self) -> FourScoreMode {
+        self.mode
+    }
+
+    fn latch(&mut self) {
+        let buttons: Vec<u32> = self
+            .controllers
+            .iter()
+            .map(|c| c.borrow().buttons() as u32)
+            .collect();
+
+        for (ix, report) in self.reports.iter_mut().enumerate() {
+            let (first, second) = (buttons[ix], buttons[ix + 2]);
+            // Once everything has been read, the ports read 1.
+            *report = match self.mode {
+                FourScoreMode::NES => [
+                    first | (second << 8) | (SIGNATURES[ix] << 16) | 0xFF00_0000,
+                    0,
+                ],
+                FourScoreMode::Famicom => [first | 0xFFFF_FF00, second | 0xFFFF_FF00],
+            };
+        }
+    }
+}
+
+impl InputDevice for FourScore {
+    fn strobe(&mut self, byte: u8) {
+        self.strobe = byte & 1 != 0;
+        if self.strobe {
+            self.latch();
+        }
+    }
+
+    fn read(&mut self, port: Port) -> u8 {
+        // While strobe is high, the buttons are constantly reloaded.
+        if self.strobe {
+            self.latch();
+        }
+
+        let report = &mut self.reports[port.index()];
+        let byte = (report[0] & 1) as u8 | ((report[1] & 1) << 1) as u8;
+        let fill = match self.mode {
+            FourScoreMode::NES => [0x8000_0000, 0],
+            FourScoreMode::Famicom => [0x8000_0000, 0x8000_0000],
+        };
+        for (line, fill) in report.iter_mut().zip(fill.iter()) {
+            *line = (*line >> 1) | fill;
+        }
+        byte
+    }
+
+    fn frame(&mut self, frame: u64) {
+        for controller in self.controllers.iter() {
+            controller.borrow_mut().frame(frame);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use crate::emulator::controller::{Button, Controller};
+    use crate::emulator::input::fourscore::{FourScore, FourScoreMode};
+    use crate::emulator::input::{InputDevice, Port};
+    use crate::emulator::io::event::{Event, EventHandler, Key};
+
+    // Four controllers, with player n pressing A on key n.
+    fn four_score(mode: FourScoreMode) -> FourScore {
+        let keys = [Key::Num1, Key::Num2, Key::Num3, Key::Num4];
+        let controllers = keys.map(|key| {
+            let keymap = [(key, Button::A)].iter().cloned().collect();
+            Rc::new(RefCell::new(Controller::new(keymap)))
+        });
+        controllers[2]
+            .borrow_mut()
+            .handle_event(Event::KeyDown(Key::Num3));
+        FourScore::new(mode, controllers)
+    }
+
+    fn read_bits(four_score: &mut FourScore, port: Port, count: usize) -> Vec<u8> {
+        (0..count).map(|_| four_score.read(port)).collect()
+    }
+
+    #[test]
+    fn test_nes_four_score() {
+        let mut four_score = four_score(FourScoreMode::NES);
+        four_score.strobe(1);
+        four_score.strobe(0);
+
+        // Player 1, then player 3 with A pressed, then the signature.
+        let bits = read_bits(&mut four_score, Port::One, 26);
+        assert_eq!(&bits[0..8], &[0; 8]);
+        assert_eq!(&bits[8..16], &[1, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(&bits[16..24], &[0, 0, 0, 1, 0, 0, 0, 0]);
+        assert_eq!(&bits[24..26], &[1, 1]);
+
+        let bits = read_bits(&mut four_score, Port::Two, 24);
+        assert_eq!(&bits[16..24], &[0, 0, 1, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_famicom_four_player() {
+        let mut four_score = four_score(FourScoreMode::Famicom);
+        four_score.strobe(1);
+        four_score.strobe(0);
+
+        // Player 3 is on D1, alongside player 1.
+        let bits = read_bits(&mut four_score, Port::One, 9);
+        assert_eq!(&bits[0..8], &[2, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(bits[8], 3);
+    }
+}
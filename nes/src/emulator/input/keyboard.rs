@@ -0,0 +1,329 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::emulator::input::{InputDevice, Port};
+use crate::emulator::io::event::{Event, EventHandler, Key};
+
+// The keys on the Family BASIC keyboard.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum FamilyKey {
+    F1,
+    F2,
+    F3,
+    F4,
+    F5,
+    F6,
+    F7,
+    F8,
+    Num1,
+    Num2,
+    Num3,
+    Num4,
+    Num5,
+    Num6,
+    Num7,
+    Num8,
+    Num9,
+    Num0,
+    A,
+    B,
+    C,
+    D,
+    E,
+    F,
+    G,
+    H,
+    I,
+    J,
+    K,
+    L,
+    M,
+    N,
+    O,
+    P,
+    Q,
+    R,
+    S,
+    T,
+    U,
+    V,
+    W,
+    X,
+    Y,
+    Z,
+    Minus,
+    Caret,
+    Yen,
+    At,
+    LeftBracket,
+    RightBracket,
+    Semicolon,
+    Colon,
+    Comma,
+    Period,
+    Slash,
+    Underscore,
+    Return,
+    Stop,
+    Kana,
+    LeftShift,
+    RightShift,
+    Control,
+    Escape,
+    Graph,
+    Space,
+    ClearHome,
+    Insert,
+    Delete,
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+// The keys read in each row, for column 0 on D1-D4, then column 1 on D1-D4.
+const MATRIX: [[FamilyKey; 8]; 9] = {
+    use FamilyKey::*;
+    [
+        [
+            F8,
+            Return,
+            LeftBracket,
+            RightBracket,
+            Kana,
+            RightShift,
+            Yen,
+            Stop,
+        ],
+        [F7, At, Colon, Semicolon, Underscore, Slash, Minus, Caret],
+        [F6, O, L, K, Period, Comma, P, Num0],
+        [F5, I, U, J, M, N, Num9, Num8],
+        [F4, Y, G, H, B, V, Num7, Num6],
+        [F3, T, R, D, F, C, Num5, Num4],
+        [F2, W, S, A, X, Z, E, Num3],
+        [F1, Escape, Q, Control, LeftShift, Graph, Num1, Num2],
+        [ClearHome, Up, Right, Left, Down, Space, Delete, Insert],
+    ]
+};
+
+// After the last row, the keyboard reads as if nothing is pressed until it's reset.
+const NUM_ROWS: usize = 10;
+
+pub type FamilyKeyMap = HashMap<Key, FamilyKey>;
+
+// Maps the host keyboard onto the keys with the same labels.
+// The host has nothing for the function keys, or most of the symbols.
+pub fn host_keymap() -> FamilyKeyMap {
+    [
+        (Key::A, FamilyKey::A),
+        (Key::B, FamilyKey::B),
+        (Key::C, FamilyKey::C),
+        (Key::D, FamilyKey::D),
+        (Key::E, FamilyKey::E),
+        (Key::F, FamilyKey::F),
+        (Key::G, FamilyKey::G),
+        (Key::H, FamilyKey::H),
+        (Key::I, FamilyKey::I),
+        (Key::J, FamilyKey::J),
+        (Key::K, FamilyKey::K),
+        (Key::L, FamilyKey::L),
+        (Key::M, FamilyKey::M),
+        (Key::N, FamilyKey::N),
+        (Key::O, FamilyKey::O),
+        (Key::P, FamilyKey::P),
+        (Key::Q, FamilyKey::Q),
+        (Key::R, FamilyKey::R),
+        (Key::S, FamilyKey::S),
+        (Key::T, FamilyKey::T),
+        (Key::U, FamilyKey::U),
+        (Key::V, FamilyKey::V),
+        (Key::W, FamilyKey::W),
+        (Key::X, FamilyKey::X),
+        (Key::Y, FamilyKey::Y),
+        (Key::Z, FamilyKey::Z),
+        (Key::Num1, FamilyKey::Num1),
+        (Key::Num2, FamilyKey::Num2),
+        (Key::Num3, FamilyKey::Num3),
+        (Key::Num4, FamilyKey::Num4),
+        (Key::Num5, FamilyKey::Num5),
+        (Key::Num6, FamilyKey::Num6),
+        (Key::Num7, FamilyKey::Num7),
+        (Key::Num8, FamilyKey::Num8),
+        (Key::Num9, FamilyKey::Num9),
+        (Key::Num0, FamilyKey::Num0),
+        (Key::Minus, FamilyKey::Minus),
+        // The caret is where equals is on a US layout.
+        (Key::Equals, FamilyKey::Caret),
+        (Key::Backspace, FamilyKey::Delete),
+        (Key::Escape, FamilyKey::Escape),
+        (Key::Return, FamilyKey::Return),
+        (Key::Space, FamilyKey::Space),
+        (Key::Shift, FamilyKey::LeftShift),
+        (Key::Control, FamilyKey::Control),
+        (Key::Up, FamilyKey::Up),
+        (Key::Down, FamilyKey::Down),
+        (Key::Left, FamilyKey::Left),
+        (Key::Right, FamilyKey::Right),
+    ]
+    .iter()
+    .cloned()
+    .collect()
+}
+
+// The Family BASIC keyboard, for the Famicom expansion port.
+// Writes to $4016 select a row and column of the key matrix, and $4017 reads the 4 keys there on
+// D1-D4, low when pressed.
+pub struct FamilyKeyboard {
+    keymap: FamilyKeyMap,
+    pressed: HashSet<FamilyKey>,
+    host_input: bool,
+    enabled: bool,
+    row: usize,
+    column: usize,
+}
+
+impl FamilyKeyboard {
+    pub fn new(keymap: FamilyKeyMap) -> FamilyKeyboard {
+        FamilyKeyboard {
+            keymap,
+            pressed: HashSet::new(),
+            host_input: true,
+            enabled: false,
+            row: 0,
+            column: 0,
+        }
+    }
+
+    pub fn is_pressed(&self, key: FamilyKey) -> bool {
+        self.pressed.contains(&key)
+    }
+
+    pub fn set_pressed(&mut self, key: FamilyKey, pressed: bool) {
+        if pressed {
+            self.pressed.insert(key);
+        } else {
+            self.pressed.remove(&key);
+        }
+    }
+
+    pub fn is_host_input(&self) -> bool {
+        self.host_input
+    }
+
+    // Whether key events are typed on the keyboard. While off, any held keys are released.
+    pub fn set_host_input(&mut self, host_input: bool) {
+        self.host_input = host_input;
+        if !host_input {
+            self.pressed.clear();
+        }
+    }
+}
+
+impl InputDevice for FamilyKeyboard {
+    // Bit 0 resets to the first row, bit 1 selects the column, and bit 2 enables the keyboard.
+    // Going back to column 0 moves on to the next row.
+    fn strobe(&mut self, byte: u8) {
+        let column = ((byte >> 1) & 1) as usize;
+        self.enabled = byte & 0x04 != 0;
+        if self.enabled {
+            if column == 0 && self.column == 1 {
+                self.row = (self.row + 1) % NUM_ROWS;
+            }
+            if byte & 0x01 != 0 {
+                self.row = 0;
+            }
+        }
+        self.column = column;
+    }
+
+    fn read(&mut self, port: Port) -> u8 {
+        if port == Port::One || !self.enabled {
+            return 0;
+        }
+
+        let keys = match MATRIX.get(self.row) {
+            Some(keys) => &keys[self.column * 4..self.column * 4 + 4],
+            None => return 0x1E,
+        };
+        keys.iter()
+            .enumerate()
+            .filter(|&(_, &key)| !self.is_pressed(key))
+            .fold(0, |byte, (ix, _)| byte | (1 << (ix + 1)))
+    }
+}
+
+impl EventHandler for FamilyKeyboard {
+    fn handle_event(&mut self, event: Event) {
+        if !self.host_input {
+            return;
+        }
+        let (key, pressed) = match event {
+            Event::KeyDown(key) => (key, true),
+            Event::KeyUp(key) => (key, false),
+            _ => return,
+        };
+        if let Some(&key) = self.keymap.get(&key) {
+            self.set_pressed(key, pressed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::emulator::input::keyboard::{host_keymap, FamilyKey, FamilyKeyboard};
+    use crate::emulator::input::{InputDevice, Port};
+    use crate::emulator::io::event::{Event, EventHandler, Key};
+
+    // Reads the whole matrix the way Family BASIC does, as a byte per row and column.
+    fn scan(keyboard: &mut FamilyKeyboard) -> Vec<u8> {
+        let mut reads = vec![];
+        keyboard.strobe(0x05);
+        for _ in 0..9 {
+            keyboard.strobe(0x04);
+            reads.push(keyboard.read(Port::Two));
+            keyboard.strobe(0x06);
+            reads.push(keyboard.read(Port::Two));
+        }
+        reads
+    }
+
+    #[test]
+    fn test_scan_matrix() {
+        let mut keyboard = FamilyKeyboard::new(host_keymap());
+        keyboard.handle_event(Event::KeyDown(Key::Return));
+        keyboard.handle_event(Event::KeyDown(Key::Space));
+
+        let reads = scan(&mut keyboard);
+        // Return is D2 of row 0, column 0, and space D2 of row 8, column 1.
+        assert_eq!(reads[0], 0x1A);
+        assert_eq!(reads[17], 0x1A);
+        assert!(reads
+            .iter()
+            .enumerate()
+            .all(|(ix, &byte)| ix == 0 || ix == 17 || byte == 0x1E));
+
+        keyboard.handle_event(Event::KeyUp(Key::Return));
+        assert_eq!(scan(&mut keyboard)[0], 0x1E);
+    }
+
+    #[test]
+    fn test_disabled() {
+        let mut keyboard = FamilyKeyboard::new(host_keymap());
+        keyboard.set_pressed(FamilyKey::F8, true);
+        keyboard.strobe(0x01);
+        assert_eq!(keyboard.read(Port::Two), 0x00);
+        keyboard.strobe(0x05);
+        assert_eq!(keyboard.read(Port::Two), 0x1C);
+        assert_eq!(keyboard.read(Port::One), 0x00);
+    }
+
+    #[test]
+    fn test_host_input() {
+        let mut keyboard = FamilyKeyboard::new(host_keymap());
+        keyboard.handle_event(Event::KeyDown(Key::Q));
+        keyboard.set_host_input(false);
+        assert!(!keyboard.is_pressed(FamilyKey::Q));
+
+        keyboard.handle_event(Event::KeyDown(Key::Q));
+        assert!(!keyboard.is_pressed(FamilyKey::Q));
+    }
+}
@@ -0,0 +1,64 @@
+pub mod fourscore;
+pub mod keyboard;
+pub mod powerpad;
+pub mod vaus;
+pub mod zapper;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// The two controller ports, read from $4016 and $4017.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Port {
+    One,
+    Two,
+}
+
+impl Port {
+    pub fn index(self) -> usize {
+        match self {
+            Port::One => 0,
+            Port::Two => 1,
+        }
+    }
+}
+
+// A device plugged into a controller port.
+// Every device is strobed by writes to $4016, then reports its state serially, a bit at a time.
+pub trait InputDevice {
+    // Bit 0 is the strobe (OUT0).
+    fn strobe(&mut self, byte: u8);
+
+    // The next bits the device drives on D0-D4 of the port's register.
+    // A device plugged into both ports, e.g. a multitap, is told which one is being read.
+    fn read(&mut self, port: Port) -> u8;
+
+    // Called as each frame's vblank begins, for anything timed in frames.
+    // A device plugged into several ports may be told more than once.
+    fn frame(&mut self, _frame: u64) {}
+}
+
+impl<D: InputDevice> InputDevice for Rc<RefCell<D>> {
+    fn strobe(&mut self, byte: u8) {
+        self.borrow_mut().strobe(byte);
+    }
+
+    fn read(&mut self, port: Port) -> u8 {
+        self.borrow_mut().read(port)
+    }
+
+    fn frame(&mut self, frame: u64) {
+        self.borrow_mut().frame(frame);
+    }
+}
+
+// An empty port.
+pub struct Unplugged;
+
+impl InputDevice for Unplugged {
+    fn strobe(&mut self, _byte: u8) {}
+
+    fn read(&mut self, _port: Port) -> u8 {
+        0
+    }
+}
@@ -0,0 +1,188 @@
+use std::collections::HashMap;
+
+use crate::emulator::input::{InputDevice, Port};
+use crate::emulator::io::event::{Event, EventHandler, Key};
+
+// Keys for each of the mat's buttons, numbered 1-12 as printed on side B:
+//
+//     1  2  3  4
+//     5  6  7  8
+//     9 10 11 12
+pub type PowerPadKeyMap = HashMap<Key, u8>;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PowerPadMode {
+    // The Power Pad in port 2, read serially on D3 and D4.
+    NES,
+    // The Family Trainer on the expansion port. Rows are selected by writing 0s to bits 0-2 of
+    // $4016, and read in parallel on D1-D4 of $4017. Connected with NES::connect_expansion.
+    Famicom,
+}
+
+// The order buttons are read, on D4 then D3. Each reads 1s once it runs out.
+const D4_ORDER: [u8; 8] = [2, 1, 5, 9, 6, 10, 11, 7];
+const D3_ORDER: [u8; 4] = [4, 3, 12, 8];
+
+// The Family Trainer's rows, selected by bits 2, 1 and 0 of $4016 respectively.
+const ROW_SELECT: [u8; 3] = [0x04, 0x02, 0x01];
+
+// Bandai's Power Pad (Family Trainer on the Famicom), a 12 button floor mat.
+pub struct PowerPad {
+    mode: PowerPadMode,
+    keymap: PowerPadKeyMap,
+    pressed: [bool; 12],
+    strobe: bool,
+    d3: u16,
+    d4: u16,
+    rows: u8,
+}
+
+impl PowerPad {
+    pub fn new(mode: PowerPadMode, keymap: PowerPadKeyMap) -> Result<PowerPad, String> {
+        if let Some(button) = keymap.values().find(|&&button| !(1..=12).contains(&button)) {
+            return Err(format!(
+                "Power Pad buttons are numbered 1-12, not {}",
+                button
+            ));
+        }
+
+        Ok(PowerPad {
+            mode,
+            keymap,
+            pressed: [false; 12],
+            strobe: false,
+            d3: 0,
+            d4: 0,
+            rows: 0x07,
+        })
+    }
+
+    pub fn is_pressed(&self, button: u8) -> bool {
+        (1..=12).contains(&button) && self.pressed[(button - 1) as usize]
+    }
+
+    fn latch(&mut self) {
+        self.d4 = self.bits(&D4_ORDER) | 0xFF00;
+        self.d3 = self.bits(&D3_ORDER) | 0xFFF0;
+    }
+
+    // The state of the buttons, one bit each in the given order.
+    fn bits(&self, order: &[u8]) -> u16 {
+        order
+            .iter()
+            .enumerate()
+            .filter(|&(_, &button)| self.is_pressed(button))
+            .fold(0, |bits, (ix, _)| bits | (1 << ix))
+    }
+
+    // The Family Trainer's selected rows, with buttons 1/5/9 on D4 down to 4/8/12 on D1.
+    // A pressed button reads 0.
+    fn read_rows(&self) -> u8 {
+        ROW_SELECT
+            .iter()
+            .enumerate()
+            .filter(|&(_, &select)| self.rows & select == 0)
+            .flat_map(|(row, _)| (0..4).map(move |column| (row as u8 * 4 + column, column)))
+            .filter(|&(ix, _)| self.pressed[ix as usize])
+            .fold(0x1E, |byte, (_, column)| byte & !(0x10 >> column))
+    }
+}
+
+impl InputDevice for PowerPad {
+    fn strobe(&mut self, byte: u8) {
+        self.rows = byte & 0x07;
+        self.strobe = byte & 1 != 0;
+        if self.strobe {
+            self.latch();
+        }
+    }
+
+    fn read(&mut self, port: Port) -> u8 {
+        match (self.mode, port) {
+            (PowerPadMode::Famicom, Port::One) => return 0,
+            (PowerPadMode::Famicom, Port::Two) => return self.read_rows(),
+            _ => (),
+        }
+
+        if self.strobe {
+            self.latch();
+        }
+
+        let byte = ((self.d3 & 1) << 3) as u8 | ((self.d4 & 1) << 4) as u8;
+        self.d3 = (self.d3 >> 1) | 0x8000;
+        self.d4 = (self.d4 >> 1) | 0x8000;
+        byte
+    }
+}
+
+impl EventHandler for PowerPad {
+    fn handle_event(&mut self, event: Event) {
+        let (key, pressed) = match event {
+            Event::KeyDown(key) => (key, true),
+            Event::KeyUp(key) => (key, false),
+            _ => return,
+        };
+        if let Some(&button) = self.keymap.get(&key) {
+            self.pressed[(button - 1) as usize] = pressed;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::emulator::input::powerpad::{PowerPad, PowerPadMode};
+    use crate::emulator::input::{InputDevice, Port};
+    use crate::emulator::io::event::{Event, EventHandler, Key};
+
+    #[test]
+    fn test_read_order() {
+        let keymap = [(Key::A, 1), (Key::B, 12)].iter().cloned().collect();
+        let mut pad = PowerPad::new(PowerPadMode::NES, keymap).unwrap();
+        pad.handle_event(Event::KeyDown(Key::A));
+        pad.handle_event(Event::KeyDown(Key::B));
+        assert!(pad.is_pressed(12));
+
+        pad.strobe(1);
+        pad.strobe(0);
+        let bytes: Vec<u8> = (0..9).map(|_| pad.read(Port::Two)).collect();
+
+        // Button 1 is second on D4, and button 12 third on D3.
+        let d4: Vec<u8> = bytes.iter().map(|b| (b >> 4) & 1).collect();
+        let d3: Vec<u8> = bytes.iter().map(|b| (b >> 3) & 1).collect();
+        assert_eq!(d4, vec![0, 1, 0, 0, 0, 0, 0, 0, 1]);
+        assert_eq!(d3, vec![0, 0, 1, 0, 1, 1, 1, 1, 1]);
+    }
+
+    #[test]
+    fn test_invalid_keymap() {
+        for &button in &[0, 13] {
+            let keymap = [(Key::A, button)].iter().cloned().collect();
+            assert!(PowerPad::new(PowerPadMode::NES, keymap).is_err());
+        }
+    }
+
+    #[test]
+    fn test_family_trainer() {
+        let keymap = [(Key::A, 1), (Key::B, 7), (Key::C, 12)]
+            .iter()
+            .cloned()
+            .collect();
+        let mut pad = PowerPad::new(PowerPadMode::Famicom, keymap).unwrap();
+        pad.handle_event(Event::KeyDown(Key::A));
+        pad.handle_event(Event::KeyDown(Key::B));
+        pad.handle_event(Event::KeyDown(Key::C));
+
+        // No rows selected.
+        pad.strobe(0x07);
+        assert_eq!(pad.read(Port::Two), 0x1E);
+
+        // Button 1 on D4, then button 7 on D2, then button 12 on D1.
+        pad.strobe(0x03);
+        assert_eq!(pad.read(Port::Two), 0x0E);
+        pad.strobe(0x05);
+        assert_eq!(pad.read(Port::Two), 0x1A);
+        pad.strobe(0x06);
+        assert_eq!(pad.read(Port::Two), 0x1C);
+        assert_eq!(pad.read(Port::One), 0);
+    }
+}
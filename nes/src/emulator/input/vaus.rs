@@ -0,0 +1,133 @@
+use crate::emulator::input::{InputDevice, Port};
+use crate::emulator::io::event::{Event, EventHandler, PointerButton};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum VausMode {
+    // Plugged into port 2, with the knob on D3 and the button on D4.
+    NES,
+    // On the expansion port, with the button on D1 of $4016 and the knob on D1 of $4017.
+    // Connected with NES::connect_expansion, so it's read alongside the controllers.
+    Famicom,
+}
+
+// The range of knob positions, which games calibrate themselves to.
+const MIN_POSITION: u32 = 0x54;
+const MAX_POSITION: u32 = 0xF4;
+
+// Taito's Vaus paddle for Arkanoid, turned by moving the pointer left and right.
+// The knob's position is latched on strobe, then read serially, high bit first and inverted.
+pub struct Vaus {
+    mode: VausMode,
+    position: u8,
+    fire: bool,
+    strobe: bool,
+    shift: u8,
+}
+
+impl Vaus {
+    pub fn new(mode: VausMode) -> Vaus {
+        Vaus {
+            mode,
+            position: ((MIN_POSITION + MAX_POSITION) / 2) as u8,
+            fire: false,
+            strobe: false,
+            shift: 0,
+        }
+    }
+
+    pub fn position(&self) -> u8 {
+        self.position
+    }
+
+    fn latch(&mut self) {
+        self.shift = !self.position;
+    }
+}
+
+impl InputDevice for Vaus {
+    fn strobe(&mut self, byte: u8) {
+        self.strobe = byte & 1 != 0;
+        if self.strobe {
+            self.latch();
+        }
+    }
+
+    fn read(&mut self, port: Port) -> u8 {
+        if self.strobe {
+            self.latch();
+        }
+
+        let fire = self.fire as u8;
+        let data = self.shift >> 7;
+        match (self.mode, port) {
+            (VausMode::NES, Port::One) => 0,
+            (VausMode::NES, Port::Two) => {
+                self.shift <<= 1;
+                (data << 3) | (fire << 4)
+            }
+            (VausMode::Famicom, Port::One) => fire << 1,
+            (VausMode::Famicom, Port::Two) => {
+                self.shift <<= 1;
+                data << 1
+            }
+        }
+    }
+}
+
+impl EventHandler for Vaus {
+    fn handle_event(&mut self, event: Event) {
+        match event {
+            Event::PointerMove(x, _) => {
+                let x = x.clamp(0, 255) as u32;
+                self.position = (MIN_POSITION + x * (MAX_POSITION - MIN_POSITION) / 255) as u8;
+            }
+            Event::PointerDown(PointerButton::Primary) => self.fire = true,
+            Event::PointerUp(PointerButton::Primary) => self.fire = false,
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::emulator::input::vaus::{Vaus, VausMode};
+    use crate::emulator::input::{InputDevice, Port};
+    use crate::emulator::io::event::{Event, EventHandler, PointerButton};
+
+    #[test]
+    fn test_knob_position() {
+        let mut vaus = Vaus::new(VausMode::NES);
+        vaus.handle_event(Event::PointerMove(-10, 0));
+        assert_eq!(vaus.position(), 0x54);
+        vaus.handle_event(Event::PointerMove(255, 0));
+        assert_eq!(vaus.position(), 0xF4);
+    }
+
+    #[test]
+    fn test_serial_read() {
+        let mut vaus = Vaus::new(VausMode::NES);
+        vaus.handle_event(Event::PointerMove(255, 0));
+        vaus.handle_event(Event::PointerDown(PointerButton::Primary));
+        vaus.strobe(1);
+        vaus.strobe(0);
+
+        // 0xF4, inverted, high bit first.
+        let bits: Vec<u8> = (0..8).map(|_| (vaus.read(Port::Two) >> 3) & 1).collect();
+        assert_eq!(bits, vec![0, 0, 0, 0, 1, 0, 1, 1]);
+        assert_eq!(vaus.read(Port::Two) & 0x10, 0x10);
+    }
+
+    #[test]
+    fn test_famicom() {
+        let mut vaus = Vaus::new(VausMode::Famicom);
+        vaus.handle_event(Event::PointerMove(0, 0));
+        vaus.handle_event(Event::PointerDown(PointerButton::Primary));
+        vaus.strobe(1);
+        vaus.strobe(0);
+
+        assert_eq!(vaus.read(Port::One), 0x02);
+        // 0x54 inverted is 0xAB.
+        assert_eq!(vaus.read(Port::Two), 0x02);
+        assert_eq!(vaus.read(Port::Two), 0x00);
+    }
+}
@@ -0,0 +1,189 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::emulator::input::{InputDevice, Port};
+use crate::emulator::io::event::{Event, EventHandler, PointerButton};
+use crate::emulator::io::Screen;
+use crate::emulator::ppu::PPU;
+
+// How far from where it's aimed the Zapper sees, in pixels.
+const SENSE_RADIUS: i32 = 2;
+
+// How many scanlines a pixel stays lit for after the beam has drawn it.
+const LIGHT_SCANLINES: i32 = 20;
+
+// How bright a pixel must be to be seen, as the average of its RGB components.
+const LIGHT_THRESHOLD: u32 = 85;
+
+// The NES Zapper light gun, for port 2.
+// The photodiode only sees light while the beam is drawing near where it's aimed, so it's
+// checked against the pixels the PPU has emitted so far, at the moment the CPU reads it.
+pub struct Zapper {
+    ppu: Rc<RefCell<PPU>>,
+    screen: Rc<RefCell<Screen>>,
+    position: Option<(i32, i32)>,
+    trigger: bool,
+    // Pulling the trigger with the secondary button aims away from the screen, e.g. to reload.
+    aimed_away: bool,
+}
+
+impl Zapper {
+    pub fn new(ppu: Rc<RefCell<PPU>>, screen: Rc<RefCell<Screen>>) -> Zapper {
+        Zapper {
+            ppu,
+            screen,
+            position: None,
+            trigger: false,
+            aimed_away: false,
+        }
+    }
+
+    fn senses_light(&self) -> bool {
+        let (x, y) = match self.position {
+            Some(position) if !self.aimed_away => position,
+            _ => return false,
+        };
+
+        let (scanline, cycle) = {
+            let ppu = self.ppu.borrow();
+            (ppu.scanline as i32, ppu.cycle as i32)
+        };
+        let screen = self.screen.borrow();
+
+        for py in (y - SENSE_RADIUS).max(0)..=(y + SENSE_RADIUS).min(239) {
+            // Only pixels the beam has passed recently are lit.
+            if scanline < py || scanline - py > LIGHT_SCANLINES {
+                continue;
+            }
+
+            for px in (x - SENSE_RADIUS).max(0)..=(x + SENSE_RADIUS).min(255) {
+                // Pixel x is drawn on cycle x + 1.
+                if scanline == py && cycle <= px + 1 {
+                    continue;
+                }
+
+                let index = screen.emitted_pixel(px as usize, py as usize);
+                let (r, g, b) = screen.palette().convert(index);
+                if (r as u32 + g as u32 + b as u32) / 3 >= LIGHT_THRESHOLD {
+                    return true;
+                }
+            }
+        }
+        false
+    }
+}
+
+impl InputDevice for Zapper {
+    fn strobe(&mut self, _byte: u8) {}
+
+    // D3 is low when light is seen, and D4 is high while the trigger is pulled.
+    fn read(&mut self, _port: Port) -> u8 {
+        let light = if self.senses_light() { 0 } else { 1 << 3 };
+        let trigger = if self.trigger { 1 << 4 } else { 0 };
+        light | trigger
+    }
+}
+
+impl EventHandler for Zapper {
+    fn handle_event(&mut self, event: Event) {
+        match event {
+            Event::PointerMove(x, y) => {
+                let on_screen = (0..256).contains(&x) && (0..240).contains(&y);
+                self.position = if on_screen { Some((x, y)) } else { None };
+            }
+            Event::PointerDown(button) => {
+                self.trigger = true;
+                self.aimed_away = button == PointerButton::Secondary;
+            }
+            Event::PointerUp(_) => {
+                self.trigger = false;
+                self.aimed_away = false;
+            }
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use crate::emulator::input::zapper::Zapper;
+    use crate::emulator::input::{InputDevice, Port};
+    use crate::emulator::io::event::{Event, EventHandler, PointerButton};
+    use crate::emulator::io::Screen;
+    use crate::emulator::memory::{Memory, PPUMemory};
+    use crate::emulator::ppu::{Colour, MirrorMode, Mirrorer, VideoOut, PPU};
+
+    struct Horizontal;
+
+    impl Mirrorer for Horizontal {
+        fn mirror_mode(&self) -> MirrorMode {
+            MirrorMode::Horizontal
+        }
+    }
+
+    // A zapper aimed at (100, 100), with a white box drawn from there to the end of the frame.
+    fn zapper() -> (Zapper, Rc<RefCell<PPU>>) {
+        let memory = PPUMemory::new(
+            Box::new(Memory::new_ram(0x2000)),
+            Box::new(Horizontal),
+            Box::new(Memory::new_ram(0x2000)),
+        );
+        let screen = Rc::new(RefCell::new(Screen::new()));
+        let ppu = Rc::new(RefCell::new(PPU::new(memory, Box::new(screen.clone()))));
+
+        for y in 0..240 {
+            for x in 0..256 {
+                let lit = (100..110).contains(&x) && y >= 100;
+                screen
+                    .borrow_mut()
+                    .emit(Colour::new(if lit { 0x30 } else { 0x0F }));
+            }
+        }
+
+        let mut zapper = Zapper::new(ppu.clone(), screen);
+        zapper.handle_event(Event::PointerMove(100, 100));
+        (zapper, ppu)
+    }
+
+    fn at(ppu: &Rc<RefCell<PPU>>, scanline: u16, cycle: u16) {
+        let mut ppu = ppu.borrow_mut();
+        ppu.scanline = scanline;
+        ppu.cycle = cycle;
+    }
+
+    #[test]
+    fn test_light_follows_beam() {
+        let (mut zapper, ppu) = zapper();
+
+        // Before the beam reaches the box.
+        at(&ppu, 90, 0);
+        assert_eq!(zapper.read(Port::Two) & 0x08, 0x08);
+
+        // Just after.
+        at(&ppu, 100, 110);
+        assert_eq!(zapper.read(Port::Two) & 0x08, 0);
+
+        // Long after, the light has faded.
+        at(&ppu, 140, 0);
+        assert_eq!(zapper.read(Port::Two) & 0x08, 0x08);
+    }
+
+    #[test]
+    fn test_trigger() {
+        let (mut zapper, ppu) = zapper();
+        at(&ppu, 100, 200);
+
+        zapper.handle_event(Event::PointerDown(PointerButton::Primary));
+        assert_eq!(zapper.read(Port::Two), 0x10);
+
+        // Aiming away from the screen doesn't see the box.
+        zapper.handle_event(Event::PointerDown(PointerButton::Secondary));
+        assert_eq!(zapper.read(Port::Two), 0x18);
+
+        zapper.handle_event(Event::PointerUp(PointerButton::Secondary));
+        assert_eq!(zapper.read(Port::Two), 0x00);
+    }
+}
@@ -0,0 +1,480 @@
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::emulator::apu::AudioOut;
+use crate::emulator::io::{OutputMode, Screen, SimpleAudioOut};
+use crate::emulator::NES_MASTER_CLOCK_HZ;
+
+// An NTSC frame averages 89341.5 PPU dots, as odd frames skip a dot while rendering, which makes
+// 60.0988 frames a second.
+pub const FRAME_MASTER_CYCLES: u64 = 357_366;
+
+const AVIF_HASINDEX: u32 = 0x10;
+const AVIIF_KEYFRAME: u32 = 0x10;
+
+const VIDEO_CHUNK: &[u8; 4] = b"00db";
+const AUDIO_CHUNK: &[u8; 4] = b"01wb";
+
+// Writes an AVI of uncompressed 24-bit video, with 16-bit PCM audio interleaved a frame at a time.
+// The header is written up front with empty sizes and lengths, which are filled in by finish()
+// along with the index.
+pub struct AviWriter<W: Write + Seek> {
+    writer: W,
+    width: usize,
+    height: usize,
+    block_align: u32,
+    frames: u32,
+    // Audio samples written per channel.
+    samples: u32,
+    // Bytes written since the start of the file.
+    len: u32,
+    // Positions of the header fields finish() fills in.
+    movi_size_pos: u32,
+    total_frames_pos: u32,
+    video_length_pos: u32,
+    audio_length_pos: u32,
+    // Each chunk's id, offset from the 'movi' list type, and size.
+    index: Vec<(&'static [u8; 4], u32, u32)>,
+}
+
+impl AviWriter<BufWriter<File>> {
+    pub fn create<P: AsRef<Path>>(
+        path: P,
+        width: usize,
+        height: usize,
+        sample_rate: u32,
+        channels: u16,
+    ) -> io::Result<AviWriter<BufWriter<File>>> {
+        let file = BufWriter::new(File::create(path)?);
+        AviWriter::new(file, width, height, sample_rate, channels)
+    }
+}
+
+impl<W: Write + Seek> AviWriter<W> {
+    pub fn new(
+        mut writer: W,
+        width: usize,
+        height: usize,
+        sample_rate: u32,
+        channels: u16,
+    ) -> io::Result<AviWriter<W>> {
+        let block_align = channels * 2;
+        let frame_size = (AviWriter::<W>::stride(width) * height) as u32;
+        let rate = NES_MASTER_CLOCK_HZ as u32;
+        let scale = FRAME_MASTER_CYCLES as u32;
+        let mut header = Header(vec![]);
+
+        header.fourcc(b"RIFF");
+        header.u32(0);
+        header.fourcc(b"AVI ");
+
+        let hdrl_pos = header.list(b"hdrl");
+        header.chunk(b"avih", 56);
+        header.u32((1_000_000 * FRAME_MASTER_CYCLES / NES_MASTER_CLOCK_HZ) as u32);
+        let max_bytes_per_sec = (frame_size as u64) * NES_MASTER_CLOCK_HZ / FRAME_MASTER_CYCLES
+            + (sample_rate as u64) * (block_align as u64);
+        header.u32(max_bytes_per_sec.min(u32::MAX as u64) as u32);
+        header.u32(0);
+        header.u32(AVIF_HASINDEX);
+        let total_frames_pos = header.u32(0);
+        header.u32(0);
+        header.u32(2);
+        header.u32(frame_size);
+        header.u32(width as u32);
+        header.u32(height as u32);
+        header.zeroes(16);
+
+        let video_pos = header.list(b"strl");
+        header.chunk(b"strh", 56);
+        header.fourcc(b"vids");
+        header.fourcc(b"DIB ");
+        header.zeroes(12);
+        header.u32(scale);
+        header.u32(rate);
+        header.u32(0);
+        let video_length_pos = header.u32(0);
+        header.u32(frame_size);
+        header.u32(u32::MAX);
+        header.u32(0);
+        header.u16(0);
+        header.u16(0);
+        header.u16(width as u16);
+        header.u16(height as u16);
+
+        // A BITMAPINFOHEADER for bottom-up BGR rows.
+        header.chunk(b"strf", 40);
+        header.u32(40);
+        header.u32(width as u32);
+        header.u32(height as u32);
+        header.u16(1);
+        header.u16(24);
+        header.u32(0);
+        header.u32(frame_size);
+        header.zeroes(16);
+        header.end_list(video_pos);
+
+        let audio_pos = header.list(b"strl");
+        header.chunk(b"strh", 56);
+        header.fourcc(b"auds");
+        header.zeroes(16);
+        header.u32(block_align as u32);
+        header.u32(sample_rate * (block_align as u32));
+        header.u32(0);
+        let audio_length_pos = header.u32(0);
+        header.u32(sample_rate * (block_align as u32) / 10);
+        header.u32(u32::MAX);
+        header.u32(block_align as u32);
+        header.zeroes(8);
+
+        // A PCM WAVEFORMAT, the same as a WAV file's fmt chunk.
+        header.chunk(b"strf", 16);
+        header.u16(1);
+        header.u16(channels);
+        header.u32(sample_rate);
+        header.u32(sample_rate * (block_align as u32));
+        header.u16(block_align);
+        header.u16(16);
+        header.end_list(audio_pos);
+        header.end_list(hdrl_pos);
+
+        let movi_size_pos = header.list(b"movi") - 4;
+
+        writer.write_all(&header.0)?;
+        Ok(AviWriter {
+            writer,
+            width,
+            height,
+            block_align: block_align as u32,
+            frames: 0,
+            samples: 0,
+            len: header.0.len() as u32,
+            movi_size_pos,
+            total_frames_pos,
+            video_length_pos,
+            audio_length_pos,
+            index: vec![],
+        })
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    // Takes RGB data, 3 bytes a pixel, from the top row down.
+    pub fn write_frame(&mut self, data: &[u8]) -> io::Result<()> {
+        if data.len() != self.width * self.height * 3 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Expected {} bytes for a {}x{} frame, got {}",
+                    self.width * self.height * 3,
+                    self.width,
+                    self.height,
+                    data.len()
+                ),
+            ));
+        }
+
+        // DIBs are stored bottom-up as BGR, with rows padded to 4 bytes.
+        let stride = AviWriter::<W>::stride(self.width);
+        let mut frame = vec![0; stride * self.height];
+        for (row, line) in data.chunks(self.width * 3).rev().enumerate() {
+            let out = &mut frame[row * stride..];
+            for (pixel, rgb) in out.chunks_mut(3).zip(line.chunks(3)) {
+                pixel.copy_from_slice(&[rgb[2], rgb[1], rgb[0]]);
+            }
+        }
+
+        self.write_chunk(VIDEO_CHUNK, &frame)?;
+        self.frames += 1;
+        Ok(())
+    }
+
+    // Samples are interleaved if there is more than one channel.
+    pub fn write_samples(&mut self, samples: &[f32]) -> io::Result<()> {
+        let mut data = Vec::with_capacity(samples.len() * 2);
+        for &sample in samples {
+            let sample = (sample.clamp(-1.0, 1.0) * (i16::MAX as f32)) as i16;
+            data.extend_from_slice(&sample.to_le_bytes());
+        }
+
+        self.write_chunk(AUDIO_CHUNK, &data)?;
+        self.samples += (data.len() as u32) / self.block_align;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        let movi_size = self.len - self.movi_size_pos - 4;
+
+        let mut idx1 = Header(vec![]);
+        idx1.chunk(b"idx1", (self.index.len() * 16) as u32);
+        for &(id, offset, size) in self.index.iter() {
+            idx1.fourcc(id);
+            idx1.u32(AVIIF_KEYFRAME);
+            idx1.u32(offset);
+            idx1.u32(size);
+        }
+        self.writer.write_all(&idx1.0)?;
+        self.len += idx1.0.len() as u32;
+
+        let fields = [
+            (4, self.len - 8),
+            (self.movi_size_pos, movi_size),
+            (self.total_frames_pos, self.frames),
+            (self.video_length_pos, self.frames),
+            (self.audio_length_pos, self.samples),
+        ];
+        for &(pos, value) in fields.iter() {
+            self.writer.seek(SeekFrom::Start(pos as u64))?;
+            self.writer.write_all(&value.to_le_bytes())?;
+        }
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    fn write_chunk(&mut self, id: &'static [u8; 4], data: &[u8]) -> io::Result<()> {
+        let padding = data.len() % 2;
+        // Leave room for the chunk's index entry too, so the index always fits.
+        self.check_space(8 + data.len() + padding + 16)?;
+
+        self.writer.write_all(id)?;
+        self.writer.write_all(&(data.len() as u32).to_le_bytes())?;
+        self.writer.write_all(data)?;
+        if padding != 0 {
+            self.writer.write_all(&[0])?;
+        }
+
+        let offset = self.len - self.movi_size_pos - 4;
+        self.index.push((id, offset, data.len() as u32));
+        self.len += (8 + data.len() + padding) as u32;
+        Ok(())
+    }
+
+    // RIFF sizes are 32 bits, so files stop growing just short of 4GB.
+    fn check_space(&self, bytes: usize) -> io::Result<()> {
+        let index_bytes = 8 + (self.index.len() as u64) * 16;
+        if (self.len as u64) + index_bytes + (bytes as u64) > u32::MAX as u64 {
+            return Err(io::Error::other("AVI file has reached its maximum size"));
+        }
+        Ok(())
+    }
+
+    fn stride(width: usize) -> usize {
+        (width * 3 + 3) & !3
+    }
+}
+
+// Builds the header, returning the positions of fields to fill in later.
+struct Header(Vec<u8>);
+
+impl Header {
+    fn fourcc(&mut self, fourcc: &[u8; 4]) -> u32 {
+        let pos = self.0.len() as u32;
+        self.0.extend_from_slice(fourcc);
+        pos
+    }
+
+    fn u32(&mut self, value: u32) -> u32 {
+        let pos = self.0.len() as u32;
+        self.0.extend_from_slice(&value.to_le_bytes());
+        pos
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn zeroes(&mut self, len: usize) {
+        self.0.resize(self.0.len() + len, 0);
+    }
+
+    fn chunk(&mut self, id: &[u8; 4], size: u32) {
+        self.fourcc(id);
+        self.u32(size);
+    }
+
+    // Starts a LIST, returning the position of its type, which its size counts from.
+    fn list(&mut self, list_type: &[u8; 4]) -> u32 {
+        self.chunk(b"LIST", 0);
+        self.fourcc(list_type)
+    }
+
+    fn end_list(&mut self, type_pos: u32) {
+        let size = (self.0.len() as u32) - type_pos;
+        let pos = (type_pos - 4) as usize;
+        self.0[pos..pos + 4].copy_from_slice(&size.to_le_bytes());
+    }
+}
+
+// Records the screen and the APU's output to an AVI, as NES frames are completed.
+// The audio is resampled separately from the frontend's, so the recording stays in step with
+// the emulated frame rate however fast the emulator is actually running.
+pub struct VideoRecorder {
+    writer: AviWriter<BufWriter<File>>,
+    audio: SimpleAudioOut,
+    frames: u64,
+    samples: u64,
+    error: Option<io::Error>,
+}
+
+impl VideoRecorder {
+    // The video is the size of the screen's current output, which mustn't change while recording.
+    pub fn create<P: AsRef<Path>>(
+        path: P,
+        screen: &Screen,
+        sample_rate: f32,
+        mode: OutputMode,
+    ) -> io::Result<VideoRecorder> {
+        let mut audio = SimpleAudioOut::new(sample_rate);
+        audio.set_mode(mode);
+
+        let (mut width, mut height) = (0, 0);
+        screen.do_render_output(|_, w, h| {
+            width = w;
+            height = h;
+        });
+        let writer = AviWriter::create(
+            path,
+            width,
+            height,
+            sample_rate as u32,
+            audio.channels() as u16,
+        )?;
+
+        Ok(VideoRecorder {
+            writer,
+            audio,
+            frames: 0,
+            samples: 0,
+            error: None,
+        })
+    }
+
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    // Called as each frame is completed, writing it with the audio emitted while it ran.
+    // Nothing more is written after an error, which is reported by finish().
+    pub fn frame(&mut self, screen: &Screen) {
+        if self.error.is_some() {
+            return;
+        }
+
+        // Keep the audio in step with the frame rate, carrying the fractions over between frames.
+        self.frames += 1;
+        let sample_rate = self.audio.sample_rate() as u64;
+        let total_samples = self.frames * FRAME_MASTER_CYCLES * sample_rate / NES_MASTER_CLOCK_HZ;
+        let mut samples = vec![];
+        self.audio
+            .consume(FRAME_MASTER_CYCLES, total_samples - self.samples, |data| {
+                samples.extend_from_slice(data)
+            });
+        self.samples = total_samples;
+
+        let writer = &mut self.writer;
+        let mut result = Ok(());
+        screen.do_render_output(|data, _, _| result = writer.write_frame(data));
+        if result.is_ok() && !samples.is_empty() {
+            result = writer.write_samples(&samples);
+        }
+        if let Err(err) = result {
+            self.error = Some(err);
+        }
+    }
+
+    // Finishes the file, reporting the first error encountered while recording, if any.
+    pub fn finish(self) -> io::Result<()> {
+        if let Some(err) = self.error {
+            return Err(err);
+        }
+        self.writer.finish()?;
+        Ok(())
+    }
+}
+
+impl AudioOut for VideoRecorder {
+    fn emit(&mut self, sample: f32) {
+        self.audio.emit(sample);
+    }
+
+    fn emit_stereo(&mut self, left: f32, right: f32) {
+        self.audio.emit_stereo(left, right);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use crate::emulator::io::avi::AviWriter;
+
+    fn u32_at(data: &[u8], offset: usize) -> u32 {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(&data[offset..offset + 4]);
+        u32::from_le_bytes(bytes)
+    }
+
+    fn find(data: &[u8], fourcc: &[u8]) -> usize {
+        data.windows(4).position(|w| w == fourcc).unwrap()
+    }
+
+    #[test]
+    fn test_avi() {
+        let mut avi = AviWriter::new(Cursor::new(vec![]), 2, 2, 48_000, 2).unwrap();
+        avi.write_frame(&[1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12])
+            .unwrap();
+        avi.write_samples(&[0.0, 1.0, -1.0, 0.5]).unwrap();
+        avi.write_frame(&[0; 12]).unwrap();
+        assert!(avi.write_frame(&[0; 9]).is_err());
+        let data = avi.finish().unwrap().into_inner();
+
+        assert_eq!(&data[0..4], b"RIFF");
+        assert_eq!(u32_at(&data, 4) as usize, data.len() - 8);
+        assert_eq!(&data[8..12], b"AVI ");
+
+        let avih = find(&data, b"avih");
+        assert_eq!(u32_at(&data, avih + 8), 16_639);
+        assert_eq!(u32_at(&data, avih + 24), 2);
+        assert_eq!(u32_at(&data, avih + 32), 2);
+        assert_eq!(u32_at(&data, avih + 40), 2);
+
+        let vids = find(&data, b"vids");
+        assert_eq!(u32_at(&data, vids + 20), 357_366);
+        assert_eq!(u32_at(&data, vids + 24), 21_477_272);
+        assert_eq!(u32_at(&data, vids + 32), 2);
+
+        let auds = find(&data, b"auds");
+        assert_eq!(u32_at(&data, auds + 20), 4);
+        assert_eq!(u32_at(&data, auds + 24), 48_000 * 4);
+        assert_eq!(u32_at(&data, auds + 32), 2);
+
+        // Rows are bottom-up BGR, padded to 4 bytes.
+        let movi = find(&data, b"movi");
+        let idx1 = find(&data, b"idx1");
+        assert_eq!(u32_at(&data, movi - 4) as usize, idx1 - movi);
+        assert_eq!(&data[movi + 4..movi + 12], b"00db\x10\x00\x00\x00");
+        assert_eq!(
+            &data[movi + 12..movi + 28],
+            &[9, 8, 7, 12, 11, 10, 0, 0, 3, 2, 1, 6, 5, 4, 0, 0]
+        );
+        assert_eq!(&data[movi + 28..movi + 36], b"01wb\x08\x00\x00\x00");
+        assert_eq!(
+            &data[movi + 36..movi + 44],
+            &[0x00, 0x00, 0xFF, 0x7F, 0x01, 0x80, 0xFF, 0x3F]
+        );
+
+        assert_eq!(u32_at(&data, idx1 + 4), 3 * 16);
+        assert_eq!(&data[idx1 + 8..idx1 + 12], b"00db");
+        assert_eq!(u32_at(&data, idx1 + 16), 4);
+        assert_eq!(&data[idx1 + 24..idx1 + 28], b"01wb");
+        assert_eq!(u32_at(&data, idx1 + 32), 4 + 24);
+        assert_eq!(u32_at(&data, idx1 + 36), 8);
+    }
+}
@@ -0,0 +1,232 @@
+use std::f64::consts::PI;
+
+// Band-limited step synthesis, in the style of blargg's blip_buf.
+//
+// Rather than filtering every input sample, the signal is described as a series of steps
+// (amplitude deltas) at given input clock times.  Each step is added into the output buffer as a
+// precomputed band-limited kernel, so work is only done when the amplitude actually changes.
+// The output is recovered by integrating the buffer.
+//
+// Everything after kernel generation is done in integer arithmetic, so output is bit-for-bit
+// reproducible.
+pub struct BlipBuffer {
+    // Output samples per input clock, as fixed point with TIME_BITS of fraction.
+    factor: u64,
+
+    // Fixed point position of input clock 0 of the current frame, relative to the first unread
+    // output sample.
+    offset: u64,
+
+    buffer: Vec<i64>,
+    integrator: i64,
+    kernel: Vec<[i64; BlipBuffer::KERNEL_WIDTH]>,
+}
+
+impl BlipBuffer {
+    // Amplitudes passed to add_delta are in units of 1 / 2^AMPLITUDE_BITS.
+    pub const AMPLITUDE_BITS: u32 = 15;
+
+    const TIME_BITS: u32 = 32;
+    const PHASE_BITS: u32 = 5;
+    const PHASES: usize = 1 << BlipBuffer::PHASE_BITS;
+    const INTERP_BITS: u32 = 15;
+    const KERNEL_WIDTH: usize = 16;
+    const KERNEL_UNIT_BITS: u32 = 15;
+
+    // Cutoff frequency of the kernel relative to the output Nyquist frequency.
+    const CUTOFF: f64 = 0.9;
+
+    pub fn new() -> BlipBuffer {
+        BlipBuffer {
+            factor: 1 << BlipBuffer::TIME_BITS,
+            offset: 0,
+            buffer: vec![0; BlipBuffer::KERNEL_WIDTH * 2],
+            integrator: 0,
+            kernel: BlipBuffer::generate_kernel(),
+        }
+    }
+
+    // Set how many output samples are produced per input clock.
+    // Round up so a frame never produces fewer samples than expected.
+    pub fn set_rates(&mut self, clock_rate: f64, sample_rate: f64) {
+        let factor = (sample_rate / clock_rate) * ((1u64 << BlipBuffer::TIME_BITS) as f64);
+        self.factor = factor.ceil() as u64;
+    }
+
+    // Add a step of `delta` at input clock `time`, relative to the start of the current frame.
+    pub fn add_delta(&mut self, time: u32, delta: i32) {
+        if delta == 0 {
+            return;
+        }
+
+        let fixed = (time as u64) * self.factor + self.offset;
+        let pos = (fixed >> BlipBuffer::TIME_BITS) as usize;
+        let phase_shift = BlipBuffer::TIME_BITS - BlipBuffer::PHASE_BITS;
+        let phase = ((fixed >> phase_shift) as usize) & (BlipBuffer::PHASES - 1);
+        let interp_shift = phase_shift - BlipBuffer::INTERP_BITS;
+        let interp = ((fixed >> interp_shift) as i64) & ((1 << BlipBuffer::INTERP_BITS) - 1);
+
+        if self.buffer.len() < pos + BlipBuffer::KERNEL_WIDTH {
+            self.buffer.resize(pos + BlipBuffer::KERNEL_WIDTH, 0);
+        }
+
+        // Interpolate between adjacent kernel phases.
+        // The final tap absorbs any rounding so each step always integrates to exactly `delta`.
+        let unit = 1i64 << BlipBuffer::KERNEL_UNIT_BITS;
+        let k0 = &self.kernel[phase];
+        let k1 = &self.kernel[phase + 1];
+        let mut total = 0;
+        for ix in 0..BlipBuffer::KERNEL_WIDTH {
+            let weight = if ix == BlipBuffer::KERNEL_WIDTH - 1 {
+                unit - total
+            } else {
+                (k0[ix] * ((1 << BlipBuffer::INTERP_BITS) - interp) + k1[ix] * interp)
+                    >> BlipBuffer::INTERP_BITS
+            };
+            total += weight;
+            self.buffer[pos + ix] += weight * (delta as i64);
+        }
+    }
+
+    // Finish the current frame, which lasted `clocks` input clocks.
+    // Samples up to the end of the frame become available to read.
+    pub fn end_frame(&mut self, clocks: u32) {
+        self.offset += (clocks as u64) * self.factor;
+        let needed = self.samples_avail() + BlipBuffer::KERNEL_WIDTH;
+        if self.buffer.len() < needed {
+            self.buffer.resize(needed, 0);
+        }
+    }
+
+    pub fn samples_avail(&self) -> usize {
+        (self.offset >> BlipBuffer::TIME_BITS) as usize
+    }
+
+    // Read up to `count` samples, in units of 1 / 2^AMPLITUDE_BITS.
+    pub fn read_samples<F: FnMut(i32)>(&mut self, count: usize, mut output: F) -> usize {
+        let count = count.min(self.samples_avail());
+        for ix in 0..count {
+            self.integrator += self.buffer[ix];
+            output((self.integrator >> BlipBuffer::KERNEL_UNIT_BITS) as i32);
+        }
+
+        self.buffer.drain(..count);
+        self.buffer.resize(self.buffer.len() + count, 0);
+        self.offset -= (count as u64) << BlipBuffer::TIME_BITS;
+        count
+    }
+
+    pub fn clear(&mut self) {
+        self.offset = 0;
+        self.integrator = 0;
+        for x in self.buffer.iter_mut() {
+            *x = 0;
+        }
+    }
+
+    // Each phase of the kernel is the difference of a windowed sinc step, offset by a fraction of
+    // a sample.  An extra phase is generated so we can always interpolate with phase + 1.
+    fn generate_kernel() -> Vec<[i64; BlipBuffer::KERNEL_WIDTH]> {
+        const SUBSTEPS: usize = 32;
+        let half_width = (BlipBuffer::KERNEL_WIDTH / 2) as f64;
+        let unit = (1i64 << BlipBuffer::KERNEL_UNIT_BITS) as f64;
+
+        let impulse = |x: f64| -> f64 {
+            if x.abs() >= half_width {
+                return 0.0;
+            }
+            let window =
+                0.42 + 0.5 * (PI * x / half_width).cos() + 0.08 * (2.0 * PI * x / half_width).cos();
+            let sinc = if x == 0.0 {
+                BlipBuffer::CUTOFF
+            } else {
+                (PI * BlipBuffer::CUTOFF * x).sin() / (PI * x)
+            };
+            sinc * window
+        };
+
+        let mut kernel = Vec::with_capacity(BlipBuffer::PHASES + 1);
+        for phase in 0..=BlipBuffer::PHASES {
+            let frac = (phase as f64) / (BlipBuffer::PHASES as f64);
+
+            let mut taps = [0.0; BlipBuffer::KERNEL_WIDTH];
+            for (ix, tap) in taps.iter_mut().enumerate() {
+                let start = (ix as f64) - half_width - frac;
+                for step in 0..SUBSTEPS {
+                    let x = start + ((step as f64) + 0.5) / (SUBSTEPS as f64);
+                    *tap += impulse(x) / (SUBSTEPS as f64);
+                }
+            }
+
+            let sum: f64 = taps.iter().sum();
+            let mut row = [0; BlipBuffer::KERNEL_WIDTH];
+            for (ix, tap) in taps.iter().enumerate() {
+                row[ix] = (tap / sum * unit).round() as i64;
+            }
+            kernel.push(row);
+        }
+
+        kernel
+    }
+}
+
+impl Default for BlipBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::emulator::io::blip::BlipBuffer;
+
+    fn render(buffer: &mut BlipBuffer, count: usize) -> Vec<i32> {
+        let mut out = vec![];
+        buffer.read_samples(count, |s| out.push(s));
+        out
+    }
+
+    #[test]
+    fn test_step_settles_to_amplitude() {
+        let mut buffer = BlipBuffer::new();
+        buffer.set_rates(1_000_000.0, 48_000.0);
+        buffer.add_delta(1234, 10_000);
+        buffer.end_frame(20_000);
+
+        let out = render(&mut buffer, 1000);
+        assert_eq!(out.len(), 960);
+        assert_eq!(out[0], 0);
+        assert_eq!(out[959], 10_000);
+    }
+
+    #[test]
+    fn test_deltas_carry_across_frames() {
+        let mut buffer = BlipBuffer::new();
+        buffer.set_rates(1_000_000.0, 48_000.0);
+        buffer.add_delta(999, 5_000);
+        buffer.end_frame(1000);
+        let first = render(&mut buffer, 1000);
+
+        buffer.add_delta(0, -5_000);
+        buffer.end_frame(10_000);
+        let second = render(&mut buffer, 1000);
+
+        assert_eq!(first.len() + second.len(), 528);
+        assert_eq!(*second.last().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_output_is_deterministic() {
+        let run = || {
+            let mut buffer = BlipBuffer::new();
+            buffer.set_rates(894_886.5, 44_100.0);
+            for ix in 0..500 {
+                buffer.add_delta(ix * 37, if ix % 2 == 0 { 3000 } else { -3000 });
+            }
+            buffer.end_frame(20_000);
+            render(&mut buffer, 2000)
+        };
+
+        assert_eq!(run(), run());
+    }
+}
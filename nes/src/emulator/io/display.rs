@@ -0,0 +1,260 @@
+// How frames are shown: which edges to crop, and the shape to draw them at.
+// Frames may be larger than 256x240, e.g. after a scaler, so everything here works in NES pixels
+// and is scaled to match the frame.
+
+// Lines and columns to crop from each edge, in NES pixels.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Overscan {
+    pub top: usize,
+    pub bottom: usize,
+    pub left: usize,
+    pub right: usize,
+}
+
+impl Overscan {
+    pub fn new() -> Overscan {
+        Overscan {
+            top: 0,
+            bottom: 0,
+            left: 0,
+            right: 0,
+        }
+    }
+
+    // Roughly what a typical NTSC TV hides.
+    pub fn ntsc() -> Overscan {
+        Overscan {
+            top: 8,
+            bottom: 8,
+            left: 8,
+            right: 8,
+        }
+    }
+
+    // Parses "top,bottom,left,right", or a single margin for every edge.
+    pub fn parse(s: &str) -> Result<Overscan, String> {
+        let margins = s
+            .split(',')
+            .map(|m| m.trim().parse::<usize>())
+            .collect::<Result<Vec<usize>, _>>()
+            .map_err(|_| format!("Invalid overscan: {}", s))?;
+        let overscan = match margins[..] {
+            [all] => Overscan {
+                top: all,
+                bottom: all,
+                left: all,
+                right: all,
+            },
+            [top, bottom, left, right] => Overscan {
+                top,
+                bottom,
+                left,
+                right,
+            },
+            _ => return Err(format!("Invalid overscan: {}", s)),
+        };
+
+        if overscan.top + overscan.bottom >= 240 || overscan.left + overscan.right >= 256 {
+            return Err(format!("Overscan leaves nothing to show: {}", s));
+        }
+        Ok(overscan)
+    }
+}
+
+impl Default for Overscan {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum AspectRatio {
+    // One square pixel per NES pixel.
+    Square,
+    // NES pixels are 8:7 wide on an NTSC TV.
+    PixelAspect,
+    // Stretched to fill a 4:3 screen, whatever is cropped.
+    Display4x3,
+}
+
+impl AspectRatio {
+    pub fn name(self) -> &'static str {
+        match self {
+            AspectRatio::Square => "square",
+            AspectRatio::PixelAspect => "8:7",
+            AspectRatio::Display4x3 => "4:3",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<AspectRatio> {
+        [
+            AspectRatio::Square,
+            AspectRatio::PixelAspect,
+            AspectRatio::Display4x3,
+        ]
+        .iter()
+        .cloned()
+        .find(|a| a.name() == name)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Display {
+    pub overscan: Overscan,
+    pub aspect_ratio: AspectRatio,
+    // Only scale by whole multiples of the display size, leaving a border if need be.
+    pub integer_scale: bool,
+}
+
+impl Display {
+    pub fn new() -> Display {
+        Display {
+            overscan: Overscan::new(),
+            aspect_ratio: AspectRatio::Square,
+            integer_scale: false,
+        }
+    }
+
+    // The visible part of a frame, as (x, y, width, height).
+    pub fn crop(&self, width: usize, height: usize) -> (usize, usize, usize, usize) {
+        let o = &self.overscan;
+        let left = (o.left * width / 256).min(width - 1);
+        let right = (o.right * width / 256).min(width - 1 - left);
+        let top = (o.top * height / 240).min(height - 1);
+        let bottom = (o.bottom * height / 240).min(height - 1 - top);
+        (left, top, width - left - right, height - top - bottom)
+    }
+
+    // Copies the visible part of an RGB frame into output, returning its size.
+    pub fn crop_frame(
+        &self,
+        input: &[u8],
+        width: usize,
+        height: usize,
+        output: &mut Vec<u8>,
+    ) -> (usize, usize) {
+        let (x, y, w, h) = self.crop(width, height);
+        output.clear();
+        for row in y..y + h {
+            let start = (row * width + x) * 3;
+            output.extend_from_slice(&input[start..start + w * 3]);
+        }
+        (w, h)
+    }
+
+    // The size to show the visible part of the picture at 1x.
+    pub fn display_size(&self) -> (f32, f32) {
+        let (_, _, width, height) = self.crop(256, 240);
+        let (width, height) = (width as f32, height as f32);
+        match self.aspect_ratio {
+            AspectRatio::Square => (width, height),
+            AspectRatio::PixelAspect => (width * 8.0 / 7.0, height),
+            AspectRatio::Display4x3 => (height * 4.0 / 3.0, height),
+        }
+    }
+
+    // Where to draw the picture in an area of the given size, as (x, y, width, height), keeping
+    // its shape and centring it.
+    pub fn fit(&self, width: u32, height: u32) -> (u32, u32, u32, u32) {
+        let (display_width, display_height) = self.display_size();
+        let mut scale = (width as f32 / display_width).min(height as f32 / display_height);
+        if self.integer_scale && scale >= 1.0 {
+            scale = scale.floor();
+        }
+
+        let w = ((display_width * scale).round() as u32).min(width);
+        let h = ((display_height * scale).round() as u32).min(height);
+        ((width - w) / 2, (height - h) / 2, w, h)
+    }
+
+    // Converts a position in an area of the given size to NES pixels, reversing fit and crop.
+    // Positions outside the picture give pixels off the edges of the screen.
+    pub fn to_screen(&self, width: u32, height: u32, x: i32, y: i32) -> (i32, i32) {
+        let (fit_x, fit_y, fit_width, fit_height) = self.fit(width, height);
+        let (crop_x, crop_y, crop_width, crop_height) = self.crop(256, 240);
+        let screen_x = (x - fit_x as i32) * crop_width as i32;
+        let screen_y = (y - fit_y as i32) * crop_height as i32;
+        (
+            crop_x as i32 + screen_x.div_euclid(fit_width.max(1) as i32),
+            crop_y as i32 + screen_y.div_euclid(fit_height.max(1) as i32),
+        )
+    }
+}
+
+impl Default for Display {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::emulator::io::display::{AspectRatio, Display, Overscan};
+
+    #[test]
+    fn test_parse_overscan() {
+        assert_eq!(Overscan::parse("8").unwrap(), Overscan::ntsc());
+        let overscan = Overscan::parse("8,16,0,4").unwrap();
+        assert_eq!(
+            (overscan.top, overscan.bottom, overscan.left, overscan.right),
+            (8, 16, 0, 4)
+        );
+        assert!(Overscan::parse("8,8").is_err());
+        assert!(Overscan::parse("120").is_err());
+    }
+
+    #[test]
+    fn test_crop_scales_with_frame() {
+        let mut display = Display::new();
+        display.overscan = Overscan::parse("8,8,4,0").unwrap();
+        assert_eq!(display.crop(256, 240), (4, 8, 252, 224));
+        assert_eq!(display.crop(512, 480), (8, 16, 504, 448));
+
+        // A 3x2 frame with the top row and left column cropped off.
+        display.overscan = Overscan::parse("120,0,128,0").unwrap();
+        let input: Vec<u8> = (0..18).collect();
+        let mut output = vec![];
+        assert_eq!(display.crop_frame(&input, 3, 2, &mut output), (2, 1));
+        assert_eq!(output, vec![12, 13, 14, 15, 16, 17]);
+    }
+
+    #[test]
+    fn test_aspect_ratio() {
+        let mut display = Display::new();
+        display.overscan = Overscan::ntsc();
+        assert_eq!(display.display_size(), (240.0, 224.0));
+
+        display.aspect_ratio = AspectRatio::PixelAspect;
+        let (width, _) = display.display_size();
+        assert!((width - 274.3).abs() < 0.1);
+
+        display.aspect_ratio = AspectRatio::Display4x3;
+        let (width, height) = display.display_size();
+        assert!((width / height - 4.0 / 3.0).abs() < 0.001);
+    }
+
+    #[test]
+    fn test_fit() {
+        let mut display = Display::new();
+        assert_eq!(display.fit(512, 600), (0, 60, 512, 480));
+
+        display.integer_scale = true;
+        assert_eq!(display.fit(700, 600), (94, 60, 512, 480));
+
+        // Too small to fit even at 1x, so it's shrunk regardless.
+        assert_eq!(display.fit(128, 120), (0, 0, 128, 120));
+    }
+
+    #[test]
+    fn test_to_screen() {
+        let mut display = Display::new();
+        display.overscan = Overscan::parse("8,8,0,0").unwrap();
+        display.integer_scale = true;
+
+        // Drawn at 2x, at (44, 16) in a 600x480 window.
+        assert_eq!(display.fit(600, 480), (44, 16, 512, 448));
+        assert_eq!(display.to_screen(600, 480, 44, 16), (0, 8));
+        assert_eq!(display.to_screen(600, 480, 555, 463), (255, 231));
+        assert_eq!(display.to_screen(600, 480, 42, 0), (-1, 0));
+    }
+}
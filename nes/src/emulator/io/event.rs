@@ -2,15 +2,57 @@ use std::cell::RefCell;
 use std::collections::VecDeque;
 use std::rc::Rc;
 
+use serde::{Deserialize, Serialize};
+
 // Framework agnostic internal event types.
 
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
 pub enum Event {
     KeyDown(Key),
     KeyUp(Key),
+    // Where a pointer is on the NES screen, in NES pixels.
+    // It may be off the edges, e.g. when aiming away from the screen.
+    PointerMove(i32, i32),
+    PointerDown(PointerButton),
+    PointerUp(PointerButton),
+    // Gamepads are numbered by player, from 0.
+    PadDown(u8, PadButton),
+    PadUp(u8, PadButton),
+    // An analog stick's position on one axis, from -32768 to 32767.
+    PadAxis(u8, PadAxis, i16),
+    PadDisconnected(u8),
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum PointerButton {
+    Primary,
+    Secondary,
+}
+
+// Gamepad buttons, named after their positions on an Xbox style pad.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, Serialize, Deserialize)]
+pub enum PadButton {
+    A,
+    B,
+    X,
+    Y,
+    Back,
+    Start,
+    LeftShoulder,
+    RightShoulder,
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight,
 }
 
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum PadAxis {
+    LeftX,
+    LeftY,
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, Serialize, Deserialize)]
 pub enum Key {
     A,
     B,
@@ -62,6 +104,11 @@ pub enum Key {
     Space,
     Shift,
     Control,
+    ScrollLock,
+    F9,
+    F10,
+    F11,
+    F12,
 }
 
 pub trait EventHandler {
@@ -0,0 +1,141 @@
+// A tiny 3x5 pixel font for drawing text into RGB frame buffers.
+// Each glyph is 5 rows, with the most significant of the low 3 bits on the left.
+// Lower case letters are drawn as upper case.
+
+pub const GLYPH_WIDTH: usize = 3;
+pub const GLYPH_HEIGHT: usize = 5;
+
+// Distance between successive characters and lines.
+pub const CHAR_ADVANCE: usize = GLYPH_WIDTH + 1;
+pub const LINE_ADVANCE: usize = GLYPH_HEIGHT + 1;
+
+const LETTERS: [[u8; 5]; 26] = [
+    [0b010, 0b101, 0b111, 0b101, 0b101],
+    [0b110, 0b101, 0b110, 0b101, 0b110],
+    [0b011, 0b100, 0b100, 0b100, 0b011],
+    [0b110, 0b101, 0b101, 0b101, 0b110],
+    [0b111, 0b100, 0b110, 0b100, 0b111],
+    [0b111, 0b100, 0b110, 0b100, 0b100],
+    [0b011, 0b100, 0b101, 0b101, 0b011],
+    [0b101, 0b101, 0b111, 0b101, 0b101],
+    [0b111, 0b010, 0b010, 0b010, 0b111],
+    [0b001, 0b001, 0b001, 0b101, 0b010],
+    [0b101, 0b101, 0b110, 0b101, 0b101],
+    [0b100, 0b100, 0b100, 0b100, 0b111],
+    [0b101, 0b111, 0b111, 0b101, 0b101],
+    [0b110, 0b101, 0b101, 0b101, 0b101],
+    [0b010, 0b101, 0b101, 0b101, 0b010],
+    [0b110, 0b101, 0b110, 0b100, 0b100],
+    [0b010, 0b101, 0b101, 0b110, 0b011],
+    [0b110, 0b101, 0b110, 0b101, 0b101],
+    [0b011, 0b100, 0b010, 0b001, 0b110],
+    [0b111, 0b010, 0b010, 0b010, 0b010],
+    [0b101, 0b101, 0b101, 0b101, 0b111],
+    [0b101, 0b101, 0b101, 0b101, 0b010],
+    [0b101, 0b101, 0b111, 0b111, 0b101],
+    [0b101, 0b101, 0b010, 0b101, 0b101],
+    [0b101, 0b101, 0b010, 0b010, 0b010],
+    [0b111, 0b001, 0b010, 0b100, 0b111],
+];
+
+const DIGITS: [[u8; 5]; 10] = [
+    [0b111, 0b101, 0b101, 0b101, 0b111],
+    [0b010, 0b110, 0b010, 0b010, 0b111],
+    [0b110, 0b001, 0b010, 0b100, 0b111],
+    [0b110, 0b001, 0b010, 0b001, 0b110],
+    [0b101, 0b101, 0b111, 0b001, 0b001],
+    [0b111, 0b100, 0b110, 0b001, 0b110],
+    [0b011, 0b100, 0b111, 0b101, 0b111],
+    [0b111, 0b001, 0b010, 0b010, 0b010],
+    [0b111, 0b101, 0b111, 0b101, 0b111],
+    [0b111, 0b101, 0b111, 0b001, 0b110],
+];
+
+pub fn glyph(c: char) -> [u8; 5] {
+    match c.to_ascii_uppercase() {
+        c @ 'A'..='Z' => LETTERS[(c as usize) - ('A' as usize)],
+        c @ '0'..='9' => DIGITS[(c as usize) - ('0' as usize)],
+        ' ' => [0b000, 0b000, 0b000, 0b000, 0b000],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        ',' => [0b000, 0b000, 0b000, 0b010, 0b100],
+        ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+        ';' => [0b000, 0b010, 0b000, 0b010, 0b100],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        '+' => [0b000, 0b010, 0b111, 0b010, 0b000],
+        '=' => [0b000, 0b111, 0b000, 0b111, 0b000],
+        '/' => [0b001, 0b001, 0b010, 0b100, 0b100],
+        '\'' => [0b010, 0b010, 0b000, 0b000, 0b000],
+        '"' => [0b101, 0b101, 0b000, 0b000, 0b000],
+        '!' => [0b010, 0b010, 0b010, 0b000, 0b010],
+        '(' => [0b001, 0b010, 0b010, 0b010, 0b001],
+        ')' => [0b100, 0b010, 0b010, 0b010, 0b100],
+        '[' => [0b011, 0b010, 0b010, 0b010, 0b011],
+        ']' => [0b110, 0b010, 0b010, 0b010, 0b110],
+        '<' => [0b001, 0b010, 0b100, 0b010, 0b001],
+        '>' => [0b100, 0b010, 0b001, 0b010, 0b100],
+        '&' => [0b010, 0b101, 0b010, 0b101, 0b011],
+        '#' => [0b101, 0b111, 0b101, 0b111, 0b101],
+        '%' => [0b101, 0b001, 0b010, 0b100, 0b101],
+        '*' => [0b000, 0b101, 0b010, 0b101, 0b000],
+        '_' => [0b000, 0b000, 0b000, 0b000, 0b111],
+        _ => [0b111, 0b001, 0b010, 0b000, 0b010],
+    }
+}
+
+// Draw a single character into an RGB buffer `width` pixels wide.
+// Pixels falling outside the buffer are clipped.
+pub fn draw_char(buffer: &mut [u8], width: usize, x: usize, y: usize, c: char, colour: [u8; 3]) {
+    for (dy, row) in glyph(c).iter().enumerate() {
+        for dx in 0..GLYPH_WIDTH {
+            if (row >> (GLYPH_WIDTH - 1 - dx)) & 1 == 0 || x + dx >= width {
+                continue;
+            }
+
+            let ix = ((y + dy) * width + x + dx) * 3;
+            if ix + 3 <= buffer.len() {
+                buffer[ix..ix + 3].copy_from_slice(&colour);
+            }
+        }
+    }
+}
+
+// Draw a single line of text, returning the x coordinate just after it.
+pub fn draw_text(
+    buffer: &mut [u8],
+    width: usize,
+    x: usize,
+    y: usize,
+    text: &str,
+    colour: [u8; 3],
+) -> usize {
+    let mut x = x;
+    for c in text.chars() {
+        draw_char(buffer, width, x, y, c, colour);
+        x += CHAR_ADVANCE;
+    }
+    x
+}
+
+#[cfg(test)]
+mod test {
+    use crate::emulator::io::font;
+
+    #[test]
+    fn test_draw_text() {
+        let mut buffer = [0; 8 * 5 * 3];
+        let end = font::draw_text(&mut buffer, 8, 0, 0, "i1", [1, 2, 3]);
+        assert_eq!(end, 8);
+
+        // Top row of "I" is solid, top row of "1" is just the middle pixel.
+        let top_row: Vec<u8> = buffer[0..8 * 3].iter().step_by(3).cloned().collect();
+        assert_eq!(top_row, vec![1, 1, 1, 0, 0, 1, 0, 0]);
+        assert_eq!(&buffer[0..3], &[1, 2, 3]);
+    }
+
+    #[test]
+    fn test_draw_text_clips() {
+        let mut buffer = [0; 4 * 5 * 3];
+        font::draw_text(&mut buffer, 4, 2, 0, "WW", [0xFF, 0xFF, 0xFF]);
+        assert!(buffer[..].iter().any(|&b| b != 0));
+    }
+}
@@ -1,11 +1,24 @@
+pub mod avi;
+pub mod blip;
+pub mod display;
 pub mod event;
+pub mod font;
 pub mod nop;
+pub mod ntsc;
 pub mod palette;
+pub mod scale;
+pub mod screenshot;
+pub mod vgm;
+pub mod wav;
 
 use std::collections::VecDeque;
 use std::f32::consts::PI;
 
 use crate::emulator::apu;
+use crate::emulator::hdpack::HDRenderer;
+use crate::emulator::io::blip::BlipBuffer;
+use crate::emulator::io::ntsc::NTSCFilter;
+use crate::emulator::io::palette::Palette;
 use crate::emulator::ppu;
 use crate::emulator::state::{SaveState, ScreenState};
 use crate::emulator::NES_APU_CLOCK_FACTOR;
@@ -19,7 +32,13 @@ pub struct Screen {
     dot: u32,
     screen_buffer: [u8; 256 * 240 * 3],
     backup_buffer: [u8; 256 * 240 * 3],
+    index_buffer: Vec<u16>,
+    backup_index_buffer: Vec<u16>,
     double_buffering: bool,
+    rgb_output: bool,
+    palette: Palette,
+    hd: Option<HDRenderer>,
+    ntsc: Option<NTSCFilter>,
 }
 
 impl ppu::VideoOut for Screen {
@@ -27,21 +46,40 @@ impl ppu::VideoOut for Screen {
         let x = self.dot;
         let y = self.scanline;
 
-        let (r, g, b) = palette::convert_colour(c);
+        let index = c.index();
+        self.index_buffer[(x + y * 256) as usize] = index;
 
-        self.screen_buffer[((x + y * 256) * 3) as usize] = r;
-        self.screen_buffer[((x + y * 256) * 3 + 1) as usize] = g;
-        self.screen_buffer[((x + y * 256) * 3 + 2) as usize] = b;
+        if self.rgb_output {
+            let (r, g, b) = self.palette.convert(index);
+            self.screen_buffer[((x + y * 256) * 3) as usize] = r;
+            self.screen_buffer[((x + y * 256) * 3 + 1) as usize] = g;
+            self.screen_buffer[((x + y * 256) * 3 + 2) as usize] = b;
+        }
 
         self.dot = (self.dot + 1) % 256;
         if self.dot == 0 {
             self.scanline = (self.scanline + 1) % 240;
-            if self.scanline == 0 && self.double_buffering {
-                // Flip the buffer.s
-                std::mem::swap(&mut self.screen_buffer, &mut self.backup_buffer);
+            if self.scanline == 0 {
+                if let Some(hd) = self.hd.as_mut() {
+                    hd.finish_frame(&self.index_buffer);
+                }
+                if let Some(ntsc) = self.ntsc.as_mut() {
+                    ntsc.finish_frame(&self.index_buffer);
+                }
+                if self.double_buffering {
+                    // Flip the buffer.s
+                    std::mem::swap(&mut self.screen_buffer, &mut self.backup_buffer);
+                    std::mem::swap(&mut self.index_buffer, &mut self.backup_index_buffer);
+                }
             }
         }
     }
+
+    fn emit_source(&mut self, source: &ppu::PixelSource) {
+        if let Some(hd) = self.hd.as_mut() {
+            hd.set_source(self.dot as usize, self.scanline as usize, source);
+        }
+    }
 }
 
 impl Screen {
@@ -51,7 +89,13 @@ impl Screen {
             dot: 0,
             screen_buffer: [0; 256 * 240 * 3],
             backup_buffer: [0; 256 * 240 * 3],
+            index_buffer: vec![0; 256 * 240],
+            backup_index_buffer: vec![0; 256 * 240],
             double_buffering: true,
+            rgb_output: true,
+            palette: Palette::new(),
+            hd: None,
+            ntsc: None,
         }
     }
 
@@ -64,9 +108,88 @@ impl Screen {
         render(buffer);
     }
 
+    // The frame as it's displayed, after the NTSC filter or HD pack if either is in use, along
+    // with its width and height.
+    pub fn do_render_output<F: FnOnce(&[u8], usize, usize)>(&self, render: F) {
+        match (&self.ntsc, &self.hd) {
+            (Some(ntsc), _) => ntsc.do_render(|data| render(data, ntsc.width(), ntsc.height())),
+            (None, Some(hd)) => hd.do_render(|data| render(data, hd.width(), hd.height())),
+            (None, None) => self.do_render(|data| render(data, 256, 240)),
+        }
+    }
+
+    // The frame as colour indices, including emphasis bits as from Colour::index.
+    pub fn do_render_indexed<F: FnOnce(&[u16])>(&self, render: F) {
+        let buffer = if self.double_buffering {
+            &self.backup_index_buffer
+        } else {
+            &self.index_buffer
+        };
+        render(buffer);
+    }
+
+    // The colour index last emitted at a position, whether earlier this frame or in the last one.
+    pub fn emitted_pixel(&self, x: usize, y: usize) -> u16 {
+        let (dot, scanline) = (self.dot as usize, self.scanline as usize);
+        let this_frame = y < scanline || (y == scanline && x < dot);
+        let buffer = if this_frame || !self.double_buffering {
+            &self.index_buffer
+        } else {
+            &self.backup_index_buffer
+        };
+        buffer[y * 256 + x]
+    }
+
     pub fn set_double_buffering(&mut self, on: bool) {
         self.double_buffering = on;
     }
+
+    // When off, only the indexed frame is produced, which saves converting every pixel to RGB.
+    pub fn set_rgb_output(&mut self, on: bool) {
+        self.rgb_output = on;
+    }
+
+    pub fn is_rgb_output(&self) -> bool {
+        self.rgb_output
+    }
+
+    // Takes effect from the next pixel, so may change mid-frame.
+    pub fn set_palette(&mut self, palette: Palette) {
+        if let Some(hd) = self.hd.as_mut() {
+            hd.set_palette(palette.clone());
+        }
+        self.palette = palette;
+    }
+
+    pub fn palette(&self) -> &Palette {
+        &self.palette
+    }
+
+    // Replaces tiles with high-definition graphics, if the PPU has tile output enabled.
+    pub fn set_hd_renderer(&mut self, mut hd: Option<HDRenderer>) {
+        if let Some(hd) = hd.as_mut() {
+            hd.set_palette(self.palette.clone());
+        }
+        self.hd = hd;
+    }
+
+    pub fn hd_renderer(&self) -> Option<&HDRenderer> {
+        self.hd.as_ref()
+    }
+
+    // Decodes frames from a simulated composite signal, which has its own picture settings
+    // in place of the palette. RGB output can be turned off while it's in use.
+    pub fn set_ntsc_filter(&mut self, ntsc: Option<NTSCFilter>) {
+        self.ntsc = ntsc;
+    }
+
+    pub fn ntsc_filter(&self) -> Option<&NTSCFilter> {
+        self.ntsc.as_ref()
+    }
+
+    pub fn ntsc_filter_mut(&mut self) -> Option<&mut NTSCFilter> {
+        self.ntsc.as_mut()
+    }
 }
 
 impl<'de> SaveState<'de, ScreenState> for Screen {
@@ -83,300 +206,224 @@ impl<'de> SaveState<'de, ScreenState> for Screen {
     }
 }
 
+// Converts the APU's raw output stream into samples at the output sample rate.
+// The APU emits one sample per APU cycle (~894kHz), but only changes in amplitude are recorded,
+// and these are resampled with band-limited step synthesis when a frame is consumed.
+//
+// In the stereo modes, consumed samples are interleaved left/right frames.
 pub struct SimpleAudioOut {
-    buffer: Vec<f32>,
-    counter: f32,
-    fir_filter: FIRFilter,
-    low_pass_filter: LowPassFilter,
-    high_pass_filter_1: HighPassFilter,
-    high_pass_filter_2: HighPassFilter,
+    sample_rate: f32,
+    mode: OutputMode,
+    left: Resampler,
+    right: Resampler,
+    clock: u32,
+    delay_line: VecDeque<f32>,
     enabled: bool,
 }
 
-impl SimpleAudioOut {
-    const APU_CLOCK: f32 = 1_789_772.0 / 2.0;
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputMode {
+    Mono,
+    // Left and right as mixed by the APU, which must also be set to stereo.
+    Stereo,
+    // Widens a mono signal by playing it on the right slightly after the left.
+    PseudoStereo { delay_ms: f32 },
+}
 
+impl SimpleAudioOut {
     pub fn new(sample_rate: f32) -> SimpleAudioOut {
         SimpleAudioOut {
-            buffer: Vec::new(),
-            counter: 0.0,
-            // This FIR filter generated from http://t-filter.engineerjs.com/
-            // Using the parameters:
-            // Sample rate: 894886Hz.
-            //   - from: 0Hz,   to: 20kHz,    gain: 1, ripple/att: 5dB
-            //   - from: 30kHz, to: 446443Hz, gain: 0, ripple/att: -90dB
-            fir_filter: FIRFilter::new(vec![
-                -0.000026911389850328808,
-                -0.00003081140855425942,
-                -0.00004779032648771152,
-                -0.00007032356766627306,
-                -0.00009945719388661462,
-                -0.0001362915825695889,
-                -0.0001819696874998152,
-                -0.0002376304515464224,
-                -0.000304409392795344,
-                -0.00038335285409846925,
-                -0.0004754340414693832,
-                -0.0005814565487689781,
-                -0.0007020383654144685,
-                -0.000837560223929689,
-                -0.0009880986359432722,
-                -0.0011533915706213244,
-                -0.0013327996339335055,
-                -0.001525238803373966,
-                -0.001729170027343746,
-                -0.0019425653403935548,
-                -0.0021628818270826543,
-                -0.0023870576384088507,
-                -0.0026115231349885587,
-                -0.0028322167224134323,
-                -0.0030446160695497635,
-                -0.003243785374697576,
-                -0.0034244531543884803,
-                -0.0035810842478539804,
-                -0.0037079740687016955,
-                -0.003799368224835729,
-                -0.0038495872614102314,
-                -0.0038531578568699824,
-                -0.003804959012169467,
-                -0.0037003696268275246,
-                -0.0035354266934205804,
-                -0.0033069760581996175,
-                -0.003012815354076389,
-                -0.0026518316881042232,
-                -0.0022241292041862665,
-                -0.0017311382250126026,
-                -0.0011756994130167975,
-                -0.0005621172682641575,
-                0.0001038056638396132,
-                0.0008147685021143271,
-                0.001562010974441583,
-                0.002335385786902094,
-                0.003123468952799138,
-                0.003913709351734435,
-                0.0046926116716311155,
-                0.005445957594914879,
-                0.006159059392517742,
-                0.006817043461108929,
-                0.007405158670513967,
-                0.007909098715661374,
-                0.00831533754275437,
-                0.008611474073370417,
-                0.008786575131287288,
-                0.008831509475056808,
-                0.008739259673585048,
-                0.008505206807054261,
-                0.008127387262416044,
-                0.007606703408582852,
-                0.006947082543758409,
-                0.006155584307559659,
-                0.005242447599070063,
-                0.0042210725502951265,
-                0.0031079342495448116,
-                0.0019224231576322659,
-                0.0006866204855758932,
-                -0.0005749961400296242,
-                -0.001835912162410459,
-                -0.003067993578580558,
-                -0.004241957926364744,
-                -0.005327890056938486,
-                -0.006295795781585666,
-                -0.007116190992407595,
-                -0.0077606932248686,
-                -0.00820261427583573,
-                -0.008417549914370916,
-                -0.008383946232000684,
-                -0.008083629953125851,
-                -0.0075022965680159445,
-                -0.006629930612600777,
-                -0.005461158359702242,
-                -0.003995513457441722,
-                -0.0022376295508218834,
-                -0.00019734313868850133,
-                0.0021103183631383604,
-                0.0046652529398613345,
-                0.007442486743310659,
-                0.010412487938170444,
-                0.01354154851228366,
-                0.016792281277884555,
-                0.02012421592250281,
-                0.02349443850438209,
-                0.026858268064359096,
-                0.030170014467297886,
-                0.03338377026120789,
-                0.036454235093255336,
-                0.03933744516127962,
-                0.04199158082248185,
-                0.04437777682414004,
-                0.04646075992837699,
-                0.04820945005061596,
-                0.0495977101310674,
-                0.050604575689545905,
-                0.05121487116819005,
-                0.05141933320399621,
-                0.05121487116819005,
-                0.050604575689545905,
-                0.0495977101310674,
-                0.04820945005061596,
-                0.04646075992837699,
-                0.04437777682414004,
-                0.04199158082248185,
-                0.03933744516127962,
-                0.036454235093255336,
-                0.03338377026120789,
-                0.030170014467297886,
-                0.026858268064359096,
-                0.02349443850438209,
-                0.02012421592250281,
-                0.016792281277884555,
-                0.01354154851228366,
-                0.010412487938170444,
-                0.007442486743310659,
-                0.0046652529398613345,
-                0.0021103183631383604,
-                -0.00019734313868850133,
-                -0.0022376295508218834,
-                -0.003995513457441722,
-                -0.005461158359702242,
-                -0.006629930612600777,
-                -0.0075022965680159445,
-                -0.008083629953125851,
-                -0.008383946232000684,
-                -0.008417549914370916,
-                -0.00820261427583573,
-                -0.0077606932248686,
-                -0.007116190992407595,
-                -0.006295795781585666,
-                -0.005327890056938486,
-                -0.004241957926364744,
-                -0.003067993578580558,
-                -0.001835912162410459,
-                -0.0005749961400296242,
-                0.0006866204855758932,
-                0.0019224231576322659,
-                0.0031079342495448116,
-                0.0042210725502951265,
-                0.005242447599070063,
-                0.006155584307559659,
-                0.006947082543758409,
-                0.007606703408582852,
-                0.008127387262416044,
-                0.008505206807054261,
-                0.008739259673585048,
-                0.008831509475056808,
-                0.008786575131287288,
-                0.008611474073370417,
-                0.00831533754275437,
-                0.007909098715661374,
-                0.007405158670513967,
-                0.006817043461108929,
-                0.006159059392517742,
-                0.005445957594914879,
-                0.0046926116716311155,
-                0.003913709351734435,
-                0.003123468952799138,
-                0.002335385786902094,
-                0.001562010974441583,
-                0.0008147685021143271,
-                0.0001038056638396132,
-                -0.0005621172682641575,
-                -0.0011756994130167975,
-                -0.0017311382250126026,
-                -0.0022241292041862665,
-                -0.0026518316881042232,
-                -0.003012815354076389,
-                -0.0033069760581996175,
-                -0.0035354266934205804,
-                -0.0037003696268275246,
-                -0.003804959012169467,
-                -0.0038531578568699824,
-                -0.0038495872614102314,
-                -0.003799368224835729,
-                -0.0037079740687016955,
-                -0.0035810842478539804,
-                -0.0034244531543884803,
-                -0.003243785374697576,
-                -0.0030446160695497635,
-                -0.0028322167224134323,
-                -0.0026115231349885587,
-                -0.0023870576384088507,
-                -0.0021628818270826543,
-                -0.0019425653403935548,
-                -0.001729170027343746,
-                -0.001525238803373966,
-                -0.0013327996339335055,
-                -0.0011533915706213244,
-                -0.0009880986359432722,
-                -0.000837560223929689,
-                -0.0007020383654144685,
-                -0.0005814565487689781,
-                -0.0004754340414693832,
-                -0.00038335285409846925,
-                -0.000304409392795344,
-                -0.0002376304515464224,
-                -0.0001819696874998152,
-                -0.0001362915825695889,
-                -0.00009945719388661462,
-                -0.00007032356766627306,
-                -0.00004779032648771152,
-                -0.00003081140855425942,
-                -0.000026911389850328808,
-            ]),
-            low_pass_filter: LowPassFilter::new(35_000.0, SimpleAudioOut::APU_CLOCK),
-            high_pass_filter_1: HighPassFilter::new(440.0, sample_rate),
-            high_pass_filter_2: HighPassFilter::new(90.0, sample_rate),
+            sample_rate,
+            mode: OutputMode::Mono,
+            left: Resampler::new(sample_rate),
+            right: Resampler::new(sample_rate),
+            clock: 0,
+            delay_line: VecDeque::new(),
             enabled: true,
         }
     }
 
     // master_cycles indicates the number of master clock cycles which have elapsed.
-    // num_samples indicates how many samples we should output that into.
+    // num_samples indicates how many samples (per channel) we should output that into.
     pub fn consume<F: FnOnce(&[f32]) -> ()>(
         &mut self,
         master_cycles: u64,
         num_samples: u64,
         consume: F,
     ) {
-        if self.buffer.len() == 0 || num_samples == 0 || !self.enabled {
-            self.buffer.clear();
+        if self.clock == 0 || num_samples == 0 || !self.enabled {
+            self.clear();
             return;
         }
 
-        let mut buf = Vec::with_capacity(num_samples as usize);
-
-        // Need to downsample all the samples we collected this frame.
-        let total = self.buffer.len();
+        // Resample everything we collected this frame.
         let apu_cycles = master_cycles / (NES_APU_CLOCK_FACTOR as u64);
-        let step = (apu_cycles as f64) / (num_samples as f64);
-
-        let mut counter = 0.0;
-        for ix in 0..total {
-            self.fir_filter.shift(self.buffer[ix]);
-
-            counter += 1.0;
-            if counter >= step {
-                counter -= step;
-                let sample = self.fir_filter.compute();
-                buf.push(sample);
+        let mut left = Vec::with_capacity(num_samples as usize);
+        self.left
+            .render(apu_cycles, num_samples, self.clock, |s| left.push(s));
+
+        let buf = match self.mode {
+            OutputMode::Mono => left,
+            OutputMode::Stereo => {
+                let mut right = Vec::with_capacity(num_samples as usize);
+                self.right
+                    .render(apu_cycles, num_samples, self.clock, |s| right.push(s));
+                SimpleAudioOut::interleave(&left, &right)
             }
-        }
+            OutputMode::PseudoStereo { .. } => {
+                let delay_line = &mut self.delay_line;
+                let right: Vec<f32> = left
+                    .iter()
+                    .map(|&s| {
+                        delay_line.push_back(s);
+                        delay_line.pop_front().unwrap_or(0.0)
+                    })
+                    .collect();
+                SimpleAudioOut::interleave(&left, &right)
+            }
+        };
 
         consume(&buf);
-        self.buffer.clear();
+        self.clock = 0;
     }
 
     pub fn set_enabled(&mut self, enabled: bool) {
         self.enabled = enabled;
     }
 
-    fn queue_sample(&mut self, sample: f32) {
-        self.buffer.push(sample);
+    pub fn sample_rate(&self) -> f32 {
+        self.sample_rate
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+        self.left = Resampler::new(sample_rate);
+        self.right = Resampler::new(sample_rate);
+        self.clear();
+    }
+
+    pub fn mode(&self) -> OutputMode {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: OutputMode) {
+        self.mode = mode;
+        self.clear();
+    }
+
+    // Number of interleaved channels in each consumed frame.
+    pub fn channels(&self) -> usize {
+        match self.mode {
+            OutputMode::Mono => 1,
+            OutputMode::Stereo | OutputMode::PseudoStereo { .. } => 2,
+        }
+    }
+
+    fn clear(&mut self) {
+        self.left.clear();
+        self.right.clear();
+        self.clock = 0;
+
+        self.delay_line.clear();
+        if let OutputMode::PseudoStereo { delay_ms } = self.mode {
+            let delay_samples = (delay_ms * self.sample_rate / 1000.0) as usize;
+            self.delay_line.resize(delay_samples, 0.0);
+        }
+    }
+
+    fn interleave(left: &[f32], right: &[f32]) -> Vec<f32> {
+        let mut buf = Vec::with_capacity(left.len() * 2);
+        for (&l, &r) in left.iter().zip(right.iter()) {
+            buf.push(l);
+            buf.push(r);
+        }
+        buf
     }
 }
 
 impl apu::AudioOut for SimpleAudioOut {
     fn emit(&mut self, sample: f32) {
-        self.queue_sample(sample);
+        self.left.queue_sample(self.clock, sample);
+        if self.mode == OutputMode::Stereo {
+            self.right.queue_sample(self.clock, sample);
+        }
+        self.clock += 1;
+    }
+
+    fn emit_stereo(&mut self, left: f32, right: f32) {
+        if self.mode == OutputMode::Stereo {
+            self.left.queue_sample(self.clock, left);
+            self.right.queue_sample(self.clock, right);
+        } else {
+            self.left.queue_sample(self.clock, (left + right) / 2.0);
+        }
+        self.clock += 1;
+    }
+}
+
+// Resamples and filters a single channel of audio.
+struct Resampler {
+    blip: BlipBuffer,
+    deltas: Vec<(u32, i32)>,
+    amplitude: i32,
+    low_pass_filter: LowPassFilter,
+    high_pass_filter_1: HighPassFilter,
+    high_pass_filter_2: HighPassFilter,
+}
+
+impl Resampler {
+    pub fn new(sample_rate: f32) -> Resampler {
+        Resampler {
+            blip: BlipBuffer::new(),
+            deltas: Vec::new(),
+            amplitude: 0,
+            // The NES itself filters its output with these 3 first-order filters.
+            low_pass_filter: LowPassFilter::new(14_000.0, sample_rate),
+            high_pass_filter_1: HighPassFilter::new(90.0, sample_rate),
+            high_pass_filter_2: HighPassFilter::new(440.0, sample_rate),
+        }
+    }
+
+    pub fn queue_sample(&mut self, clock: u32, sample: f32) {
+        let amplitude = (sample * ((1 << BlipBuffer::AMPLITUDE_BITS) as f32)) as i32;
+        if amplitude != self.amplitude {
+            self.deltas.push((clock, amplitude - self.amplitude));
+            self.amplitude = amplitude;
+        }
+    }
+
+    pub fn render<F: FnMut(f32)>(
+        &mut self,
+        apu_cycles: u64,
+        num_samples: u64,
+        clock: u32,
+        mut output: F,
+    ) {
+        self.blip.set_rates(apu_cycles as f64, num_samples as f64);
+        for &(time, delta) in self.deltas.iter() {
+            self.blip.add_delta(time, delta);
+        }
+        self.blip.end_frame(clock);
+        self.deltas.clear();
+
+        let scale = 1.0 / ((1 << BlipBuffer::AMPLITUDE_BITS) as f32);
+        let low_pass_filter = &mut self.low_pass_filter;
+        let high_pass_filter_1 = &mut self.high_pass_filter_1;
+        let high_pass_filter_2 = &mut self.high_pass_filter_2;
+        let avail = self.blip.samples_avail();
+        self.blip.read_samples(avail, |s| {
+            let mut sample = (s as f32) * scale;
+            sample = high_pass_filter_1.process(sample);
+            sample = high_pass_filter_2.process(sample);
+            sample = low_pass_filter.process(sample);
+            output(sample);
+        });
+    }
+
+    pub fn clear(&mut self) {
+        self.blip.clear();
+        self.deltas.clear();
+        self.amplitude = 0;
     }
 }
 
@@ -429,39 +476,133 @@ impl HighPassFilter {
     }
 }
 
-struct FIRFilter {
-    impulse_response: Vec<f32>,
-    buffer: VecDeque<f32>,
-}
-
-impl FIRFilter {
-    pub fn new(impulse_response: Vec<f32>) -> FIRFilter {
-        let order = impulse_response.len();
-        let mut buffer = VecDeque::with_capacity(order);
-        for _ in 0..order {
-            buffer.push_back(0.0);
+#[cfg(test)]
+mod test {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use crate::emulator::apu::{AudioOut, APU};
+    use crate::emulator::clock::Ticker;
+    use crate::emulator::io::palette::Palette;
+    use crate::emulator::io::{OutputMode, Screen, SimpleAudioOut};
+    use crate::emulator::memory::{Memory, Writer};
+    use crate::emulator::ppu::{Colour, VideoOut};
+    use crate::emulator::{NES_APU_CLOCK_FACTOR, NES_CPU_CLOCK_FACTOR};
+
+    // Emit a square wave for a frame, and return what the output produces.
+    fn render<F: FnMut(&mut SimpleAudioOut, f32)>(
+        out: &mut SimpleAudioOut,
+        mut emit: F,
+    ) -> Vec<f32> {
+        for ix in 0..8000 {
+            emit(out, if (ix / 100) % 2 == 0 { 0.5 } else { 0.0 });
         }
 
-        FIRFilter {
-            impulse_response,
-            buffer,
-        }
+        let mut buf = vec![];
+        let master_cycles = 8000 * (NES_APU_CLOCK_FACTOR as u64);
+        out.consume(master_cycles, 400, |data| buf.extend_from_slice(data));
+        buf
     }
 
-    pub fn shift(&mut self, sample: f32) {
-        self.buffer.pop_front();
-        self.buffer.push_back(sample);
+    #[test]
+    fn test_stereo_is_interleaved() {
+        let mut out = SimpleAudioOut::new(48_000.0);
+        out.set_mode(OutputMode::Stereo);
+        assert_eq!(out.channels(), 2);
+
+        let buf = render(&mut out, |out, s| out.emit_stereo(s, 0.0));
+        assert_eq!(buf.len(), 800);
+        assert!(buf.iter().step_by(2).any(|&s| s != 0.0));
+        assert!(buf.iter().skip(1).step_by(2).all(|&s| s == 0.0));
     }
 
-    pub fn compute(&self) -> f32 {
-        self.compute_sample()
+    #[test]
+    fn test_stereo_downmixes_in_mono() {
+        let mut mono = SimpleAudioOut::new(48_000.0);
+        let mut stereo = SimpleAudioOut::new(48_000.0);
+        assert_eq!(stereo.channels(), 1);
+
+        let expected = render(&mut mono, |out, s| out.emit(s / 2.0));
+        let actual = render(&mut stereo, |out, s| out.emit_stereo(s, 0.0));
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn test_pseudo_stereo_delays_right() {
+        let mut out = SimpleAudioOut::new(48_000.0);
+        out.set_mode(OutputMode::PseudoStereo { delay_ms: 1.0 });
+
+        let buf = render(&mut out, |out, s| out.emit(s));
+        let left: Vec<f32> = buf.iter().step_by(2).cloned().collect();
+        let right: Vec<f32> = buf.iter().skip(1).step_by(2).cloned().collect();
+        assert!(right[..48].iter().all(|&s| s == 0.0));
+        assert_eq!(left[..352], right[48..]);
     }
 
-    fn compute_sample(&self) -> f32 {
-        let mut sample = 0.0;
-        for ix in 0..self.impulse_response.len() {
-            sample += self.buffer[ix] * self.impulse_response[ix];
+    // A frame of a 220Hz triangle, through the APU into the output, quantised to 16 bits.
+    fn render_apu_frame() -> Vec<i16> {
+        let out = Rc::new(RefCell::new(SimpleAudioOut::new(48_000.0)));
+        let mut apu = APU::new(Box::new(out.clone()), Box::new(Memory::new_ram(0x10000)));
+        apu.write(0x4015, 0x04);
+        apu.write(0x4008, 0xFF);
+        apu.write(0x400A, 0xFD);
+        apu.write(0x400B, 0x08);
+        for _ in 0..29830 {
+            apu.tick();
         }
-        sample
+
+        let mut buf = vec![];
+        let master_cycles = 29830 * (NES_CPU_CLOCK_FACTOR as u64);
+        out.borrow_mut()
+            .consume(master_cycles, 800, |data| buf.extend_from_slice(data));
+        buf.iter()
+            .map(|&s| (s * (i16::MAX as f32)) as i16)
+            .collect()
+    }
+
+    #[test]
+    fn test_apu_output_regression() {
+        let samples = render_apu_frame();
+        assert_eq!(samples.len(), 800);
+        assert_eq!(samples, render_apu_frame());
+
+        // The triangle's linear counter isn't loaded until the first quarter frame.
+        assert!(samples[..200].iter().all(|&s| s == 0));
+        assert_eq!(
+            &samples[250..256],
+            &[-1123, -1073, -1159, -1397, -1438, -1419]
+        );
+        assert_eq!(&samples[600..606], &[1370, 1460, 1397, 1325, 1249, 1146]);
+    }
+
+    fn emit_frame(screen: &mut Screen) {
+        for ix in 0..256 * 240 {
+            let mut colour = Colour::new((ix % 64) as u8);
+            colour.em_b = ix == 1;
+            screen.emit(colour);
+        }
+    }
+
+    #[test]
+    fn test_screen_indexed_output() {
+        let mut screen = Screen::new();
+        emit_frame(&mut screen);
+
+        let mut indices = vec![];
+        screen.do_render_indexed(|frame| indices.extend_from_slice(frame));
+        assert_eq!(&indices[..4], &[0x00, 0x101, 0x02, 0x03]);
+
+        let (r, g, b) = Palette::new().convert(0x101);
+        screen.do_render(|frame| assert_eq!(&frame[3..6], &[r, g, b]));
+    }
+
+    #[test]
+    fn test_screen_without_rgb_output() {
+        let mut screen = Screen::new();
+        screen.set_rgb_output(false);
+        emit_frame(&mut screen);
+
+        screen.do_render(|frame| assert!(frame.iter().all(|&b| b == 0)));
+        screen.do_render_indexed(|frame| assert_eq!(frame[63], 0x3F));
     }
 }
@@ -0,0 +1,353 @@
+use crate::emulator::io::palette;
+use crate::emulator::io::palette::NTSCSettings;
+
+// Simulates the PPU's composite video signal being decoded by a TV, in the spirit of Blargg's
+// nes_ntsc, for the colour fringing and dot crawl that games were designed around.
+//
+// Each NES pixel is 8 samples of a signal with 12 samples per cycle of the colour subcarrier.
+// Luma is found by averaging over a whole cycle, and chroma by demodulating against the
+// subcarrier, with the settings controlling how much the two bleed into each other.
+
+// 2048 samples per line, at about 3.4 per output pixel, which keeps the 8:7 pixel aspect ratio.
+pub const NTSC_WIDTH: usize = 602;
+pub const NTSC_HEIGHT: usize = 240;
+
+const SAMPLES_PER_PIXEL: usize = 8;
+const SAMPLES_PER_LINE: usize = 256 * SAMPLES_PER_PIXEL;
+const PHASES: usize = 12;
+
+// Extra samples either side of the line, so filters don't run off the end.
+const PADDING: usize = 3 * PHASES;
+
+const GAMMA_TABLE_SIZE: usize = 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NTSCFilterSettings {
+    // Picture settings as for a generated palette.
+    pub picture: NTSCSettings,
+
+    // From -1.0 (blurry) to 1.0 (over-sharpened).
+    pub sharpness: f32,
+
+    // How much of the colour signal shows up in brightness, as dot crawl and rainbows. 0.0 to 1.0.
+    pub artifacts: f32,
+
+    // How much brightness edges show up as colour. 0.0 to 1.0.
+    pub fringing: f32,
+
+    // How far colours spread horizontally. 0.0 to 1.0.
+    pub bleed: f32,
+
+    // The subcarrier's phase changes every frame, making artifacts crawl.
+    pub dot_crawl: bool,
+}
+
+impl NTSCFilterSettings {
+    pub fn composite() -> NTSCFilterSettings {
+        NTSCFilterSettings {
+            picture: NTSCSettings::new(),
+            sharpness: 0.0,
+            artifacts: 1.0,
+            fringing: 1.0,
+            bleed: 0.5,
+            dot_crawl: true,
+        }
+    }
+
+    // Separate luma and chroma, so no artifacts or fringing.
+    pub fn svideo() -> NTSCFilterSettings {
+        NTSCFilterSettings {
+            picture: NTSCSettings::new(),
+            sharpness: 0.2,
+            artifacts: 0.0,
+            fringing: 0.0,
+            bleed: 0.5,
+            dot_crawl: true,
+        }
+    }
+
+    pub fn rgb() -> NTSCFilterSettings {
+        NTSCFilterSettings {
+            picture: NTSCSettings::new(),
+            sharpness: 0.2,
+            artifacts: 0.0,
+            fringing: 0.0,
+            bleed: 0.0,
+            dot_crawl: false,
+        }
+    }
+
+    pub fn monochrome() -> NTSCFilterSettings {
+        let mut settings = NTSCFilterSettings::composite();
+        settings.picture.saturation = 0.0;
+        settings
+    }
+
+    pub fn preset(name: &str) -> Option<NTSCFilterSettings> {
+        match name {
+            "composite" => Some(NTSCFilterSettings::composite()),
+            "svideo" => Some(NTSCFilterSettings::svideo()),
+            "rgb" => Some(NTSCFilterSettings::rgb()),
+            "monochrome" => Some(NTSCFilterSettings::monochrome()),
+            _ => None,
+        }
+    }
+}
+
+pub struct NTSCFilter {
+    settings: NTSCFilterSettings,
+
+    // Signal levels for each colour index at each phase, and their average.
+    signal: Vec<[f32; PHASES]>,
+    luma: Vec<f32>,
+    phase_vectors: [(f32, f32); PHASES],
+    gamma: Vec<u8>,
+
+    // The subcarrier's phase at the start of the frame.
+    frame_phase: usize,
+
+    output: Vec<u8>,
+
+    // Per line working space: the signal, and running sums for box filtering it.
+    samples: Vec<f32>,
+    exact_luma: Vec<f32>,
+    luma_sums: Vec<f32>,
+    exact_sums: Vec<f32>,
+    i_sums: Vec<f32>,
+    q_sums: Vec<f32>,
+}
+
+impl NTSCFilter {
+    pub fn new(settings: NTSCFilterSettings) -> NTSCFilter {
+        let samples = SAMPLES_PER_LINE + 2 * PADDING;
+        let mut filter = NTSCFilter {
+            settings,
+            signal: vec![],
+            luma: vec![],
+            phase_vectors: [(0.0, 0.0); PHASES],
+            gamma: vec![],
+            frame_phase: 0,
+            output: vec![0; NTSC_WIDTH * NTSC_HEIGHT * 3],
+            samples: vec![0.0; samples],
+            exact_luma: vec![0.0; samples],
+            luma_sums: vec![0.0; samples + 1],
+            exact_sums: vec![0.0; samples + 1],
+            i_sums: vec![0.0; samples + 1],
+            q_sums: vec![0.0; samples + 1],
+        };
+        filter.set_settings(settings);
+        filter
+    }
+
+    pub fn settings(&self) -> NTSCFilterSettings {
+        self.settings
+    }
+
+    pub fn set_settings(&mut self, settings: NTSCFilterSettings) {
+        self.settings = settings;
+
+        self.signal = (0..512)
+            .map(|index| {
+                let mut levels = [0.0; PHASES];
+                for (phase, level) in levels.iter_mut().enumerate() {
+                    *level = palette::signal_level(index, phase as u16);
+                }
+                levels
+            })
+            .collect();
+        self.luma = self
+            .signal
+            .iter()
+            .map(|levels| levels.iter().sum::<f32>() / PHASES as f32)
+            .collect();
+
+        for (phase, vector) in self.phase_vectors.iter_mut().enumerate() {
+            *vector = palette::phase_vector(phase as u16, &settings.picture);
+        }
+
+        self.gamma = (0..GAMMA_TABLE_SIZE)
+            .map(|ix| {
+                let v = ix as f32 / (GAMMA_TABLE_SIZE - 1) as f32;
+                palette::gamma_correct(v, &settings.picture)
+            })
+            .collect();
+    }
+
+    pub fn width(&self) -> usize {
+        NTSC_WIDTH
+    }
+
+    pub fn height(&self) -> usize {
+        NTSC_HEIGHT
+    }
+
+    // The last complete frame, as RGB.
+    pub fn do_render<F: FnOnce(&[u8])>(&self, render: F) {
+        render(&self.output);
+    }
+
+    // Takes a 256x240 frame of colour indices, including emphasis bits as from Colour::index.
+    pub fn finish_frame(&mut self, indices: &[u16]) {
+        for y in 0..NTSC_HEIGHT {
+            // Each line is 341 pixels, so starts 4 phases on from the last.
+            let phase = (self.frame_phase + y * 4) % PHASES;
+            self.filter_line(&indices[y * 256..(y + 1) * 256], y, phase);
+        }
+
+        if self.settings.dot_crawl {
+            self.frame_phase = (self.frame_phase + 4) % PHASES;
+        }
+    }
+
+    fn filter_line(&mut self, line: &[u16], y: usize, line_phase: usize) {
+        let settings = self.settings;
+        let len = self.samples.len();
+        let sample_phase = |n: usize| (line_phase + PHASES * 3 + n - PADDING) % PHASES;
+
+        // Generate the signal, with black either side of the picture.
+        for n in PADDING..PADDING + SAMPLES_PER_LINE {
+            let index = line[(n - PADDING) / SAMPLES_PER_PIXEL] & 0x1FF;
+            self.samples[n] = self.signal[index as usize][sample_phase(n)];
+            self.exact_luma[n] = self.luma[index as usize];
+        }
+        running_sums(&self.samples, &mut self.luma_sums);
+        running_sums(&self.exact_luma, &mut self.exact_sums);
+
+        // Chroma is demodulated from what's left after removing luma.
+        // A TV can only estimate luma by filtering the signal, and the error shows up as colour
+        // fringes at brightness edges. Without fringing, the exact luma is removed instead.
+        self.i_sums[0] = 0.0;
+        self.q_sums[0] = 0.0;
+        for n in 0..len {
+            let filtered = box_filter(&self.luma_sums, n as f32 + 0.5, PHASES);
+            let luma =
+                settings.fringing * filtered + (1.0 - settings.fringing) * self.exact_luma[n];
+            let chroma = self.samples[n] - luma;
+            let (cos, sin) = self.phase_vectors[sample_phase(n)];
+            self.i_sums[n + 1] = self.i_sums[n] + chroma * cos;
+            self.q_sums[n + 1] = self.q_sums[n] + chroma * sin;
+        }
+
+        // Chroma filters must be whole cycles, or the subcarrier beats against itself.
+        let chroma_width = PHASES * (1 + (settings.bleed * 2.0).round() as usize);
+
+        let scale = SAMPLES_PER_LINE as f32 / NTSC_WIDTH as f32;
+        for x in 0..NTSC_WIDTH {
+            let centre = PADDING as f32 + (x as f32 + 0.5) * scale;
+
+            // Filtering out the subcarrier leaves some of the colour signal in at edges.
+            let filtered = box_filter(&self.luma_sums, centre, PHASES);
+            let exact = box_filter(&self.exact_sums, centre, PHASES / 2);
+            let wide = box_filter(&self.exact_sums, centre, PHASES * 2);
+            let luma = settings.artifacts * filtered
+                + (1.0 - settings.artifacts) * exact
+                + settings.sharpness * (exact - wide);
+
+            let i = box_filter(&self.i_sums, centre, chroma_width);
+            let q = box_filter(&self.q_sums, centre, chroma_width);
+
+            let rgb = palette::yiq_to_rgb(luma, i, q, &settings.picture);
+            let offset = (y * NTSC_WIDTH + x) * 3;
+            for (ix, v) in rgb.iter().enumerate() {
+                let entry = (v * (GAMMA_TABLE_SIZE - 1) as f32).round();
+                let entry = entry.clamp(0.0, (GAMMA_TABLE_SIZE - 1) as f32) as usize;
+                self.output[offset + ix] = self.gamma[entry];
+            }
+        }
+    }
+}
+
+fn running_sums(values: &[f32], sums: &mut [f32]) {
+    sums[0] = 0.0;
+    for (ix, v) in values.iter().enumerate() {
+        sums[ix + 1] = sums[ix] + v;
+    }
+}
+
+// The average of the width samples centred on a position, given their running sums.
+fn box_filter(sums: &[f32], centre: f32, width: usize) -> f32 {
+    let last = sums.len() - 1;
+    let start = ((centre - width as f32 / 2.0).round().max(0.0) as usize).min(last);
+    let end = (start + width).min(last);
+    (sums[end] - sums[start]) / width as f32
+}
+
+#[cfg(test)]
+mod test {
+    use crate::emulator::io::ntsc::{NTSCFilter, NTSCFilterSettings, NTSC_WIDTH};
+    use crate::emulator::io::palette::{NTSCSettings, Palette};
+
+    fn render(filter: &mut NTSCFilter, index: impl Fn(usize, usize) -> u16) -> Vec<u8> {
+        let mut indices = vec![];
+        for y in 0..240 {
+            for x in 0..256 {
+                indices.push(index(x, y));
+            }
+        }
+        filter.finish_frame(&indices);
+
+        let mut frame = vec![];
+        filter.do_render(|data| frame.extend_from_slice(data));
+        frame
+    }
+
+    fn pixel(frame: &[u8], x: usize, y: usize) -> (u8, u8, u8) {
+        let offset = (y * NTSC_WIDTH + x) * 3;
+        (frame[offset], frame[offset + 1], frame[offset + 2])
+    }
+
+    fn distance(a: (u8, u8, u8), b: (u8, u8, u8)) -> i32 {
+        (a.0 as i32 - b.0 as i32).abs()
+            + (a.1 as i32 - b.1 as i32).abs()
+            + (a.2 as i32 - b.2 as i32).abs()
+    }
+
+    #[test]
+    fn test_flat_colour_matches_palette() {
+        let palette = Palette::generate(&NTSCSettings::new());
+        for &settings in &[NTSCFilterSettings::composite(), NTSCFilterSettings::rgb()] {
+            let mut filter = NTSCFilter::new(settings);
+            for &index in &[0x0F, 0x16, 0x2A, 0x30, 0x92] {
+                let frame = render(&mut filter, |_, _| index);
+                println!(
+                    "{:x} {:?} {:?}",
+                    index,
+                    pixel(&frame, 300, 120),
+                    palette.convert(index)
+                );
+                assert!(distance(pixel(&frame, 300, 120), palette.convert(index)) <= 6);
+            }
+        }
+    }
+
+    #[test]
+    fn test_fringing() {
+        // Alternating black and white columns make colour on a composite signal, but not RGB.
+        let stripes = |x: usize, _| if x % 2 == 1 { 0x30 } else { 0x0F };
+
+        let mut rgb = NTSCFilter::new(NTSCFilterSettings::rgb());
+        let (r, g, b) = pixel(&render(&mut rgb, stripes), 300, 120);
+        assert!(r.max(g).max(b) - r.min(g).min(b) <= 2);
+
+        let mut composite = NTSCFilter::new(NTSCFilterSettings::composite());
+        let (r, g, b) = pixel(&render(&mut composite, stripes), 300, 120);
+        assert!(r.max(g).max(b) - r.min(g).min(b) > 10);
+    }
+
+    #[test]
+    fn test_dot_crawl() {
+        let stripes = |x: usize, _| if x % 2 == 1 { 0x30 } else { 0x0F };
+
+        let mut filter = NTSCFilter::new(NTSCFilterSettings::composite());
+        let first = render(&mut filter, stripes);
+        let second = render(&mut filter, stripes);
+        assert_ne!(first, second);
+
+        let mut settings = NTSCFilterSettings::composite();
+        settings.dot_crawl = false;
+        let mut filter = NTSCFilter::new(settings);
+        let first = render(&mut filter, stripes);
+        let second = render(&mut filter, stripes);
+        assert_eq!(first, second);
+    }
+}
@@ -1,3 +1,7 @@
+use std::f32::consts::PI;
+use std::fs;
+use std::path::Path;
+
 use crate::emulator::ppu::Colour;
 
 // Palette generated by https://bisqwit.iki.fi/utils/nespalette.php
@@ -100,19 +104,252 @@ pub const PALETTE: [u8; 1536] = [
     0x61, 0x65, 0x86, 0x72, 0x66, 0x82, 0x86, 0x5e, 0x5e, 0x5e, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
 ];
 
+// Converts a colour with the default palette.
 pub fn convert_colour(c: Colour) -> (u8, u8, u8) {
-    let mut byte = c.as_byte() as usize;
-    if c.em_r {
-        byte |= 0x40
-    };
-    if c.em_g {
-        byte |= 0x80
-    };
-    if c.em_b {
-        byte |= 0x100
-    };
-    let r = PALETTE[byte * 3];
-    let g = PALETTE[byte * 3 + 1];
-    let b = PALETTE[byte * 3 + 2];
+    convert_index(c.index())
+}
+
+// Converts a colour index including emphasis bits, see Colour::index.
+pub fn convert_index(index: u16) -> (u8, u8, u8) {
+    let index = (index & 0x1FF) as usize;
+    let r = PALETTE[index * 3];
+    let g = PALETTE[index * 3 + 1];
+    let b = PALETTE[index * 3 + 2];
     (r, g, b)
 }
+
+// How much emphasis darkens the other colour channels.
+const EMPHASIS_ATTENUATION: f32 = 0.746;
+
+// RGB for all 512 combinations of colour and emphasis bits.
+#[derive(Clone)]
+pub struct Palette {
+    colours: Vec<u8>,
+}
+
+impl Default for Palette {
+    fn default() -> Palette {
+        Palette::new()
+    }
+}
+
+impl Palette {
+    pub fn new() -> Palette {
+        Palette {
+            colours: PALETTE.to_vec(),
+        }
+    }
+
+    // Reads a .pal file, either 64 colours, or 512 with every combination of emphasis bits.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Palette, String> {
+        let data = fs::read(path).map_err(|e| e.to_string())?;
+        Palette::from_bytes(&data)
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Palette, String> {
+        match data.len() {
+            1536 => Ok(Palette {
+                colours: data.to_vec(),
+            }),
+            192 => {
+                // Approximate emphasis by dimming the channels which aren't emphasised.
+                let mut colours = Vec::with_capacity(1536);
+                for emphasis in 0..8 {
+                    for colour in data.chunks(3) {
+                        for (channel, &value) in colour.iter().enumerate() {
+                            let dimmed = emphasis != 0 && emphasis & (1 << channel) == 0;
+                            colours.push(if dimmed {
+                                (value as f32 * EMPHASIS_ATTENUATION) as u8
+                            } else {
+                                value
+                            });
+                        }
+                    }
+                }
+                Ok(Palette { colours })
+            }
+            size => Err(format!(
+                "Palette files should be 192 or 1536 bytes, not {}",
+                size
+            )),
+        }
+    }
+
+    // Models the colours as decoded from the NTSC signal the PPU generates.
+    // Based on http://wiki.nesdev.com/w/index.php/NTSC_video
+    pub fn generate(settings: &NTSCSettings) -> Palette {
+        let mut colours = Vec::with_capacity(1536);
+        for index in 0..512 {
+            let (r, g, b) = generate_colour(index, settings);
+            colours.extend_from_slice(&[r, g, b]);
+        }
+        Palette { colours }
+    }
+
+    pub fn convert(&self, index: u16) -> (u8, u8, u8) {
+        let index = (index & 0x1FF) as usize;
+        (
+            self.colours[index * 3],
+            self.colours[index * 3 + 1],
+            self.colours[index * 3 + 2],
+        )
+    }
+
+    // The palette as a 1536 byte .pal file.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.colours
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NTSCSettings {
+    // In degrees.
+    pub hue: f32,
+    pub saturation: f32,
+    pub contrast: f32,
+    // Added to the luma, so 0.0 is unchanged.
+    pub brightness: f32,
+    // The display's gamma, compared to the 2.2 NTSC assumes.
+    pub gamma: f32,
+}
+
+impl Default for NTSCSettings {
+    fn default() -> NTSCSettings {
+        NTSCSettings::new()
+    }
+}
+
+impl NTSCSettings {
+    pub fn new() -> NTSCSettings {
+        NTSCSettings {
+            hue: 0.0,
+            saturation: 1.0,
+            contrast: 1.0,
+            brightness: 0.0,
+            gamma: 1.8,
+        }
+    }
+}
+
+// Signal levels for the 4 luma levels, low then high, relative to black and white.
+const SIGNAL_LOW: [f32; 4] = [0.350, 0.518, 0.962, 1.550];
+const SIGNAL_HIGH: [f32; 4] = [1.094, 1.506, 1.962, 1.962];
+const SIGNAL_BLACK: f32 = 0.518;
+const SIGNAL_WHITE: f32 = 1.962;
+
+fn generate_colour(index: u16, settings: &NTSCSettings) -> (u8, u8, u8) {
+    let (mut y, mut i, mut q) = (0.0, 0.0, 0.0);
+    for phase in 0..12 {
+        let signal = signal_level(index, phase) / 12.0;
+        let (cos, sin) = phase_vector(phase, settings);
+        y += signal;
+        i += signal * cos;
+        q += signal * sin;
+    }
+
+    let [r, g, b] = yiq_to_rgb(y, i, q, settings);
+    (
+        gamma_correct(r, settings),
+        gamma_correct(g, settings),
+        gamma_correct(b, settings),
+    )
+}
+
+// The PPU's output for a colour index at one of the 12 phases of the colour subcarrier,
+// where 0.0 is black and 1.0 is white.
+pub(crate) fn signal_level(index: u16, phase: u16) -> f32 {
+    let colour = index & 0x0F;
+    let level = if colour > 13 { 1 } else { (index >> 4) & 0x03 };
+    let emphasis = index >> 6;
+
+    // The signal is a square wave, high for half of the 12 phases.
+    // Colour 0 is always high, and colours 13-15 always low.
+    let (mut low, mut high) = (SIGNAL_LOW[level as usize], SIGNAL_HIGH[level as usize]);
+    if colour == 0 {
+        low = high;
+    } else if colour > 12 {
+        high = low;
+    }
+    let in_phase = |colour: u16| (colour + phase) % 12 < 6;
+
+    let mut signal = if in_phase(colour) { high } else { low };
+    if (emphasis & 0x1 != 0 && in_phase(0))
+        || (emphasis & 0x2 != 0 && in_phase(4))
+        || (emphasis & 0x4 != 0 && in_phase(8))
+    {
+        signal *= EMPHASIS_ATTENUATION;
+    }
+
+    (signal - SIGNAL_BLACK) / (SIGNAL_WHITE - SIGNAL_BLACK)
+}
+
+// The (cos, sin) of the subcarrier at a phase, for demodulating the I and Q components.
+pub(crate) fn phase_vector(phase: u16, settings: &NTSCSettings) -> (f32, f32) {
+    let angle = PI * (phase as f32 + 4.0) / 6.0 + settings.hue.to_radians();
+    (angle.cos(), angle.sin())
+}
+
+// Applies the picture settings, giving linear RGB from 0.0 to 1.0 before gamma correction.
+pub(crate) fn yiq_to_rgb(y: f32, i: f32, q: f32, settings: &NTSCSettings) -> [f32; 3] {
+    let y = y * settings.contrast + settings.brightness;
+    let i = i * settings.saturation * settings.contrast;
+    let q = q * settings.saturation * settings.contrast;
+    [
+        y + 0.946_882 * i + 0.623_557 * q,
+        y - 0.274_788 * i - 0.635_691 * q,
+        y - 1.108_545 * i + 1.709_007 * q,
+    ]
+}
+
+pub(crate) fn gamma_correct(v: f32, settings: &NTSCSettings) -> u8 {
+    let v = if v <= 0.0 {
+        0.0
+    } else {
+        v.powf(2.2 / settings.gamma)
+    };
+    (v * 255.0).clamp(0.0, 255.0) as u8
+}
+
+#[cfg(test)]
+mod test {
+    use crate::emulator::io::palette::{NTSCSettings, Palette, PALETTE};
+
+    #[test]
+    fn test_generate_default_palette() {
+        // The default settings should reproduce the built in palette.
+        let palette = Palette::generate(&NTSCSettings::new());
+        for (generated, expected) in palette.as_bytes().iter().zip(PALETTE.iter()) {
+            assert!((*generated as i32 - *expected as i32).abs() <= 1);
+        }
+    }
+
+    #[test]
+    fn test_palette_settings() {
+        let mut settings = NTSCSettings::new();
+        settings.saturation = 0.0;
+        let grey = Palette::generate(&settings);
+        let (r, g, b) = grey.convert(0x16);
+        assert_eq!((r, r), (g, b));
+
+        settings = NTSCSettings::new();
+        settings.brightness = 0.2;
+        let bright = Palette::generate(&settings);
+        assert!(bright.convert(0x00).0 > Palette::new().convert(0x00).0);
+    }
+
+    #[test]
+    fn test_load_palette() {
+        let mut data = vec![0; 192];
+        data[3..6].copy_from_slice(&[100, 150, 200]);
+        let palette = Palette::from_bytes(&data).unwrap();
+        assert_eq!(palette.convert(0x01), (100, 150, 200));
+
+        // Red emphasis dims green and blue.
+        assert_eq!(palette.convert(0x41), (100, 111, 149));
+
+        let full = Palette::from_bytes(&PALETTE).unwrap();
+        assert_eq!(full.convert(0x116), Palette::new().convert(0x116));
+
+        assert!(Palette::from_bytes(&[0; 100]).is_err());
+    }
+}
@@ -0,0 +1,431 @@
+// Software upscaling of RGB frames, for pixel art filters which a GPU texture stretch can't do.
+// Scalers work on any frame size, so can be applied after the NTSC filter or an HD pack.
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Scaler {
+    None,
+    Nearest2x,
+    Nearest3x,
+    Nearest4x,
+    // AdvMAME's Scale2x/Scale3x, which round off diagonal edges.
+    Scale2x,
+    Scale3x,
+    // Blends the corners and sides of a pixel into neighbours which differ from it, telling
+    // colours apart with hqx's YUV thresholds. This isn't hq2x/hq3x, which pick from 256
+    // interpolation patterns, but a few simpler rules.
+    Smooth2x,
+    Smooth3x,
+    // Hyllian's xBR (level 1), which finds edges from a 5x5 neighbourhood.
+    XBR2x,
+    // Scanlines and an RGB aperture grille, 3 output pixels per input pixel.
+    CRT,
+}
+
+pub const SCALERS: [Scaler; 10] = [
+    Scaler::None,
+    Scaler::Nearest2x,
+    Scaler::Nearest3x,
+    Scaler::Nearest4x,
+    Scaler::Scale2x,
+    Scaler::Scale3x,
+    Scaler::Smooth2x,
+    Scaler::Smooth3x,
+    Scaler::XBR2x,
+    Scaler::CRT,
+];
+
+type Pixel = [u8; 3];
+
+impl Scaler {
+    pub fn name(self) -> &'static str {
+        match self {
+            Scaler::None => "none",
+            Scaler::Nearest2x => "nearest2x",
+            Scaler::Nearest3x => "nearest3x",
+            Scaler::Nearest4x => "nearest4x",
+            Scaler::Scale2x => "scale2x",
+            Scaler::Scale3x => "scale3x",
+            Scaler::Smooth2x => "smooth2x",
+            Scaler::Smooth3x => "smooth3x",
+            Scaler::XBR2x => "xbr2x",
+            Scaler::CRT => "crt",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Scaler> {
+        SCALERS.iter().cloned().find(|s| s.name() == name)
+    }
+
+    // The next scaler in SCALERS, wrapping around.
+    pub fn next(self) -> Scaler {
+        let ix = SCALERS.iter().position(|&s| s == self).unwrap_or(0);
+        SCALERS[(ix + 1) % SCALERS.len()]
+    }
+
+    pub fn factor(self) -> usize {
+        match self {
+            Scaler::None => 1,
+            Scaler::Nearest2x | Scaler::Scale2x | Scaler::Smooth2x | Scaler::XBR2x => 2,
+            Scaler::Nearest3x | Scaler::Scale3x | Scaler::Smooth3x | Scaler::CRT => 3,
+            Scaler::Nearest4x => 4,
+        }
+    }
+
+    pub fn output_size(self, width: usize, height: usize) -> (usize, usize) {
+        (width * self.factor(), height * self.factor())
+    }
+
+    // Scales an RGB frame, replacing the contents of output.
+    pub fn apply(self, input: &[u8], width: usize, height: usize, output: &mut Vec<u8>) {
+        let frame = Frame {
+            data: input,
+            width,
+            height,
+        };
+        let factor = self.factor();
+        output.clear();
+        output.resize(width * height * factor * factor * 3, 0);
+
+        let mut block = [[0; 3]; 16];
+        for y in 0..height {
+            for x in 0..width {
+                let block = &mut block[..factor * factor];
+                match self {
+                    Scaler::None | Scaler::Nearest2x | Scaler::Nearest3x | Scaler::Nearest4x => {
+                        block.iter_mut().for_each(|p| *p = frame.get(x, y, 0, 0));
+                    }
+                    Scaler::Scale2x => scale2x(&frame, x, y, block),
+                    Scaler::Scale3x => scale3x(&frame, x, y, block),
+                    Scaler::Smooth2x => smooth2x(&frame, x, y, block),
+                    Scaler::Smooth3x => smooth3x(&frame, x, y, block),
+                    Scaler::XBR2x => xbr2x(&frame, x, y, block),
+                    Scaler::CRT => crt(&frame, x, y, block),
+                }
+
+                let out_width = width * factor;
+                for (ix, p) in block.iter().enumerate() {
+                    let ox = x * factor + ix % factor;
+                    let oy = y * factor + ix / factor;
+                    let offset = (oy * out_width + ox) * 3;
+                    output[offset..offset + 3].copy_from_slice(p);
+                }
+            }
+        }
+    }
+}
+
+struct Frame<'a> {
+    data: &'a [u8],
+    width: usize,
+    height: usize,
+}
+
+impl<'a> Frame<'a> {
+    // The pixel at an offset from (x, y), clamped to the edges of the frame.
+    fn get(&self, x: usize, y: usize, dx: isize, dy: isize) -> Pixel {
+        let x = (x as isize + dx).clamp(0, self.width as isize - 1) as usize;
+        let y = (y as isize + dy).clamp(0, self.height as isize - 1) as usize;
+        let offset = (y * self.width + x) * 3;
+        [
+            self.data[offset],
+            self.data[offset + 1],
+            self.data[offset + 2],
+        ]
+    }
+
+    // The 3x3 neighbourhood, in rows: A B C / D E F / G H I.
+    fn neighbours(&self, x: usize, y: usize) -> [Pixel; 9] {
+        let mut result = [[0; 3]; 9];
+        for (ix, p) in result.iter_mut().enumerate() {
+            *p = self.get(x, y, (ix % 3) as isize - 1, (ix / 3) as isize - 1);
+        }
+        result
+    }
+}
+
+fn scale2x(frame: &Frame, x: usize, y: usize, out: &mut [Pixel]) {
+    let [_, b, _, d, e, f, _, h, _] = frame.neighbours(x, y);
+    if b != h && d != f {
+        out[0] = if d == b { d } else { e };
+        out[1] = if b == f { f } else { e };
+        out[2] = if d == h { d } else { e };
+        out[3] = if h == f { f } else { e };
+    } else {
+        out.iter_mut().for_each(|p| *p = e);
+    }
+}
+
+fn scale3x(frame: &Frame, x: usize, y: usize, out: &mut [Pixel]) {
+    let [a, b, c, d, e, f, g, h, i] = frame.neighbours(x, y);
+    if b != h && d != f {
+        out[0] = if d == b { d } else { e };
+        out[1] = if (d == b && e != c) || (b == f && e != a) {
+            b
+        } else {
+            e
+        };
+        out[2] = if b == f { f } else { e };
+        out[3] = if (d == b && e != g) || (d == h && e != a) {
+            d
+        } else {
+            e
+        };
+        out[4] = e;
+        out[5] = if (b == f && e != i) || (h == f && e != c) {
+            f
+        } else {
+            e
+        };
+        out[6] = if d == h { d } else { e };
+        out[7] = if (d == h && e != i) || (h == f && e != g) {
+            h
+        } else {
+            e
+        };
+        out[8] = if h == f { f } else { e };
+    } else {
+        out.iter_mut().for_each(|p| *p = e);
+    }
+}
+
+fn yuv(p: Pixel) -> (i32, i32, i32) {
+    let (r, g, b) = (p[0] as i32, p[1] as i32, p[2] as i32);
+    let y = (r * 299 + g * 587 + b * 114) / 1000;
+    let u = (b - y) * 492 / 1000 + 128;
+    let v = (r - y) * 877 / 1000 + 128;
+    (y, u, v)
+}
+
+// The thresholds hqx uses to decide whether pixels are part of the same shape.
+fn similar(a: Pixel, b: Pixel) -> bool {
+    let (y1, u1, v1) = yuv(a);
+    let (y2, u2, v2) = yuv(b);
+    (y1 - y2).abs() <= 48 && (u1 - u2).abs() <= 7 && (v1 - v2).abs() <= 6
+}
+
+// Weighted average of pixels.
+fn blend(pixels: &[(Pixel, u32)]) -> Pixel {
+    let total: u32 = pixels.iter().map(|&(_, w)| w).sum();
+    let mut result = [0; 3];
+    for (ix, channel) in result.iter_mut().enumerate() {
+        let sum: u32 = pixels.iter().map(|&(p, w)| p[ix] as u32 * w).sum();
+        *channel = ((sum + total / 2) / total) as u8;
+    }
+    result
+}
+
+// The corner of the centre pixel e nearest to its neighbours a (horizontal), b (vertical)
+// and d (diagonal).
+fn smooth_corner(e: Pixel, a: Pixel, b: Pixel, d: Pixel) -> Pixel {
+    if similar(a, b) && !similar(e, a) && !similar(e, b) {
+        // An edge runs diagonally across the corner.
+        if similar(e, d) {
+            blend(&[(e, 6), (a, 1), (b, 1)])
+        } else {
+            blend(&[(e, 2), (a, 1), (b, 1)])
+        }
+    } else if !similar(e, d) {
+        blend(&[(e, 3), (d, 1)])
+    } else {
+        e
+    }
+}
+
+fn smooth_side(e: Pixel, n: Pixel) -> Pixel {
+    if similar(e, n) {
+        e
+    } else {
+        blend(&[(e, 7), (n, 1)])
+    }
+}
+
+fn smooth2x(frame: &Frame, x: usize, y: usize, out: &mut [Pixel]) {
+    let [a, b, c, d, e, f, g, h, i] = frame.neighbours(x, y);
+    out[0] = smooth_corner(e, d, b, a);
+    out[1] = smooth_corner(e, f, b, c);
+    out[2] = smooth_corner(e, d, h, g);
+    out[3] = smooth_corner(e, f, h, i);
+}
+
+fn smooth3x(frame: &Frame, x: usize, y: usize, out: &mut [Pixel]) {
+    let [a, b, c, d, e, f, g, h, i] = frame.neighbours(x, y);
+    out[0] = smooth_corner(e, d, b, a);
+    out[1] = smooth_side(e, b);
+    out[2] = smooth_corner(e, f, b, c);
+    out[3] = smooth_side(e, d);
+    out[4] = e;
+    out[5] = smooth_side(e, f);
+    out[6] = smooth_corner(e, d, h, g);
+    out[7] = smooth_side(e, h);
+    out[8] = smooth_corner(e, f, h, i);
+}
+
+fn distance(a: Pixel, b: Pixel) -> u32 {
+    let (y1, u1, v1) = yuv(a);
+    let (y2, u2, v2) = yuv(b);
+    (48 * (y1 - y2).abs() + 7 * (u1 - u2).abs() + 6 * (v1 - v2).abs()) as u32
+}
+
+fn xbr2x(frame: &Frame, x: usize, y: usize, out: &mut [Pixel]) {
+    // Each corner is the bottom right one, with the neighbourhood mirrored to match.
+    for (ix, &(sx, sy)) in [(-1, -1), (1, -1), (-1, 1), (1, 1)].iter().enumerate() {
+        let p = |dx: isize, dy: isize| frame.get(x, y, dx * sx, dy * sy);
+        out[ix] = xbr_corner(p);
+    }
+}
+
+// Decides whether an edge crosses the bottom right corner, by comparing the weighted differences
+// along both diagonals:
+//
+//         A1 B1 C1
+//      A0 A  B  C  C4
+//      D0 D  E  F  F4
+//      G0 G  H  I  I4
+//         G5 H5 I5
+fn xbr_corner<P: Fn(isize, isize) -> Pixel>(p: P) -> Pixel {
+    let e = p(0, 0);
+    let (b, c, d, f) = (p(0, -1), p(1, -1), p(-1, 0), p(1, 0));
+    let (g, h, i) = (p(-1, 1), p(0, 1), p(1, 1));
+    let (f4, i4, h5, i5) = (p(2, 0), p(2, 1), p(0, 2), p(1, 2));
+
+    if e == f || e == h {
+        return e;
+    }
+
+    let across =
+        distance(e, c) + distance(e, g) + distance(i, f4) + distance(i, h5) + 4 * distance(h, f);
+    let along =
+        distance(h, d) + distance(h, i5) + distance(f, i4) + distance(f, b) + 4 * distance(e, i);
+
+    if across < along {
+        let nearest = if distance(e, f) <= distance(e, h) {
+            f
+        } else {
+            h
+        };
+        blend(&[(e, 1), (nearest, 1)])
+    } else {
+        e
+    }
+}
+
+// Each column of a pixel favours one colour, and the last row is a darker gap between scanlines.
+const CRT_MASK: [[u32; 3]; 3] = [[255, 180, 180], [180, 255, 180], [180, 180, 255]];
+const CRT_SCANLINE: [u32; 3] = [255, 255, 140];
+
+fn crt(frame: &Frame, x: usize, y: usize, out: &mut [Pixel]) {
+    let e = frame.get(x, y, 0, 0);
+    // Soften horizontally a little, as the beam would.
+    let left = blend(&[(e, 3), (frame.get(x, y, -1, 0), 1)]);
+    let right = blend(&[(e, 3), (frame.get(x, y, 1, 0), 1)]);
+    let columns = [left, e, right];
+
+    for (ix, p) in out.iter_mut().enumerate() {
+        let (column, row) = (ix % 3, ix / 3);
+        for channel in 0..3 {
+            let value = columns[column][channel] as u32 * CRT_MASK[column][channel] / 255
+                * CRT_SCANLINE[row]
+                / 255;
+            // Make up some of the brightness the mask loses.
+            p[channel] = (value * 5 / 4).min(255) as u8;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::emulator::io::scale::{Scaler, SCALERS};
+
+    const BLACK: [u8; 3] = [0, 0, 0];
+    const WHITE: [u8; 3] = [255, 255, 255];
+
+    // A frame from rows of '#' (white) and '.' (black).
+    fn frame(rows: &[&str]) -> (Vec<u8>, usize, usize) {
+        let mut data = vec![];
+        for row in rows {
+            for c in row.chars() {
+                data.extend_from_slice(if c == '#' { &WHITE } else { &BLACK });
+            }
+        }
+        (data, rows[0].len(), rows.len())
+    }
+
+    fn pixel(data: &[u8], width: usize, x: usize, y: usize) -> [u8; 3] {
+        let offset = (y * width + x) * 3;
+        [data[offset], data[offset + 1], data[offset + 2]]
+    }
+
+    #[test]
+    fn test_output_sizes() {
+        let (input, width, height) = frame(&["#.", ".#"]);
+        for &scaler in SCALERS.iter() {
+            let mut output = vec![];
+            scaler.apply(&input, width, height, &mut output);
+            let (w, h) = scaler.output_size(width, height);
+            assert_eq!(output.len(), w * h * 3, "{:?}", scaler);
+            assert_eq!(Scaler::from_name(scaler.name()), Some(scaler));
+        }
+        assert_eq!(Scaler::CRT.next(), Scaler::None);
+    }
+
+    #[test]
+    fn test_nearest() {
+        let (input, width, height) = frame(&["#.", ".."]);
+        let mut output = vec![];
+        Scaler::Nearest3x.apply(&input, width, height, &mut output);
+        assert_eq!(pixel(&output, 6, 2, 2), WHITE);
+        assert_eq!(pixel(&output, 6, 3, 2), BLACK);
+    }
+
+    #[test]
+    fn test_scale2x_rounds_diagonals() {
+        let (input, width, height) = frame(&["#..", "##.", "..."]);
+        let mut output = vec![];
+        Scaler::Scale2x.apply(&input, width, height, &mut output);
+
+        // The black pixel in the corner of the L shape gets its inner corner filled in.
+        assert_eq!(pixel(&output, 6, 2, 1), WHITE);
+        assert_eq!(pixel(&output, 6, 2, 0), BLACK);
+        assert_eq!(pixel(&output, 6, 3, 1), BLACK);
+    }
+
+    #[test]
+    fn test_smoothing_scalers_keep_flat_areas() {
+        let (input, width, height) = frame(&["###", "###", "###"]);
+        for &scaler in &[
+            Scaler::Scale3x,
+            Scaler::Smooth2x,
+            Scaler::Smooth3x,
+            Scaler::XBR2x,
+        ] {
+            let mut output = vec![];
+            scaler.apply(&input, width, height, &mut output);
+            assert!(output.iter().all(|&b| b == 255), "{:?}", scaler);
+        }
+    }
+
+    #[test]
+    fn test_xbr_smooths_edges() {
+        let (input, width, height) = frame(&["....", ".#..", ".##.", "...."]);
+        let mut output = vec![];
+        Scaler::XBR2x.apply(&input, width, height, &mut output);
+
+        // The top right corner of (1, 1) is on the diagonal edge.
+        let p = pixel(&output, 8, 3, 2);
+        assert!(p[0] > 0 && p[0] < 255);
+
+        // Where it joins the pixel below, it's left alone.
+        assert_eq!(pixel(&output, 8, 2, 3), WHITE);
+    }
+
+    #[test]
+    fn test_crt_scanlines() {
+        let (input, width, height) = frame(&["#"]);
+        let mut output = vec![];
+        Scaler::CRT.apply(&input, width, height, &mut output);
+        let middle = pixel(&output, 3, 1, 1);
+        let gap = pixel(&output, 3, 1, 2);
+        assert!(gap[1] < middle[1]);
+        assert!(middle[1] > middle[0]);
+    }
+}
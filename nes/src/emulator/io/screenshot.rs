@@ -0,0 +1,102 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::emulator::io::Screen;
+use crate::emulator::ppu::debug::{PPUDebug, PPUDebugRender};
+
+// Encodes RGB data, 3 bytes a pixel, as a PNG.
+pub fn write_png<W: Write>(
+    writer: W,
+    width: usize,
+    height: usize,
+    data: &[u8],
+) -> Result<(), String> {
+    if data.len() != width * height * 3 {
+        return Err(format!(
+            "Expected {} bytes for a {}x{} image, got {}",
+            width * height * 3,
+            width,
+            height,
+            data.len()
+        ));
+    }
+
+    let mut encoder = png::Encoder::new(writer, width as u32, height as u32);
+    encoder.set_color(png::ColorType::RGB);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+    writer.write_image_data(data).map_err(|e| e.to_string())
+}
+
+pub fn save_png<P: AsRef<Path>>(
+    path: P,
+    width: usize,
+    height: usize,
+    data: &[u8],
+) -> Result<(), String> {
+    let file = File::create(path).map_err(|e| e.to_string())?;
+    write_png(BufWriter::new(file), width, height, data)
+}
+
+// Saves the last complete frame, after the NTSC filter or HD pack if either is in use.
+pub fn save_screen<P: AsRef<Path>>(screen: &Screen, path: P) -> Result<(), String> {
+    let mut result = Ok(());
+    screen.do_render_output(|data, width, height| result = save_png(path, width, height, data));
+    result
+}
+
+// Saves the PPU debug panels as one image, all at full size: the nametables on the left, with the
+// pattern tables, sprites and palettes stacked on their right.
+pub fn save_ppu_debug<P: AsRef<Path>>(render: &PPUDebugRender, path: P) -> Result<(), String> {
+    let left = PPUDebug::NAMETABLE_WIDTH;
+    let width = left + PPUDebug::PATTERN_WIDTH;
+    let height = PPUDebug::NAMETABLE_HEIGHT;
+    let mut data = vec![0; width * height * 3];
+
+    let panels: [(&[u8], usize, usize, usize); 4] = [
+        (&render.nametables, PPUDebug::NAMETABLE_WIDTH, 0, 0),
+        (&render.patterns, PPUDebug::PATTERN_WIDTH, left, 0),
+        (
+            &render.sprites,
+            PPUDebug::SPRITE_WIDTH,
+            left,
+            PPUDebug::PATTERN_HEIGHT,
+        ),
+        (
+            &render.palettes,
+            PPUDebug::PALETTE_WIDTH,
+            left,
+            PPUDebug::PATTERN_HEIGHT + PPUDebug::SPRITE_HEIGHT,
+        ),
+    ];
+    for &(panel, panel_width, x, y) in panels.iter() {
+        for (row, line) in panel.chunks(panel_width * 3).enumerate() {
+            let start = ((y + row) * width + x) * 3;
+            data[start..start + line.len()].copy_from_slice(line);
+        }
+    }
+
+    save_png(path, width, height, &data)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::emulator::io::screenshot::write_png;
+
+    #[test]
+    fn test_write_png() {
+        let data: Vec<u8> = (0..4 * 2 * 3).map(|b| b as u8).collect();
+        let mut png = vec![];
+        write_png(&mut png, 4, 2, &data).unwrap();
+
+        let decoder = png::Decoder::new(&png[..]);
+        let (info, mut reader) = decoder.read_info().unwrap();
+        assert_eq!((info.width, info.height), (4, 2));
+        let mut decoded = vec![0; info.buffer_size()];
+        reader.next_frame(&mut decoded).unwrap();
+        assert_eq!(decoded, data);
+
+        assert!(write_png(&mut vec![], 4, 3, &data).is_err());
+    }
+}
@@ -0,0 +1,199 @@
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::emulator::apu::log::{RegisterLog, SampleBlock};
+use crate::emulator::{NES_CPU_CLOCK_FACTOR, NES_MASTER_CLOCK_HZ};
+
+// VGM timestamps are always counted in samples at this rate.
+const VGM_SAMPLE_RATE: u64 = 44_100;
+
+// The NES APU chip was added to the format in version 1.61.
+const VGM_VERSION: u32 = 0x161;
+const HEADER_SIZE: usize = 0x100;
+
+const CMD_WAIT: u8 = 0x61;
+const CMD_WAIT_NTSC_FRAME: u8 = 0x62;
+const CMD_WAIT_PAL_FRAME: u8 = 0x63;
+const CMD_END: u8 = 0x66;
+const CMD_DATA_BLOCK: u8 = 0x67;
+const CMD_WAIT_SHORT: u8 = 0x70;
+const CMD_NES_APU_WRITE: u8 = 0xB4;
+
+const DATA_BLOCK_NES_APU_RAM: u8 = 0xC2;
+
+// Writes a register log as a VGM file for the NES APU.
+// See https://vgmrips.net/wiki/VGM_Specification
+pub fn save_vgm<P: AsRef<Path>>(path: P, log: &RegisterLog) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    write_vgm(&mut file, log)?;
+    file.flush()
+}
+
+pub fn write_vgm<W: Write>(writer: &mut W, log: &RegisterLog) -> io::Result<()> {
+    let mut data = vec![];
+    let mut samples = 0;
+
+    // DMC sample data is loaded into the player's memory as it changes, ahead of any writes made
+    // at the same time, which might start playing it.
+    let mut blocks = log.sample_blocks().iter().peekable();
+    for write in log.writes() {
+        while let Some(block) = blocks.next_if(|block| block.cycle <= write.cycle) {
+            write_wait_until(&mut data, &mut samples, block.cycle);
+            write_sample_block(&mut data, block);
+        }
+
+        let register = match register_index(write.address) {
+            None => continue,
+            Some(register) => register,
+        };
+
+        write_wait_until(&mut data, &mut samples, write.cycle);
+        data.push(CMD_NES_APU_WRITE);
+        data.push(register);
+        data.push(write.value);
+    }
+    for block in blocks {
+        write_wait_until(&mut data, &mut samples, block.cycle);
+        write_sample_block(&mut data, block);
+    }
+
+    let total_samples = cycles_to_samples(log.duration_cycles());
+    write_wait(&mut data, total_samples - samples);
+    data.push(CMD_END);
+
+    let mut header = [0; HEADER_SIZE];
+    header[0x00..0x04].copy_from_slice(b"Vgm ");
+    header[0x04..0x08].copy_from_slice(&((HEADER_SIZE + data.len() - 0x04) as u32).to_le_bytes());
+    header[0x08..0x0C].copy_from_slice(&VGM_VERSION.to_le_bytes());
+    header[0x18..0x1C].copy_from_slice(&(total_samples as u32).to_le_bytes());
+    header[0x24..0x28].copy_from_slice(&60u32.to_le_bytes());
+    // Offsets are relative to the field they're stored in.
+    header[0x34..0x38].copy_from_slice(&((HEADER_SIZE - 0x34) as u32).to_le_bytes());
+    let cpu_hz = NES_MASTER_CLOCK_HZ / (NES_CPU_CLOCK_FACTOR as u64);
+    header[0x84..0x88].copy_from_slice(&(cpu_hz as u32).to_le_bytes());
+
+    writer.write_all(&header)?;
+    writer.write_all(&data)
+}
+
+// VGM registers 0x00-0x1F are $4000-$401F, 0x20-0x3E are the FDS at $4080-$409E,
+// and 0x3F is $4023. Other expansion audio can't be represented, though as no expansion audio is
+// emulated, the log only has the 2A03's registers anyway.
+fn register_index(address: u16) -> Option<u8> {
+    match address {
+        0x4000..=0x401F => Some((address - 0x4000) as u8),
+        0x4080..=0x409E => Some((address - 0x4080 + 0x20) as u8),
+        0x4023 => Some(0x3F),
+        _ => None,
+    }
+}
+
+fn cycles_to_samples(cycles: u64) -> u64 {
+    cycles * (NES_CPU_CLOCK_FACTOR as u64) * VGM_SAMPLE_RATE / NES_MASTER_CLOCK_HZ
+}
+
+// A data block writing sample data into the player's memory.
+fn write_sample_block(data: &mut Vec<u8>, block: &SampleBlock) {
+    data.push(CMD_DATA_BLOCK);
+    data.push(CMD_END);
+    data.push(DATA_BLOCK_NES_APU_RAM);
+    data.extend_from_slice(&(block.data.len() as u32 + 2).to_le_bytes());
+    data.extend_from_slice(&block.address.to_le_bytes());
+    data.extend_from_slice(&block.data);
+}
+
+// Waits from the current position, in samples, until a cycle of the log.
+fn write_wait_until(data: &mut Vec<u8>, samples: &mut u64, cycle: u64) {
+    let target = cycles_to_samples(cycle);
+    write_wait(data, target - *samples);
+    *samples = target;
+}
+
+fn write_wait(data: &mut Vec<u8>, samples: u64) {
+    let mut remaining = samples;
+    while remaining > 0 {
+        match remaining {
+            1..=16 => {
+                data.push(CMD_WAIT_SHORT + (remaining - 1) as u8);
+                remaining = 0;
+            }
+            735 => {
+                data.push(CMD_WAIT_NTSC_FRAME);
+                remaining = 0;
+            }
+            882 => {
+                data.push(CMD_WAIT_PAL_FRAME);
+                remaining = 0;
+            }
+            _ => {
+                let wait = remaining.min(0xFFFF);
+                data.push(CMD_WAIT);
+                data.extend_from_slice(&(wait as u16).to_le_bytes());
+                remaining -= wait;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::emulator::apu::log::RegisterLog;
+    use crate::emulator::io::vgm::write_vgm;
+
+    fn u32_at(data: &[u8], offset: usize) -> u32 {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(&data[offset..offset + 4]);
+        u32::from_le_bytes(bytes)
+    }
+
+    #[test]
+    fn test_vgm_export() {
+        // 29830 CPU cycles is 1/60th of a second, or exactly 735 samples.
+        let mut log = RegisterLog::new(0);
+        log.record(0, 0x4015, 0x01);
+        log.record(29830, 0x4000, 0xBF);
+        log.record(29830, 0x5000, 0xFF);
+        log.finish(29830 + 100);
+
+        let mut data = vec![];
+        write_vgm(&mut data, &log).unwrap();
+
+        assert_eq!(&data[0..4], b"Vgm ");
+        assert_eq!(u32_at(&data, 0x04) as usize, data.len() - 4);
+        assert_eq!(u32_at(&data, 0x08), 0x161);
+        assert_eq!(u32_at(&data, 0x34), 0xCC);
+        assert_eq!(u32_at(&data, 0x84), 1_789_772);
+
+        // The unsupported $5000 write is dropped.
+        assert_eq!(
+            &data[0x100..],
+            &[0xB4, 0x15, 0x01, 0x62, 0xB4, 0x00, 0xBF, 0x71, 0x66]
+        );
+        assert_eq!(u32_at(&data, 0x18), 735 + 2);
+    }
+
+    #[test]
+    fn test_vgm_sample_data() {
+        let mut log = RegisterLog::new(0);
+        log.record_sample_data(0, &[(0xC000, 0xAA), (0xC001, 0x55)]);
+        log.record(0, 0x4015, 0x10);
+        // A bank switch changes the second byte, which is loaded again before it's next played.
+        log.record_sample_data(29830, &[(0xC000, 0xAA), (0xC001, 0x66)]);
+        log.record(29830, 0x4015, 0x10);
+
+        let mut data = vec![];
+        write_vgm(&mut data, &log).unwrap();
+
+        assert_eq!(
+            &data[0x100..],
+            &[
+                0x67, 0x66, 0xC2, 4, 0, 0, 0, 0x00, 0xC0, 0xAA, 0x55, // Both bytes.
+                0xB4, 0x15, 0x10, 0x62, // Play, and wait a frame.
+                0x67, 0x66, 0xC2, 3, 0, 0, 0, 0x01, 0xC0, 0x66, // The changed byte.
+                0xB4, 0x15, 0x10, 0x66,
+            ]
+        );
+    }
+}
@@ -0,0 +1,387 @@
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use crate::emulator::apu::{AudioOut, Channel};
+use crate::emulator::io::SimpleAudioOut;
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum SampleFormat {
+    Int16,
+    Float32,
+}
+
+impl SampleFormat {
+    fn bytes_per_sample(self) -> u16 {
+        match self {
+            SampleFormat::Int16 => 2,
+            SampleFormat::Float32 => 4,
+        }
+    }
+
+    fn format_tag(self) -> u16 {
+        match self {
+            SampleFormat::Int16 => 1,
+            SampleFormat::Float32 => 3,
+        }
+    }
+}
+
+// Writes a RIFF WAVE file.
+// The header is written up front with empty sizes, which are filled in by finish().
+// Float files also carry a fact chunk with the sample count, as non-PCM formats should.
+pub struct WavWriter<W: Write + Seek> {
+    writer: W,
+    format: SampleFormat,
+    block_align: u16,
+    header_bytes: u32,
+    data_bytes: u32,
+}
+
+impl WavWriter<BufWriter<File>> {
+    pub fn create<P: AsRef<Path>>(
+        path: P,
+        sample_rate: u32,
+        channels: u16,
+        format: SampleFormat,
+    ) -> io::Result<WavWriter<BufWriter<File>>> {
+        let file = BufWriter::new(File::create(path)?);
+        WavWriter::new(file, sample_rate, channels, format)
+    }
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    // Where the fact chunk's sample count is, in float files.
+    const FACT_SAMPLES_POS: u64 = 46;
+
+    pub fn new(
+        mut writer: W,
+        sample_rate: u32,
+        channels: u16,
+        format: SampleFormat,
+    ) -> io::Result<WavWriter<W>> {
+        let block_align = channels * format.bytes_per_sample();
+
+        writer.write_all(b"RIFF")?;
+        writer.write_all(&0u32.to_le_bytes())?;
+        writer.write_all(b"WAVE")?;
+
+        let fmt_bytes: u32 = match format {
+            SampleFormat::Int16 => 16,
+            SampleFormat::Float32 => 18,
+        };
+        writer.write_all(b"fmt ")?;
+        writer.write_all(&fmt_bytes.to_le_bytes())?;
+        writer.write_all(&format.format_tag().to_le_bytes())?;
+        writer.write_all(&channels.to_le_bytes())?;
+        writer.write_all(&sample_rate.to_le_bytes())?;
+        writer.write_all(&(sample_rate * (block_align as u32)).to_le_bytes())?;
+        writer.write_all(&block_align.to_le_bytes())?;
+        writer.write_all(&(format.bytes_per_sample() * 8).to_le_bytes())?;
+
+        let mut header_bytes = 20 + fmt_bytes + 8;
+        if format == SampleFormat::Float32 {
+            // No extra format information.
+            writer.write_all(&0u16.to_le_bytes())?;
+
+            writer.write_all(b"fact")?;
+            writer.write_all(&4u32.to_le_bytes())?;
+            writer.write_all(&0u32.to_le_bytes())?;
+            header_bytes += 12;
+        }
+
+        writer.write_all(b"data")?;
+        writer.write_all(&0u32.to_le_bytes())?;
+
+        Ok(WavWriter {
+            writer,
+            format,
+            block_align,
+            header_bytes,
+            data_bytes: 0,
+        })
+    }
+
+    // Samples are interleaved if there is more than one channel.
+    // RIFF sizes are 32 bits, so nothing more is written once the file would pass 4GiB.
+    pub fn write_samples(&mut self, samples: &[f32]) -> io::Result<()> {
+        let data_bytes = (samples.len() as u64)
+            .checked_mul(self.format.bytes_per_sample() as u64)
+            .and_then(|bytes| bytes.checked_add(self.data_bytes as u64))
+            .filter(|&bytes| bytes + (self.header_bytes as u64) <= u32::MAX as u64)
+            .ok_or_else(|| io::Error::other("WAV file has reached its maximum size"))?;
+
+        for &sample in samples {
+            match self.format {
+                SampleFormat::Int16 => {
+                    let sample = (sample.clamp(-1.0, 1.0) * (i16::MAX as f32)) as i16;
+                    self.writer.write_all(&sample.to_le_bytes())?;
+                }
+                SampleFormat::Float32 => {
+                    self.writer.write_all(&sample.to_le_bytes())?;
+                }
+            }
+        }
+
+        self.data_bytes = data_bytes as u32;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.writer.seek(SeekFrom::Start(4))?;
+        self.writer
+            .write_all(&(self.header_bytes - 8 + self.data_bytes).to_le_bytes())?;
+        if self.format == SampleFormat::Float32 {
+            let samples = self.data_bytes / (self.block_align as u32);
+            self.writer
+                .seek(SeekFrom::Start(WavWriter::<W>::FACT_SAMPLES_POS))?;
+            self.writer.write_all(&samples.to_le_bytes())?;
+        }
+        self.writer
+            .seek(SeekFrom::Start((self.header_bytes - 4) as u64))?;
+        self.writer.write_all(&self.data_bytes.to_le_bytes())?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+struct Recording {
+    mix: WavWriter<BufWriter<File>>,
+    tracks: Vec<(SimpleAudioOut, WavWriter<BufWriter<File>>)>,
+    error: Option<io::Error>,
+}
+
+// Wraps a SimpleAudioOut, teeing its output to disk while recording.
+// Per-channel tracks require the APU to have channel output enabled.
+pub struct WavRecorder {
+    output: SimpleAudioOut,
+    recording: Option<Recording>,
+}
+
+impl WavRecorder {
+    pub fn new(output: SimpleAudioOut) -> WavRecorder {
+        WavRecorder {
+            output,
+            recording: None,
+        }
+    }
+
+    pub fn output(&self) -> &SimpleAudioOut {
+        &self.output
+    }
+
+    pub fn output_mut(&mut self) -> &mut SimpleAudioOut {
+        &mut self.output
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    // Records the mixed output to `path`.
+    // If `per_channel` is set, each APU channel is also recorded to its own file alongside it,
+    // e.g. "song.wav" gets "song.pulse1.wav", "song.triangle.wav" etc.
+    // Any recording already running is finished first.
+    pub fn start_recording<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        format: SampleFormat,
+        per_channel: bool,
+    ) -> io::Result<()> {
+        self.stop_recording()?;
+
+        let path = path.as_ref();
+        let sample_rate = self.output.sample_rate() as u32;
+        let channels = self.output.channels() as u16;
+        let mix = WavWriter::create(path, sample_rate, channels, format)?;
+
+        let mut tracks = vec![];
+        if per_channel {
+            for &channel in Channel::ALL.iter() {
+                let track_path = WavRecorder::track_path(path, channel);
+                let writer = WavWriter::create(track_path, sample_rate, 1, format)?;
+                tracks.push((SimpleAudioOut::new(self.output.sample_rate()), writer));
+            }
+        }
+
+        self.recording = Some(Recording {
+            mix,
+            tracks,
+            error: None,
+        });
+        Ok(())
+    }
+
+    // Finishes all files, reporting the first error encountered while recording, if any.
+    pub fn stop_recording(&mut self) -> io::Result<()> {
+        let recording = match self.recording.take() {
+            None => return Ok(()),
+            Some(recording) => recording,
+        };
+
+        if let Some(err) = recording.error {
+            return Err(err);
+        }
+
+        recording.mix.finish()?;
+        for (_, writer) in recording.tracks {
+            writer.finish()?;
+        }
+        Ok(())
+    }
+
+    // See SimpleAudioOut::consume.
+    pub fn consume<F: FnOnce(&[f32])>(&mut self, master_cycles: u64, num_samples: u64, consume: F) {
+        let recording = match self.recording {
+            None => {
+                self.output.consume(master_cycles, num_samples, consume);
+                return;
+            }
+            Some(ref mut recording) => recording,
+        };
+
+        let mut result = Ok(());
+        let mix = &mut recording.mix;
+        self.output.consume(master_cycles, num_samples, |data| {
+            result = mix.write_samples(data);
+            consume(data);
+        });
+
+        for (output, writer) in recording.tracks.iter_mut() {
+            output.consume(master_cycles, num_samples, |data| {
+                if result.is_ok() {
+                    result = writer.write_samples(data);
+                }
+            });
+        }
+
+        // Keep the first error to report when recording stops.
+        if let Err(err) = result {
+            recording.error.get_or_insert(err);
+        }
+    }
+
+    fn track_path(path: &Path, channel: Channel) -> PathBuf {
+        let stem = path
+            .file_stem()
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let name = match channel {
+            Channel::Pulse1 => "pulse1",
+            Channel::Pulse2 => "pulse2",
+            Channel::Triangle => "triangle",
+            Channel::Noise => "noise",
+            Channel::DMC => "dmc",
+        };
+        path.with_file_name(format!("{}.{}.wav", stem, name))
+    }
+}
+
+impl AudioOut for WavRecorder {
+    fn emit(&mut self, sample: f32) {
+        self.output.emit(sample);
+    }
+
+    fn emit_stereo(&mut self, left: f32, right: f32) {
+        self.output.emit_stereo(left, right);
+    }
+
+    fn emit_channels(&mut self, levels: &[f32; 5]) {
+        if let Some(ref mut recording) = self.recording {
+            for ((output, _), &level) in recording.tracks.iter_mut().zip(levels.iter()) {
+                output.emit(level);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+    use std::fs;
+    use std::io::Cursor;
+
+    use crate::emulator::apu::AudioOut;
+    use crate::emulator::io::wav::{SampleFormat, WavRecorder, WavWriter};
+    use crate::emulator::io::SimpleAudioOut;
+
+    fn u32_at(data: &[u8], offset: usize) -> u32 {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(&data[offset..offset + 4]);
+        u32::from_le_bytes(bytes)
+    }
+
+    #[test]
+    fn test_int16_wav() {
+        let mut wav = WavWriter::new(Cursor::new(vec![]), 48_000, 2, SampleFormat::Int16).unwrap();
+        wav.write_samples(&[0.0, 1.0, -1.0, 2.0]).unwrap();
+        let data = wav.finish().unwrap().into_inner();
+
+        assert_eq!(&data[0..4], b"RIFF");
+        assert_eq!(u32_at(&data, 4), 36 + 8);
+        assert_eq!(&data[8..16], b"WAVEfmt ");
+        assert_eq!(&data[20..24], &[1, 0, 2, 0]);
+        assert_eq!(u32_at(&data, 24), 48_000);
+        assert_eq!(u32_at(&data, 28), 48_000 * 4);
+        assert_eq!(&data[32..36], &[4, 0, 16, 0]);
+        assert_eq!(&data[36..40], b"data");
+        assert_eq!(u32_at(&data, 40), 8);
+        assert_eq!(
+            &data[44..],
+            &[0x00, 0x00, 0xFF, 0x7F, 0x01, 0x80, 0xFF, 0x7F]
+        );
+    }
+
+    #[test]
+    fn test_float_wav() {
+        let mut wav =
+            WavWriter::new(Cursor::new(vec![]), 44_100, 1, SampleFormat::Float32).unwrap();
+        wav.write_samples(&[0.25, -0.5]).unwrap();
+        let data = wav.finish().unwrap().into_inner();
+
+        assert_eq!(u32_at(&data, 4), 50 + 8);
+        assert_eq!(u32_at(&data, 16), 18);
+        assert_eq!(&data[20..24], &[3, 0, 1, 0]);
+        assert_eq!(&data[32..38], &[4, 0, 32, 0, 0, 0]);
+        assert_eq!(&data[38..42], b"fact");
+        assert_eq!(u32_at(&data, 42), 4);
+        assert_eq!(u32_at(&data, 46), 2);
+        assert_eq!(&data[50..54], b"data");
+        assert_eq!(u32_at(&data, 54), 8);
+        assert_eq!(&data[58..62], &0.25f32.to_le_bytes());
+        assert_eq!(&data[62..66], &(-0.5f32).to_le_bytes());
+    }
+
+    #[test]
+    fn test_wav_size_limit() {
+        let mut wav = WavWriter::new(Cursor::new(vec![]), 48_000, 1, SampleFormat::Int16).unwrap();
+        wav.data_bytes = u32::MAX - 44 - 2;
+        assert!(wav.write_samples(&[0.0]).is_ok());
+        assert!(wav.write_samples(&[0.0]).is_err());
+        assert_eq!(wav.data_bytes, u32::MAX - 44);
+    }
+
+    #[test]
+    fn test_restart_recording_finishes_file() {
+        let first = env::temp_dir().join("wav_restart_first.wav");
+        let second = env::temp_dir().join("wav_restart_second.wav");
+        let mut recorder = WavRecorder::new(SimpleAudioOut::new(48_000.0));
+        recorder
+            .start_recording(&first, SampleFormat::Int16, false)
+            .unwrap();
+        for _ in 0..1000 {
+            recorder.emit(0.5);
+        }
+        recorder.consume(24_000, 50, |_| ());
+        recorder
+            .start_recording(&second, SampleFormat::Int16, false)
+            .unwrap();
+        recorder.stop_recording().unwrap();
+
+        let data = fs::read(&first).unwrap();
+        assert_eq!(u32_at(&data, 4) as usize, data.len() - 8);
+        assert_eq!(u32_at(&data, 40) as usize, data.len() - 44);
+        assert!(data.len() > 44);
+    }
+}
@@ -66,10 +66,9 @@ impl MMC3 {
             self.irq_flag = self.irq_enabled;
         }
     }
-}
 
-impl Mapper for MMC3 {
-    fn read_chr(&mut self, address: u16) -> u8 {
+    // Maps a PPU address to an offset into CHR memory.
+    fn chr_address(&self, address: u16) -> usize {
         let (bank_ix, bank_size) = match address {
             // CHR banks.
             0x0000..=0x03FF => {
@@ -132,7 +131,13 @@ impl Mapper for MMC3 {
         };
 
         let base = self.bank_registers[bank_ix];
-        let offset = (address % bank_size) as usize;
+        base + (address % bank_size) as usize
+    }
+}
+
+impl Mapper for MMC3 {
+    fn read_chr(&mut self, address: u16) -> u8 {
+        let chr_address = self.chr_address(address);
 
         // Update A12 and clock IRQ.
         let a12 = address & 0x1000 == 0x1000;
@@ -145,7 +150,12 @@ impl Mapper for MMC3 {
         }
         self.ppu_a12 = a12;
 
-        self.chr_mem.get(base + offset)
+        self.chr_mem.get(chr_address)
+    }
+
+    // Peeking doesn't count towards the A12 edges which clock the IRQ.
+    fn peek_chr(&mut self, address: u16) -> u8 {
+        self.chr_mem.get(self.chr_address(address))
     }
 
     fn write_chr(&mut self, address: u16, byte: u8) {
@@ -1,6 +1,7 @@
 use std::cell::RefCell;
 use std::rc::Rc;
 
+use crate::emulator::input::{InputDevice, Port, Unplugged};
 use crate::emulator::ppu::{MirrorMode, Mirrorer};
 use crate::emulator::state::{MapperState, MemoryState, SaveState};
 
@@ -8,6 +9,11 @@ const ADDRESS_SPACE: usize = 65536;
 
 pub trait Reader {
     fn read(&mut self, address: u16) -> u8;
+
+    // Read without any side effects, e.g. on mapper IRQ counters.
+    fn peek(&mut self, address: u16) -> u8 {
+        self.read(address)
+    }
 }
 
 pub trait Writer {
@@ -21,6 +27,10 @@ impl<M: Reader> Reader for Rc<RefCell<M>> {
     fn read(&mut self, address: u16) -> u8 {
         self.borrow_mut().read(address)
     }
+
+    fn peek(&mut self, address: u16) -> u8 {
+        self.borrow_mut().peek(address)
+    }
 }
 
 impl<M: Writer> Writer for Rc<RefCell<M>> {
@@ -32,24 +42,40 @@ impl<M: Writer> Writer for Rc<RefCell<M>> {
 pub struct IORegisters {
     apu: Box<dyn ReadWriter>,
     oamdma: Option<u8>,
-    joy1: Box<dyn ReadWriter>,
-    joy2: Box<dyn ReadWriter>,
+    ports: [Box<dyn InputDevice>; 2],
+    // The Famicom's expansion port, read alongside both controller ports.
+    expansion: Box<dyn InputDevice>,
 }
 
 impl IORegisters {
     pub fn new(
         apu: Box<dyn ReadWriter>,
-        joy1: Box<dyn ReadWriter>,
-        joy2: Box<dyn ReadWriter>,
+        port1: Box<dyn InputDevice>,
+        port2: Box<dyn InputDevice>,
     ) -> IORegisters {
         IORegisters {
             apu,
             oamdma: None,
-            joy1,
-            joy2,
+            ports: [port1, port2],
+            expansion: Box::new(Unplugged),
         }
     }
 
+    // Plugs a device into a controller port, in place of whatever was there.
+    pub fn connect(&mut self, port: Port, device: Box<dyn InputDevice>) {
+        self.ports[port.index()] = device;
+    }
+
+    // Plugs a device into the expansion port, in place of whatever was there.
+    pub fn connect_expansion(&mut self, device: Box<dyn InputDevice>) {
+        self.expansion = device;
+    }
+
+    pub fn frame(&mut self, frame: u64) {
+        self.ports.iter_mut().for_each(|p| p.frame(frame));
+        self.expansion.frame(frame);
+    }
+
     pub fn get_oamdma(&mut self) -> Option<u8> {
         let res = self.oamdma;
         self.oamdma = None;
@@ -62,8 +88,8 @@ impl Reader for IORegisters {
         match address {
             0x4000..=0x4013 | 0x4015 => self.apu.read(address),
             0x4014 => self.oamdma.unwrap_or(0),
-            0x4016 => self.joy1.read(address),
-            0x4017 => self.joy2.read(address),
+            0x4016 => self.ports[0].read(Port::One) | self.expansion.read(Port::One),
+            0x4017 => self.ports[1].read(Port::Two) | self.expansion.read(Port::Two),
             _ => 0,
         }
     }
@@ -74,12 +100,13 @@ impl Writer for IORegisters {
         match address {
             0x4000..=0x4013 | 0x4015 => self.apu.write(address, byte),
             0x4014 => self.oamdma = Some(byte),
-            0x4016 => self.joy1.write(address, byte),
-            0x4017 => {
-                // This address half drives the APU and half the joypad.
-                self.apu.write(address, byte);
-                self.joy2.write(address, byte);
+            // Both ports are strobed together, and the expansion port gets all of OUT0-2.
+            0x4016 => {
+                self.ports.iter_mut().for_each(|p| p.strobe(byte));
+                self.expansion.strobe(byte);
             }
+            // Writes to $4017 only go to the APU's frame counter.
+            0x4017 => self.apu.write(address, byte),
             _ => (),
         }
     }
@@ -193,6 +220,12 @@ impl Reader for PPUMemory {
             .map(|(mem, addr)| mem.read(addr))
             .unwrap_or(0)
     }
+
+    fn peek(&mut self, address: u16) -> u8 {
+        self.map(address)
+            .map(|(mem, addr)| mem.peek(addr))
+            .unwrap_or(0)
+    }
 }
 
 impl Writer for PPUMemory {
@@ -207,6 +240,9 @@ pub trait Mapper: SaveState<'static, MapperState> {
     fn read_prg(&mut self, address: u16) -> u8;
     fn write_prg(&mut self, address: u16, byte: u8);
     fn mirror_mode(&self) -> MirrorMode;
+    fn peek_chr(&mut self, address: u16) -> u8 {
+        self.read_chr(address)
+    }
     fn irq_triggered(&self) -> bool {
         false
     }
@@ -223,6 +259,10 @@ impl Mapper for MapperRef {
         self.borrow_mut().write_chr(address, byte)
     }
 
+    fn peek_chr(&mut self, address: u16) -> u8 {
+        self.borrow_mut().peek_chr(address)
+    }
+
     fn read_prg(&mut self, address: u16) -> u8 {
         self.borrow_mut().read_prg(address)
     }
@@ -288,6 +328,10 @@ impl<M: Mapper> Reader for ChrMapper<M> {
     fn read(&mut self, address: u16) -> u8 {
         self.mapper.read_chr(address)
     }
+
+    fn peek(&mut self, address: u16) -> u8 {
+        self.mapper.peek_chr(address)
+    }
 }
 
 impl<M: Mapper> Writer for ChrMapper<M> {
@@ -4,10 +4,14 @@ pub mod clock;
 pub mod components;
 pub mod controller;
 pub mod cpu;
+pub mod hdpack;
 pub mod ines;
+pub mod input;
 pub mod io;
 pub mod mappers;
 pub mod memory;
+pub mod movie;
+pub mod nsf;
 pub mod ppu;
 pub mod state;
 pub mod util;
@@ -16,18 +20,24 @@ pub mod util;
 mod test;
 
 use std::cell::RefCell;
+use std::path::Path;
 use std::rc::Rc;
 
 use crate::emulator::apu::AudioOut;
-use crate::emulator::controller::Button;
-use crate::emulator::io::event::{EventBus, Key};
-use crate::emulator::io::Screen;
+use crate::emulator::controller::{Bindings, KeyMap};
+use crate::emulator::input::{InputDevice, Port};
+use crate::emulator::io::avi::VideoRecorder;
+use crate::emulator::io::event::EventBus;
+use crate::emulator::io::{OutputMode, Screen};
 use crate::emulator::memory::{IORegisters, Writer};
+use crate::emulator::movie::{Movie, MovieMode, MovieSession, MovieStart};
+use crate::emulator::movie::{COMMAND_POWER, COMMAND_RESET};
 use crate::emulator::state::{NESState, SaveState};
 
 // Timings (NTSC).
 // Master clock = 21.477272 MHz ~= 46.5ns per clock.
 // CPU clock = 12 master clocks.
+// APU clock = 24 master clocks, though the APU itself is ticked at the CPU rate.
 // PPU clock = 4 master clocks.
 pub const NES_MASTER_CLOCK_HZ: u64 = 21_477_272;
 pub const NES_CPU_CLOCK_FACTOR: u32 = 12;
@@ -46,7 +56,15 @@ pub struct NES {
     pub screen: Rc<RefCell<Screen>>,
     pub joy1: Rc<RefCell<controller::Controller>>,
     pub joy2: Rc<RefCell<controller::Controller>>,
+    io_registers: Rc<RefCell<IORegisters>>,
     nmi_pin: bool,
+    frame: u64,
+    // Frames run since power-on, carried in save states unlike the PPU's count.
+    frame_count: u64,
+    // The state straight after power-on, restored to power cycle.
+    power_on_state: Option<NESState>,
+    movie: Option<MovieSession>,
+    video_recorder: Option<Rc<RefCell<VideoRecorder>>>,
 }
 
 impl NES {
@@ -55,13 +73,11 @@ impl NES {
         screen: Rc<RefCell<Screen>>,
         audio: A,
         rom: ines::ROM,
+        bindings: &Bindings,
     ) -> NES
     where
         A: AudioOut + 'static,
     {
-        // Create master clock.
-        let mut clock = clock::Clock::new();
-
         // Load ROM into memory.
         let mapper = rom.get_mapper();
 
@@ -88,26 +104,9 @@ impl NES {
             Box::new(memory::PrgMapper::new(mapper.clone())),
         )));
 
-        // Create controllers.
-        let joy1 = Rc::new(RefCell::new(controller::Controller::new(
-            [
-                (Key::Z, Button::A),
-                (Key::X, Button::B),
-                (Key::A, Button::Start),
-                (Key::S, Button::Select),
-                (Key::Up, Button::Up),
-                (Key::Down, Button::Down),
-                (Key::Left, Button::Left),
-                (Key::Right, Button::Right),
-            ]
-            .iter()
-            .cloned()
-            .collect(),
-        )));
-
-        let joy2 = Rc::new(RefCell::new(controller::Controller::new(
-            [].iter().cloned().collect(),
-        )));
+        // Create controllers, bound once everything's wired up.
+        let joy1 = Rc::new(RefCell::new(controller::Controller::new(KeyMap::new())));
+        let joy2 = Rc::new(RefCell::new(controller::Controller::new(KeyMap::new())));
 
         event_bus.borrow_mut().register(Box::new(joy1.clone()));
         event_bus.borrow_mut().register(Box::new(joy2.clone()));
@@ -131,17 +130,9 @@ impl NES {
         cpu.borrow_mut().disable_bcd();
         cpu.borrow_mut().startup_sequence();
 
-        let dma_controller = DMAController::new(io_registers.clone(), cpu.clone());
+        let clock = NES::new_clock(&cpu, &ppu, &apu, &io_registers);
 
-        // Wire up the clock timings.
-        let cpu_ticker = clock::ScaledTicker::new(Box::new(dma_controller), NES_CPU_CLOCK_FACTOR);
-        let ppu_ticker = clock::ScaledTicker::new(Box::new(ppu.clone()), NES_PPU_CLOCK_FACTOR);
-        let apu_ticker = clock::ScaledTicker::new(Box::new(apu.clone()), NES_APU_CLOCK_FACTOR);
-        clock.manage(cpu_ticker);
-        clock.manage(apu_ticker);
-        clock.manage(ppu_ticker);
-
-        NES {
+        let mut nes = NES {
             clock,
             cpu,
             ppu,
@@ -153,8 +144,37 @@ impl NES {
             screen,
             joy1,
             joy2,
+            io_registers,
             nmi_pin: false,
-        }
+            frame: 0,
+            frame_count: 0,
+            power_on_state: None,
+            movie: None,
+            video_recorder: None,
+        };
+        nes.set_bindings(bindings);
+        nes.power_on_state = Some(nes.freeze());
+        nes
+    }
+
+    // Creates the master clock, with a fresh DMA controller in front of the CPU.
+    fn new_clock(
+        cpu: &Rc<RefCell<cpu::CPU>>,
+        ppu: &Rc<RefCell<ppu::PPU>>,
+        apu: &Rc<RefCell<apu::APU>>,
+        io_registers: &Rc<RefCell<IORegisters>>,
+    ) -> clock::Clock {
+        let mut clock = clock::Clock::new();
+        let dma_controller = DMAController::new(io_registers.clone(), cpu.clone());
+
+        // Wire up the clock timings.
+        let cpu_ticker = clock::ScaledTicker::new(Box::new(dma_controller), NES_CPU_CLOCK_FACTOR);
+        let ppu_ticker = clock::ScaledTicker::new(Box::new(ppu.clone()), NES_PPU_CLOCK_FACTOR);
+        let apu_ticker = clock::ScaledTicker::new(Box::new(apu.clone()), NES_CPU_CLOCK_FACTOR);
+        clock.manage(cpu_ticker);
+        clock.manage(apu_ticker);
+        clock.manage(ppu_ticker);
+        clock
     }
 
     #[inline]
@@ -177,6 +197,21 @@ impl NES {
             self.cpu.borrow_mut().trigger_irq();
         }
 
+        // Input devices are told when each frame begins, e.g. for turbo buttons.
+        // Both controllers are told even while unplugged, so their turbo and macros keep time.
+        let frame = self.ppu.borrow().frame();
+        if frame != self.frame {
+            self.frame = frame;
+            self.frame_count += 1;
+            self.io_registers.borrow_mut().frame(frame);
+            self.joy1.borrow_mut().frame(frame);
+            self.joy2.borrow_mut().frame(frame);
+            self.movie_frame();
+            if let Some(recorder) = &self.video_recorder {
+                recorder.borrow_mut().frame(&self.screen.borrow());
+            }
+        }
+
         cycles
     }
 
@@ -188,13 +223,187 @@ impl NES {
         cycles
     }
 
+    // Plugs a device into a controller port, in place of the standard controller.
+    // Devices which take input need registering with the event bus separately.
+    pub fn connect(&mut self, port: Port, device: Box<dyn InputDevice>) {
+        self.io_registers.borrow_mut().connect(port, device);
+    }
+
+    // Plugs a device into the Famicom expansion port, which is read alongside both controllers.
+    pub fn connect_expansion(&mut self, device: Box<dyn InputDevice>) {
+        self.io_registers.borrow_mut().connect_expansion(device);
+    }
+
+    // Rebinds both controllers' keys and gamepad buttons.
+    pub fn set_bindings(&mut self, bindings: &Bindings) {
+        for (player, (joy, bindings)) in [&self.joy1, &self.joy2]
+            .iter()
+            .zip(bindings.players.iter())
+            .enumerate()
+        {
+            let mut joy = joy.borrow_mut();
+            joy.set_keymap(bindings.keys.clone());
+            joy.set_pad(player as u8, bindings.pad.clone());
+            joy.set_turbo_rate(bindings.turbo_rate);
+            joy.set_macros(bindings.macros.clone());
+        }
+    }
+
+    // While recording a movie, resets and power cycles happen at the start of the next frame, so
+    // they can be replayed at the same point.
     pub fn reset(&mut self) {
+        if !self.record_command(COMMAND_RESET) {
+            self.do_reset();
+        }
+    }
+
+    // Restores the state at power-on, though the frame count carries on.
+    // Host-side settings such as bindings, connected devices and audio mixing are kept.
+    pub fn power_cycle(&mut self) {
+        if !self.record_command(COMMAND_POWER) {
+            self.do_power_cycle();
+        }
+    }
+
+    fn record_command(&mut self, command: u8) -> bool {
+        match &mut self.movie {
+            Some(session) => session.record_command(command),
+            None => false,
+        }
+    }
+
+    fn do_reset(&mut self) {
         // Silence APU.
         self.apu.borrow_mut().write(0x4015, 0x00);
 
         // Restart CPU.
         self.cpu.borrow_mut().startup_sequence();
     }
+
+    // Besides the saved state, the APU, any OAM DMA in progress and the clock's phase between the
+    // CPU, PPU and APU all go back to how they were at power-on.
+    fn do_power_cycle(&mut self) {
+        if let Some(state) = self.power_on_state.clone() {
+            self.restore(state);
+        }
+        self.apu
+            .borrow_mut()
+            .power_on(Box::new(memory::PrgMapper::new(self.mapper.clone())));
+        self.io_registers.borrow_mut().get_oamdma();
+        self.clock = NES::new_clock(&self.cpu, &self.ppu, &self.apu, &self.io_registers);
+        self.nmi_pin = false;
+        self.frame = self.ppu.borrow().frame();
+    }
+
+    // The number of frames run since power-on.
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count
+    }
+
+    // Starts recording the controllers into a movie.
+    // A movie from power-on power cycles first, and one from a save state should be started
+    // straight after loading it.
+    pub fn record_movie(&mut self, movie: Movie) {
+        self.start_movie(movie, MovieMode::Recording);
+    }
+
+    // Plays a movie back in place of the controllers, starting the same way as record_movie.
+    // Unless it's read-only, loading a save state during playback carries on recording from there.
+    pub fn play_movie(&mut self, movie: Movie, read_only: bool) {
+        let mode = if read_only {
+            MovieMode::ReadOnly
+        } else {
+            MovieMode::ReadWrite
+        };
+        self.start_movie(movie, mode);
+    }
+
+    fn start_movie(&mut self, movie: Movie, mode: MovieMode) {
+        self.stop_movie();
+        if movie.start == MovieStart::PowerOn {
+            self.do_power_cycle();
+        }
+        self.movie = Some(MovieSession::new(movie, mode, self.frame_count));
+    }
+
+    // Stops recording or playback, handing the controllers back to the player.
+    pub fn stop_movie(&mut self) -> Option<Movie> {
+        self.joy1.borrow_mut().set_movie_input(None);
+        self.joy2.borrow_mut().set_movie_input(None);
+        self.movie.take().map(|session| session.into_movie())
+    }
+
+    pub fn movie(&self) -> Option<&MovieSession> {
+        self.movie.as_ref()
+    }
+
+    pub fn movie_mut(&mut self) -> Option<&mut MovieSession> {
+        self.movie.as_mut()
+    }
+
+    // Records each frame from here on to an uncompressed AVI, along with the APU's output
+    // resampled to sample_rate. The mode should match the APU's, as for SimpleAudioOut.
+    pub fn start_video_recording<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        sample_rate: f32,
+        mode: OutputMode,
+    ) -> std::io::Result<()> {
+        self.stop_video_recording()?;
+        let recorder = VideoRecorder::create(path, &self.screen.borrow(), sample_rate, mode)?;
+        let recorder = Rc::new(RefCell::new(recorder));
+        self.apu
+            .borrow_mut()
+            .set_recording_output(Some(Box::new(recorder.clone())));
+        self.video_recorder = Some(recorder);
+        Ok(())
+    }
+
+    // Finishes the recording, reporting the first error encountered while recording, if any.
+    pub fn stop_video_recording(&mut self) -> std::io::Result<()> {
+        let recorder = match self.video_recorder.take() {
+            None => return Ok(()),
+            Some(recorder) => recorder,
+        };
+        self.apu.borrow_mut().set_recording_output(None);
+        match Rc::try_unwrap(recorder) {
+            Ok(recorder) => recorder.into_inner().finish(),
+            Err(_) => Err(std::io::Error::other("Video recorder is still in use")),
+        }
+    }
+
+    pub fn is_recording_video(&self) -> bool {
+        self.video_recorder.is_some()
+    }
+
+    fn movie_frame(&mut self) {
+        let commands = match &mut self.movie {
+            Some(session) => {
+                let mut joy1 = self.joy1.borrow_mut();
+                let mut joy2 = self.joy2.borrow_mut();
+                session.frame(self.frame_count, [&mut joy1, &mut joy2], &self.ram.borrow())
+            }
+            None => return,
+        };
+
+        if commands & COMMAND_POWER != 0 {
+            self.do_power_cycle();
+        } else if commands & COMMAND_RESET != 0 {
+            self.do_reset();
+        }
+    }
+
+    fn restore(&mut self, state: NESState) {
+        self.cpu.borrow_mut().hydrate(state.cpu);
+        self.ppu.borrow_mut().hydrate(state.ppu);
+        self.mapper.borrow_mut().hydrate(state.mapper);
+        self.ram.borrow_mut().hydrate(state.ram);
+        self.sram.borrow_mut().hydrate(state.sram);
+        self.vram.borrow_mut().hydrate(state.vram);
+        self.screen.borrow_mut().hydrate(state.screen);
+        self.joy1.borrow_mut().hydrate(state.joy1);
+        self.joy2.borrow_mut().hydrate(state.joy2);
+    }
 }
 
 pub struct DMAController {
@@ -256,18 +465,15 @@ impl<'de> SaveState<'de, NESState> for NES {
             screen: self.screen.borrow_mut().freeze(),
             joy1: self.joy1.borrow_mut().freeze(),
             joy2: self.joy2.borrow_mut().freeze(),
+            frame_count: self.frame_count,
         }
     }
 
     fn hydrate(&mut self, state: NESState) {
-        self.cpu.borrow_mut().hydrate(state.cpu);
-        self.ppu.borrow_mut().hydrate(state.ppu);
-        self.mapper.borrow_mut().hydrate(state.mapper);
-        self.ram.borrow_mut().hydrate(state.ram);
-        self.sram.borrow_mut().hydrate(state.sram);
-        self.vram.borrow_mut().hydrate(state.vram);
-        self.screen.borrow_mut().hydrate(state.screen);
-        self.joy1.borrow_mut().hydrate(state.joy1);
-        self.joy2.borrow_mut().hydrate(state.joy2);
+        self.frame_count = state.frame_count;
+        self.restore(state);
+        if let Some(session) = &mut self.movie {
+            session.seek(self.frame_count);
+        }
     }
 }
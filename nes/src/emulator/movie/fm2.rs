@@ -0,0 +1,175 @@
+// FCEUX's text movie format.
+// A header of "key value" lines, then a line per frame, e.g. "|1|R......A|........||" for a
+// reset while Right and A are held on the first controller.
+//
+// Only text movies starting from power-on, with standard controllers or nothing in the ports, can
+// be read.
+
+use crate::emulator::movie::{Movie, MovieFrame, MovieStart};
+
+// The buttons in the order they're written, from bit 7 of Controller::buttons() down to bit 0.
+const BUTTONS: &[u8; 8] = b"RLDUTSBA";
+
+pub fn parse(text: &str) -> Result<Movie, String> {
+    let mut movie = Movie::new("", MovieStart::PowerOn);
+    // FM2 files have no RAM hashes to check against.
+    movie.hash_interval = 0;
+
+    for (line_ix, line) in text.lines().enumerate() {
+        let line = line.trim_end_matches('\r');
+        if line.starts_with('|') {
+            let frame = parse_frame(line)
+                .ok_or_else(|| format!("Bad input on line {}: {}", line_ix + 1, line))?;
+            movie.frames.push(frame);
+            continue;
+        }
+
+        let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+        match (key, value) {
+            ("version", version) if version != "3" => {
+                return Err(format!("Unsupported FM2 version: {}", version))
+            }
+            ("binary", "1") => return Err(String::from("Binary FM2 movies aren't supported")),
+            ("rerecordCount", count) => movie.rerecords = count.parse().unwrap_or(0),
+            ("romFilename", name) => movie.rom_name = String::from(name),
+            ("palFlag", "1") => return Err(String::from("PAL movies aren't supported")),
+            ("fourscore", "1") => return Err(String::from("Four Score movies aren't supported")),
+            ("port0", port) | ("port1", port) if port != "0" && port != "1" => {
+                return Err(String::from("Only gamepads are supported"))
+            }
+            ("port2", port) if port != "0" => {
+                return Err(String::from("Expansion port devices aren't supported"))
+            }
+            ("FDS", "1") => return Err(String::from("FDS movies aren't supported")),
+            ("savestate", _) => {
+                return Err(String::from(
+                    "Movies starting from a save state aren't supported",
+                ))
+            }
+            _ => (),
+        }
+    }
+    Ok(movie)
+}
+
+// "|commands|port0|port1|port2|", where each port has a character per button, '.' or ' ' when
+// it's released.
+fn parse_frame(line: &str) -> Option<MovieFrame> {
+    let mut fields = line.split('|').skip(1);
+    let commands = fields.next()?.trim().parse().ok()?;
+    let mut buttons = [0; 2];
+    for byte in buttons.iter_mut() {
+        let field = fields.next()?.as_bytes();
+        if !field.is_empty() && field.len() != BUTTONS.len() {
+            return None;
+        }
+        *byte = field
+            .iter()
+            .enumerate()
+            .filter(|&(_, &c)| c != b'.' && c != b' ')
+            .fold(0, |byte, (ix, _)| byte | (0x80 >> ix));
+    }
+    Some(MovieFrame { commands, buttons })
+}
+
+pub fn write(movie: &Movie) -> Result<String, String> {
+    if movie.start != MovieStart::PowerOn {
+        return Err(String::from(
+            "Movies starting from a save state can't be saved as FM2",
+        ));
+    }
+
+    let mut text = String::new();
+    text.push_str("version 3\n");
+    // FCEUX checks its own version number here, so ours wouldn't mean anything to it.
+    text.push_str("emuVersion 0\n");
+    text.push_str(&format!("rerecordCount {}\n", movie.rerecords));
+    text.push_str("palFlag 0\n");
+    text.push_str(&format!("romFilename {}\n", movie.rom_name));
+    text.push_str("guid 00000000-0000-0000-0000-000000000000\n");
+    text.push_str("fourscore 0\n");
+    text.push_str("port0 1\n");
+    text.push_str("port1 1\n");
+    text.push_str("port2 0\n");
+    text.push_str("FDS 0\n");
+
+    for frame in movie.frames.iter() {
+        text.push_str(&format!("|{}|", frame.commands));
+        for &byte in frame.buttons.iter() {
+            for (ix, &c) in BUTTONS.iter().enumerate() {
+                text.push(if byte & (0x80 >> ix) != 0 {
+                    c as char
+                } else {
+                    '.'
+                });
+            }
+            text.push('|');
+        }
+        text.push_str("|\n");
+    }
+    Ok(text)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::emulator::movie::fm2::{parse, write};
+    use crate::emulator::movie::{Movie, MovieFrame, MovieStart, COMMAND_POWER, COMMAND_RESET};
+
+    #[test]
+    fn test_parse() {
+        let text = "version 3\r\n\
+                    emuVersion 22020\r\n\
+                    rerecordCount 12\r\n\
+                    romFilename Super Mario Bros.\r\n\
+                    port0 1\r\n\
+                    port1 1\r\n\
+                    port2 0\r\n\
+                    |1|R......A|........||\r\n\
+                    |0|   U    |.L.....A||\r\n";
+        let movie = parse(text).unwrap();
+        assert_eq!(movie.rom_name, "Super Mario Bros.");
+        assert_eq!(movie.rerecords, 12);
+        assert_eq!(
+            movie.frames,
+            vec![
+                MovieFrame {
+                    commands: COMMAND_RESET,
+                    buttons: [0x81, 0x00],
+                },
+                MovieFrame {
+                    commands: 0,
+                    buttons: [0x10, 0x41],
+                },
+            ]
+        );
+
+        // An empty port has an empty field.
+        let movie = parse("version 3\nport0 1\nport1 0\n|0|.......A||\n").unwrap();
+        assert_eq!(movie.frames[0].buttons, [0x01, 0x00]);
+
+        assert!(parse("version 3\nport1 2\n").is_err());
+        assert!(parse("version 3\nbinary 1\n").is_err());
+        assert!(parse("version 3\n|0|RLD|\n").is_err());
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut movie = Movie::new("game", MovieStart::PowerOn);
+        movie.hash_interval = 0;
+        movie.rerecords = 3;
+        movie.frames = vec![
+            MovieFrame {
+                commands: COMMAND_POWER,
+                buttons: [0xFF, 0x00],
+            },
+            MovieFrame {
+                commands: 0,
+                buttons: [0x08, 0x24],
+            },
+        ];
+        assert_eq!(parse(&write(&movie).unwrap()).unwrap(), movie);
+
+        movie.start = MovieStart::SaveState(vec![]);
+        assert!(write(&movie).is_err());
+    }
+}
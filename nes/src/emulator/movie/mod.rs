@@ -0,0 +1,394 @@
+pub mod fm2;
+
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+
+use crate::emulator::controller::Controller;
+use crate::emulator::memory::Memory;
+
+// Commands carried out at the start of a frame, numbered as in FM2 files.
+pub const COMMAND_RESET: u8 = 0x01;
+pub const COMMAND_POWER: u8 = 0x02;
+
+// How often RAM is hashed while recording, in frames.
+pub const DEFAULT_HASH_INTERVAL: u32 = 60;
+
+// Our own format, a header followed by 3 bytes a frame.
+const MAGIC: &[u8] = b"NESMV\x1A";
+const VERSION: u8 = 1;
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct MovieFrame {
+    pub commands: u8,
+    // Each controller's buttons, as returned by Controller::buttons().
+    pub buttons: [u8; 2],
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum MovieStart {
+    PowerOn,
+    // A save state, in whatever form the frontend keeps them.
+    SaveState(Vec<u8>),
+}
+
+// A recording of both controllers, frame by frame.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Movie {
+    pub rom_name: String,
+    pub start: MovieStart,
+    pub frames: Vec<MovieFrame>,
+    pub rerecords: u32,
+    // A hash of RAM at the start of every hash_interval'th frame, to spot playback desyncing.
+    // FM2 files don't have them.
+    pub hash_interval: u32,
+    pub ram_hashes: Vec<u64>,
+}
+
+impl Movie {
+    pub fn new(rom_name: &str, start: MovieStart) -> Movie {
+        Movie {
+            rom_name: String::from(rom_name),
+            start,
+            frames: vec![],
+            rerecords: 0,
+            hash_interval: DEFAULT_HASH_INTERVAL,
+            ram_hashes: vec![],
+        }
+    }
+
+    // Loads either format.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Movie, String> {
+        let mut file = File::open(path).map_err(|e| e.to_string())?;
+        let mut contents = vec![];
+        file.read_to_end(&mut contents).map_err(|e| e.to_string())?;
+        Movie::from_bytes(&contents)
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Movie, String> {
+        if data.starts_with(MAGIC) {
+            Movie::parse(&data[MAGIC.len()..])
+        } else if data.starts_with(b"version 3") {
+            let text = String::from_utf8_lossy(data);
+            fm2::parse(&text)
+        } else {
+            Err(String::from("Not a movie file"))
+        }
+    }
+
+    // Saves as FM2 if the path ends in .fm2, or in our own format otherwise.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        let path = path.as_ref();
+        let is_fm2 = path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("fm2"));
+        let data = if is_fm2 {
+            fm2::write(self)?.into_bytes()
+        } else {
+            self.to_bytes()
+        };
+
+        let mut file = File::create(path).map_err(|e| e.to_string())?;
+        file.write_all(&data).map_err(|e| e.to_string())
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = MAGIC.to_vec();
+        data.push(VERSION);
+        data.extend_from_slice(&self.rerecords.to_le_bytes());
+        data.extend_from_slice(&self.hash_interval.to_le_bytes());
+        data.extend_from_slice(&(self.rom_name.len() as u32).to_le_bytes());
+        data.extend_from_slice(self.rom_name.as_bytes());
+
+        match self.start {
+            MovieStart::PowerOn => data.push(0),
+            MovieStart::SaveState(ref state) => {
+                data.push(1);
+                data.extend_from_slice(&(state.len() as u32).to_le_bytes());
+                data.extend_from_slice(state);
+            }
+        }
+
+        data.extend_from_slice(&(self.frames.len() as u32).to_le_bytes());
+        for frame in self.frames.iter() {
+            data.push(frame.commands);
+            data.extend_from_slice(&frame.buttons);
+        }
+
+        data.extend_from_slice(&(self.ram_hashes.len() as u32).to_le_bytes());
+        for hash in self.ram_hashes.iter() {
+            data.extend_from_slice(&hash.to_le_bytes());
+        }
+        data
+    }
+
+    fn parse(data: &[u8]) -> Result<Movie, String> {
+        let mut reader = ByteReader { data, pos: 0 };
+        let version = reader.bytes(1)?[0];
+        if version != VERSION {
+            return Err(format!("Unsupported movie version: {}", version));
+        }
+
+        let rerecords = reader.u32()?;
+        let hash_interval = reader.u32()?;
+        let name_len = reader.u32()? as usize;
+        let rom_name = String::from_utf8_lossy(reader.bytes(name_len)?).to_string();
+
+        let start = match reader.bytes(1)?[0] {
+            0 => MovieStart::PowerOn,
+            1 => {
+                let len = reader.u32()? as usize;
+                MovieStart::SaveState(reader.bytes(len)?.to_vec())
+            }
+            start => return Err(format!("Unknown movie start: {}", start)),
+        };
+
+        let num_frames = reader.u32()? as usize;
+        let frames = reader
+            .bytes(num_frames * 3)?
+            .chunks(3)
+            .map(|frame| MovieFrame {
+                commands: frame[0],
+                buttons: [frame[1], frame[2]],
+            })
+            .collect();
+
+        let num_hashes = reader.u32()? as usize;
+        if num_hashes * 8 > reader.remaining() {
+            return Err(String::from("Movie file is truncated"));
+        }
+        let mut ram_hashes = Vec::with_capacity(num_hashes);
+        for _ in 0..num_hashes {
+            let mut bytes = [0; 8];
+            bytes.copy_from_slice(reader.bytes(8)?);
+            ram_hashes.push(u64::from_le_bytes(bytes));
+        }
+
+        Ok(Movie {
+            rom_name,
+            start,
+            frames,
+            rerecords,
+            hash_interval,
+            ram_hashes,
+        })
+    }
+}
+
+struct ByteReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let end = self.pos + len;
+        if end > self.data.len() {
+            return Err(String::from("Movie file is truncated"));
+        }
+        let bytes = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn remaining(&self) -> usize {
+        self.data.len() - self.pos
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.bytes(4)?);
+        Ok(u32::from_le_bytes(bytes))
+    }
+}
+
+// FNV-1a, which is plenty to tell whether two runs have diverged.
+pub fn ram_hash(ram: &Memory) -> u64 {
+    (0..ram.len()).fold(0xCBF2_9CE4_8422_2325, |hash, ix| {
+        (hash ^ ram.get(ix) as u64).wrapping_mul(0x0100_0000_01B3)
+    })
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum MovieMode {
+    Recording,
+    // Playing back, with the controllers ignored.
+    ReadOnly,
+    // Playing back, but loading a save state carries on recording from there.
+    ReadWrite,
+    // Played back to the end.
+    Finished,
+}
+
+// A movie being recorded or played back, which takes over the controllers frame by frame.
+pub struct MovieSession {
+    movie: Movie,
+    mode: MovieMode,
+    // The frame count before the movie's first frame.
+    start_frame: u64,
+    // Commands to record on the next frame.
+    pending_commands: u8,
+    // The first frame RAM didn't match the recording on.
+    desync: Option<usize>,
+}
+
+impl MovieSession {
+    pub fn new(movie: Movie, mode: MovieMode, start_frame: u64) -> MovieSession {
+        MovieSession {
+            movie,
+            mode,
+            start_frame,
+            pending_commands: 0,
+            desync: None,
+        }
+    }
+
+    pub fn movie(&self) -> &Movie {
+        &self.movie
+    }
+
+    pub fn into_movie(self) -> Movie {
+        self.movie
+    }
+
+    pub fn mode(&self) -> MovieMode {
+        self.mode
+    }
+
+    // Switches between read-only and read+write playback.
+    pub fn set_read_only(&mut self, read_only: bool) {
+        self.mode = match (self.mode, read_only) {
+            (MovieMode::ReadOnly, false) => MovieMode::ReadWrite,
+            (MovieMode::ReadWrite, true) => MovieMode::ReadOnly,
+            (mode, _) => mode,
+        };
+    }
+
+    pub fn desync(&self) -> Option<usize> {
+        self.desync
+    }
+
+    // While recording, commands are held until the next frame so they're carried out at the
+    // same point on playback. Returns whether the command was recorded.
+    pub fn record_command(&mut self, command: u8) -> bool {
+        if self.mode == MovieMode::Recording {
+            self.pending_commands |= command;
+            true
+        } else {
+            false
+        }
+    }
+
+    // Called at the start of each frame, with the frame count so far.
+    // Takes over the controllers for the frame, and returns the commands to carry out first.
+    pub fn frame(&mut self, frame: u64, mut joys: [&mut Controller; 2], ram: &Memory) -> u8 {
+        if frame <= self.start_frame {
+            return 0;
+        }
+        let ix = (frame - self.start_frame - 1) as usize;
+        let interval = self.movie.hash_interval as usize;
+        let hash_ix = if interval > 0 && ix.is_multiple_of(interval) {
+            Some(ix / interval)
+        } else {
+            None
+        };
+
+        let frame = match self.mode {
+            MovieMode::Recording => {
+                if let Some(hash_ix) = hash_ix {
+                    self.movie.ram_hashes.truncate(hash_ix);
+                    self.movie.ram_hashes.push(ram_hash(ram));
+                }
+                let frame = MovieFrame {
+                    commands: self.pending_commands,
+                    buttons: [joys[0].live_buttons(), joys[1].live_buttons()],
+                };
+                self.pending_commands = 0;
+                self.movie.frames.truncate(ix);
+                self.movie.frames.push(frame);
+                frame
+            }
+            MovieMode::ReadOnly | MovieMode::ReadWrite if ix < self.movie.frames.len() => {
+                let recorded = hash_ix.and_then(|hash_ix| self.movie.ram_hashes.get(hash_ix));
+                if self.desync.is_none() && recorded.is_some_and(|&hash| hash != ram_hash(ram)) {
+                    self.desync = Some(ix);
+                }
+                self.movie.frames[ix]
+            }
+            MovieMode::ReadOnly | MovieMode::ReadWrite => {
+                self.mode = MovieMode::Finished;
+                for joy in joys {
+                    joy.set_movie_input(None);
+                }
+                return 0;
+            }
+            MovieMode::Finished => return 0,
+        };
+
+        for (joy, &buttons) in joys.iter_mut().zip(frame.buttons.iter()) {
+            joy.set_movie_input(Some(buttons));
+        }
+        frame.commands
+    }
+
+    // Called after loading a save state, with the frame count it was saved at.
+    // Recording, or read+write playback, carries on recording from there, counting a rerecord.
+    pub fn seek(&mut self, frame: u64) {
+        let ix = frame.saturating_sub(self.start_frame) as usize;
+        self.desync = None;
+        self.pending_commands = 0;
+        match self.mode {
+            MovieMode::Recording | MovieMode::ReadWrite if ix <= self.movie.frames.len() => {
+                self.movie.frames.truncate(ix);
+                let interval = self.movie.hash_interval.max(1) as usize;
+                self.movie.ram_hashes.truncate(ix.div_ceil(interval));
+                self.movie.rerecords += 1;
+                self.mode = MovieMode::Recording;
+            }
+            MovieMode::Finished if ix < self.movie.frames.len() => {
+                self.mode = MovieMode::ReadOnly;
+            }
+            _ => (),
+        }
+    }
+
+    // The number of frames recorded or played so far.
+    pub fn position(&self, frame: u64) -> usize {
+        frame.saturating_sub(self.start_frame) as usize
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::emulator::movie::{Movie, MovieFrame, MovieStart, COMMAND_RESET};
+
+    #[test]
+    fn test_round_trip() {
+        let mut movie = Movie::new("game", MovieStart::SaveState(vec![1, 2, 3]));
+        movie.rerecords = 4;
+        movie.frames = vec![
+            MovieFrame {
+                commands: COMMAND_RESET,
+                buttons: [0x01, 0x80],
+            },
+            MovieFrame {
+                commands: 0,
+                buttons: [0x10, 0x00],
+            },
+        ];
+        movie.ram_hashes = vec![0x0123_4567_89AB_CDEF];
+
+        let loaded = Movie::from_bytes(&movie.to_bytes()).unwrap();
+        assert_eq!(loaded, movie);
+
+        let truncated = &movie.to_bytes()[..20];
+        assert!(Movie::from_bytes(truncated).is_err());
+
+        // A corrupt hash count is caught before anything's allocated for it.
+        let mut corrupt = movie.to_bytes();
+        let len = corrupt.len();
+        corrupt.truncate(len - 8);
+        corrupt[len - 12..].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(Movie::from_bytes(&corrupt).is_err());
+    }
+}
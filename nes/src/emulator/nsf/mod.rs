@@ -0,0 +1,442 @@
+mod player;
+
+pub use self::player::{render_to_wav, NSFPlayer};
+
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+// NSF and NSFe music rips.
+// See https://wiki.nesdev.com/w/index.php/NSF and https://wiki.nesdev.com/w/index.php/NSFe
+
+// Expansion audio chips, as flagged in the header.
+// None of these are emulated, so their registers are ignored.
+pub const CHIP_VRC6: u8 = 1 << 0;
+pub const CHIP_VRC7: u8 = 1 << 1;
+pub const CHIP_FDS: u8 = 1 << 2;
+pub const CHIP_MMC5: u8 = 1 << 3;
+pub const CHIP_NAMCO_163: u8 = 1 << 4;
+pub const CHIP_SUNSOFT_5B: u8 = 1 << 5;
+
+const CHIP_NAMES: [(u8, &str); 6] = [
+    (CHIP_VRC6, "VRC6"),
+    (CHIP_VRC7, "VRC7"),
+    (CHIP_FDS, "FDS"),
+    (CHIP_MMC5, "MMC5"),
+    (CHIP_NAMCO_163, "Namco 163"),
+    (CHIP_SUNSOFT_5B, "Sunsoft 5B"),
+];
+
+// Default NTSC and PAL play rates, in microseconds.
+const DEFAULT_PLAY_SPEED: u16 = 16639;
+const DEFAULT_PAL_PLAY_SPEED: u16 = 19997;
+
+pub struct NSF {
+    pub total_songs: u8,
+
+    // 0-based, unlike in the file itself.
+    pub starting_song: u8,
+
+    pub load_addr: u16,
+    pub init_addr: u16,
+    pub play_addr: u16,
+
+    pub title: String,
+    pub artist: String,
+    pub copyright: String,
+    pub ripper: String,
+
+    // Microseconds between calls to PLAY, when played as NTSC and as PAL.
+    pub play_speed: u16,
+    pub pal_play_speed: u16,
+
+    // Initial values of $5FF8-$5FFF, if the tune uses bankswitching.
+    pub banks: Option<[u8; 8]>,
+
+    // PAL only, or either region. Tunes with neither are NTSC only.
+    pub pal: bool,
+    pub dual: bool,
+    pub expansion_chips: u8,
+
+    // NSFe metadata, all indexed by track number.
+    pub track_titles: Vec<Option<String>>,
+    pub track_lengths: Vec<Option<u32>>,
+    pub track_fades: Vec<Option<u32>>,
+    pub playlist: Option<Vec<u8>>,
+
+    pub data: Vec<u8>,
+}
+
+impl NSF {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<NSF, String> {
+        let mut file = File::open(path).map_err(|e| e.to_string())?;
+        let mut contents = vec![];
+        file.read_to_end(&mut contents).map_err(|e| e.to_string())?;
+        NSF::from_bytes(contents)
+    }
+
+    pub fn from_bytes(data: Vec<u8>) -> Result<NSF, String> {
+        if data.starts_with(b"NESM\x1A") {
+            NSF::parse_nsf(data)
+        } else if data.starts_with(b"NSFE") {
+            NSF::parse_nsfe(&data)
+        } else {
+            Err(String::from("Not an NSF or NSFe file"))
+        }
+    }
+
+    // Track lengths and fades are in milliseconds.
+    pub fn track_length(&self, track: u8) -> Option<u32> {
+        self.track_lengths.get(track as usize).cloned().flatten()
+    }
+
+    pub fn track_fade(&self, track: u8) -> Option<u32> {
+        self.track_fades.get(track as usize).cloned().flatten()
+    }
+
+    pub fn track_title(&self, track: u8) -> Option<&str> {
+        match self.track_titles.get(track as usize) {
+            Some(Some(title)) => Some(title),
+            _ => None,
+        }
+    }
+
+    // Tracks in the order they should be presented.
+    pub fn tracks(&self) -> Vec<u8> {
+        match self.playlist {
+            Some(ref playlist) => playlist.clone(),
+            None => (0..self.total_songs).collect(),
+        }
+    }
+
+    pub fn expansion_chip_names(&self) -> Vec<&'static str> {
+        CHIP_NAMES
+            .iter()
+            .filter(|(flag, _)| self.expansion_chips & flag != 0)
+            .map(|&(_, name)| name)
+            .collect()
+    }
+
+    fn empty() -> NSF {
+        NSF {
+            total_songs: 0,
+            starting_song: 0,
+            load_addr: 0,
+            init_addr: 0,
+            play_addr: 0,
+            title: String::new(),
+            artist: String::new(),
+            copyright: String::new(),
+            ripper: String::new(),
+            play_speed: DEFAULT_PLAY_SPEED,
+            pal_play_speed: DEFAULT_PAL_PLAY_SPEED,
+            banks: None,
+            pal: false,
+            dual: false,
+            expansion_chips: 0,
+            track_titles: vec![],
+            track_lengths: vec![],
+            track_fades: vec![],
+            playlist: None,
+            data: vec![],
+        }
+    }
+
+    fn parse_nsf(data: Vec<u8>) -> Result<NSF, String> {
+        if data.len() < 0x80 {
+            return Err(String::from("NSF header is truncated"));
+        }
+
+        let mut nsf = NSF::empty();
+        nsf.total_songs = data[0x06];
+        nsf.starting_song = data[0x07].saturating_sub(1);
+        nsf.load_addr = read_u16(&data, 0x08);
+        nsf.init_addr = read_u16(&data, 0x0A);
+        nsf.play_addr = read_u16(&data, 0x0C);
+        nsf.title = read_string(&data[0x0E..0x2E]);
+        nsf.artist = read_string(&data[0x2E..0x4E]);
+        nsf.copyright = read_string(&data[0x4E..0x6E]);
+        nsf.play_speed = read_speed(&data, 0x6E, DEFAULT_PLAY_SPEED);
+        nsf.banks = read_banks(&data[0x70..0x78]);
+        nsf.pal_play_speed = read_speed(&data, 0x78, DEFAULT_PAL_PLAY_SPEED);
+        nsf.pal = data[0x7A] & 0x3 == 0x1;
+        nsf.dual = data[0x7A] & 0x2 != 0;
+        nsf.expansion_chips = data[0x7B];
+        nsf.data = data[0x80..].to_vec();
+        nsf.validate()
+    }
+
+    fn parse_nsfe(data: &[u8]) -> Result<NSF, String> {
+        let mut nsf = NSF::empty();
+        let mut seen_info = false;
+        let mut seen_data = false;
+
+        let mut offset = 4;
+        while offset + 8 <= data.len() {
+            let len = read_u32(data, offset) as usize;
+            let id = &data[offset + 4..offset + 8];
+            let start = offset + 8;
+            let end = start + len;
+            if end > data.len() {
+                return Err(format!(
+                    "NSFe chunk {} is truncated",
+                    String::from_utf8_lossy(id)
+                ));
+            }
+            let chunk = &data[start..end];
+            offset = end;
+
+            match id {
+                b"INFO" => {
+                    if chunk.len() < 9 {
+                        return Err(String::from("NSFe INFO chunk is truncated"));
+                    }
+                    nsf.load_addr = read_u16(chunk, 0);
+                    nsf.init_addr = read_u16(chunk, 2);
+                    nsf.play_addr = read_u16(chunk, 4);
+                    nsf.pal = chunk[6] & 0x3 == 0x1;
+                    nsf.dual = chunk[6] & 0x2 != 0;
+                    nsf.expansion_chips = chunk[7];
+                    nsf.total_songs = chunk[8];
+                    nsf.starting_song = chunk.get(9).cloned().unwrap_or(0);
+                    seen_info = true;
+                }
+                b"DATA" => {
+                    nsf.data = chunk.to_vec();
+                    seen_data = true;
+                }
+                b"BANK" => {
+                    let mut banks = [0; 8];
+                    for (bank, &byte) in banks.iter_mut().zip(chunk.iter()) {
+                        *bank = byte;
+                    }
+                    nsf.banks = Some(banks);
+                }
+                // NTSC, then optionally PAL and Dendy rates, which we don't use.
+                b"RATE" => {
+                    if chunk.len() >= 2 {
+                        nsf.play_speed = read_speed(chunk, 0, DEFAULT_PLAY_SPEED);
+                    }
+                    if chunk.len() >= 4 {
+                        nsf.pal_play_speed = read_speed(chunk, 2, DEFAULT_PAL_PLAY_SPEED);
+                    }
+                }
+                b"NEND" => break,
+                b"auth" => {
+                    let mut strings = chunk.split(|&b| b == 0).map(read_string);
+                    nsf.title = strings.next().unwrap_or_default();
+                    nsf.artist = strings.next().unwrap_or_default();
+                    nsf.copyright = strings.next().unwrap_or_default();
+                    nsf.ripper = strings.next().unwrap_or_default();
+                }
+                b"tlbl" => {
+                    nsf.track_titles = chunk
+                        .split(|&b| b == 0)
+                        .map(|s| Some(read_string(s)).filter(|s| !s.is_empty()))
+                        .collect();
+                }
+                b"time" => nsf.track_lengths = read_times(chunk),
+                b"fade" => nsf.track_fades = read_times(chunk),
+                b"plst" => nsf.playlist = Some(chunk.to_vec()),
+                _ => {
+                    // Chunks starting with a capital letter are required to play the file.
+                    if id[0].is_ascii_uppercase() {
+                        return Err(format!(
+                            "Unsupported NSFe chunk: {}",
+                            String::from_utf8_lossy(id)
+                        ));
+                    }
+                }
+            }
+        }
+
+        if !seen_info || !seen_data {
+            return Err(String::from("NSFe is missing INFO or DATA chunk"));
+        }
+
+        nsf.validate()
+    }
+
+    fn validate(self) -> Result<NSF, String> {
+        if self.expansion_chips & CHIP_FDS != 0 {
+            return Err(String::from("FDS NSFs are not supported"));
+        }
+
+        if self.banks.is_none() && self.load_addr < 0x8000 {
+            return Err(format!("Unsupported load address: {:04X}", self.load_addr));
+        }
+
+        if self.total_songs == 0 {
+            return Err(String::from("NSF contains no songs"));
+        }
+
+        Ok(self)
+    }
+}
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    (data[offset] as u16) | ((data[offset + 1] as u16) << 8)
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    (read_u16(data, offset) as u32) | ((read_u16(data, offset + 2) as u32) << 16)
+}
+
+// A rate of 0 would call PLAY continuously, so it's taken to mean the default.
+fn read_speed(data: &[u8], offset: usize, default: u16) -> u16 {
+    match read_u16(data, offset) {
+        0 => default,
+        speed => speed,
+    }
+}
+
+fn read_string(data: &[u8]) -> String {
+    let end = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+    String::from_utf8_lossy(&data[..end]).trim().to_string()
+}
+
+// Bankswitching is only used if any of the initial bank values are non-zero.
+fn read_banks(data: &[u8]) -> Option<[u8; 8]> {
+    if data.iter().all(|&b| b == 0) {
+        return None;
+    }
+
+    let mut banks = [0; 8];
+    banks.copy_from_slice(data);
+    Some(banks)
+}
+
+// Negative times mean "use the default".
+fn read_times(data: &[u8]) -> Vec<Option<u32>> {
+    data.chunks_exact(4)
+        .map(|chunk| {
+            let time = read_u32(chunk, 0) as i32;
+            if time < 0 {
+                None
+            } else {
+                Some(time as u32)
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::emulator::nsf::{CHIP_VRC6, NSF};
+
+    pub fn nsf_header(songs: u8, load: u16, init: u16, play: u16) -> Vec<u8> {
+        let mut data = vec![0; 0x80];
+        data[0..5].copy_from_slice(b"NESM\x1A");
+        data[5] = 1;
+        data[6] = songs;
+        data[7] = 1;
+        data[0x08..0x0A].copy_from_slice(&load.to_le_bytes());
+        data[0x0A..0x0C].copy_from_slice(&init.to_le_bytes());
+        data[0x0C..0x0E].copy_from_slice(&play.to_le_bytes());
+        data[0x0E..0x13].copy_from_slice(b"Title");
+        data[0x2E..0x34].copy_from_slice(b"Artist");
+        data[0x6E..0x70].copy_from_slice(&16639u16.to_le_bytes());
+        data
+    }
+
+    fn chunk(id: &[u8], body: &[u8]) -> Vec<u8> {
+        let mut data = (body.len() as u32).to_le_bytes().to_vec();
+        data.extend_from_slice(id);
+        data.extend_from_slice(body);
+        data
+    }
+
+    #[test]
+    fn test_parse_nsf() {
+        let mut data = nsf_header(3, 0x8000, 0x8001, 0x8002);
+        data[0x7B] = CHIP_VRC6;
+        data[0x72] = 5;
+        data.extend_from_slice(&[0xEA, 0x60, 0x60]);
+
+        let nsf = NSF::from_bytes(data).unwrap();
+        assert_eq!(nsf.total_songs, 3);
+        assert_eq!(nsf.starting_song, 0);
+        assert_eq!(nsf.load_addr, 0x8000);
+        assert_eq!(nsf.init_addr, 0x8001);
+        assert_eq!(nsf.play_addr, 0x8002);
+        assert_eq!(nsf.title, "Title");
+        assert_eq!(nsf.artist, "Artist");
+        assert_eq!(nsf.copyright, "");
+        assert_eq!(nsf.banks, Some([0, 0, 5, 0, 0, 0, 0, 0]));
+        assert_eq!(nsf.expansion_chip_names(), vec!["VRC6"]);
+        assert_eq!(nsf.data, vec![0xEA, 0x60, 0x60]);
+        assert_eq!(nsf.tracks(), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn test_parse_nsfe() {
+        let mut data = b"NSFE".to_vec();
+        data.extend(chunk(
+            b"INFO",
+            &[0x00, 0x80, 0x01, 0x80, 0x02, 0x80, 0x00, 0x00, 0x02, 0x01],
+        ));
+        data.extend(chunk(b"DATA", &[0xEA, 0x60, 0x60]));
+        data.extend(chunk(b"auth", b"Game\0Composer\0\0Ripper\0"));
+        data.extend(chunk(b"tlbl", b"Intro\0\0"));
+        let mut times = 90_000i32.to_le_bytes().to_vec();
+        times.extend_from_slice(&(-1i32).to_le_bytes());
+        data.extend(chunk(b"time", &times));
+        data.extend(chunk(b"plst", &[1, 0]));
+        data.extend(chunk(b"xtra", &[1, 2, 3]));
+        data.extend(chunk(b"NEND", &[]));
+
+        let nsf = NSF::from_bytes(data).unwrap();
+        assert_eq!(nsf.total_songs, 2);
+        assert_eq!(nsf.starting_song, 1);
+        assert_eq!(nsf.title, "Game");
+        assert_eq!(nsf.artist, "Composer");
+        assert_eq!(nsf.ripper, "Ripper");
+        assert_eq!(nsf.track_title(0), Some("Intro"));
+        assert_eq!(nsf.track_title(1), None);
+        assert_eq!(nsf.track_length(0), Some(90_000));
+        assert_eq!(nsf.track_length(1), None);
+        assert_eq!(nsf.track_fade(0), None);
+        assert_eq!(nsf.tracks(), vec![1, 0]);
+        assert_eq!(nsf.data, vec![0xEA, 0x60, 0x60]);
+    }
+
+    #[test]
+    fn test_play_speeds() {
+        let mut data = nsf_header(1, 0x8000, 0x8000, 0x8000);
+        data[0x78..0x7A].copy_from_slice(&20000u16.to_le_bytes());
+        data[0x7A] = 0x2;
+        data.push(0x60);
+        let nsf = NSF::from_bytes(data).unwrap();
+        assert_eq!((nsf.play_speed, nsf.pal_play_speed), (16639, 20000));
+        assert!(nsf.dual && !nsf.pal);
+
+        let mut data = b"NSFE".to_vec();
+        data.extend(chunk(
+            b"INFO",
+            &[0x00, 0x80, 0x00, 0x80, 0x00, 0x80, 0x01, 0, 1],
+        ));
+        data.extend(chunk(b"DATA", &[0x60]));
+        data.extend(chunk(b"RATE", &[0x00, 0x00, 0x00, 0x00]));
+        let nsf = NSF::from_bytes(data).unwrap();
+        assert_eq!((nsf.play_speed, nsf.pal_play_speed), (16639, 19997));
+        assert!(nsf.pal && !nsf.dual);
+    }
+
+    #[test]
+    fn test_unknown_required_nsfe_chunk() {
+        let mut data = b"NSFE".to_vec();
+        data.extend(chunk(
+            b"INFO",
+            &[0x00, 0x80, 0x01, 0x80, 0x02, 0x80, 0, 0, 1],
+        ));
+        data.extend(chunk(b"DATA", &[0xEA]));
+        data.extend(chunk(b"WHAT", &[]));
+
+        assert!(NSF::from_bytes(data).is_err());
+    }
+
+    #[test]
+    fn test_not_nsf() {
+        assert!(NSF::from_bytes(b"NES\x1A".to_vec()).is_err());
+    }
+}
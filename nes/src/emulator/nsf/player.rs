@@ -0,0 +1,518 @@
+use std::cell::RefCell;
+use std::io;
+use std::path::Path;
+use std::rc::Rc;
+
+use crate::emulator::apu::{AudioOut, APU};
+use crate::emulator::clock;
+use crate::emulator::cpu;
+use crate::emulator::io::wav::{SampleFormat, WavWriter};
+use crate::emulator::io::SimpleAudioOut;
+use crate::emulator::memory::{Memory, Reader, Writer};
+use crate::emulator::nsf::NSF;
+use crate::emulator::{NES_CPU_CLOCK_FACTOR, NES_MASTER_CLOCK_HZ};
+
+// Tunes are driven by a small stub program, living in otherwise unused address space.
+// Reset jumps to the INIT stub, which calls INIT and then idles.
+// NMI jumps to the PLAY stub, which calls PLAY and returns to idling.
+const INIT_STUB: u16 = 0x3FF0;
+const IDLE_LOOP: u16 = 0x3FF7;
+const PLAY_STUB: u16 = 0x3FFA;
+const STUB_SIZE: usize = 16;
+
+// Used when rendering tracks without NSFe timing information.
+pub const DEFAULT_TRACK_LENGTH_MS: u32 = 150_000;
+pub const DEFAULT_FADE_MS: u32 = 5_000;
+
+// Banked PRG data, visible at $8000-$FFFF in 4KB pages.
+struct NSFRom {
+    data: Vec<u8>,
+    banks: [u8; 8],
+    initial_banks: [u8; 8],
+    bankswitched: bool,
+}
+
+impl NSFRom {
+    fn new(nsf: &NSF) -> NSFRom {
+        // Without bankswitching the data is simply loaded at the load address.
+        // With it, the load address only determines the offset within the first bank.
+        let (padding, initial_banks, bankswitched) = match nsf.banks {
+            Some(banks) => ((nsf.load_addr & 0x0FFF) as usize, banks, true),
+            None => (
+                (nsf.load_addr - 0x8000) as usize,
+                [0, 1, 2, 3, 4, 5, 6, 7],
+                false,
+            ),
+        };
+
+        let mut data = vec![0; padding];
+        data.extend_from_slice(&nsf.data);
+        let pages = data.len().div_ceil(0x1000);
+        data.resize(pages.max(8) * 0x1000, 0);
+
+        NSFRom {
+            data,
+            banks: initial_banks,
+            initial_banks,
+            bankswitched,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.banks = self.initial_banks;
+    }
+
+    fn switch_bank(&mut self, slot: usize, page: u8) {
+        if self.bankswitched {
+            self.banks[slot] = page;
+        }
+    }
+}
+
+impl Reader for NSFRom {
+    fn read(&mut self, address: u16) -> u8 {
+        let slot = ((address - 0x8000) >> 12) as usize;
+        let pages = self.data.len() / 0x1000;
+        let page = (self.banks[slot] as usize) % pages;
+        self.data[page * 0x1000 + (address & 0x0FFF) as usize]
+    }
+}
+
+// The CPU's view of memory when playing an NSF.
+struct NSFMemory {
+    ram: Memory,
+    wram: Memory,
+    apu: Rc<RefCell<APU>>,
+    rom: Rc<RefCell<NSFRom>>,
+    stub: [u8; STUB_SIZE],
+
+    // Set when the CPU is sat in the idle loop, i.e. INIT or PLAY has returned.
+    idle: bool,
+}
+
+impl NSFMemory {
+    fn set_stub(&mut self, song: u8, region: u8, init_addr: u16, play_addr: u16) {
+        let [init_lo, init_hi] = init_addr.to_le_bytes();
+        let [play_lo, play_hi] = play_addr.to_le_bytes();
+        let [idle_lo, idle_hi] = IDLE_LOOP.to_le_bytes();
+        // INIT stub: LDA #song, LDX #region, JSR INIT, JMP IDLE_LOOP.
+        // PLAY stub: JSR PLAY, RTI.
+        self.stub = [
+            0xA9, song, 0xA2, region, 0x20, init_lo, init_hi, 0x4C, idle_lo, idle_hi, 0x20,
+            play_lo, play_hi, 0x40, 0x00, 0x00,
+        ];
+    }
+}
+
+impl Reader for NSFMemory {
+    fn read(&mut self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x1FFF => self.ram.read(address & 0x7FF),
+            INIT_STUB..=0x3FFF => {
+                if address == IDLE_LOOP {
+                    self.idle = true;
+                } else if address == PLAY_STUB {
+                    self.idle = false;
+                }
+                self.stub[(address - INIT_STUB) as usize]
+            }
+            0x4015 => self.apu.borrow_mut().read(address),
+            0x6000..=0x7FFF => self.wram.read(address - 0x6000),
+            // Hijack the NMI and reset vectors to point at our stubs.
+            0xFFFA => PLAY_STUB.to_le_bytes()[0],
+            0xFFFB => PLAY_STUB.to_le_bytes()[1],
+            0xFFFC => INIT_STUB.to_le_bytes()[0],
+            0xFFFD => INIT_STUB.to_le_bytes()[1],
+            0x8000..=0xFFFF => self.rom.borrow_mut().read(address),
+            _ => 0,
+        }
+    }
+}
+
+impl Writer for NSFMemory {
+    fn write(&mut self, address: u16, byte: u8) {
+        match address {
+            0x0000..=0x1FFF => self.ram.write(address & 0x7FF, byte),
+            0x4000..=0x4013 | 0x4015 | 0x4017 => self.apu.borrow_mut().write(address, byte),
+            0x5FF8..=0x5FFF => self
+                .rom
+                .borrow_mut()
+                .switch_bank((address - 0x5FF8) as usize, byte),
+            0x6000..=0x7FFF => self.wram.write(address - 0x6000, byte),
+            _ => (),
+        }
+    }
+}
+
+// Plays NSF tunes on the APU, with the CPU running the tune's own driver code.
+pub struct NSFPlayer {
+    nsf: NSF,
+    clock: clock::Clock,
+    pub cpu: Rc<RefCell<cpu::CPU>>,
+    pub apu: Rc<RefCell<APU>>,
+    memory: Rc<RefCell<NSFMemory>>,
+    rom: Rc<RefCell<NSFRom>>,
+
+    track: u8,
+    pal: bool,
+    elapsed_cycles: u64,
+    play_period: u64,
+    next_play: u64,
+}
+
+impl NSFPlayer {
+    pub fn new<A>(nsf: NSF, audio: A) -> NSFPlayer
+    where
+        A: AudioOut + 'static,
+    {
+        let rom = Rc::new(RefCell::new(NSFRom::new(&nsf)));
+
+        let apu = Rc::new(RefCell::new(APU::new(
+            Box::new(audio),
+            Box::new(rom.clone()),
+        )));
+
+        let memory = Rc::new(RefCell::new(NSFMemory {
+            ram: Memory::new_ram(0x800),
+            wram: Memory::new_ram(0x2000),
+            apu: apu.clone(),
+            rom: rom.clone(),
+            stub: [0; STUB_SIZE],
+            idle: false,
+        }));
+
+        let cpu = Rc::new(RefCell::new(cpu::new(Box::new(memory.clone()))));
+        cpu.borrow_mut().disable_bcd();
+
+        let mut clock = clock::Clock::new();
+        clock.manage(clock::ScaledTicker::new(
+            Box::new(cpu.clone()),
+            NES_CPU_CLOCK_FACTOR,
+        ));
+        clock.manage(clock::ScaledTicker::new(
+            Box::new(apu.clone()),
+            NES_CPU_CLOCK_FACTOR,
+        ));
+
+        let track = nsf.starting_song;
+        let pal = nsf.pal;
+
+        let mut player = NSFPlayer {
+            nsf,
+            clock,
+            cpu,
+            apu,
+            memory,
+            rom,
+            track,
+            pal,
+            elapsed_cycles: 0,
+            play_period: 0,
+            next_play: 0,
+        };
+        player.play_track(track);
+        player
+    }
+
+    pub fn nsf(&self) -> &NSF {
+        &self.nsf
+    }
+
+    pub fn track(&self) -> u8 {
+        self.track
+    }
+
+    // Time spent playing the current track.
+    pub fn elapsed_ms(&self) -> u64 {
+        self.elapsed_cycles * 1000 / NES_MASTER_CLOCK_HZ
+    }
+
+    pub fn is_pal(&self) -> bool {
+        self.pal
+    }
+
+    // Dual region tunes play as NTSC unless set to PAL, which restarts the track.
+    // Other tunes always play in their own region.
+    pub fn set_pal(&mut self, pal: bool) {
+        if self.nsf.dual {
+            self.pal = pal;
+            self.play_track(self.track);
+        }
+    }
+
+    // Reset the machine and run INIT for the given (0-based) track.
+    pub fn play_track(&mut self, track: u8) {
+        self.track = track.min(self.nsf.total_songs - 1);
+        self.elapsed_cycles = 0;
+        let speed = if self.pal {
+            self.nsf.pal_play_speed
+        } else {
+            self.nsf.play_speed
+        };
+        self.play_period = (speed as u64) * NES_MASTER_CLOCK_HZ / 1_000_000;
+        self.next_play = self.play_period;
+
+        {
+            let mut memory = self.memory.borrow_mut();
+            memory.ram = Memory::new_ram(0x800);
+            memory.wram = Memory::new_ram(0x2000);
+            memory.idle = false;
+            let region = if self.pal { 1 } else { 0 };
+            memory.set_stub(self.track, region, self.nsf.init_addr, self.nsf.play_addr);
+        }
+
+        self.rom.borrow_mut().reset();
+
+        {
+            let mut apu = self.apu.borrow_mut();
+            for address in 0x4000..=0x4013 {
+                apu.write(address, 0x00);
+            }
+            apu.write(0x4015, 0x00);
+            apu.write(0x4015, 0x0F);
+            apu.write(0x4017, 0x40);
+        }
+
+        self.cpu.borrow_mut().startup_sequence();
+    }
+
+    #[inline]
+    pub fn tick(&mut self) -> u64 {
+        let cycles = self.clock.tick();
+        self.elapsed_cycles += cycles;
+
+        if self.elapsed_cycles >= self.next_play {
+            // If the previous call hasn't returned yet, just skip this one.
+            if self.memory.borrow().idle {
+                self.cpu.borrow_mut().trigger_nmi();
+            }
+            self.next_play += self.play_period;
+        }
+
+        if self.apu.borrow().irq_triggered() {
+            self.cpu.borrow_mut().trigger_irq();
+        }
+
+        cycles
+    }
+
+    pub fn tick_multi(&mut self, ticks: u32) -> u64 {
+        let mut cycles = 0u64;
+        for _ in 0..ticks {
+            cycles += self.tick()
+        }
+        cycles
+    }
+}
+
+// Render a track to a WAV file without any frontend.
+// Plays for the track's NSFe length if it has one, fading out at the end.
+pub fn render_to_wav<P: AsRef<Path>>(
+    nsf: NSF,
+    track: u8,
+    path: P,
+    sample_rate: u32,
+    format: SampleFormat,
+) -> io::Result<()> {
+    let length_ms = nsf.track_length(track).unwrap_or(DEFAULT_TRACK_LENGTH_MS) as u64;
+    let fade_ms = nsf.track_fade(track).unwrap_or(DEFAULT_FADE_MS) as u64;
+    let total_samples = (length_ms + fade_ms) * (sample_rate as u64) / 1000;
+    let fade_start = length_ms * (sample_rate as u64) / 1000;
+
+    let audio = Rc::new(RefCell::new(SimpleAudioOut::new(sample_rate as f32)));
+    let mut player = NSFPlayer::new(nsf, audio.clone());
+    player.play_track(track);
+
+    let mut wav = WavWriter::create(path, sample_rate, 1, format)?;
+    let frame_cycles = NES_MASTER_CLOCK_HZ / 60;
+    let mut elapsed_cycles = 0u64;
+    let mut written = 0u64;
+    let mut buf = vec![];
+
+    while written < total_samples {
+        let mut cycles = 0;
+        while cycles < frame_cycles {
+            cycles += player.tick();
+        }
+        elapsed_cycles += cycles;
+
+        // Work out the sample count from the total so rounding errors don't accumulate.
+        let target = elapsed_cycles * (sample_rate as u64) / NES_MASTER_CLOCK_HZ;
+        let num_samples = target.min(total_samples) - written;
+
+        buf.clear();
+        audio
+            .borrow_mut()
+            .consume(cycles, num_samples, |data| buf.extend_from_slice(data));
+        buf.truncate(num_samples as usize);
+
+        for (ix, sample) in buf.iter_mut().enumerate() {
+            let position = written + (ix as u64);
+            if position >= fade_start && fade_ms > 0 {
+                let fade_samples = total_samples - fade_start;
+                *sample *= 1.0 - ((position - fade_start) as f32) / (fade_samples as f32);
+            }
+        }
+
+        wav.write_samples(&buf)?;
+        written += buf.len() as u64;
+
+        // Guard against the resampler producing nothing, e.g. if the APU is never clocked.
+        if buf.is_empty() && num_samples > 0 {
+            break;
+        }
+    }
+
+    wav.finish()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use crate::emulator::apu::AudioOut;
+    use crate::emulator::nsf::test::nsf_header;
+    use crate::emulator::nsf::{NSFPlayer, NSF};
+    use crate::emulator::NES_MASTER_CLOCK_HZ;
+
+    struct CountingAudio {
+        samples: u64,
+        non_zero: u64,
+    }
+
+    impl AudioOut for CountingAudio {
+        fn emit(&mut self, sample: f32) {
+            self.samples += 1;
+            if sample != 0.0 {
+                self.non_zero += 1;
+            }
+        }
+    }
+
+    // INIT stores the song number in $00 and enables pulse 1 at max volume.
+    // PLAY increments $01.
+    fn test_program() -> Vec<u8> {
+        vec![
+            // $8000: INIT
+            0x85, 0x00, // STA $00
+            0xA9, 0xBF, // LDA #$BF
+            0x8D, 0x00, 0x40, // STA $4000
+            0xA9, 0xFF, // LDA #$FF
+            0x8D, 0x02, 0x40, // STA $4002
+            0xA9, 0x08, // LDA #$08
+            0x8D, 0x03, 0x40, // STA $4003
+            0x60, // RTS
+            // $8012: PLAY
+            0xE6, 0x01, // INC $01
+            0x60, // RTS
+        ]
+    }
+
+    fn test_nsf(data: Vec<u8>) -> NSF {
+        let mut nsf = nsf_header(4, 0x8000, 0x8000, 0x8012);
+        nsf.extend(data);
+        NSF::from_bytes(nsf).unwrap()
+    }
+
+    fn run_ms(player: &mut NSFPlayer, ms: u64) {
+        let target = NES_MASTER_CLOCK_HZ * ms / 1000;
+        let mut cycles = 0;
+        while cycles < target {
+            cycles += player.tick();
+        }
+    }
+
+    #[test]
+    fn test_init_and_play() {
+        let audio = Rc::new(RefCell::new(CountingAudio {
+            samples: 0,
+            non_zero: 0,
+        }));
+        let mut player = NSFPlayer::new(test_nsf(test_program()), audio.clone());
+        player.play_track(2);
+        run_ms(&mut player, 1000);
+
+        let mut cpu = player.cpu.borrow_mut();
+        assert_eq!(cpu.load_memory(0x0000), 2);
+        // ~60 calls to PLAY per second.
+        let plays = cpu.load_memory(0x0001);
+        assert!((59..=61).contains(&plays), "{} plays", plays);
+        assert!(audio.borrow().non_zero > 0);
+    }
+
+    #[test]
+    fn test_pal_play_speed() {
+        let plays_per_second = |region: u8, pal: bool| {
+            let mut data = nsf_header(1, 0x8000, 0x8000, 0x8012);
+            data[0x78..0x7A].copy_from_slice(&20000u16.to_le_bytes());
+            data[0x7A] = region;
+            data.extend(test_program());
+            let audio = Rc::new(RefCell::new(CountingAudio {
+                samples: 0,
+                non_zero: 0,
+            }));
+            let mut player = NSFPlayer::new(NSF::from_bytes(data).unwrap(), audio);
+            player.set_pal(pal);
+            run_ms(&mut player, 1000);
+            let plays = player.cpu.borrow_mut().load_memory(0x0001);
+            plays
+        };
+
+        assert!((49..=51).contains(&plays_per_second(0x1, false)));
+        assert!((59..=61).contains(&plays_per_second(0x2, false)));
+        assert!((49..=51).contains(&plays_per_second(0x2, true)));
+        assert!((59..=61).contains(&plays_per_second(0x0, true)));
+    }
+
+    #[test]
+    fn test_play_track_resets_state() {
+        let audio = Rc::new(RefCell::new(CountingAudio {
+            samples: 0,
+            non_zero: 0,
+        }));
+        let mut player = NSFPlayer::new(test_nsf(test_program()), audio);
+        run_ms(&mut player, 500);
+        player.play_track(1);
+        run_ms(&mut player, 100);
+
+        let mut cpu = player.cpu.borrow_mut();
+        assert_eq!(cpu.load_memory(0x0000), 1);
+        assert!(cpu.load_memory(0x0001) <= 6);
+        assert_eq!(player.track(), 1);
+    }
+
+    #[test]
+    fn test_bankswitching() {
+        // Two 4K pages.  INIT switches page 1 into $9000, and copies a byte from it.
+        let mut data = vec![0; 0x2000];
+        let init = [
+            0xA9, 0x01, // LDA #$01
+            0x8D, 0xF9, 0x5F, // STA $5FF9
+            0xAD, 0x00, 0x90, // LDA $9000
+            0x85, 0x02, // STA $02
+            0x60, // RTS
+        ];
+        data[..init.len()].copy_from_slice(&init);
+        data[0x0FFF] = 0x60; // PLAY: RTS
+        data[0x1000] = 0x42;
+
+        let mut header = nsf_header(1, 0x8000, 0x8000, 0x8FFF);
+        header[0x70] = 0;
+        header[0x71] = 0;
+        header[0x72] = 1;
+        header.extend(data);
+        let nsf = NSF::from_bytes(header).unwrap();
+        assert!(nsf.banks.is_some());
+
+        let audio = Rc::new(RefCell::new(CountingAudio {
+            samples: 0,
+            non_zero: 0,
+        }));
+        let mut player = NSFPlayer::new(nsf, audio);
+        run_ms(&mut player, 20);
+
+        assert_eq!(player.cpu.borrow_mut().load_memory(0x0002), 0x42);
+    }
+}
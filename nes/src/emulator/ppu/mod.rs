@@ -33,6 +33,15 @@ pub struct Colour {
 }
 
 impl Colour {
+    pub fn new(byte: u8) -> Colour {
+        Colour {
+            byte: byte & 0x3F,
+            em_r: false,
+            em_b: false,
+            em_g: false,
+        }
+    }
+
     pub fn hue(&self) -> u8 {
         self.byte & 0b1111
     }
@@ -44,16 +53,72 @@ impl Colour {
     pub fn as_byte(&self) -> u8 {
         self.byte
     }
+
+    // The colour byte with the emphasis bits above it, as used to index a full 512 colour palette.
+    pub fn index(&self) -> u16 {
+        let mut index = self.byte as u16;
+        if self.em_r {
+            index |= 0x40;
+        }
+        if self.em_g {
+            index |= 0x80;
+        }
+        if self.em_b {
+            index |= 0x100;
+        }
+        index
+    }
+}
+
+// A tile which contributed to a pixel.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct TileSource {
+    // The tile's 16 bytes of pattern data.
+    pub chr: [u8; 16],
+
+    // The palette it was drawn with, where colour 0 is the backdrop colour.
+    pub palette: [u8; 4],
+
+    // The pixel's index into the palette, 0 being transparent.
+    pub colour: u8,
+
+    // The pixel's position in the pattern data, i.e. before any flipping.
+    pub x: u8,
+    pub y: u8,
+    pub h_flip: bool,
+    pub v_flip: bool,
+
+    // Sprites only.
+    pub behind_background: bool,
+}
+
+// Describes where a pixel came from, for replacing tiles with other graphics.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct PixelSource {
+    pub backdrop: u8,
+
+    // The background tile under the pixel, if the background is shown there.
+    pub background: Option<TileSource>,
+
+    // The frontmost opaque sprite pixel, if any.
+    pub sprite: Option<TileSource>,
 }
 
 pub trait VideoOut {
     fn emit(&mut self, c: Colour);
+
+    // Only called when the PPU has tile output enabled, just before the pixel it describes.
+    fn emit_source(&mut self, _source: &PixelSource) {}
 }
 
 impl<V: VideoOut> VideoOut for Rc<RefCell<V>> {
     fn emit(&mut self, c: Colour) {
         self.borrow_mut().emit(c);
     }
+
+    fn emit_source(&mut self, source: &PixelSource) {
+        self.borrow_mut().emit_source(source);
+    }
 }
 
 #[derive(Clone, Copy, Debug, Serialize, Deserialize)]
@@ -140,18 +205,20 @@ pub struct PPU {
     oam: [u8; 256],
 
     // Secondary OAM holds 8 sprites to be rendered on the current scanline.
-    secondary_oam: [u8; 32],
+    // With the sprite limit off, any further sprites on the scanline are kept after them.
+    secondary_oam: [u8; 256],
 
     // Eight pairs of 8-bit shift registers to hold the bitmap data for 8 sprites to be rendered on
     // the current scanline.
-    sprites_tile_high: [u8; 8],
-    sprites_tile_low: [u8; 8],
+    // As with secondary OAM, there are more than the hardware's 8 for when the sprite limit is off.
+    sprites_tile_high: [u8; MAX_SPRITES],
+    sprites_tile_low: [u8; MAX_SPRITES],
 
     // Eight latches containing the attribute bytes for the 8 sprites.
-    sprites_attribute: [u8; 8],
+    sprites_attribute: [u8; MAX_SPRITES],
 
     // Eight counters containing the X positions for the 8 sprites.
-    sprites_x: [u8; 8],
+    sprites_x: [u8; MAX_SPRITES],
 
     // --- Counters for tracking the current rendering stage.
 
@@ -162,6 +229,9 @@ pub struct PPU {
     // Each scanline takes 341 cycles to render.
     pub cycle: u16,
 
+    // How many times vblank has begun.
+    frame: u64,
+
     // -- Internal State --
 
     // Byte fetched from nametable indicating which tile to fetch from pattern table.
@@ -183,14 +253,39 @@ pub struct PPU {
     sprite_0_next_line: bool,
     sprite_0_this_line: bool,
 
+    // Sprites found past the first 8 on a scanline, only when the sprite limit is off.
+    // They're drawn, but otherwise invisible to the CPU: the overflow flag and sprite 0 hit work
+    // from the first 8 as normal.
+    sprite_limit: bool,
+    extra_sprites_copied: u8,
+    num_extra_sprites: u8,
+
     // Bytes read from $2007 are delayed in this buffer.
     ppudata_read_buffer: u8,
 
     // Internal memory latch, causes reads from write-only registers to return the previously read
     // value.
     bus_latch: u8,
+
+    // -- Tile Output State --
+    // Only tracked when tile output is enabled.
+    tile_output: bool,
+
+    // Pattern data and row of the background tile being fetched, and of the 2 tiles in the shift
+    // registers.
+    bg_tile_latch: ([u8; 16], u8),
+    bg_tiles: [([u8; 16], u8); 2],
+
+    // Pattern data and row of each sprite on the current scanline, and how many of its pixels have
+    // been drawn.
+    sprites_chr: [[u8; 16]; MAX_SPRITES],
+    sprites_row: [u8; MAX_SPRITES],
+    sprites_column: [u8; MAX_SPRITES],
 }
 
+// Every sprite in OAM, for when the sprite limit is off.
+const MAX_SPRITES: usize = 64;
+
 impl clock::Ticker for PPU {
     #[inline]
     fn tick(&mut self) -> u32 {
@@ -220,13 +315,14 @@ impl PPU {
             attribute_latch_1: 0,
             attribute_latch_2: 0,
             oam: [0; 256],
-            secondary_oam: [0; 32],
-            sprites_tile_high: [0; 8],
-            sprites_tile_low: [0; 8],
-            sprites_attribute: [0; 8],
-            sprites_x: [0; 8],
+            secondary_oam: [0; 256],
+            sprites_tile_high: [0; MAX_SPRITES],
+            sprites_tile_low: [0; MAX_SPRITES],
+            sprites_attribute: [0; MAX_SPRITES],
+            sprites_x: [0; MAX_SPRITES],
             scanline: 261,
             cycle: 0,
+            frame: 0,
             tmp_pattern_coords: 0,
             tmp_attribute_byte: 0,
             tmp_oam_byte: 0,
@@ -238,11 +334,47 @@ impl PPU {
             num_sprites: 0,
             sprite_0_next_line: false,
             sprite_0_this_line: false,
+            sprite_limit: true,
+            extra_sprites_copied: 0,
+            num_extra_sprites: 0,
             ppudata_read_buffer: 0,
             bus_latch: 0,
+            tile_output: false,
+            bg_tile_latch: ([0; 16], 0),
+            bg_tiles: [([0; 16], 0); 2],
+            sprites_chr: [[0; 16]; MAX_SPRITES],
+            sprites_row: [0; MAX_SPRITES],
+            sprites_column: [0; MAX_SPRITES],
         }
     }
 
+    pub fn is_tile_output(&self) -> bool {
+        self.tile_output
+    }
+
+    // Whether to describe where each pixel came from, see PixelSource.
+    pub fn set_tile_output(&mut self, enabled: bool) {
+        self.tile_output = enabled;
+    }
+
+    pub fn is_sprite_limit(&self) -> bool {
+        self.sprite_limit
+    }
+
+    // Whether to draw at most 8 sprites per scanline, like the hardware.
+    // Turning it off reduces flicker in games which cycle sprites to work around the limit.
+    pub fn set_sprite_limit(&mut self, enabled: bool) {
+        self.sprite_limit = enabled;
+        if enabled {
+            self.extra_sprites_copied = 0;
+            self.num_extra_sprites = 0;
+        }
+    }
+
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
     pub fn nmi_triggered(&self) -> bool {
         self.ppustatus.is_set(flags::PPUSTATUS::V) && self.ppuctrl.is_set(flags::PPUCTRL::V)
     }
@@ -328,6 +460,7 @@ impl PPU {
         if self.scanline == 241 && self.cycle == 1 {
             // Set VBlank flag.
             self.ppustatus.set(flags::PPUSTATUS::V);
+            self.frame += 1;
         }
         // Otherwise idle.
         if self.cycle == 0 {
@@ -404,6 +537,11 @@ impl PPU {
         // Mux the correct bits and load into the bit-latches.
         self.attribute_latch_1 = self.tmp_attribute_byte & 1;
         self.attribute_latch_2 = (self.tmp_attribute_byte >> 1) & 1;
+
+        if self.tile_output {
+            self.bg_tiles[0] = self.bg_tiles[1];
+            self.bg_tiles[1] = self.bg_tile_latch;
+        }
     }
 
     // Shift the registers.
@@ -441,6 +579,9 @@ impl PPU {
             5 => {
                 let addr = self.pattern_address_low();
                 self.tile_latch_low = self.memory.read(addr);
+                if self.tile_output {
+                    self.bg_tile_latch = (self.peek_tile(addr & !0x7), self.fine_y_scroll() as u8);
+                }
             }
 
             // 4. Tile bitmap high.
@@ -499,6 +640,21 @@ impl PPU {
             self.ppustatus.set(flags::PPUSTATUS::S);
         }
 
+        if self.tile_output {
+            let background = if should_render_background {
+                Some((bg_colour, bg_palette))
+            } else {
+                None
+            };
+            let sprite = if sprite_colour != 0 {
+                Some((sprite_colour, sprite_attribute, sprite_ix))
+            } else {
+                None
+            };
+            let source = self.pixel_source(background, sprite);
+            self.output.emit_source(&source);
+        }
+
         let colour_addr = if sprite_colour != 0 && (sprite_attribute & 0x20 == 0 || bg_colour == 0)
         {
             // Render sprite.
@@ -537,7 +693,10 @@ impl PPU {
             }
             65..=256 => {
                 if self.scanline != 261 {
-                    self.sprite_evaluation_cycle()
+                    self.sprite_evaluation_cycle();
+                    if self.cycle == 256 && !self.sprite_limit {
+                        self.extra_sprite_evaluation();
+                    }
                 }
             }
             257..=320 => self.sprite_fetch_cycle(),
@@ -552,6 +711,8 @@ impl PPU {
         self.num_sprites = self.sprites_copied;
         self.sprite_0_this_line = self.sprite_0_next_line;
         self.sprite_0_next_line = false;
+        self.num_extra_sprites = self.extra_sprites_copied;
+        self.extra_sprites_copied = 0;
         self.tmp_oam_byte = 0;
         self.sprite_n = 0;
         self.sprite_m = 0;
@@ -573,12 +734,7 @@ impl PPU {
             return;
         }
 
-        let sprite_height = if self.ppuctrl.is_set(flags::PPUCTRL::H) {
-            16
-        } else {
-            8
-        };
-        let min_y = self.scanline.saturating_sub(sprite_height - 1);
+        let min_y = self.scanline.saturating_sub(self.sprite_height() - 1);
         let max_y = self.scanline;
 
         match self.sprite_eval_phase {
@@ -665,20 +821,65 @@ impl PPU {
         }
     }
 
+    // Copies the in-range sprites after the first 8 into the rest of secondary OAM.
+    // This isn't part of the hardware's evaluation, so is done all at once at the end of it.
+    fn extra_sprite_evaluation(&mut self) {
+        let min_y = self.scanline.saturating_sub(self.sprite_height() - 1);
+        let max_y = self.scanline;
+
+        let mut in_range = 0;
+        for n in 0..64 {
+            let y = self.oam[n * 4] as u16;
+            if y < min_y || y > max_y {
+                continue;
+            }
+
+            // The first 8 are already in secondary OAM.
+            in_range += 1;
+            if in_range <= 8 {
+                continue;
+            }
+
+            let slot = 8 + self.extra_sprites_copied as usize;
+            self.secondary_oam[slot * 4..slot * 4 + 4].copy_from_slice(&self.oam[n * 4..n * 4 + 4]);
+            self.extra_sprites_copied += 1;
+        }
+    }
+
+    fn sprite_height(&self) -> u16 {
+        if self.ppuctrl.is_set(flags::PPUCTRL::H) {
+            16
+        } else {
+            8
+        }
+    }
+
     fn sprite_fetch_cycle(&mut self) {
         // Loading the sprite data for next scanline into registers.
         // Technically this data should be handled 1 byte per cycle.
         // But because we're only shuffling data around internally, it's impossible
         // for anything weird to happen inbetween, so just do it in one go.
+        if self.cycle == 320 {
+            // Extra sprites are fetched without going through the bus, so mappers watching PPU
+            // reads see the same as with the sprite limit on.
+            for sprite_ix in 8..8 + self.extra_sprites_copied as usize {
+                self.fetch_sprite(sprite_ix, false);
+            }
+        }
+
         if self.cycle % 8 != 1 {
             return;
         }
 
         let sprite_ix = (self.cycle - 256) / 8;
-        let y = self.secondary_oam[(sprite_ix * 4) as usize];
-        let tile_no = self.secondary_oam[(sprite_ix * 4 + 1) as usize];
-        let attribute = self.secondary_oam[(sprite_ix * 4 + 2) as usize];
-        let x = self.secondary_oam[(sprite_ix * 4 + 3) as usize];
+        self.fetch_sprite(sprite_ix as usize, true);
+    }
+
+    fn fetch_sprite(&mut self, sprite_ix: usize, on_bus: bool) {
+        let y = self.secondary_oam[sprite_ix * 4];
+        let tile_no = self.secondary_oam[sprite_ix * 4 + 1];
+        let attribute = self.secondary_oam[sprite_ix * 4 + 2];
+        let x = self.secondary_oam[sprite_ix * 4 + 3];
 
         // 8x16 sprites?
         let tall_sprites = self.ppuctrl.is_set(flags::PPUCTRL::H);
@@ -718,8 +919,17 @@ impl PPU {
         let tile_addr_low = pattern_table_base | ((tile_index as u16) << 4) | offset;
         let tile_addr_high = tile_addr_low | 0b1000 | offset;
 
-        let mut tile_byte_low = self.memory.read(tile_addr_low);
-        let mut tile_byte_high = self.memory.read(tile_addr_high);
+        let (mut tile_byte_low, mut tile_byte_high) = if on_bus {
+            (
+                self.memory.read(tile_addr_low),
+                self.memory.read(tile_addr_high),
+            )
+        } else {
+            (
+                self.memory.peek(tile_addr_low),
+                self.memory.peek(tile_addr_high),
+            )
+        };
 
         if attribute & 0x40 != 0 {
             // Horizontal flip.
@@ -727,10 +937,84 @@ impl PPU {
             tile_byte_high = util::reverse_bits(tile_byte_high);
         }
 
-        self.sprites_tile_low[sprite_ix as usize] = tile_byte_low;
-        self.sprites_tile_high[sprite_ix as usize] = tile_byte_high;
-        self.sprites_attribute[sprite_ix as usize] = attribute;
-        self.sprites_x[sprite_ix as usize] = x;
+        self.sprites_tile_low[sprite_ix] = tile_byte_low;
+        self.sprites_tile_high[sprite_ix] = tile_byte_high;
+        self.sprites_attribute[sprite_ix] = attribute;
+        self.sprites_x[sprite_ix] = x;
+
+        if self.tile_output {
+            self.sprites_chr[sprite_ix] = self.peek_tile(tile_addr_low & !0x7);
+            self.sprites_row[sprite_ix] = offset as u8;
+            self.sprites_column[sprite_ix] = 0;
+        }
+    }
+
+    // --- TILE OUTPUT
+    fn peek_tile(&mut self, address: u16) -> [u8; 16] {
+        let mut chr = [0; 16];
+        for (ix, byte) in chr.iter_mut().enumerate() {
+            *byte = self.memory.peek(address + ix as u16);
+        }
+        chr
+    }
+
+    fn palette_colours(&mut self, index: u8) -> [u8; 4] {
+        [
+            self.memory.peek(0x3F00),
+            self.memory.peek(PPU::palette_address(index, 1)),
+            self.memory.peek(PPU::palette_address(index, 2)),
+            self.memory.peek(PPU::palette_address(index, 3)),
+        ]
+    }
+
+    // Background is (colour, palette), sprite is (colour, attribute, index).
+    fn pixel_source(
+        &mut self,
+        background: Option<(u8, u8)>,
+        sprite: Option<(u8, u8, u8)>,
+    ) -> PixelSource {
+        let background = background.map(|(colour, palette)| {
+            // Work out which of the 2 tiles in the shift registers the pixel came from.
+            let bit = 15 - self.fine_x - ((self.cycle - 1) % 8) as u8;
+            let (tile, x) = if bit >= 8 {
+                (0, 15 - bit)
+            } else {
+                (1, 7 - bit)
+            };
+            let (chr, y) = self.bg_tiles[tile];
+            TileSource {
+                chr,
+                palette: self.palette_colours(palette),
+                colour,
+                x,
+                y,
+                h_flip: false,
+                v_flip: false,
+                behind_background: false,
+            }
+        });
+
+        let sprite = sprite.map(|(colour, attribute, ix)| {
+            let ix = ix as usize;
+            let h_flip = attribute & 0x40 != 0;
+            let column = self.sprites_column[ix];
+            TileSource {
+                chr: self.sprites_chr[ix],
+                palette: self.palette_colours((attribute & 0x3) | 0x04),
+                colour,
+                x: if h_flip { 7 - column } else { column },
+                y: self.sprites_row[ix],
+                h_flip,
+                v_flip: attribute & 0x80 != 0,
+                behind_background: attribute & 0x20 != 0,
+            }
+        });
+
+        PixelSource {
+            backdrop: self.memory.peek(0x3F00),
+            background,
+            sprite,
+        }
     }
 
     // --- SCROLLING
@@ -861,7 +1145,8 @@ impl PPU {
     }
 
     fn sprite_colour(&self) -> (u8, u8, u8) {
-        for ix in 0..self.num_sprites {
+        // Extra sprites are lower priority than the first 8, as they come after them in OAM.
+        for ix in (0..self.num_sprites).chain(8..8 + self.num_extra_sprites) {
             // Don't consider inactive sprites.
             if self.sprites_x[ix as usize] > 0 {
                 continue;
@@ -879,13 +1164,16 @@ impl PPU {
     }
 
     fn shift_sprite_registers(&mut self) {
-        for ix in 0..8 {
+        for ix in 0..8 + self.num_extra_sprites as usize {
             // Decrement x if non-zero, otherwise shift tiles.
             if self.sprites_x[ix] > 0 {
                 self.sprites_x[ix] -= 1;
             } else {
                 self.sprites_tile_high[ix] <<= 1;
                 self.sprites_tile_low[ix] <<= 1;
+                if self.tile_output {
+                    self.sprites_column[ix] = self.sprites_column[ix].saturating_add(1);
+                }
             }
         }
     }
@@ -21,11 +21,11 @@ impl<'de> SaveState<'de, PPUState> for PPU {
             attribute_latch_1: self.attribute_latch_1,
             attribute_latch_2: self.attribute_latch_2,
             oam: self.oam.to_vec(),
-            secondary_oam: self.secondary_oam.to_vec(),
-            sprites_tile_high: self.sprites_tile_high.to_vec(),
-            sprites_tile_low: self.sprites_tile_low.to_vec(),
-            sprites_attribute: self.sprites_attribute.to_vec(),
-            sprites_x: self.sprites_x.to_vec(),
+            secondary_oam: self.secondary_oam[..32].to_vec(),
+            sprites_tile_high: self.sprites_tile_high[..8].to_vec(),
+            sprites_tile_low: self.sprites_tile_low[..8].to_vec(),
+            sprites_attribute: self.sprites_attribute[..8].to_vec(),
+            sprites_x: self.sprites_x[..8].to_vec(),
             scanline: self.scanline,
             cycle: self.cycle,
             tmp_pattern_coords: self.tmp_pattern_coords,
@@ -62,15 +62,12 @@ impl<'de> SaveState<'de, PPUState> for PPU {
         self.attribute_latch_1 = state.attribute_latch_1;
         self.attribute_latch_2 = state.attribute_latch_2;
         self.oam.copy_from_slice(state.oam.as_slice());
-        self.secondary_oam
-            .copy_from_slice(state.secondary_oam.as_slice());
-        self.sprites_tile_high
-            .copy_from_slice(state.sprites_tile_high.as_slice());
-        self.sprites_tile_low
-            .copy_from_slice(state.sprites_tile_low.as_slice());
-        self.sprites_attribute
-            .copy_from_slice(state.sprites_attribute.as_slice());
-        self.sprites_x.copy_from_slice(state.sprites_x.as_slice());
+        // Only the hardware's 8 sprites are saved, so extra sprites come back from the next line.
+        self.secondary_oam[..32].copy_from_slice(state.secondary_oam.as_slice());
+        self.sprites_tile_high[..8].copy_from_slice(state.sprites_tile_high.as_slice());
+        self.sprites_tile_low[..8].copy_from_slice(state.sprites_tile_low.as_slice());
+        self.sprites_attribute[..8].copy_from_slice(state.sprites_attribute.as_slice());
+        self.sprites_x[..8].copy_from_slice(state.sprites_x.as_slice());
         self.scanline = state.scanline;
         self.cycle = state.cycle;
         self.tmp_pattern_coords = state.tmp_pattern_coords;
@@ -84,6 +81,8 @@ impl<'de> SaveState<'de, PPUState> for PPU {
         self.num_sprites = state.num_sprites;
         self.sprite_0_next_line = state.sprite_0_next_line;
         self.sprite_0_this_line = state.sprite_0_this_line;
+        self.extra_sprites_copied = 0;
+        self.num_extra_sprites = 0;
         self.ppudata_read_buffer = state.ppudata_read_buffer;
         self.bus_latch = state.bus_latch;
     }
@@ -1,5 +1,6 @@
 mod background;
 mod data;
+mod sprites;
 
 use crate::emulator::memory;
 use crate::emulator::memory::Writer;
@@ -0,0 +1,86 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::emulator::clock::Ticker;
+use crate::emulator::memory::Writer;
+use crate::emulator::ppu::flags;
+use crate::emulator::ppu::test::load_data_into_vram;
+use crate::emulator::ppu::test::new_ppu;
+use crate::emulator::ppu::{Colour, VideoOut, PPU};
+
+// Keeps the colour bytes of the last frame.
+struct FrameCapture {
+    dot: usize,
+    frame: Rc<RefCell<Vec<u8>>>,
+}
+
+impl VideoOut for FrameCapture {
+    fn emit(&mut self, c: Colour) {
+        self.frame.borrow_mut()[self.dot] = c.byte;
+        self.dot = (self.dot + 1) % (256 * 240);
+    }
+}
+
+// 9 solid sprites side by side on scanlines 11-18, one more than the hardware can draw.
+fn nine_sprites(sprite_limit: bool) -> (PPU, Rc<RefCell<Vec<u8>>>) {
+    let frame = Rc::new(RefCell::new(vec![0; 256 * 240]));
+    let capture = FrameCapture {
+        dot: 0,
+        frame: frame.clone(),
+    };
+    let mut ppu = new_ppu(Box::new(capture));
+    ppu.set_sprite_limit(sprite_limit);
+
+    // Tile 1 is solid colour 1, drawn in red, on black.
+    load_data_into_vram(&mut ppu, 0x0010, &[0xFF; 8]);
+    load_data_into_vram(&mut ppu, 0x3F00, &[0x0F]);
+    load_data_into_vram(&mut ppu, 0x3F11, &[0x16]);
+
+    ppu.write(0x2003, 0);
+    for n in 0..64 {
+        let sprite = if n < 9 {
+            [10, 1, 0, n * 16]
+        } else {
+            [0xFF, 0, 0, 0]
+        };
+        sprite.iter().for_each(|&b| ppu.write(0x2004, b));
+    }
+
+    // PPUMASK.  Enable sprites only.
+    ppu.write(0x2001, 0b0001_0100);
+
+    // Run up to the end of the second frame.
+    for _ in 0..2 {
+        while ppu.scanline != 240 {
+            ppu.tick();
+        }
+        while ppu.scanline == 240 {
+            ppu.tick();
+        }
+    }
+
+    (ppu, frame)
+}
+
+#[test]
+fn test_sprite_limit() {
+    let (ppu, frame) = nine_sprites(true);
+    let frame = frame.borrow();
+
+    assert_eq!(frame[12 * 256 + 7 * 16 + 4], 0x16);
+    assert_eq!(frame[12 * 256 + 8 * 16 + 4], 0x0F);
+    assert!(ppu.ppustatus.is_set(flags::PPUSTATUS::O));
+}
+
+#[test]
+fn test_no_sprite_limit() {
+    let (ppu, frame) = nine_sprites(false);
+    let frame = frame.borrow();
+
+    assert_eq!(frame[12 * 256 + 7 * 16 + 4], 0x16);
+    assert_eq!(frame[12 * 256 + 8 * 16 + 4], 0x16);
+    assert_eq!(frame[19 * 256 + 8 * 16 + 4], 0x0F);
+
+    // The CPU still sees the overflow.
+    assert!(ppu.ppustatus.is_set(flags::PPUSTATUS::O));
+}
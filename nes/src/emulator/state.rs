@@ -1,15 +1,43 @@
 // This file contains the save states API.
 // Changes could break old save states.
 
+use std::io::{Read, Write};
+
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
 use serde::{Deserialize, Serialize};
+use serde_json::Serializer;
 
 use crate::emulator::ppu::MirrorMode;
+use crate::emulator::NES;
 
 pub trait SaveState<'de, T: Serialize + Deserialize<'de>> {
     fn freeze(&mut self) -> T;
     fn hydrate(&mut self, t: T);
 }
 
+// States are kept as gzipped JSON, in files or embedded in movies.
+pub fn write_state<W: Write>(nes: &mut NES, writer: W) -> Result<W, String> {
+    let gzip = GzEncoder::new(writer, Compression::best());
+    let mut serializer = Serializer::new(gzip);
+
+    let state = nes.freeze();
+
+    state
+        .serialize(&mut serializer)
+        .map_err(|e| e.to_string())?;
+
+    serializer.into_inner().finish().map_err(|e| e.to_string())
+}
+
+pub fn read_state<R: Read>(nes: &mut NES, reader: R) -> Result<(), String> {
+    let gzip = GzDecoder::new(reader);
+    let state = serde_json::from_reader(gzip).map_err(|e| e.to_string())?;
+    nes.hydrate(state);
+    Ok(())
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct NESState {
     pub cpu: CPUState,
@@ -21,6 +49,9 @@ pub struct NESState {
     pub screen: ScreenState,
     pub joy1: ControllerState,
     pub joy2: ControllerState,
+    // Frames run since power-on, which movies are synced to.
+    #[serde(default)]
+    pub frame_count: u64,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
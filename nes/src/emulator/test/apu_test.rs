@@ -0,0 +1,50 @@
+use crate::emulator::test::load_and_run_blargg_test_rom;
+use crate::emulator::test::load_and_run_blargg_test_rom_with_cycles;
+use crate::emulator::test::test_resource_path;
+
+// -- apu_test test ROMs --
+// Only singles 4, 5, 7 and 8 are checked in, so 1-3 and 6 are covered by the full ROM.
+#[test]
+fn test_apu_test() {
+    let path = test_resource_path("apu_test/apu_test.nes");
+    let (status, output) = load_and_run_blargg_test_rom_with_cycles(path, 1_000_000_000);
+
+    assert_eq!(status, 0x00);
+    assert_eq!(output, "\n8-dmc_rates\n\nPassed\nAll 8 tests passed\n\n\n");
+}
+
+#[test]
+fn test_apu_test_4() {
+    let path = test_resource_path("apu_test/rom_singles/4-jitter.nes");
+    let (status, output) = load_and_run_blargg_test_rom(path);
+
+    assert_eq!(status, 0x00);
+    assert_eq!(output, "\n4-jitter\n\nPassed\n");
+}
+
+#[test]
+fn test_apu_test_5() {
+    let path = test_resource_path("apu_test/rom_singles/5-len_timing.nes");
+    let (status, output) = load_and_run_blargg_test_rom(path);
+
+    assert_eq!(status, 0x00);
+    assert_eq!(output, "\n5-len_timing\n\nPassed\n");
+}
+
+#[test]
+fn test_apu_test_7() {
+    let path = test_resource_path("apu_test/rom_singles/7-dmc_basics.nes");
+    let (status, output) = load_and_run_blargg_test_rom(path);
+
+    assert_eq!(status, 0x00);
+    assert_eq!(output, "\n7-dmc_basics\n\nPassed\n");
+}
+
+#[test]
+fn test_apu_test_8() {
+    let path = test_resource_path("apu_test/rom_singles/8-dmc_rates.nes");
+    let (status, output) = load_and_run_blargg_test_rom(path);
+
+    assert_eq!(status, 0x00);
+    assert_eq!(output, "\n8-dmc_rates\n\nPassed\n");
+}
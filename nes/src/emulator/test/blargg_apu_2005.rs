@@ -0,0 +1,72 @@
+use crate::emulator::test::load_and_run_blargg_2005_test_rom;
+use crate::emulator::test::test_resource_path;
+
+// -- blargg_apu_2005.07.30 test ROMs --
+#[test]
+fn test_blargg_apu_2005_01() {
+    let path = test_resource_path("blargg_apu_2005.07.30/01.len_ctr.nes");
+    assert_eq!(load_and_run_blargg_2005_test_rom(path), 0x01);
+}
+
+#[test]
+fn test_blargg_apu_2005_02() {
+    let path = test_resource_path("blargg_apu_2005.07.30/02.len_table.nes");
+    assert_eq!(load_and_run_blargg_2005_test_rom(path), 0x01);
+}
+
+#[test]
+fn test_blargg_apu_2005_03() {
+    let path = test_resource_path("blargg_apu_2005.07.30/03.irq_flag.nes");
+    assert_eq!(load_and_run_blargg_2005_test_rom(path), 0x01);
+}
+
+#[test]
+fn test_blargg_apu_2005_04() {
+    let path = test_resource_path("blargg_apu_2005.07.30/04.clock_jitter.nes");
+    assert_eq!(load_and_run_blargg_2005_test_rom(path), 0x01);
+}
+
+#[test]
+fn test_blargg_apu_2005_05() {
+    let path = test_resource_path("blargg_apu_2005.07.30/05.len_timing_mode0.nes");
+    assert_eq!(load_and_run_blargg_2005_test_rom(path), 0x01);
+}
+
+#[test]
+fn test_blargg_apu_2005_06() {
+    let path = test_resource_path("blargg_apu_2005.07.30/06.len_timing_mode1.nes");
+    assert_eq!(load_and_run_blargg_2005_test_rom(path), 0x01);
+}
+
+#[test]
+fn test_blargg_apu_2005_07() {
+    let path = test_resource_path("blargg_apu_2005.07.30/07.irq_flag_timing.nes");
+    assert_eq!(load_and_run_blargg_2005_test_rom(path), 0x01);
+}
+
+// TODO: Get 08 to pass once my CPU is timed cycle-accurate. Register writes land at the start
+// of each instruction rather than on its last cycle, so the frame IRQ arrives a few cycles early.
+#[ignore = "needs a cycle-accurate CPU"]
+#[test]
+fn test_blargg_apu_2005_08() {
+    let path = test_resource_path("blargg_apu_2005.07.30/08.irq_timing.nes");
+    assert_eq!(load_and_run_blargg_2005_test_rom(path), 0x01);
+}
+
+#[test]
+fn test_blargg_apu_2005_09() {
+    let path = test_resource_path("blargg_apu_2005.07.30/09.reset_timing.nes");
+    assert_eq!(load_and_run_blargg_2005_test_rom(path), 0x01);
+}
+
+#[test]
+fn test_blargg_apu_2005_10() {
+    let path = test_resource_path("blargg_apu_2005.07.30/10.len_halt_timing.nes");
+    assert_eq!(load_and_run_blargg_2005_test_rom(path), 0x01);
+}
+
+#[test]
+fn test_blargg_apu_2005_11() {
+    let path = test_resource_path("blargg_apu_2005.07.30/11.len_reload_timing.nes");
+    assert_eq!(load_and_run_blargg_2005_test_rom(path), 0x01);
+}
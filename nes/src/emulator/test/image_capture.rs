@@ -2,9 +2,7 @@ use std::cell::RefCell;
 use std::path::Path;
 use std::rc::Rc;
 
-use sdl2::pixels;
-use sdl2::surface;
-
+use crate::emulator::io::screenshot::save_screen;
 use crate::emulator::io::Screen;
 
 pub struct ImageCapture {
@@ -16,28 +14,10 @@ impl ImageCapture {
         ImageCapture { screen }
     }
 
-    // SDL2 must already be initialized when this is called.
-    pub fn save_bmp(&self, path: &Path) {
-        self.screen.borrow().do_render(|buffer| {
-            // Make a copy of the data so it doesn't need to be mutable.
-            let mut copy = Vec::from(buffer);
-            let surface = surface::Surface::from_data(
-                copy.as_mut_slice(),
-                256,
-                240,
-                256 * 3,
-                pixels::PixelFormatEnum::RGB24,
-            );
-
-            let result = match surface {
-                Err(cause) => panic!("Failed to create surface: {}", cause),
-                Ok(s) => s.save_bmp(path),
-            };
-
-            match result {
-                Err(cause) => panic!("Failed to save bmp image: {}", cause),
-                Ok(_) => (),
-            };
-        });
+    pub fn save_png(&self, path: &Path) {
+        match save_screen(&self.screen.borrow(), path) {
+            Err(cause) => panic!("Failed to save png image: {}", cause),
+            Ok(_) => (),
+        };
     }
 }
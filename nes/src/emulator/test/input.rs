@@ -0,0 +1,20 @@
+use crate::emulator::input::{Port, Unplugged};
+
+use crate::emulator::test::prepare_ete_test;
+use crate::emulator::test::test_resource_path;
+
+// A macro on player 2's controller carries on playing while something else is in its port.
+#[test]
+fn test_controller_frames_while_unplugged() {
+    let path = test_resource_path("nestest/nestest.nes");
+    let (mut nes, _, _) = prepare_ete_test(&path);
+    nes.connect(Port::Two, Box::new(Unplugged));
+    nes.joy2.borrow_mut().play(vec![0x01; 3]);
+    assert_eq!(nes.joy2.borrow().buttons(), 0x01);
+
+    let end = nes.ppu.borrow().frame() + 5;
+    while nes.ppu.borrow().frame() < end {
+        nes.tick();
+    }
+    assert_eq!(nes.joy2.borrow().buttons(), 0x00);
+}
@@ -16,7 +16,7 @@ fn test_instr_timing_1() {
     run_for(&mut nes, 220_500_000);
     assert_image(
         &mut image,
-        test_resource_path("instr_timing/1-instr_timing.bmp"),
+        test_resource_path("instr_timing/1-instr_timing.png"),
     );
 }
 
@@ -16,7 +16,7 @@ macro_rules! test_mapper {
                 let path = test_resource_path(&format!("mappers/{}.nes", $rom));
                 let (mut nes, _, image) = prepare_ete_test(&path);
                 run_for(&mut nes, $cycles);
-                assert_image(&image, test_resource_path(&format!("mappers/{}.bmp", $rom)));
+                assert_image(&image, test_resource_path(&format!("mappers/{}.png", $rom)));
             }
 
             #[test]
@@ -31,7 +31,7 @@ macro_rules! test_mapper {
                 run_for(&mut nes_2, $cycles / 2);
                 assert_image(
                     &image_2,
-                    test_resource_path(&format!("mappers/{}.bmp", $rom)),
+                    test_resource_path(&format!("mappers/{}.png", $rom)),
                 );
             }
         }
@@ -0,0 +1,164 @@
+use std::cell::RefCell;
+use std::path::Path;
+use std::rc::Rc;
+
+use crate::emulator::apu::AudioOut;
+use crate::emulator::controller::Bindings;
+use crate::emulator::ines;
+use crate::emulator::io::event::EventBus;
+use crate::emulator::io::event::{Event, Key};
+use crate::emulator::io::Screen;
+use crate::emulator::memory::Writer;
+use crate::emulator::movie::{Movie, MovieMode, MovieStart};
+use crate::emulator::state::{read_state, write_state, SaveState};
+use crate::emulator::NES;
+
+use crate::emulator::test::prepare_ete_test;
+use crate::emulator::test::run_for;
+use crate::emulator::test::test_resource_path;
+
+// Records nestest being started from the menu, then plays it back on a fresh NES without any
+// input. With RAM hashed every frame, any divergence shows up as a desync.
+#[test]
+fn test_movie_playback() {
+    let path = test_resource_path("nestest/nestest.nes");
+    let (mut nes, event_bus, _) = prepare_ete_test(&path);
+    let mut movie = Movie::new("nestest", MovieStart::PowerOn);
+    movie.hash_interval = 1;
+    nes.record_movie(movie);
+
+    run_for(&mut nes, 2_000_000);
+    event_bus.borrow_mut().broadcast(Event::KeyDown(Key::A));
+    run_for(&mut nes, 200_000);
+    event_bus.borrow_mut().broadcast(Event::KeyUp(Key::A));
+    run_for(&mut nes, 2_000_000);
+    let movie = nes.stop_movie().unwrap();
+    assert!(movie.frames.iter().any(|frame| frame.buttons[0] != 0));
+    assert_eq!(movie.ram_hashes.len(), movie.frames.len());
+
+    let mut movie = Movie::from_bytes(&movie.to_bytes()).unwrap();
+    let (mut nes_2, _, _) = prepare_ete_test(&path);
+    nes_2.play_movie(movie.clone(), true);
+    run_for(&mut nes_2, 4_400_000);
+
+    let session = nes_2.movie().unwrap();
+    assert_eq!(session.mode(), MovieMode::Finished);
+    assert_eq!(session.desync(), None);
+
+    // Without the button press, the tests never start.
+    for frame in movie.frames.iter_mut() {
+        frame.buttons = [0, 0];
+    }
+    nes_2.play_movie(movie, true);
+    run_for(&mut nes_2, 4_400_000);
+    assert!(nes_2.movie().unwrap().desync().is_some());
+}
+
+struct CapturedAudio {
+    samples: Vec<f32>,
+}
+
+impl AudioOut for CapturedAudio {
+    fn emit(&mut self, sample: f32) {
+        self.samples.push(sample);
+    }
+}
+
+// Sets up a NES like prepare_ete_test, but keeping everything the APU outputs.
+fn prepare_with_audio<P: AsRef<Path>>(
+    path: P,
+) -> (NES, Rc<RefCell<EventBus>>, Rc<RefCell<CapturedAudio>>) {
+    let rom = ines::ROM::load(path);
+    let event_bus = Rc::new(RefCell::new(EventBus::new()));
+    let screen = Rc::new(RefCell::new(Screen::new()));
+    let audio = Rc::new(RefCell::new(CapturedAudio { samples: vec![] }));
+    let nes = NES::new(
+        event_bus.clone(),
+        screen,
+        audio.clone(),
+        rom,
+        &Bindings::new(),
+    );
+    (nes, event_bus, audio)
+}
+
+// A movie from power-on recorded part way through a session plays back identically on a fresh
+// NES, so power cycling has to leave nothing behind from before, down to the clock's phase.
+#[test]
+fn test_movie_power_on_after_running() {
+    let path = test_resource_path("nestest/nestest.nes");
+    let (mut nes, event_bus, audio) = prepare_with_audio(&path);
+    nes.tick_multi(1_234_567);
+    // Leave the APU's frame counter in five-step mode and the DMC's output raised, which
+    // power-on should undo.
+    nes.apu.borrow_mut().write(0x4017, 0x80);
+    nes.apu.borrow_mut().write(0x4011, 0x7F);
+    let start = audio.borrow().samples.len();
+
+    let mut movie = Movie::new("nestest", MovieStart::PowerOn);
+    movie.hash_interval = 1;
+    nes.record_movie(movie);
+    nes.tick_multi(200_000);
+    event_bus.borrow_mut().broadcast(Event::KeyDown(Key::A));
+    nes.tick_multi(20_000);
+    event_bus.borrow_mut().broadcast(Event::KeyUp(Key::A));
+    nes.tick_multi(200_000);
+    let movie = nes.stop_movie().unwrap();
+
+    let (mut nes_2, _, audio_2) = prepare_with_audio(&path);
+    nes_2.play_movie(movie, true);
+    nes_2.tick_multi(420_000);
+    assert_eq!(nes_2.movie().unwrap().desync(), None);
+    nes_2.stop_movie();
+
+    assert_eq!(
+        format!("{:?}", nes.freeze().cpu),
+        format!("{:?}", nes_2.freeze().cpu)
+    );
+    assert_eq!(
+        format!("{:?}", nes.freeze().ppu),
+        format!("{:?}", nes_2.freeze().ppu)
+    );
+    assert_eq!(
+        format!("{:?}", nes.freeze().ram),
+        format!("{:?}", nes_2.freeze().ram)
+    );
+    assert_eq!(
+        audio.borrow().samples[start..],
+        audio_2.borrow().samples[..]
+    );
+}
+
+// Loading a save state while recording rewinds the movie to where it was saved.
+#[test]
+fn test_movie_rerecord() {
+    let path = test_resource_path("nestest/nestest.nes");
+    let (mut nes, _, _) = prepare_ete_test(&path);
+    nes.record_movie(Movie::new("nestest", MovieStart::PowerOn));
+
+    run_for(&mut nes, 1_000_000);
+    let state = nes.freeze();
+    let saved_at = nes.movie().unwrap().position(nes.frame_count());
+    run_for(&mut nes, 1_000_000);
+    nes.hydrate(state);
+
+    let movie = nes.stop_movie().unwrap();
+    assert_eq!(movie.frames.len(), saved_at);
+    assert_eq!(movie.rerecords, 1);
+}
+
+// States written the way the frontends save them load back to the same point.
+#[test]
+fn test_state_round_trip() {
+    let path = test_resource_path("nestest/nestest.nes");
+    let (mut nes, _, _) = prepare_ete_test(&path);
+
+    run_for(&mut nes, 1_000_000);
+    let state = write_state(&mut nes, vec![]).unwrap();
+    let saved = format!("{:?}", nes.freeze());
+    run_for(&mut nes, 1_000_000);
+
+    read_state(&mut nes, &state[..]).unwrap();
+    assert_eq!(format!("{:?}", nes.freeze()), saved);
+    assert!(read_state(&mut nes, &state[..10]).is_err());
+}
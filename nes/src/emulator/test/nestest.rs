@@ -14,14 +14,14 @@ fn test_nestest_visual() {
 
     // Check the menu load.
     run_for(&mut nes, 2_000_000);
-    assert_image(&image, test_resource_path("nestest/capture_01_menu.bmp"));
+    assert_image(&image, test_resource_path("nestest/capture_01_menu.png"));
 
     // Start tests.
     event_bus.borrow_mut().broadcast(Event::KeyDown(Key::A));
 
     // Wait for tests to finish and check they pass.
     run_for(&mut nes, 7_000_000);
-    assert_image(&image, test_resource_path("nestest/capture_02_passed.bmp"));
+    assert_image(&image, test_resource_path("nestest/capture_02_passed.png"));
 }
 
 #[test]
@@ -31,7 +31,7 @@ fn test_nestest_savestate() {
 
     // Check the menu load.
     run_for(&mut nes, 2_000_000);
-    assert_image(&image, test_resource_path("nestest/capture_01_menu.bmp"));
+    assert_image(&image, test_resource_path("nestest/capture_01_menu.png"));
 
     // Start tests.
     event_bus.borrow_mut().broadcast(Event::KeyDown(Key::A));
@@ -47,6 +47,6 @@ fn test_nestest_savestate() {
     run_for(&mut nes_2, 3_000_000);
     assert_image(
         &image_2,
-        test_resource_path("nestest/capture_02_passed.bmp"),
+        test_resource_path("nestest/capture_02_passed.png"),
     );
 }
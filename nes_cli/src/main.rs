@@ -0,0 +1,511 @@
+use std::cell::RefCell;
+use std::env;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::process;
+use std::rc::Rc;
+
+use nes::emulator::controller::Bindings;
+use nes::emulator::ines;
+use nes::emulator::io;
+use nes::emulator::io::event::EventBus;
+use nes::emulator::io::screenshot::save_screen;
+use nes::emulator::io::wav::{SampleFormat, WavRecorder};
+use nes::emulator::io::OutputMode;
+use nes::emulator::movie::{Movie, MovieMode, MovieStart};
+use nes::emulator::state::{read_state, write_state};
+use nes::emulator::{NES, NES_MASTER_CLOCK_HZ};
+
+const SAMPLE_RATE: f32 = 48_000.0;
+
+// Exit codes, which stay clear of the small numbers blargg's tests report failures with.
+const EXIT_USAGE: i32 = 64;
+const EXIT_DESYNC: i32 = 65;
+const EXIT_IO: i32 = 74;
+const EXIT_TIMEOUT: i32 = 124;
+
+// How long blargg's tests get to finish without --frames, a minute at 60 frames per second.
+const DEFAULT_BLARGG_FRAMES: u64 = 60 * 60;
+
+const USAGE: &str = "Usage: nes_cli ROM [options]
+
+Runs a ROM with no window or sound, until a number of frames, the end of a movie, or a
+blargg test finishing.
+
+Options:
+  --frames=N           Stop after N frames.
+  --blargg             Stop when the test at $6000 finishes, and exit with its result.
+  --movie=PATH         Play a movie (.fm2 or our own), stopping at its end.
+  --load-state=PATH    Start from a save state.
+  --screenshot=PATH    Save the last frame as a PNG.
+  --wav=PATH           Record the audio.
+  --avi=PATH           Record video and audio as an uncompressed AVI.
+  --ram=PATH           Dump the 2KB of internal RAM at the end.
+  --save-state=PATH    Save the state at the end.
+
+Exit codes:
+  0    Finished, or the blargg test passed.
+  N    The blargg test failed with result code N.
+  64   Bad arguments.
+  65   The movie desynced.
+  74   A file couldn't be read or written.
+  124  The blargg test didn't finish in time.";
+
+struct Options {
+    rom_path: String,
+    frames: Option<u64>,
+    blargg: bool,
+    movie: Option<String>,
+    load_state: Option<String>,
+    screenshot: Option<String>,
+    wav: Option<String>,
+    avi: Option<String>,
+    ram: Option<String>,
+    save_state: Option<String>,
+}
+
+impl Options {
+    fn parse(args: &[String]) -> Result<Options, String> {
+        let rom_path = match args.get(1) {
+            Some(path) if !path.starts_with("--") => path.clone(),
+            _ => return Err(String::from("You must pass in a path to an iNES ROM.")),
+        };
+
+        let mut options = Options {
+            rom_path,
+            frames: None,
+            blargg: false,
+            movie: None,
+            load_state: None,
+            screenshot: None,
+            wav: None,
+            avi: None,
+            ram: None,
+            save_state: None,
+        };
+        for arg in args.iter().skip(2) {
+            let (name, value) = match arg.find('=') {
+                Some(ix) => (&arg[..ix], Some(arg[ix + 1..].to_string())),
+                None => (arg.as_str(), None),
+            };
+            match (name, value) {
+                ("--frames", Some(frames)) => match frames.parse() {
+                    Err(_) => return Err(format!("Invalid number of frames: {}", frames)),
+                    Ok(frames) => options.frames = Some(frames),
+                },
+                ("--blargg", None) => options.blargg = true,
+                ("--movie", path) if path.is_some() => options.movie = path,
+                ("--load-state", path) if path.is_some() => options.load_state = path,
+                ("--screenshot", path) if path.is_some() => options.screenshot = path,
+                ("--wav", path) if path.is_some() => options.wav = path,
+                ("--avi", path) if path.is_some() => options.avi = path,
+                ("--ram", path) if path.is_some() => options.ram = path,
+                ("--save-state", path) if path.is_some() => options.save_state = path,
+                _ => return Err(format!("Unrecognised argument: {}", arg)),
+            }
+        }
+
+        if options.frames.is_none() && !options.blargg && options.movie.is_none() {
+            return Err(String::from(
+                "Nothing to stop at, pass --frames, --blargg or --movie.",
+            ));
+        }
+        Ok(options)
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let options = match Options::parse(&args) {
+        Err(cause) => {
+            eprintln!("{}\n\n{}", cause, USAGE);
+            process::exit(EXIT_USAGE);
+        }
+        Ok(options) => options,
+    };
+
+    match run(&options) {
+        Err(cause) => {
+            eprintln!("{}", cause);
+            process::exit(EXIT_IO);
+        }
+        Ok(code) => process::exit(code),
+    }
+}
+
+// Runs the ROM as the options say, returning the exit code.
+fn run(options: &Options) -> Result<i32, String> {
+    let rom = ines::ROM::from_bytes(read_file(&options.rom_path)?);
+    let event_bus = Rc::new(RefCell::new(EventBus::new()));
+    let screen = Rc::new(RefCell::new(io::Screen::new()));
+    let audio = Rc::new(RefCell::new(WavRecorder::new(io::SimpleAudioOut::new(
+        SAMPLE_RATE,
+    ))));
+    let mut nes = NES::new(
+        event_bus,
+        screen.clone(),
+        audio.clone(),
+        rom,
+        &Bindings::new(),
+    );
+
+    if let Some(path) = &options.load_state {
+        read_state(&mut nes, &read_file(path)?[..])?;
+    }
+    if let Some(path) = &options.movie {
+        let movie = Movie::load(path)?;
+        if let MovieStart::SaveState(ref state) = movie.start {
+            read_state(&mut nes, &state[..])?;
+        }
+        nes.play_movie(movie, true);
+    }
+    if let Some(path) = &options.wav {
+        audio
+            .borrow_mut()
+            .start_recording(path, SampleFormat::Int16, false)
+            .map_err(|e| format!("Couldn't record audio: {}", e))?;
+    }
+    if let Some(path) = &options.avi {
+        nes.start_video_recording(path, SAMPLE_RATE, OutputMode::Mono)
+            .map_err(|e| format!("Couldn't record video: {}", e))?;
+    }
+
+    let max_frames = match (options.frames, options.blargg) {
+        (Some(frames), _) => Some(frames),
+        (None, true) => Some(DEFAULT_BLARGG_FRAMES),
+        (None, false) => None,
+    };
+
+    let mut blargg = BlarggMonitor::new();
+    let mut result = None;
+    let mut frames = 0;
+    let mut cycles = 0;
+    let mut samples = 0;
+    while max_frames.is_none_or(|max| frames < max) {
+        let frame_cycles = run_frame(&mut nes);
+        frames += 1;
+
+        // Keep the audio in step with the master clock, however long the frame was.
+        cycles += frame_cycles;
+        let total_samples = cycles * SAMPLE_RATE as u64 / NES_MASTER_CLOCK_HZ;
+        audio
+            .borrow_mut()
+            .consume(frame_cycles, total_samples - samples, |_| ());
+        samples = total_samples;
+
+        if options.blargg {
+            result = blargg.check(&mut nes);
+            if result.is_some() {
+                break;
+            }
+        }
+        if nes
+            .movie()
+            .is_some_and(|session| session.mode() == MovieMode::Finished)
+            && !options.blargg
+        {
+            break;
+        }
+    }
+    println!("Ran {} frames", frames);
+
+    if let Some(path) = &options.screenshot {
+        save_screen(&screen.borrow(), path)
+            .map_err(|e| format!("Couldn't write {}: {}", path, e))?;
+    }
+    if let Some(path) = &options.ram {
+        let ram = nes.ram.borrow();
+        let data: Vec<u8> = (0..ram.len()).map(|ix| ram.get(ix)).collect();
+        write_file(path, &data)?;
+    }
+    if let Some(path) = &options.save_state {
+        let state = write_state(&mut nes, vec![])?;
+        write_file(path, &state)?;
+    }
+    if options.wav.is_some() {
+        audio
+            .borrow_mut()
+            .stop_recording()
+            .map_err(|e| format!("Couldn't record audio: {}", e))?;
+    }
+
+    if options.avi.is_some() {
+        nes.stop_video_recording()
+            .map_err(|e| format!("Couldn't record video: {}", e))?;
+    }
+
+    if let Some(frame) = nes.movie().and_then(|session| session.desync()) {
+        println!("Movie desynced at frame {}", frame);
+        return Ok(EXIT_DESYNC);
+    }
+    if !options.blargg {
+        return Ok(0);
+    }
+    match result {
+        None => {
+            println!("Test didn't finish after {} frames", frames);
+            Ok(EXIT_TIMEOUT)
+        }
+        Some(status) => {
+            println!("{}", blargg_output(&mut nes));
+            println!("Result: {}", if status == 0 { "passed" } else { "failed" });
+            Ok(status as i32)
+        }
+    }
+}
+
+// Runs until the next frame begins, returning the master clock cycles taken.
+fn run_frame(nes: &mut NES) -> u64 {
+    let frame = nes.frame_count();
+    let mut cycles = 0;
+    while nes.frame_count() == frame {
+        cycles += nes.tick();
+    }
+    cycles
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum BlarggStatus {
+    Running,
+    Reset,
+    Finished(u8),
+}
+
+// Follows a blargg test ROM through $6000, which reads $80 while the test runs, $81 when it wants
+// resetting, and then the result code. $6001-$6003 read DE B0 61 once it's valid.
+struct BlarggMonitor {
+    started: bool,
+    reset_countdown: Option<u8>,
+}
+
+impl BlarggMonitor {
+    // The ROMs ask for the reset to be at least 100ms after they ask.
+    const RESET_DELAY_FRAMES: u8 = 6;
+
+    fn new() -> BlarggMonitor {
+        BlarggMonitor {
+            started: false,
+            reset_countdown: None,
+        }
+    }
+
+    // The result code, once the test has finished.
+    fn check(&mut self, nes: &mut NES) -> Option<u8> {
+        let (status, signature) = {
+            let mut cpu = nes.cpu.borrow_mut();
+            let signature = [
+                cpu.load_memory(0x6001),
+                cpu.load_memory(0x6002),
+                cpu.load_memory(0x6003),
+            ];
+            (cpu.load_memory(0x6000), signature)
+        };
+        match self.update(status, signature) {
+            BlarggStatus::Running => None,
+            BlarggStatus::Reset => {
+                nes.reset();
+                None
+            }
+            BlarggStatus::Finished(result) => Some(result),
+        }
+    }
+
+    // Steps through one frame's reading of $6000-$6003.
+    fn update(&mut self, status: u8, signature: [u8; 3]) -> BlarggStatus {
+        if signature != [0xDE, 0xB0, 0x61] {
+            return BlarggStatus::Running;
+        }
+
+        match status {
+            0x80 => self.started = true,
+            0x81 => match self.reset_countdown {
+                None => self.reset_countdown = Some(BlarggMonitor::RESET_DELAY_FRAMES),
+                Some(0) => {
+                    self.reset_countdown = None;
+                    return BlarggStatus::Reset;
+                }
+                Some(n) => self.reset_countdown = Some(n - 1),
+            },
+            status if self.started => return BlarggStatus::Finished(status),
+            _ => (),
+        }
+        BlarggStatus::Running
+    }
+}
+
+// The text the test has written from $6004.
+fn blargg_output(nes: &mut NES) -> String {
+    let mut cpu = nes.cpu.borrow_mut();
+    let text: Vec<u8> = (0x6004..0x8000)
+        .map(|address| cpu.load_memory(address))
+        .take_while(|&byte| byte != 0)
+        .collect();
+    String::from_utf8_lossy(&text).trim_end().to_string()
+}
+
+fn read_file(path: &str) -> Result<Vec<u8>, String> {
+    let mut contents = vec![];
+    File::open(path)
+        .and_then(|mut file| file.read_to_end(&mut contents))
+        .map_err(|e| format!("Couldn't read {}: {}", path, e))?;
+    Ok(contents)
+}
+
+fn write_file(path: &str, data: &[u8]) -> Result<(), String> {
+    File::create(path)
+        .and_then(|mut file| file.write_all(data))
+        .map_err(|e| format!("Couldn't write {}: {}", path, e))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{BlarggMonitor, BlarggStatus, Options};
+
+    const SIGNATURE: [u8; 3] = [0xDE, 0xB0, 0x61];
+
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        let args: Vec<String> = ["nes_cli"]
+            .iter()
+            .chain(args.iter())
+            .map(|arg| arg.to_string())
+            .collect();
+        Options::parse(&args)
+    }
+
+    #[test]
+    fn test_parse() {
+        let options = parse(&[
+            "game.nes",
+            "--frames=120",
+            "--blargg",
+            "--movie=run.fm2",
+            "--load-state=in.state",
+            "--screenshot=shot.png",
+            "--wav=out.wav",
+            "--avi=out.avi",
+            "--ram=ram.bin",
+            "--save-state=out.state",
+        ])
+        .unwrap();
+        assert_eq!(options.rom_path, "game.nes");
+        assert_eq!(options.frames, Some(120));
+        assert!(options.blargg);
+        assert_eq!(options.movie.as_deref(), Some("run.fm2"));
+        assert_eq!(options.load_state.as_deref(), Some("in.state"));
+        assert_eq!(options.screenshot.as_deref(), Some("shot.png"));
+        assert_eq!(options.wav.as_deref(), Some("out.wav"));
+        assert_eq!(options.avi.as_deref(), Some("out.avi"));
+        assert_eq!(options.ram.as_deref(), Some("ram.bin"));
+        assert_eq!(options.save_state.as_deref(), Some("out.state"));
+    }
+
+    #[test]
+    fn test_parse_defaults() {
+        let options = parse(&["game.nes", "--blargg"]).unwrap();
+        assert_eq!(options.frames, None);
+        assert!(options.blargg);
+        assert_eq!(options.movie, None);
+        assert_eq!(options.wav, None);
+
+        // Anything that ends the run will do on its own.
+        assert!(parse(&["game.nes", "--frames=1"]).is_ok());
+        assert!(parse(&["game.nes", "--movie=run.fm2"]).is_ok());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let error = |args: &[&str]| parse(args).err().unwrap();
+
+        assert!(error(&[]).starts_with("You must pass in a path"));
+        assert!(error(&["--frames=10"]).starts_with("You must pass in a path"));
+        assert!(error(&["game.nes"]).starts_with("Nothing to stop at"));
+
+        assert_eq!(
+            error(&["game.nes", "--frames=ten"]),
+            "Invalid number of frames: ten"
+        );
+        assert_eq!(
+            error(&["game.nes", "--frames=-1"]),
+            "Invalid number of frames: -1"
+        );
+        assert_eq!(
+            error(&["game.nes", "--frames="]),
+            "Invalid number of frames: "
+        );
+
+        // Missing values, values where none are taken, and unknown flags.
+        for arg in [
+            "--frames",
+            "--movie",
+            "--wav",
+            "--save-state",
+            "--blargg=1",
+            "--fast",
+        ]
+        .iter()
+        {
+            assert_eq!(
+                error(&["game.nes", "--frames=1", arg]),
+                format!("Unrecognised argument: {}", arg)
+            );
+        }
+    }
+
+    #[test]
+    fn test_blargg_result() {
+        let mut monitor = BlarggMonitor::new();
+
+        // Nothing counts until the test has said it's running.
+        assert_eq!(monitor.update(0x00, SIGNATURE), BlarggStatus::Running);
+        assert_eq!(monitor.update(0x80, SIGNATURE), BlarggStatus::Running);
+        assert_eq!(monitor.update(0x80, SIGNATURE), BlarggStatus::Running);
+        assert_eq!(monitor.update(0x03, SIGNATURE), BlarggStatus::Finished(3));
+
+        let mut monitor = BlarggMonitor::new();
+        monitor.update(0x80, SIGNATURE);
+        assert_eq!(monitor.update(0x00, SIGNATURE), BlarggStatus::Finished(0));
+    }
+
+    #[test]
+    fn test_blargg_signature() {
+        let mut monitor = BlarggMonitor::new();
+
+        // Without the signature, $6000 is just whatever was in SRAM.
+        assert_eq!(monitor.update(0x80, [0, 0, 0]), BlarggStatus::Running);
+        assert_eq!(monitor.update(0x01, [0, 0, 0]), BlarggStatus::Running);
+        assert_eq!(monitor.update(0x01, SIGNATURE), BlarggStatus::Running);
+
+        monitor.update(0x80, SIGNATURE);
+        assert_eq!(
+            monitor.update(0x01, [0xDE, 0xB0, 0x00]),
+            BlarggStatus::Running
+        );
+        assert_eq!(monitor.update(0x01, SIGNATURE), BlarggStatus::Finished(1));
+    }
+
+    #[test]
+    fn test_blargg_reset() {
+        let mut monitor = BlarggMonitor::new();
+        monitor.update(0x80, SIGNATURE);
+
+        // The reset comes after waiting out the delay, and only once.
+        for _ in 0..=BlarggMonitor::RESET_DELAY_FRAMES {
+            assert_eq!(monitor.update(0x81, SIGNATURE), BlarggStatus::Running);
+        }
+        assert_eq!(monitor.update(0x81, SIGNATURE), BlarggStatus::Reset);
+        assert_eq!(monitor.update(0x80, SIGNATURE), BlarggStatus::Running);
+        assert_eq!(monitor.update(0x00, SIGNATURE), BlarggStatus::Finished(0));
+
+        // A second request waits out the whole delay again.
+        let mut monitor = BlarggMonitor::new();
+        monitor.update(0x80, SIGNATURE);
+        for _ in 0..=BlarggMonitor::RESET_DELAY_FRAMES {
+            monitor.update(0x81, SIGNATURE);
+        }
+        assert_eq!(monitor.update(0x81, SIGNATURE), BlarggStatus::Reset);
+        for _ in 0..=BlarggMonitor::RESET_DELAY_FRAMES {
+            assert_eq!(monitor.update(0x81, SIGNATURE), BlarggStatus::Running);
+        }
+        assert_eq!(monitor.update(0x81, SIGNATURE), BlarggStatus::Reset);
+    }
+}
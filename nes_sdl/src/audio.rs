@@ -10,10 +10,11 @@ pub struct AudioQueue {
 }
 
 impl AudioQueue {
-    pub fn new(audio: sdl2::AudioSubsystem, output: Portal<Vec<f32>>) -> AudioQueue {
+    // Samples pushed to the output portal must be interleaved if channels > 1.
+    pub fn new(audio: sdl2::AudioSubsystem, output: Portal<Vec<f32>>, channels: u8) -> AudioQueue {
         let spec = audio::AudioSpecDesired {
             freq: Some(SAMPLE_RATE as i32),
-            channels: Some(1),
+            channels: Some(channels),
             samples: Some(1024),
         };
 
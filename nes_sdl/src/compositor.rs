@@ -1,4 +1,5 @@
 use nes::emulator::apu::debug::APUDebug;
+use nes::emulator::io::display::Display;
 use nes::emulator::ppu::debug::{PPUDebug, PPUDebugRender};
 
 use crate::controller::DebugMode;
@@ -8,9 +9,38 @@ use sdl2::{pixels, rect, render, video};
 
 const SCALE: u8 = 4;
 
+// An RGB frame of emulator output.
+// Frames are normally 256x240, but may be larger, e.g. with an HD pack or a scaler.
+#[derive(Clone, Debug)]
+pub struct Frame {
+    pub width: usize,
+    pub height: usize,
+    pub data: Vec<u8>,
+}
+
+impl Frame {
+    pub fn new(width: usize, height: usize) -> Frame {
+        Frame {
+            width,
+            height,
+            data: vec![0; width * height * 3],
+        }
+    }
+
+    // Replaces the frame's contents, resizing it if needed.
+    pub fn set(&mut self, width: usize, height: usize, data: &[u8]) {
+        self.width = width;
+        self.height = height;
+        self.data.clear();
+        self.data.extend_from_slice(data);
+    }
+}
+
 pub struct Compositor {
     canvas: render::Canvas<video::Window>,
     nes_texture: render::Texture,
+    nes_size: (usize, usize),
+    display: Display,
     debug_canvas: render::Canvas<video::Window>,
     pattern_texture: render::Texture,
     nametable_texture: render::Texture,
@@ -18,7 +48,7 @@ pub struct Compositor {
     palette_texture: render::Texture,
     waveform_texture: render::Texture,
 
-    nes_output: Portal<Box<[u8]>>,
+    nes_output: Portal<Frame>,
     ppu_debug: Portal<PPUDebugRender>,
     apu_debug: Portal<Box<[u8]>>,
     debug_mode: DebugMode,
@@ -27,13 +57,20 @@ pub struct Compositor {
 impl Compositor {
     pub fn new(
         video: sdl2::VideoSubsystem,
-        nes_output: Portal<Box<[u8]>>,
+        display: Display,
+        nes_output: Portal<Frame>,
         ppu_debug: Portal<PPUDebugRender>,
         apu_debug: Portal<Box<[u8]>>,
     ) -> Compositor {
+        let (width, height) = display.display_size();
         let mut main_window = video
-            .window("NES", 256 * SCALE as u32, 240 * SCALE as u32)
+            .window(
+                "NES",
+                (width * SCALE as f32).round() as u32,
+                (height * SCALE as f32).round() as u32,
+            )
             .position_centered()
+            .resizable()
             .opengl()
             .build()
             .unwrap();
@@ -42,15 +79,8 @@ impl Compositor {
 
         let canvas = main_window.into_canvas().accelerated().build().unwrap();
 
-        let texture_creator = canvas.texture_creator();
-        let nes_texture = match texture_creator.create_texture_static(
-            Some(pixels::PixelFormatEnum::RGB24),
-            256,
-            240,
-        ) {
-            Err(cause) => panic!("Failed to create texture: {}", cause),
-            Ok(t) => t,
-        };
+        let nes_size = nes_output.consume(|frame| (frame.width, frame.height));
+        let nes_texture = create_nes_texture(&canvas, nes_size);
 
         let debug_window = video
             .window("NES (Debug)", 256 * 2 as u32, 472 * 2 as u32)
@@ -113,6 +143,8 @@ impl Compositor {
         Compositor {
             canvas,
             nes_texture,
+            nes_size,
+            display,
             debug_canvas,
             pattern_texture,
             nametable_texture,
@@ -136,6 +168,12 @@ impl Compositor {
         }
     }
 
+    // The main window's ID and size, for mapping mouse positions onto it.
+    pub fn main_window(&self) -> (u32, (u32, u32)) {
+        let window = self.canvas.window();
+        (window.id(), window.size())
+    }
+
     pub fn set_window_title(&mut self, title: &str) {
         match self.canvas.window_mut().set_title(title) {
             Err(cause) => panic!("failed to set window title: {}", cause),
@@ -157,11 +195,29 @@ impl Compositor {
 
     fn render_main(&mut self) {
         self.canvas.clear();
-        let texture = &mut self.nes_texture;
-        self.nes_output.consume(|data| {
-            let _ = texture.update(None, data, 256 * 3);
+        let Compositor {
+            canvas,
+            nes_texture,
+            nes_size,
+            nes_output,
+            ..
+        } = self;
+        nes_output.consume(|frame| {
+            // The frame size changes when switching scalers.
+            if (frame.width, frame.height) != *nes_size {
+                *nes_size = (frame.width, frame.height);
+                *nes_texture = create_nes_texture(canvas, *nes_size);
+            }
+            let _ = nes_texture.update(None, &frame.data, frame.width * 3);
         });
-        let _ = self.canvas.copy(&texture, None, None);
+
+        // Crop the overscan, and draw what's left at the right shape for the window.
+        let (x, y, w, h) = self.display.crop(self.nes_size.0, self.nes_size.1);
+        let src = rect::Rect::new(x as i32, y as i32, w as u32, h as u32);
+        let (width, height) = self.canvas.output_size().unwrap_or((256, 240));
+        let (x, y, w, h) = self.display.fit(width, height);
+        let dst = rect::Rect::new(x as i32, y as i32, w, h);
+        let _ = self.canvas.copy(&self.nes_texture, src, dst);
         self.canvas.present();
     }
 
@@ -218,3 +274,17 @@ impl Compositor {
         self.debug_canvas.present();
     }
 }
+
+fn create_nes_texture(
+    canvas: &render::Canvas<video::Window>,
+    size: (usize, usize),
+) -> render::Texture {
+    match canvas.texture_creator().create_texture_static(
+        Some(pixels::PixelFormatEnum::RGB24),
+        size.0 as u32,
+        size.1 as u32,
+    ) {
+        Err(cause) => panic!("Failed to create texture: {}", cause),
+        Ok(t) => t,
+    }
+}
@@ -0,0 +1,178 @@
+use std::collections::HashMap;
+use std::fs::{create_dir_all, File};
+use std::path::PathBuf;
+
+use dirs;
+use serde::{Deserialize, Serialize};
+
+use nes::emulator::apu::Channel;
+use nes::emulator::controller::Bindings;
+use nes::emulator::io::event::Key;
+
+// Emulator functions which can be bound to keys.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum Hotkey {
+    Quit,
+    ToggleTracing,
+    DumpTrace,
+    CycleDebugMode,
+    // Sets the speed, or with Shift saves and with Ctrl loads the numbered state.
+    Slot(u8),
+    Reset,
+    ToggleRecording,
+    ToggleRegisterLog,
+    CycleScaler,
+    ToggleSpriteLimit,
+    ToggleFamilyKeyboard,
+    // Rebinds player 1's controller, or with Shift player 2's.
+    Rebind,
+    CycleTurboRate,
+    // Starts or stops recording a macro on player 1's controller, or with Shift player 2's.
+    RecordMacro,
+    // Starts or stops recording a movie, from the current state or with Shift from power-on.
+    RecordMovie,
+    ToggleMovieReadOnly,
+    // Saves a screenshot, and with Shift the PPU debug panels too.
+    Screenshot,
+    // Starts or stops recording video and audio as an uncompressed AVI.
+    ToggleVideoRecording,
+    // Toggles mute, Shift toggles solo, Ctrl lowers volume and Ctrl+Shift raises it.
+    Channel(Channel),
+}
+
+pub type HotkeyMap = HashMap<Key, Hotkey>;
+
+fn default_hotkeys() -> HotkeyMap {
+    let mut hotkeys: HotkeyMap = [
+        (Key::Escape, Hotkey::Quit),
+        (Key::Tab, Hotkey::ToggleTracing),
+        (Key::Return, Hotkey::DumpTrace),
+        (Key::Backquote, Hotkey::CycleDebugMode),
+        (Key::Backspace, Hotkey::Reset),
+        (Key::M, Hotkey::ToggleRecording),
+        (Key::V, Hotkey::ToggleRegisterLog),
+        (Key::F, Hotkey::CycleScaler),
+        (Key::L, Hotkey::ToggleSpriteLimit),
+        (Key::ScrollLock, Hotkey::ToggleFamilyKeyboard),
+        (Key::Y, Hotkey::Rebind),
+        (Key::Minus, Hotkey::CycleTurboRate),
+        (Key::Equals, Hotkey::RecordMacro),
+        (Key::F9, Hotkey::RecordMovie),
+        (Key::F10, Hotkey::ToggleMovieReadOnly),
+        (Key::F11, Hotkey::ToggleVideoRecording),
+        (Key::F12, Hotkey::Screenshot),
+        (Key::Q, Hotkey::Channel(Channel::Pulse1)),
+        (Key::W, Hotkey::Channel(Channel::Pulse2)),
+        (Key::E, Hotkey::Channel(Channel::Triangle)),
+        (Key::R, Hotkey::Channel(Channel::Noise)),
+        (Key::T, Hotkey::Channel(Channel::DMC)),
+    ]
+    .iter()
+    .cloned()
+    .collect();
+
+    let num_keys = [
+        Key::Num0,
+        Key::Num1,
+        Key::Num2,
+        Key::Num3,
+        Key::Num4,
+        Key::Num5,
+        Key::Num6,
+        Key::Num7,
+        Key::Num8,
+        Key::Num9,
+    ];
+    for (num, &key) in num_keys.iter().enumerate() {
+        hotkeys.insert(key, Hotkey::Slot(num as u8));
+    }
+    hotkeys
+}
+
+// Settings kept between runs, in config.json in the data dir.
+// Anything missing from the file is left at its default.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Config {
+    #[serde(default = "Bindings::new")]
+    pub bindings: Bindings,
+    #[serde(default = "default_hotkeys")]
+    pub hotkeys: HotkeyMap,
+}
+
+impl Config {
+    pub fn new() -> Config {
+        Config {
+            bindings: Bindings::new(),
+            hotkeys: default_hotkeys(),
+        }
+    }
+
+    // The defaults are used if there's no config file yet, or it can't be read.
+    pub fn load() -> Config {
+        let file = match File::open(config_file_path()) {
+            Err(_) => return Config::new(),
+            Ok(file) => file,
+        };
+        match serde_json::from_reader(file) {
+            Err(cause) => {
+                println!("Couldn't read config, using defaults: {}", cause);
+                Config::new()
+            }
+            Ok(config) => config,
+        }
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        create_dir_all(data_dir()).map_err(|e| e.to_string())?;
+        let file = File::create(config_file_path()).map_err(|e| e.to_string())?;
+        serde_json::to_writer_pretty(file, self).map_err(|e| e.to_string())
+    }
+
+    // The keys bound to each channel, with the label drawn next to its waveform.
+    pub fn channel_keys(&self) -> Vec<(Key, char, Channel)> {
+        let mut channel_keys: Vec<(Key, char, Channel)> = self
+            .hotkeys
+            .iter()
+            .filter_map(|(&key, &hotkey)| match hotkey {
+                Hotkey::Channel(channel) => Some((key, key_label(key), channel)),
+                _ => None,
+            })
+            .collect();
+        channel_keys
+            .sort_by_key(|&(_, _, channel)| Channel::ALL.iter().position(|&c| c == channel));
+        channel_keys
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Where config and save states are kept.
+pub fn data_dir() -> PathBuf {
+    let mut path = match dirs::data_dir() {
+        Some(path) => path,
+        None => panic!("Couldn't get data dir!"),
+    };
+
+    path.push("nes");
+    path
+}
+
+fn config_file_path() -> PathBuf {
+    let mut path = data_dir();
+    path.push("config.json");
+    path
+}
+
+// Letters and digits label themselves, and anything else is a '?'.
+fn key_label(key: Key) -> char {
+    let name = format!("{:?}", key);
+    let name = name.strip_prefix("Num").unwrap_or(&name);
+    match name.chars().next() {
+        Some(c) if name.len() == 1 => c,
+        _ => '?',
+    }
+}
@@ -3,21 +3,43 @@ use std::collections::HashMap;
 use std::fs::{create_dir_all, File};
 use std::path::PathBuf;
 use std::rc::Rc;
-
-use dirs;
-use flate2::read::GzDecoder;
-use flate2::write::GzEncoder;
-use flate2::Compression;
-use serde::Serialize;
-use serde_json::Serializer;
-
-use nes::emulator::io::event::{Event, EventHandler, Key};
-use nes::emulator::io::{Screen, SimpleAudioOut};
-use nes::emulator::state::SaveState;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use nes::emulator::apu::Channel;
+use nes::emulator::controller::{Button, Controller as Joypad, Macro, TURBO_RATES};
+use nes::emulator::input::keyboard::FamilyKeyboard;
+use nes::emulator::io::event::{Event, EventHandler, Key, PadButton};
+use nes::emulator::io::scale::Scaler;
+use nes::emulator::io::screenshot::{save_ppu_debug, save_screen};
+use nes::emulator::io::vgm::save_vgm;
+use nes::emulator::io::wav::{SampleFormat, WavRecorder};
+use nes::emulator::io::Screen;
+use nes::emulator::movie::{Movie, MovieMode, MovieStart};
+use nes::emulator::ppu::debug::PPUDebug;
+use nes::emulator::state::{read_state, write_state};
 use nes::emulator::{NES, NES_MASTER_CLOCK_HZ};
 
+use crate::config::{data_dir, Config, Hotkey};
 use crate::portal::Portal;
 
+const CHANNEL_VOLUME_STEP: f32 = 0.25;
+
+// Keys for the Power Pad's buttons 1-12, from keys not used for anything else.
+pub const POWER_PAD_KEYS: [Key; 12] = [
+    Key::U,
+    Key::I,
+    Key::O,
+    Key::P,
+    Key::G,
+    Key::H,
+    Key::J,
+    Key::K,
+    Key::C,
+    Key::B,
+    Key::N,
+    Key::D,
+];
+
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum DebugMode {
     OFF,
@@ -31,6 +53,7 @@ pub struct EmulatorState {
     pub is_tracing: bool,
     pub target_hz: u64,
     pub debug_mode: DebugMode,
+    pub scaler: Scaler,
 }
 
 impl EmulatorState {
@@ -40,17 +63,13 @@ impl EmulatorState {
             is_tracing: false,
             target_hz: NES_MASTER_CLOCK_HZ,
             debug_mode: DebugMode::APU,
+            scaler: Scaler::None,
         }
     }
 }
 
 fn save_state_dir() -> PathBuf {
-    let mut path = match dirs::data_dir() {
-        Some(path) => path,
-        None => panic!("Couldn't get data dir!"),
-    };
-
-    path.push("nes");
+    let mut path = data_dir();
     path.push("save_states");
     path
 }
@@ -64,28 +83,26 @@ fn save_state_file_path(name: &str) -> PathBuf {
 fn save_state(nes: &mut NES, name: &str) -> Result<(), String> {
     create_dir_all(save_state_dir()).map_err(|e| e.to_string())?;
     let state_file = File::create(save_state_file_path(name)).map_err(|e| e.to_string())?;
-    let gzip = GzEncoder::new(state_file, Compression::best());
-    let mut serializer = Serializer::new(gzip);
-
-    let state = nes.freeze();
-
-    state
-        .serialize(&mut serializer)
-        .map_err(|e| e.to_string())?;
-
-    serializer
-        .into_inner()
-        .try_finish()
-        .map_err(|e| e.to_string())?;
-
-    Ok(())
+    write_state(nes, state_file).map(|_| ())
 }
 
 pub fn load_state(nes: &mut NES, name: &str) -> Result<(), String> {
     let state_file = File::open(save_state_file_path(name)).map_err(|e| e.to_string())?;
-    let gzip = GzDecoder::new(state_file);
-    let state = serde_json::from_reader(gzip).map_err(|e| e.to_string())?;
-    nes.hydrate(state);
+    read_state(nes, state_file)
+}
+
+fn movie_dir() -> PathBuf {
+    let mut path = data_dir();
+    path.push("movies");
+    path
+}
+
+// Plays a movie read-only, loading the state it starts from first.
+pub fn play_movie(nes: &mut NES, movie: Movie) -> Result<(), String> {
+    if let MovieStart::SaveState(ref state) = movie.start {
+        read_state(nes, &state[..])?;
+    }
+    nes.play_movie(movie, true);
     Ok(())
 }
 
@@ -93,17 +110,41 @@ pub struct Controller {
     nes: NES,
     rom_name: Option<String>,
     screen: Rc<RefCell<Screen>>,
-    audio_output: Rc<RefCell<SimpleAudioOut>>,
+    audio_output: Rc<RefCell<WavRecorder>>,
     key_states: HashMap<Key, bool>,
     state_portal: Portal<EmulatorState>,
+    // While the host keyboard is typing on this, hotkeys are ignored.
+    family_keyboard: Option<Rc<RefCell<FamilyKeyboard>>>,
+    config: Config,
+    // The player whose controller is being rebound, and the button being bound next.
+    rebinding: Option<(usize, usize)>,
+    // A macro which has been recorded for a player, waiting for a key to replay it.
+    unbound_macro: Option<(usize, Macro)>,
+    // The movie's mode and desync when last reported, to report any change.
+    movie_status: Option<(MovieMode, Option<usize>)>,
 }
 
+// The order buttons are asked for when rebinding.
+const REBIND_ORDER: [Button; 10] = [
+    Button::Up,
+    Button::Down,
+    Button::Left,
+    Button::Right,
+    Button::B,
+    Button::A,
+    Button::Select,
+    Button::Start,
+    Button::TurboB,
+    Button::TurboA,
+];
+
 impl Controller {
     pub fn new(
         nes: NES,
         screen: Rc<RefCell<Screen>>,
-        audio_output: Rc<RefCell<SimpleAudioOut>>,
+        audio_output: Rc<RefCell<WavRecorder>>,
         state_portal: Portal<EmulatorState>,
+        config: Config,
     ) -> Controller {
         Controller {
             nes,
@@ -112,6 +153,11 @@ impl Controller {
             audio_output,
             key_states: HashMap::new(),
             state_portal,
+            family_keyboard: None,
+            config,
+            rebinding: None,
+            unbound_macro: None,
+            movie_status: None,
         }
     }
 
@@ -135,6 +181,280 @@ impl Controller {
         self.state_portal.consume(|state| state.is_tracing = on)
     }
 
+    pub fn set_family_keyboard(&mut self, keyboard: Rc<RefCell<FamilyKeyboard>>) {
+        self.family_keyboard = Some(keyboard);
+    }
+
+    fn is_typing(&self) -> bool {
+        self.family_keyboard
+            .as_ref()
+            .is_some_and(|keyboard| keyboard.borrow().is_host_input())
+    }
+
+    pub fn toggle_family_keyboard(&mut self) {
+        if let Some(keyboard) = &self.family_keyboard {
+            let mut keyboard = keyboard.borrow_mut();
+            let typing = !keyboard.is_host_input();
+            keyboard.set_host_input(typing);
+            println!("Keyboard input: {}", if typing { "ON" } else { "OFF" });
+        }
+    }
+
+    fn handle_hotkey(&mut self, hotkey: Hotkey) {
+        if self.is_typing() && hotkey != Hotkey::ToggleFamilyKeyboard {
+            return;
+        }
+
+        match hotkey {
+            Hotkey::Quit => self.stop(),
+            Hotkey::ToggleTracing => {
+                if self.is_tracing() {
+                    self.nes.cpu.borrow_mut().stop_tracing();
+                    self.set_tracing(false);
+                } else {
+                    self.set_tracing(true);
+                    self.nes.cpu.borrow_mut().start_tracing();
+                }
+                println!(
+                    "CPU Tracing: {}",
+                    if self.is_tracing() { "ON" } else { "OFF" }
+                );
+            }
+            Hotkey::DumpTrace => self.dump_trace(),
+            Hotkey::CycleDebugMode => self.cycle_debug_mode(),
+            Hotkey::Slot(num) => self.handle_num_key(num),
+            Hotkey::Reset => self.reset(),
+            Hotkey::ToggleRecording => self.toggle_recording(),
+            Hotkey::ToggleRegisterLog => self.toggle_register_log(),
+            Hotkey::CycleScaler => self.cycle_scaler(),
+            Hotkey::ToggleSpriteLimit => self.toggle_sprite_limit(),
+            Hotkey::ToggleFamilyKeyboard => self.toggle_family_keyboard(),
+            Hotkey::Rebind => self.start_rebinding(),
+            Hotkey::CycleTurboRate => self.cycle_turbo_rate(),
+            Hotkey::RecordMacro => self.toggle_macro_recording(),
+            Hotkey::RecordMovie => self.toggle_movie_recording(),
+            Hotkey::ToggleMovieReadOnly => self.toggle_movie_read_only(),
+            Hotkey::Screenshot => self.screenshot(),
+            Hotkey::ToggleVideoRecording => self.toggle_video_recording(),
+            Hotkey::Channel(channel) => self.handle_channel_key(channel),
+        }
+    }
+
+    fn joypad(&self, player: usize) -> Rc<RefCell<Joypad>> {
+        match player {
+            0 => self.nes.joy1.clone(),
+            _ => self.nes.joy2.clone(),
+        }
+    }
+
+    fn save_config(&self, what: &str) {
+        match self.config.save() {
+            Err(cause) => println!("Failed to save {}: {}", what, cause),
+            Ok(_) => println!("Saved {}", what),
+        };
+    }
+
+    pub fn cycle_turbo_rate(&mut self) {
+        let current = self.nes.joy1.borrow().turbo_rate();
+        let ix = TURBO_RATES.iter().position(|&r| r == current).unwrap_or(0);
+        let rate = TURBO_RATES[(ix + 1) % TURBO_RATES.len()];
+        for player in 0..2 {
+            self.joypad(player).borrow_mut().set_turbo_rate(rate);
+            self.config.bindings.players[player].turbo_rate = rate;
+        }
+        println!("Turbo rate: {}Hz", rate);
+        self.save_config("turbo rate");
+    }
+
+    // Once recording stops, the next key pressed replays the macro.
+    fn toggle_macro_recording(&mut self) {
+        let shift_modifier = *self.key_states.get(&Key::Shift).unwrap_or(&false);
+        let player = if shift_modifier { 1 } else { 0 };
+        let joypad = self.joypad(player);
+        let mut joypad = joypad.borrow_mut();
+
+        if !joypad.is_recording() {
+            joypad.start_recording();
+            println!("Recording macro for controller {}", player + 1);
+            return;
+        }
+
+        match joypad.stop_recording() {
+            Some(recording) if !recording.is_empty() => {
+                println!(
+                    "Recorded {} frames. Press a key to replay them, or Escape to discard them.",
+                    recording.len()
+                );
+                self.unbound_macro = Some((player, recording));
+            }
+            _ => println!("Macro is empty"),
+        }
+    }
+
+    fn bind_macro(&mut self, key: Key) {
+        let (player, recording) = match self.unbound_macro.take() {
+            Some(unbound) => unbound,
+            None => return,
+        };
+        if key == Key::Escape {
+            println!("Discarded macro");
+            return;
+        }
+
+        let macros = &mut self.config.bindings.players[player].macros;
+        macros.insert(key, recording);
+        let macros = macros.clone();
+        self.joypad(player).borrow_mut().set_macros(macros);
+        self.save_config("macro");
+    }
+
+    // Asks for a key or gamepad button for each of the controller's buttons in turn, then saves
+    // them. Escape keeps a button's current bindings.
+    fn start_rebinding(&mut self) {
+        let shift_modifier = *self.key_states.get(&Key::Shift).unwrap_or(&false);
+        let player = if shift_modifier { 1 } else { 0 };
+        println!(
+            "Rebinding controller {}. Press Escape to keep a button's current binding.",
+            player + 1
+        );
+        self.rebinding = Some((player, 0));
+        self.prompt_rebinding();
+    }
+
+    fn prompt_rebinding(&self) {
+        if let Some((_, ix)) = self.rebinding {
+            println!("Press a key or gamepad button for {:?}", REBIND_ORDER[ix]);
+        }
+    }
+
+    fn rebind_key(&mut self, key: Key) {
+        if let Some((player, ix)) = self.rebinding {
+            if key != Key::Escape {
+                let button = REBIND_ORDER[ix];
+                let keys = &mut self.config.bindings.players[player].keys;
+                keys.retain(|_, &mut b| b != button);
+                keys.insert(key, button);
+            }
+            self.next_rebinding();
+        }
+    }
+
+    fn rebind_pad(&mut self, pad_button: PadButton) {
+        if let Some((player, ix)) = self.rebinding {
+            let button = REBIND_ORDER[ix];
+            let pad = &mut self.config.bindings.players[player].pad;
+            pad.retain(|_, &mut b| b != button);
+            pad.insert(pad_button, button);
+            self.next_rebinding();
+        }
+    }
+
+    fn next_rebinding(&mut self) {
+        self.rebinding = match self.rebinding {
+            Some((player, ix)) if ix + 1 < REBIND_ORDER.len() => Some((player, ix + 1)),
+            _ => None,
+        };
+
+        if self.rebinding.is_some() {
+            self.prompt_rebinding();
+        } else {
+            self.nes.set_bindings(&self.config.bindings);
+            self.save_config("bindings");
+        }
+    }
+
+    pub fn play_movie(&mut self, movie: Movie) -> Result<(), String> {
+        play_movie(&mut self.nes, movie)?;
+        self.report_movie();
+        Ok(())
+    }
+
+    // Starts recording a movie from the current state, or with Shift from power-on.
+    // Stopping recording, or playback, saves the movie to the data dir.
+    fn toggle_movie_recording(&mut self) {
+        if let Some(movie) = self.nes.stop_movie() {
+            self.movie_status = None;
+            self.save_movie(&movie);
+            return;
+        }
+
+        let rom_name = self.rom_name.clone().unwrap_or(String::from("unknown"));
+        let shift_modifier = *self.key_states.get(&Key::Shift).unwrap_or(&false);
+        let start = if shift_modifier {
+            MovieStart::PowerOn
+        } else {
+            match write_state(&mut self.nes, vec![]) {
+                Err(cause) => {
+                    println!("Failed to start movie: {}", cause);
+                    return;
+                }
+                Ok(state) => MovieStart::SaveState(state),
+            }
+        };
+        self.nes.record_movie(Movie::new(&rom_name, start));
+        self.report_movie();
+    }
+
+    // Movies from power-on are also saved as FM2, for other emulators.
+    fn save_movie(&self, movie: &Movie) {
+        if let Err(cause) = create_dir_all(movie_dir()) {
+            println!("Failed to save movie: {}", cause);
+            return;
+        }
+
+        let mut extensions = vec!["nmv"];
+        if movie.start == MovieStart::PowerOn {
+            extensions.push("fm2");
+        }
+        for extension in extensions {
+            let mut path = movie_dir();
+            path.push(format!("{}.{}", movie.rom_name, extension));
+            match movie.save(&path) {
+                Err(cause) => println!("Failed to save movie: {}", cause),
+                Ok(_) => println!(
+                    "Saved {} frame movie to {}",
+                    movie.frames.len(),
+                    path.display()
+                ),
+            };
+        }
+    }
+
+    // While playing back read+write, loading a state carries on recording from there.
+    fn toggle_movie_read_only(&mut self) {
+        if let Some(session) = self.nes.movie_mut() {
+            let read_only = session.mode() != MovieMode::ReadOnly;
+            session.set_read_only(read_only);
+            println!("Movie read-only: {}", if read_only { "ON" } else { "OFF" });
+        }
+    }
+
+    // Reports when the movie changes mode, e.g. finishes playing, or desyncs.
+    pub fn report_movie(&mut self) {
+        let session = match self.nes.movie() {
+            Some(session) => session,
+            None => return,
+        };
+        let status = Some((session.mode(), session.desync()));
+        if status == self.movie_status {
+            return;
+        }
+
+        let position = session.position(self.nes.frame_count());
+        match status {
+            Some((_, Some(frame))) if self.movie_status.and_then(|(_, d)| d).is_none() => {
+                println!("Movie desynced at frame {}", frame)
+            }
+            Some((MovieMode::Recording, _)) => println!("Recording movie from frame {}", position),
+            Some((MovieMode::ReadOnly, _)) | Some((MovieMode::ReadWrite, _)) => {
+                println!("Playing {} frame movie", session.movie().frames.len())
+            }
+            Some((MovieMode::Finished, _)) => println!("Movie finished"),
+            None => (),
+        }
+        self.movie_status = status;
+    }
+
     pub fn set_rom_name(&mut self, name: &str) {
         self.rom_name = Some(String::from(name));
     }
@@ -151,6 +471,17 @@ impl Controller {
         self.state_portal.consume(|state| {
             state.is_running = false;
         });
+
+        // Make sure any recording in progress is finished off properly.
+        if self.audio_output.borrow().is_recording() {
+            self.toggle_recording();
+        }
+        if self.nes.apu.borrow().is_logging_registers() {
+            self.toggle_register_log();
+        }
+        if self.nes.is_recording_video() {
+            self.toggle_video_recording();
+        }
     }
 
     pub fn reset(&mut self) {
@@ -162,6 +493,7 @@ impl Controller {
         self.screen.borrow_mut().set_double_buffering(hz > 200_000);
         self.audio_output
             .borrow_mut()
+            .output_mut()
             .set_enabled(hz >= 10_000_000 && hz <= 50_000_000);
     }
 
@@ -183,6 +515,25 @@ impl Controller {
         });
     }
 
+    pub fn scaler(&self) -> Scaler {
+        self.state_portal.consume(|state| state.scaler)
+    }
+
+    pub fn cycle_scaler(&self) {
+        let scaler = self.state_portal.consume(|state| {
+            state.scaler = state.scaler.next();
+            state.scaler
+        });
+        println!("Scaler: {}", scaler.name());
+    }
+
+    pub fn toggle_sprite_limit(&mut self) {
+        let mut ppu = self.nes.ppu.borrow_mut();
+        let limit = !ppu.is_sprite_limit();
+        ppu.set_sprite_limit(limit);
+        println!("Sprite limit: {}", if limit { "ON" } else { "OFF" });
+    }
+
     pub fn dump_trace(&mut self) {
         if self.is_tracing() {
             println!("Flushing CPU trace buffer to ./cpu.trace");
@@ -244,6 +595,137 @@ impl Controller {
             self.set_target_hz(target_hz);
         }
     }
+
+    // Record audio to the working directory.
+    // With Shift, each APU channel is also recorded to its own track.
+    fn toggle_recording(&mut self) {
+        let mut audio_output = self.audio_output.borrow_mut();
+        if audio_output.is_recording() {
+            self.nes.apu.borrow_mut().set_channel_output(false);
+            match audio_output.stop_recording() {
+                Err(cause) => println!("Failed to record audio: {}", cause),
+                Ok(_) => println!("Stopped recording audio"),
+            };
+            return;
+        }
+
+        let per_channel = *self.key_states.get(&Key::Shift).unwrap_or(&false);
+        let path = self.capture_path("wav");
+
+        match audio_output.start_recording(&path, SampleFormat::Int16, per_channel) {
+            Err(cause) => println!("Failed to start recording: {}", cause),
+            Ok(_) => {
+                self.nes.apu.borrow_mut().set_channel_output(per_channel);
+                println!("Recording audio to {}", path);
+            }
+        };
+    }
+
+    // Log APU register writes, saving them as a VGM file in the working directory when stopped.
+    fn toggle_register_log(&mut self) {
+        let mut apu = self.nes.apu.borrow_mut();
+        let log = match apu.stop_register_log() {
+            None => {
+                apu.start_register_log();
+                println!("Logging APU register writes");
+                return;
+            }
+            Some(log) => log,
+        };
+
+        let path = self.capture_path("vgm");
+        match save_vgm(&path, &log) {
+            Err(cause) => println!("Failed to save VGM: {}", cause),
+            Ok(_) => println!("Saved APU register log to {}", path),
+        };
+    }
+
+    // Save the screen as a PNG in the working directory.
+    // With Shift, the PPU debug panels are saved alongside it.
+    fn screenshot(&mut self) {
+        let path = self.capture_path("png");
+        match save_screen(&self.screen.borrow(), &path) {
+            Err(cause) => println!("Failed to save screenshot: {}", cause),
+            Ok(_) => println!("Saved screenshot to {}", path),
+        };
+
+        if *self.key_states.get(&Key::Shift).unwrap_or(&false) {
+            let path = self.capture_path("ppu.png");
+            let mut result = Ok(());
+            PPUDebug::new(self.nes.ppu.clone())
+                .do_render(|render| result = save_ppu_debug(render, &path));
+            match result {
+                Err(cause) => println!("Failed to save PPU debug panels: {}", cause),
+                Ok(_) => println!("Saved PPU debug panels to {}", path),
+            };
+        }
+    }
+
+    // Records an AVI in the working directory, with audio matching the output's.
+    fn toggle_video_recording(&mut self) {
+        if self.nes.is_recording_video() {
+            match self.nes.stop_video_recording() {
+                Err(cause) => println!("Failed to record video: {}", cause),
+                Ok(_) => println!("Stopped recording video"),
+            };
+            return;
+        }
+
+        let path = self.capture_path("avi");
+        let (sample_rate, mode) = {
+            let audio_output = self.audio_output.borrow();
+            (
+                audio_output.output().sample_rate(),
+                audio_output.output().mode(),
+            )
+        };
+        match self.nes.start_video_recording(&path, sample_rate, mode) {
+            Err(cause) => println!("Failed to start recording video: {}", cause),
+            Ok(_) => println!("Recording video to {}", path),
+        };
+    }
+
+    // A path in the working directory, named after the ROM and the current time.
+    fn capture_path(&self, extension: &str) -> String {
+        let rom_name = match self.rom_name {
+            Some(ref name) => name.clone(),
+            None => String::from("unknown"),
+        };
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        format!("./{}-{}.{}", rom_name, timestamp, extension)
+    }
+
+    fn handle_channel_key(&mut self, channel: Channel) {
+        let shift_modifier = *self.key_states.get(&Key::Shift).unwrap_or(&false);
+        let ctrl_modifier = *self.key_states.get(&Key::Control).unwrap_or(&false);
+        let mut apu = self.nes.apu.borrow_mut();
+
+        if ctrl_modifier {
+            let step = if shift_modifier {
+                CHANNEL_VOLUME_STEP
+            } else {
+                -CHANNEL_VOLUME_STEP
+            };
+            let volume = apu.channel_volume(channel) + step;
+            apu.set_channel_volume(channel, volume);
+            println!(
+                "{:?} volume: {:.0}%",
+                channel,
+                apu.channel_volume(channel) * 100.0
+            );
+        } else if shift_modifier {
+            let solo = !apu.is_channel_solo(channel);
+            apu.set_channel_solo(channel, solo);
+            println!("{:?} solo: {}", channel, if solo { "ON" } else { "OFF" });
+        } else {
+            let muted = !apu.is_channel_muted(channel);
+            apu.set_channel_muted(channel, muted);
+            println!("{:?} muted: {}", channel, if muted { "ON" } else { "OFF" });
+        }
+    }
 }
 
 impl EventHandler for Controller {
@@ -251,42 +733,23 @@ impl EventHandler for Controller {
         match event {
             Event::KeyDown(key) => {
                 self.key_states.insert(key, true);
-                match key {
-                    Key::Escape => self.stop(),
-                    Key::Tab => {
-                        if self.is_tracing() {
-                            self.nes.cpu.borrow_mut().stop_tracing();
-                            self.set_tracing(false);
-                        } else {
-                            self.set_tracing(true);
-                            self.nes.cpu.borrow_mut().start_tracing();
-                        }
-                        println!(
-                            "CPU Tracing: {}",
-                            if self.is_tracing() { "ON" } else { "OFF" }
-                        );
-                    }
-                    Key::Return => {
-                        self.dump_trace();
-                    }
-                    Key::Backquote => self.cycle_debug_mode(),
-                    Key::Num1 => self.handle_num_key(1),
-                    Key::Num2 => self.handle_num_key(2),
-                    Key::Num3 => self.handle_num_key(3),
-                    Key::Num4 => self.handle_num_key(4),
-                    Key::Num5 => self.handle_num_key(5),
-                    Key::Num6 => self.handle_num_key(6),
-                    Key::Num7 => self.handle_num_key(7),
-                    Key::Num8 => self.handle_num_key(8),
-                    Key::Num9 => self.handle_num_key(9),
-                    Key::Num0 => self.handle_num_key(0),
-                    Key::Backspace => self.reset(),
-                    _ => (),
-                };
+                if self.rebinding.is_some() {
+                    self.rebind_key(key);
+                    return;
+                }
+                if self.unbound_macro.is_some() {
+                    self.bind_macro(key);
+                    return;
+                }
+                if let Some(&hotkey) = self.config.hotkeys.get(&key) {
+                    self.handle_hotkey(hotkey);
+                }
             }
             Event::KeyUp(key) => {
                 self.key_states.insert(key, false);
             }
+            Event::PadDown(_, button) if self.rebinding.is_some() => self.rebind_pad(button),
+            _ => (),
         };
     }
 }
@@ -1,23 +1,120 @@
-use nes::emulator::io::event::{Event, Key};
+use nes::emulator::io::display::Display;
+use nes::emulator::io::event::{Event, Key, PadAxis, PadButton, PointerButton};
+use sdl2::controller::{Axis, Button, GameController};
 use sdl2::event;
+use sdl2::event::WindowEvent;
 use sdl2::keyboard::Keycode;
+use sdl2::mouse::MouseButton;
+use sdl2::GameControllerSubsystem;
 
 use crate::portal::Portal;
 
+// The most gamepads that can be connected, one for each player on a Four Score.
+const MAX_PADS: u8 = 4;
+
 // Responsible for collecting SDL events and rebroadcasting them as internal events.
+// Mouse positions in the main window are converted to NES pixels, and gamepads are numbered by
+// player in the order they're connected.
 pub struct InputPump {
     event_pump: sdl2::EventPump,
     events: Portal<Vec<Event>>,
+    display: Display,
+    window_id: u32,
+    window_size: (u32, u32),
+    game_controller: GameControllerSubsystem,
+    pads: Vec<(u8, GameController)>,
 }
 
 impl InputPump {
-    pub fn new(event_pump: sdl2::EventPump, events: Portal<Vec<Event>>) -> InputPump {
-        InputPump { event_pump, events }
+    pub fn new(
+        event_pump: sdl2::EventPump,
+        game_controller: GameControllerSubsystem,
+        events: Portal<Vec<Event>>,
+        display: Display,
+        (window_id, window_size): (u32, (u32, u32)),
+    ) -> InputPump {
+        InputPump {
+            event_pump,
+            events,
+            display,
+            window_id,
+            window_size,
+            game_controller,
+            pads: Vec::new(),
+        }
+    }
+
+    // The player using the pad with the given joystick id.
+    fn player(&self, which: i32) -> Option<u8> {
+        self.pads
+            .iter()
+            .find(|(_, pad)| pad.instance_id() == which)
+            .map(|&(player, _)| player)
+    }
+
+    // Pads already plugged in at startup are added too.
+    fn add_pad(&mut self, joystick_index: u32) {
+        let player = match (0..MAX_PADS).find(|&p| self.pads.iter().all(|&(q, _)| p != q)) {
+            Some(player) => player,
+            None => return,
+        };
+        match self.game_controller.open(joystick_index) {
+            Err(cause) => println!("Couldn't open gamepad: {}", cause),
+            Ok(pad) => {
+                println!("Gamepad {} connected: {}", player + 1, pad.name());
+                self.pads.push((player, pad));
+            }
+        }
+    }
+
+    fn remove_pad(&mut self, which: i32) -> Option<u8> {
+        let player = self.player(which)?;
+        self.pads.retain(|(p, _)| *p != player);
+        println!("Gamepad {} disconnected", player + 1);
+        Some(player)
     }
 
     pub fn pump(&mut self) {
         while let Some(e) = self.event_pump.poll_event() {
-            let internal_event = convert_sdl_event_to_internal(e);
+            let internal_event = match e {
+                event::Event::Window {
+                    window_id,
+                    win_event: WindowEvent::SizeChanged(width, height),
+                    ..
+                } if window_id == self.window_id => {
+                    self.window_size = (width as u32, height as u32);
+                    None
+                }
+                event::Event::MouseMotion {
+                    window_id, x, y, ..
+                } if window_id == self.window_id => {
+                    let (width, height) = self.window_size;
+                    let (x, y) = self.display.to_screen(width, height, x, y);
+                    Some(Event::PointerMove(x, y))
+                }
+                event::Event::ControllerDeviceAdded { which, .. } => {
+                    self.add_pad(which);
+                    None
+                }
+                event::Event::ControllerDeviceRemoved { which, .. } => {
+                    self.remove_pad(which).map(Event::PadDisconnected)
+                }
+                event::Event::ControllerButtonDown { which, button, .. } => self
+                    .player(which)
+                    .zip(convert_sdl_button_to_internal(button))
+                    .map(|(player, button)| Event::PadDown(player, button)),
+                event::Event::ControllerButtonUp { which, button, .. } => self
+                    .player(which)
+                    .zip(convert_sdl_button_to_internal(button))
+                    .map(|(player, button)| Event::PadUp(player, button)),
+                event::Event::ControllerAxisMotion {
+                    which, axis, value, ..
+                } => self
+                    .player(which)
+                    .zip(convert_sdl_axis_to_internal(axis))
+                    .map(|(player, axis)| Event::PadAxis(player, axis, value)),
+                e => convert_sdl_event_to_internal(e),
+            };
 
             if let Some(e) = internal_event {
                 self.events.consume(|portal| {
@@ -36,6 +133,46 @@ fn convert_sdl_event_to_internal(event: event::Event) -> Option<Event> {
         event::Event::KeyUp { keycode, .. } => keycode
             .and_then(|k| convert_sdl_keycode_to_internal(k))
             .map(|k| Event::KeyUp(k)),
+        event::Event::MouseButtonDown { mouse_btn, .. } => {
+            convert_sdl_mouse_button_to_internal(mouse_btn).map(Event::PointerDown)
+        }
+        event::Event::MouseButtonUp { mouse_btn, .. } => {
+            convert_sdl_mouse_button_to_internal(mouse_btn).map(Event::PointerUp)
+        }
+        _ => None,
+    }
+}
+
+fn convert_sdl_mouse_button_to_internal(button: MouseButton) -> Option<PointerButton> {
+    match button {
+        MouseButton::Left => Some(PointerButton::Primary),
+        MouseButton::Right => Some(PointerButton::Secondary),
+        _ => None,
+    }
+}
+
+fn convert_sdl_button_to_internal(button: Button) -> Option<PadButton> {
+    match button {
+        Button::A => Some(PadButton::A),
+        Button::B => Some(PadButton::B),
+        Button::X => Some(PadButton::X),
+        Button::Y => Some(PadButton::Y),
+        Button::Back => Some(PadButton::Back),
+        Button::Start => Some(PadButton::Start),
+        Button::LeftShoulder => Some(PadButton::LeftShoulder),
+        Button::RightShoulder => Some(PadButton::RightShoulder),
+        Button::DPadUp => Some(PadButton::DPadUp),
+        Button::DPadDown => Some(PadButton::DPadDown),
+        Button::DPadLeft => Some(PadButton::DPadLeft),
+        Button::DPadRight => Some(PadButton::DPadRight),
+        _ => None,
+    }
+}
+
+fn convert_sdl_axis_to_internal(axis: Axis) -> Option<PadAxis> {
+    match axis {
+        Axis::LeftX => Some(PadAxis::LeftX),
+        Axis::LeftY => Some(PadAxis::LeftY),
         _ => None,
     }
 }
@@ -59,6 +196,7 @@ fn convert_sdl_keycode_to_internal(keycode: Keycode) -> Option<Key> {
         Keycode::O => Some(Key::O),
         Keycode::P => Some(Key::P),
         Keycode::Q => Some(Key::Q),
+        Keycode::R => Some(Key::R),
         Keycode::S => Some(Key::S),
         Keycode::T => Some(Key::T),
         Keycode::U => Some(Key::U),
@@ -95,6 +233,11 @@ fn convert_sdl_keycode_to_internal(keycode: Keycode) -> Option<Key> {
 
         Keycode::LShift => Some(Key::Shift),
         Keycode::LCtrl => Some(Key::Control),
+        Keycode::ScrollLock => Some(Key::ScrollLock),
+        Keycode::F9 => Some(Key::F9),
+        Keycode::F10 => Some(Key::F10),
+        Keycode::F11 => Some(Key::F11),
+        Keycode::F12 => Some(Key::F12),
 
         _ => None,
     }
@@ -1,8 +1,10 @@
 pub mod audio;
 pub mod compositor;
+pub mod config;
 pub mod controller;
 pub mod governer;
 pub mod input;
+pub mod nsf;
 pub mod portal;
 
 use std::cell::RefCell;
@@ -13,17 +15,36 @@ use std::sync::{Arc, Condvar, Mutex};
 use std::time::Duration;
 
 use nes::emulator::apu::debug::APUDebug;
+use nes::emulator::apu::APU;
+use nes::emulator::controller::{default_padmap, Controller as Joypad, KeyMap};
+use nes::emulator::hdpack::{HDPack, HDRenderer};
 use nes::emulator::ines;
+use nes::emulator::input::fourscore::{FourScore, FourScoreMode};
+use nes::emulator::input::keyboard::{host_keymap, FamilyKeyboard};
+use nes::emulator::input::powerpad::{PowerPad, PowerPadMode};
+use nes::emulator::input::vaus::{Vaus, VausMode};
+use nes::emulator::input::zapper::Zapper;
+use nes::emulator::input::Port;
 use nes::emulator::io;
+use nes::emulator::io::display::{AspectRatio, Display, Overscan};
 use nes::emulator::io::event::{Event, EventBus};
+use nes::emulator::io::ntsc::{NTSCFilter, NTSCFilterSettings, NTSC_HEIGHT, NTSC_WIDTH};
+use nes::emulator::io::palette::{NTSCSettings, Palette};
+use nes::emulator::io::scale::Scaler;
+use nes::emulator::io::wav::WavRecorder;
+use nes::emulator::io::OutputMode;
+use nes::emulator::movie::Movie;
+use nes::emulator::nsf::{NSFPlayer, NSF};
 use nes::emulator::ppu::debug::{PPUDebug, PPUDebugRender};
 use nes::emulator::NES;
 
 use crate::audio::{AudioQueue, SAMPLE_RATE};
-use crate::compositor::Compositor;
-use crate::controller::{Controller, DebugMode, EmulatorState};
+use crate::compositor::{Compositor, Frame};
+use crate::config::Config;
+use crate::controller::{Controller, DebugMode, EmulatorState, POWER_PAD_KEYS};
 use crate::governer::Governer;
 use crate::input::InputPump;
+use crate::nsf::{nsf_loop, NSFController};
 use crate::portal::Portal;
 
 pub const RENDER_FPS: u64 = 60;
@@ -34,23 +55,125 @@ fn main() {
     let args: Vec<String> = env::args().collect();
 
     let rom_path = match args.get(1) {
-        None => panic!("You must pass in a path to a iNes ROM file."),
+        None => panic!("You must pass in a path to a iNes ROM or NSF file."),
         Some(path) => path,
     };
 
+    // Optional audio output mode, palette, HD graphics pack, and display settings.
+    let mut audio_mode = OutputMode::Mono;
+    let mut famicom_panning = false;
+    let mut palette = Palette::new();
+    let mut hd_pack_dir = None;
+    let mut ntsc_filter = None;
+    let mut scaler = Scaler::None;
+    let mut display = Display::new();
+    let mut sprite_limit = true;
+    let mut four_score = None;
+    let mut zapper = false;
+    let mut vaus = None;
+    let mut power_pad = None;
+    let mut family_keyboard = false;
+    let mut deadzone = None;
+    let mut movie = None;
+    let mut nsf_pal = false;
+    for arg in args.iter().skip(2) {
+        match arg.as_str() {
+            "--stereo" => audio_mode = OutputMode::Stereo,
+            "--famicom-stereo" => {
+                audio_mode = OutputMode::Stereo;
+                famicom_panning = true;
+            }
+            "--pseudo-stereo" => audio_mode = OutputMode::PseudoStereo { delay_ms: 15.0 },
+            arg if arg.starts_with("--palette=") => {
+                palette = match Palette::load(&arg[10..]) {
+                    Err(cause) => panic!("Couldn't load palette: {}", cause),
+                    Ok(palette) => palette,
+                };
+            }
+            "--ntsc-palette" => palette = Palette::generate(&NTSCSettings::new()),
+            arg if arg.starts_with("--ntsc-palette=") => {
+                palette = Palette::generate(&parse_ntsc_settings(&arg[15..]));
+            }
+            arg if arg.starts_with("--hd-pack=") => hd_pack_dir = Some(&arg[10..]),
+            "--ntsc" => ntsc_filter = Some(NTSCFilterSettings::composite()),
+            arg if arg.starts_with("--ntsc=") => match NTSCFilterSettings::preset(&arg[7..]) {
+                None => panic!("Unrecognised NTSC preset: {}", &arg[7..]),
+                Some(settings) => ntsc_filter = Some(settings),
+            },
+            arg if arg.starts_with("--scaler=") => match Scaler::from_name(&arg[9..]) {
+                None => panic!("Unrecognised scaler: {}", &arg[9..]),
+                Some(s) => scaler = s,
+            },
+            "--overscan" => display.overscan = Overscan::ntsc(),
+            arg if arg.starts_with("--overscan=") => match Overscan::parse(&arg[11..]) {
+                Err(cause) => panic!("{}", cause),
+                Ok(overscan) => display.overscan = overscan,
+            },
+            arg if arg.starts_with("--aspect=") => match AspectRatio::from_name(&arg[9..]) {
+                None => panic!("Unrecognised aspect ratio: {}", &arg[9..]),
+                Some(aspect_ratio) => display.aspect_ratio = aspect_ratio,
+            },
+            "--integer-scale" => display.integer_scale = true,
+            "--no-sprite-limit" => sprite_limit = false,
+            "--four-score" => four_score = Some(FourScoreMode::NES),
+            "--famicom-four-player" => four_score = Some(FourScoreMode::Famicom),
+            "--zapper" => zapper = true,
+            "--vaus" => vaus = Some(VausMode::NES),
+            "--famicom-vaus" => vaus = Some(VausMode::Famicom),
+            "--power-pad" => power_pad = Some(PowerPadMode::NES),
+            "--family-trainer" => power_pad = Some(PowerPadMode::Famicom),
+            "--family-keyboard" => family_keyboard = true,
+            arg if arg.starts_with("--deadzone=") => match arg[11..].parse::<i16>() {
+                Err(_) => panic!("Invalid deadzone: {}", &arg[11..]),
+                Ok(d) => deadzone = Some(d),
+            },
+            arg if arg.starts_with("--movie=") => match Movie::load(&arg[8..]) {
+                Err(cause) => panic!("Couldn't load movie: {}", cause),
+                Ok(m) => movie = Some(m),
+            },
+            // Plays NSFs which support both regions as PAL.
+            "--nsf-pal" => nsf_pal = true,
+            arg => panic!("Unrecognised argument: {}", arg),
+        }
+    }
+
     // -- Initialize --
 
-    let rom = ines::ROM::load(rom_path);
+    // NSF music rips are played with a track list in place of the game screen.
+    let extension = Path::new(rom_path)
+        .extension()
+        .map(|s| s.to_string_lossy().to_lowercase());
+    let media = match extension.as_deref() {
+        Some("nsf") | Some("nsfe") => match NSF::load(rom_path) {
+            Err(cause) => panic!("Couldn't load NSF: {}", cause),
+            Ok(nsf) => Media::Music(Box::new(nsf)),
+        },
+        _ => Media::Game(ines::ROM::load(rom_path)),
+    };
+    let hd_pack = match (hd_pack_dir, &media) {
+        (Some(dir), Media::Game(rom)) => match HDPack::load(dir, rom.chr_rom()) {
+            Err(cause) => panic!("Couldn't load HD pack: {}", cause),
+            Ok(pack) => Some(pack),
+        },
+        _ => None,
+    };
+    let scale = hd_pack.as_ref().map_or(1, |pack| pack.scale());
+    let (width, height) = match ntsc_filter {
+        Some(_) => (NTSC_WIDTH, NTSC_HEIGHT),
+        None => (256 * scale, 240 * scale),
+    };
+    let (width, height) = scaler.output_size(width, height);
     let rom_name = Path::new(rom_path)
         .file_stem()
         .map(|s| s.to_string_lossy().to_string())
         .unwrap_or(String::from("unknown"));
+    let config = Config::load();
 
     let sdl_context = sdl2::init().unwrap();
     let video = sdl_context.video().unwrap();
     let audio = sdl_context.audio().unwrap();
 
-    let video_portal = Portal::new(vec![0; 256 * 240 * 3].into_boxed_slice());
+    let video_portal = Portal::new(Frame::new(width, height));
     let ppu_debug_portal: Portal<PPUDebugRender> = Portal::new(PPUDebugRender::new());
     let apu_debug_portal = Portal::new(
         vec![0; APUDebug::WAVEFORM_WIDTH * APUDebug::WAVEFORM_HEIGHT * 3].into_boxed_slice(),
@@ -60,16 +183,33 @@ fn main() {
 
     let mut compositor = Compositor::new(
         video,
+        display,
         video_portal.clone(),
         ppu_debug_portal.clone(),
         apu_debug_portal.clone(),
     );
-    let mut audio_queue = AudioQueue::new(audio, audio_portal.clone());
-    let mut input = InputPump::new(sdl_context.event_pump().unwrap(), event_portal.clone());
+    let audio_channels = match audio_mode {
+        OutputMode::Mono => 1,
+        OutputMode::Stereo | OutputMode::PseudoStereo { .. } => 2,
+    };
+    let mut audio_queue = AudioQueue::new(audio, audio_portal.clone(), audio_channels);
+    let mut input = InputPump::new(
+        sdl_context.event_pump().unwrap(),
+        sdl_context.game_controller().unwrap(),
+        event_portal.clone(),
+        display,
+        compositor.main_window(),
+    );
 
-    compositor.set_window_title(&format!("[NES] {}", rom_name));
+    let kind = match media {
+        Media::Game(_) => "NES",
+        Media::Music(_) => "NSF",
+    };
+    compositor.set_window_title(&format!("[{}] {}", kind, rom_name));
 
-    let state = Portal::new(EmulatorState::new());
+    let mut initial_state = EmulatorState::new();
+    initial_state.scaler = scaler;
+    let state = Portal::new(initial_state);
     let emu_state = state.clone();
 
     let ui_sync = Arc::new((Mutex::new(()), Condvar::new()));
@@ -78,25 +218,158 @@ fn main() {
     // -- Run --
     let _ = std::thread::spawn(std::panic::AssertUnwindSafe(move || {
         let event_bus = Rc::new(RefCell::new(EventBus::new()));
+
+        let rom = match media {
+            Media::Game(rom) => rom,
+            Media::Music(nsf) => {
+                let mut audio_output = io::SimpleAudioOut::new(SAMPLE_RATE);
+                audio_output.set_mode(audio_mode);
+                let audio_output = Rc::new(RefCell::new(audio_output));
+
+                let mut player = NSFPlayer::new(*nsf, audio_output.clone());
+                player.set_pal(nsf_pal);
+                configure_stereo(&mut player.apu.borrow_mut(), audio_mode, famicom_panning);
+
+                let controller = Rc::new(RefCell::new(NSFController::new(
+                    player,
+                    audio_output,
+                    emu_state,
+                    config.channel_keys(),
+                )));
+                event_bus
+                    .borrow_mut()
+                    .register(Box::new(controller.clone()));
+                nsf_loop(
+                    emu_sync,
+                    controller,
+                    video_portal.clone(),
+                    apu_debug_portal.clone(),
+                    audio_portal.clone(),
+                    event_bus,
+                    event_portal.clone(),
+                );
+                return;
+            }
+        };
+
         let video_output = Rc::new(RefCell::new(io::Screen::new()));
-        let audio_output = Rc::new(RefCell::new(io::SimpleAudioOut::new(SAMPLE_RATE)));
+        video_output.borrow_mut().set_palette(palette);
+        if let Some(settings) = ntsc_filter {
+            let mut screen = video_output.borrow_mut();
+            screen.set_ntsc_filter(Some(NTSCFilter::new(settings)));
+            screen.set_rgb_output(false);
+        }
+        let mut simple_audio_output = io::SimpleAudioOut::new(SAMPLE_RATE);
+        simple_audio_output.set_mode(audio_mode);
+        let audio_output = Rc::new(RefCell::new(WavRecorder::new(simple_audio_output)));
 
-        let nes = NES::new(
+        let mut nes = NES::new(
             event_bus.clone(),
             video_output.clone(),
             audio_output.clone(),
             rom,
+            &config.bindings,
         );
+        configure_stereo(&mut nes.apu.borrow_mut(), audio_mode, famicom_panning);
+        nes.ppu.borrow_mut().set_sprite_limit(sprite_limit);
+        if let Some(deadzone) = deadzone {
+            nes.joy1.borrow_mut().set_deadzone(deadzone);
+            nes.joy2.borrow_mut().set_deadzone(deadzone);
+        }
+
+        if let Some(mode) = four_score {
+            // Players 3 and 4 only have gamepads.
+            let joy3 = Rc::new(RefCell::new(Joypad::new(KeyMap::new())));
+            let joy4 = Rc::new(RefCell::new(Joypad::new(KeyMap::new())));
+            for (player, joy) in [(2, &joy3), (3, &joy4)] {
+                let mut joy = joy.borrow_mut();
+                joy.set_pad(player, default_padmap());
+                if let Some(deadzone) = deadzone {
+                    joy.set_deadzone(deadzone);
+                }
+            }
+            event_bus.borrow_mut().register(Box::new(joy3.clone()));
+            event_bus.borrow_mut().register(Box::new(joy4.clone()));
+
+            let controllers = [nes.joy1.clone(), nes.joy2.clone(), joy3, joy4];
+            let four_score = Rc::new(RefCell::new(FourScore::new(mode, controllers)));
+            nes.connect(Port::One, Box::new(four_score.clone()));
+            nes.connect(Port::Two, Box::new(four_score));
+        }
+
+        if zapper {
+            let zapper = Rc::new(RefCell::new(Zapper::new(
+                nes.ppu.clone(),
+                video_output.clone(),
+            )));
+            event_bus.borrow_mut().register(Box::new(zapper.clone()));
+            nes.connect(Port::Two, Box::new(zapper));
+        }
+
+        if let Some(mode) = vaus {
+            let vaus = Rc::new(RefCell::new(Vaus::new(mode)));
+            event_bus.borrow_mut().register(Box::new(vaus.clone()));
+            match mode {
+                VausMode::NES => nes.connect(Port::Two, Box::new(vaus)),
+                VausMode::Famicom => nes.connect_expansion(Box::new(vaus)),
+            }
+        }
+
+        if let Some(mode) = power_pad {
+            let keymap = POWER_PAD_KEYS
+                .iter()
+                .enumerate()
+                .map(|(ix, &key)| (key, ix as u8 + 1))
+                .collect();
+            let power_pad = match PowerPad::new(mode, keymap) {
+                Err(cause) => panic!("Couldn't create Power Pad: {}", cause),
+                Ok(power_pad) => Rc::new(RefCell::new(power_pad)),
+            };
+            event_bus.borrow_mut().register(Box::new(power_pad.clone()));
+            match mode {
+                PowerPadMode::NES => nes.connect(Port::Two, Box::new(power_pad)),
+                PowerPadMode::Famicom => nes.connect_expansion(Box::new(power_pad)),
+            }
+        }
+
+        // The host keyboard types on the Family BASIC keyboard, until toggled with scroll lock.
+        let family_keyboard = if family_keyboard {
+            let keyboard = Rc::new(RefCell::new(FamilyKeyboard::new(host_keymap())));
+            event_bus.borrow_mut().register(Box::new(keyboard.clone()));
+            nes.connect_expansion(Box::new(keyboard.clone()));
+            Some(keyboard)
+        } else {
+            None
+        };
+
+        if let Some(pack) = hd_pack {
+            nes.ppu.borrow_mut().set_tile_output(true);
+            let renderer = HDRenderer::new(pack, Box::new(nes.ram.clone()));
+            video_output.borrow_mut().set_hd_renderer(Some(renderer));
+        }
+
         let ppu_debug = PPUDebug::new(nes.ppu.clone());
-        let apu_debug = APUDebug::new(nes.apu.clone());
+        let mut apu_debug = APUDebug::new(nes.apu.clone());
+        for &(_, label, channel) in config.channel_keys().iter() {
+            apu_debug.set_channel_label(channel, label);
+        }
 
         let controller = Rc::new(RefCell::new(Controller::new(
             nes,
             video_output.clone(),
             audio_output.clone(),
             emu_state,
+            config,
         )));
         controller.borrow_mut().set_rom_name(&rom_name);
+        if let Some(keyboard) = family_keyboard {
+            controller.borrow_mut().set_family_keyboard(keyboard);
+        }
+        if let Some(movie) = movie {
+            if let Err(cause) = controller.borrow_mut().play_movie(movie) {
+                panic!("Couldn't play movie: {}", cause);
+            }
+        }
         controller.borrow_mut().start();
         event_bus
             .borrow_mut()
@@ -135,6 +408,41 @@ fn main() {
     }
 }
 
+enum Media {
+    Game(ines::ROM),
+    Music(Box<NSF>),
+}
+
+// Settings are given as e.g. "hue=-5,saturation=1.2", with any left out at their defaults.
+fn parse_ntsc_settings(arg: &str) -> NTSCSettings {
+    let mut settings = NTSCSettings::new();
+    for setting in arg.split(',') {
+        let mut parts = setting.splitn(2, '=');
+        let (name, value) = match (parts.next(), parts.next().map(|v| v.parse::<f32>())) {
+            (Some(name), Some(Ok(value))) => (name, value),
+            _ => panic!("Invalid palette setting: {}", setting),
+        };
+        match name {
+            "hue" => settings.hue = value,
+            "saturation" => settings.saturation = value,
+            "contrast" => settings.contrast = value,
+            "brightness" => settings.brightness = value,
+            "gamma" => settings.gamma = value,
+            _ => panic!("Unrecognised palette setting: {}", name),
+        }
+    }
+    settings
+}
+
+fn configure_stereo(apu: &mut APU, audio_mode: OutputMode, famicom_panning: bool) {
+    if audio_mode == OutputMode::Stereo {
+        apu.set_stereo(true);
+        if famicom_panning {
+            apu.set_famicom_panning();
+        }
+    }
+}
+
 fn ui_loop(
     sync: Arc<(Mutex<()>, Condvar)>,
     compositor: &mut Compositor,
@@ -160,12 +468,12 @@ fn main_loop(
     sync: Arc<(Mutex<()>, Condvar)>,
     controller: Rc<RefCell<Controller>>,
     video_output: Rc<RefCell<io::Screen>>,
-    video_portal: Portal<Box<[u8]>>,
+    video_portal: Portal<Frame>,
     mut ppu_debug: PPUDebug,
     ppu_debug_portal: Portal<PPUDebugRender>,
     mut apu_debug: APUDebug,
     apu_debug_portal: Portal<Box<[u8]>>,
-    audio_output: Rc<RefCell<io::SimpleAudioOut>>,
+    audio_output: Rc<RefCell<WavRecorder>>,
     audio_portal: Portal<Vec<f32>>,
     event_bus: Rc<RefCell<EventBus>>,
     event_portal: Portal<Vec<Event>>,
@@ -173,6 +481,7 @@ fn main_loop(
     let mut frame_count: u64 = 0;
     let mut agg_cycles: u64 = 0;
     let mut governer = Governer::new(RENDER_FPS);
+    let mut scaled = Vec::new();
 
     while controller.borrow().is_running() {
         let target_hz = controller.borrow().target_hz();
@@ -190,13 +499,34 @@ fn main_loop(
             // Batching ticks here is a massive perf win since finding the elapsed time is costly.
             cycles_this_frame += controller.borrow_mut().tick_multi(100);
         }
+        controller.borrow_mut().report_movie();
 
         // Drive rendering.
-        video_output.borrow().do_render(|data| {
-            video_portal.consume(|portal| {
-                copy_buffer(data, portal);
-            });
-        });
+        let scaler = controller.borrow().scaler();
+        {
+            let screen = video_output.borrow();
+            let (width, height) = match (screen.ntsc_filter(), screen.hd_renderer()) {
+                (Some(ntsc), _) => (ntsc.width(), ntsc.height()),
+                (None, Some(hd)) => (hd.width(), hd.height()),
+                (None, None) => (256, 240),
+            };
+            let render = |data: &[u8]| {
+                let data = match scaler {
+                    Scaler::None => data,
+                    _ => {
+                        scaler.apply(data, width, height, &mut scaled);
+                        &scaled
+                    }
+                };
+                let (width, height) = scaler.output_size(width, height);
+                video_portal.consume(|portal| portal.set(width, height, data));
+            };
+            match (screen.ntsc_filter(), screen.hd_renderer()) {
+                (Some(ntsc), _) => ntsc.do_render(render),
+                (None, Some(hd)) => hd.do_render(render),
+                (None, None) => screen.do_render(render),
+            }
+        }
 
         match controller.borrow().debug_mode() {
             DebugMode::PPU => ppu_debug.do_render(|buffers| {
@@ -0,0 +1,294 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::{Arc, Condvar, Mutex};
+
+use nes::emulator::apu::debug::APUDebug;
+use nes::emulator::apu::Channel;
+use nes::emulator::io::event::{Event, EventBus, EventHandler, Key};
+use nes::emulator::io::font;
+use nes::emulator::io::SimpleAudioOut;
+use nes::emulator::nsf::NSFPlayer;
+use nes::emulator::NES_MASTER_CLOCK_HZ;
+
+use crate::audio::SAMPLE_RATE;
+use crate::compositor::Frame;
+use crate::controller::{DebugMode, EmulatorState};
+use crate::governer::Governer;
+use crate::portal::Portal;
+use crate::RENDER_FPS;
+
+const WIDTH: usize = 256;
+const HEIGHT: usize = 240;
+const LIST_TOP: usize = 40;
+const ROW_HEIGHT: usize = 8;
+const VISIBLE_ROWS: usize = 23;
+const MAX_COLUMNS: usize = (WIDTH - 8) / font::CHAR_ADVANCE;
+
+const WHITE: [u8; 3] = [0xFF, 0xFF, 0xFF];
+const GREY: [u8; 3] = [0x80, 0x80, 0x80];
+const GREEN: [u8; 3] = [0x40, 0xFF, 0x40];
+const YELLOW: [u8; 3] = [0xFF, 0xFF, 0x40];
+
+// Drives an NSFPlayer in place of the NES, showing a track list instead of the game screen.
+pub struct NSFController {
+    player: NSFPlayer,
+    audio_output: Rc<RefCell<SimpleAudioOut>>,
+    apu_debug: APUDebug,
+    tracks: Vec<u8>,
+    selected: usize,
+    paused: bool,
+    state_portal: Portal<EmulatorState>,
+    channel_keys: Vec<(Key, char, Channel)>,
+}
+
+impl NSFController {
+    pub fn new(
+        player: NSFPlayer,
+        audio_output: Rc<RefCell<SimpleAudioOut>>,
+        state_portal: Portal<EmulatorState>,
+        channel_keys: Vec<(Key, char, Channel)>,
+    ) -> NSFController {
+        let tracks = player.nsf().tracks();
+        let selected = tracks
+            .iter()
+            .position(|&t| t == player.track())
+            .unwrap_or(0);
+
+        let mut apu_debug = APUDebug::new(player.apu.clone());
+        for &(_, label, channel) in channel_keys.iter() {
+            apu_debug.set_channel_label(channel, label);
+        }
+
+        NSFController {
+            player,
+            audio_output,
+            apu_debug,
+            tracks,
+            selected,
+            paused: false,
+            state_portal,
+            channel_keys,
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.state_portal.consume(|state| state.is_running)
+    }
+
+    pub fn debug_mode(&self) -> DebugMode {
+        self.state_portal.consume(|state| state.debug_mode)
+    }
+
+    // Run for one frame's worth of cycles, moving on to the next track when this one ends.
+    pub fn run_frame(&mut self) -> u64 {
+        if self.paused {
+            return 0;
+        }
+
+        let target_cycles = NES_MASTER_CLOCK_HZ / RENDER_FPS;
+        let mut cycles = 0;
+        while cycles < target_cycles {
+            cycles += self.player.tick();
+        }
+
+        let nsf = self.player.nsf();
+        let track = self.player.track();
+        if let Some(length) = nsf.track_length(track) {
+            let fade = nsf.track_fade(track).unwrap_or(0);
+            if self.player.elapsed_ms() >= (length + fade) as u64 {
+                self.play_relative(1);
+            }
+        }
+
+        cycles
+    }
+
+    fn play_selected(&mut self) {
+        if let Some(&track) = self.tracks.get(self.selected) {
+            self.player.play_track(track);
+            self.paused = false;
+        }
+    }
+
+    fn play_relative(&mut self, offset: isize) {
+        let current = self
+            .tracks
+            .iter()
+            .position(|&t| t == self.player.track())
+            .unwrap_or(0) as isize;
+        let next = current + offset;
+        if next >= 0 && (next as usize) < self.tracks.len() {
+            self.selected = next as usize;
+            self.play_selected();
+        }
+    }
+
+    fn move_selection(&mut self, offset: isize) {
+        // An NSFe playlist can be empty.
+        if self.tracks.is_empty() {
+            return;
+        }
+        let selected = (self.selected as isize + offset).max(0) as usize;
+        self.selected = selected.min(self.tracks.len() - 1);
+    }
+
+    fn toggle_channel_mute(&mut self, key: Key) {
+        if let Some(&(_, _, channel)) = self.channel_keys.iter().find(|&&(k, _, _)| k == key) {
+            let mut apu = self.player.apu.borrow_mut();
+            let muted = !apu.is_channel_muted(channel);
+            apu.set_channel_muted(channel, muted);
+        }
+    }
+
+    pub fn render(&self, buffer: &mut [u8]) {
+        for byte in buffer.iter_mut() {
+            *byte = 0;
+        }
+
+        let nsf = self.player.nsf();
+        let mut y = 4;
+        for (text, colour) in [
+            (&nsf.title, WHITE),
+            (&nsf.artist, GREY),
+            (&nsf.copyright, GREY),
+        ]
+        .iter()
+        {
+            draw_line(buffer, 4, y, text, *colour);
+            y += font::LINE_ADVANCE + 1;
+        }
+
+        let chips = nsf.expansion_chip_names();
+        if !chips.is_empty() {
+            let text = format!("Expansion audio not emulated: {}", chips.join(", "));
+            draw_line(buffer, 4, y, &text, YELLOW);
+        }
+
+        // Scroll the list to keep the selection in view.
+        let first = self
+            .selected
+            .saturating_sub(VISIBLE_ROWS / 2)
+            .min(self.tracks.len().saturating_sub(VISIBLE_ROWS));
+
+        for (row, (ix, &track)) in self
+            .tracks
+            .iter()
+            .enumerate()
+            .skip(first)
+            .take(VISIBLE_ROWS)
+            .enumerate()
+        {
+            let y = LIST_TOP + row * ROW_HEIGHT;
+            let playing = track == self.player.track();
+            let cursor = if ix == self.selected { '>' } else { ' ' };
+            let title = match nsf.track_title(track) {
+                Some(title) => String::from(title),
+                None => format!("Track {}", track + 1),
+            };
+
+            let time = if playing {
+                let elapsed = format_time(self.player.elapsed_ms() as u32);
+                match nsf.track_length(track) {
+                    Some(length) => format!("{}/{}", elapsed, format_time(length)),
+                    None => elapsed,
+                }
+            } else {
+                nsf.track_length(track).map(format_time).unwrap_or_default()
+            };
+
+            let colour = if playing { GREEN } else { WHITE };
+            let line = format!("{} {:>3} {}", cursor, track + 1, title);
+            draw_line(buffer, 4, y, &line, colour);
+
+            let time_x = WIDTH - 4 - time.len() * font::CHAR_ADVANCE;
+            font::draw_text(buffer, WIDTH, time_x, y, &time, colour);
+        }
+
+        let help = if self.paused {
+            "Paused - Space to resume"
+        } else {
+            "Up/Down select, Return play, Left/Right skip"
+        };
+        draw_line(buffer, 4, HEIGHT - 8, help, GREY);
+    }
+}
+
+impl EventHandler for NSFController {
+    fn handle_event(&mut self, event: Event) {
+        if let Event::KeyDown(key) = event {
+            match key {
+                Key::Escape => self.state_portal.consume(|state| state.is_running = false),
+                Key::Up => self.move_selection(-1),
+                Key::Down => self.move_selection(1),
+                Key::Left => self.play_relative(-1),
+                Key::Right => self.play_relative(1),
+                Key::Return => self.play_selected(),
+                Key::Space => self.paused = !self.paused,
+                Key::Backquote => self.state_portal.consume(|state| {
+                    state.debug_mode = match state.debug_mode {
+                        DebugMode::APU => DebugMode::OFF,
+                        _ => DebugMode::APU,
+                    };
+                }),
+                _ => self.toggle_channel_mute(key),
+            }
+        }
+    }
+}
+
+fn draw_line(buffer: &mut [u8], x: usize, y: usize, text: &str, colour: [u8; 3]) {
+    let text: String = text.chars().take(MAX_COLUMNS).collect();
+    font::draw_text(buffer, WIDTH, x, y, &text, colour);
+}
+
+fn format_time(ms: u32) -> String {
+    let seconds = ms / 1000;
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+pub fn nsf_loop(
+    sync: Arc<(Mutex<()>, Condvar)>,
+    controller: Rc<RefCell<NSFController>>,
+    video_portal: Portal<Frame>,
+    apu_debug_portal: Portal<Box<[u8]>>,
+    audio_portal: Portal<Vec<f32>>,
+    event_bus: Rc<RefCell<EventBus>>,
+    event_portal: Portal<Vec<Event>>,
+) {
+    let mut governer = Governer::new(RENDER_FPS);
+    let mut screen = vec![0; WIDTH * HEIGHT * 3];
+
+    while controller.borrow().is_running() {
+        event_portal.consume(|events| {
+            events
+                .drain(..)
+                .for_each(|e| event_bus.borrow_mut().broadcast(e));
+        });
+
+        let mut controller = controller.borrow_mut();
+        let cycles = controller.run_frame();
+
+        controller.render(&mut screen);
+        video_portal.consume(|portal| portal.set(WIDTH, HEIGHT, &screen));
+
+        if controller.debug_mode() == DebugMode::APU {
+            controller.apu_debug.do_render(|data| {
+                apu_debug_portal.consume(|portal| portal.copy_from_slice(data));
+            });
+        }
+
+        let request_samples = SAMPLE_RATE / (RENDER_FPS as f32);
+        controller
+            .audio_output
+            .borrow_mut()
+            .consume(cycles, request_samples as u64, |data| {
+                audio_portal.consume(|portal| portal.extend_from_slice(data));
+            });
+        drop(controller);
+
+        let (_, cvar) = &*sync;
+        cvar.notify_one();
+
+        governer.synchronize();
+    }
+}
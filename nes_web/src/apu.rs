@@ -0,0 +1,24 @@
+use nes::emulator::apu;
+
+use wasm_bindgen::prelude::*;
+
+// WASM-facing copy of the internal APU channel type.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Channel {
+    Pulse1,
+    Pulse2,
+    Triangle,
+    Noise,
+    DMC,
+}
+
+pub fn convert_wasm_channel_to_internal(channel: Channel) -> apu::Channel {
+    match channel {
+        Channel::Pulse1 => apu::Channel::Pulse1,
+        Channel::Pulse2 => apu::Channel::Pulse2,
+        Channel::Triangle => apu::Channel::Triangle,
+        Channel::Noise => apu::Channel::Noise,
+        Channel::DMC => apu::Channel::DMC,
+    }
+}
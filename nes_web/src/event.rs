@@ -7,6 +7,9 @@ use wasm_bindgen::prelude::*;
 pub struct Event {
     event_type: EventType,
     key: Option<Key>,
+    button: Option<PointerButton>,
+    x: i32,
+    y: i32,
 }
 
 #[wasm_bindgen]
@@ -15,6 +18,9 @@ impl Event {
         Event {
             event_type: EventType::KeyDown,
             key: Some(key),
+            button: None,
+            x: 0,
+            y: 0,
         }
     }
 
@@ -22,6 +28,40 @@ impl Event {
         Event {
             event_type: EventType::KeyUp,
             key: Some(key),
+            button: None,
+            x: 0,
+            y: 0,
+        }
+    }
+
+    // Position on the NES screen, in NES pixels.
+    pub fn pointer_move(x: i32, y: i32) -> Event {
+        Event {
+            event_type: EventType::PointerMove,
+            key: None,
+            button: None,
+            x,
+            y,
+        }
+    }
+
+    pub fn pointer_down(button: PointerButton) -> Event {
+        Event {
+            event_type: EventType::PointerDown,
+            key: None,
+            button: Some(button),
+            x: 0,
+            y: 0,
+        }
+    }
+
+    pub fn pointer_up(button: PointerButton) -> Event {
+        Event {
+            event_type: EventType::PointerUp,
+            key: None,
+            button: Some(button),
+            x: 0,
+            y: 0,
         }
     }
 }
@@ -32,6 +72,16 @@ impl Event {
 pub enum EventType {
     KeyDown,
     KeyUp,
+    PointerMove,
+    PointerDown,
+    PointerUp,
+}
+
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum PointerButton {
+    Primary,
+    Secondary,
 }
 
 #[wasm_bindgen]
@@ -87,6 +137,11 @@ pub enum Key {
     Space,
     Shift,
     Control,
+    ScrollLock,
+    F9,
+    F10,
+    F11,
+    F12,
 }
 
 pub fn convert_wasm_event_to_internal(event: Event) -> event::Event {
@@ -95,6 +150,20 @@ pub fn convert_wasm_event_to_internal(event: Event) -> event::Event {
             event::Event::KeyDown(convert_wasm_key_to_internal(event.key.unwrap()))
         }
         EventType::KeyUp => event::Event::KeyUp(convert_wasm_key_to_internal(event.key.unwrap())),
+        EventType::PointerMove => event::Event::PointerMove(event.x, event.y),
+        EventType::PointerDown => {
+            event::Event::PointerDown(convert_wasm_button_to_internal(event.button.unwrap()))
+        }
+        EventType::PointerUp => {
+            event::Event::PointerUp(convert_wasm_button_to_internal(event.button.unwrap()))
+        }
+    }
+}
+
+fn convert_wasm_button_to_internal(button: PointerButton) -> event::PointerButton {
+    match button {
+        PointerButton::Primary => event::PointerButton::Primary,
+        PointerButton::Secondary => event::PointerButton::Secondary,
     }
 }
 
@@ -150,5 +219,10 @@ fn convert_wasm_key_to_internal(key: Key) -> event::Key {
         Key::Space => event::Key::Space,
         Key::Shift => event::Key::Shift,
         Key::Control => event::Key::Control,
+        Key::ScrollLock => event::Key::ScrollLock,
+        Key::F9 => event::Key::F9,
+        Key::F10 => event::Key::F10,
+        Key::F11 => event::Key::F11,
+        Key::F12 => event::Key::F12,
     }
 }
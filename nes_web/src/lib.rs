@@ -1,3 +1,4 @@
+pub mod apu;
 pub mod event;
 
 use std::cell::RefCell;
@@ -5,9 +6,17 @@ use std::rc::Rc;
 
 use wasm_bindgen::prelude::*;
 
+use nes::emulator::controller::Bindings;
 use nes::emulator::ines;
+use nes::emulator::input::keyboard::{host_keymap, FamilyKeyboard};
+use nes::emulator::input::vaus::{Vaus, VausMode};
+use nes::emulator::input::zapper::Zapper;
+use nes::emulator::input::Port;
 use nes::emulator::io;
+use nes::emulator::io::display::{AspectRatio, Display, Overscan};
 use nes::emulator::io::event::EventBus;
+use nes::emulator::io::palette::{NTSCSettings, Palette};
+use nes::emulator::io::scale::Scaler;
 use nes::emulator::NES;
 
 #[wasm_bindgen]
@@ -16,6 +25,8 @@ pub struct Emulator {
     event_bus: Rc<RefCell<EventBus>>,
     video_out: Rc<RefCell<io::Screen>>,
     audio_out: Rc<RefCell<io::SimpleAudioOut>>,
+    scaler: Scaler,
+    display: Display,
 }
 
 #[wasm_bindgen]
@@ -26,13 +37,21 @@ impl Emulator {
         let audio_out = Rc::new(RefCell::new(io::SimpleAudioOut::new(48_000.0)));
         let rom = ines::ROM::from_bytes(rom_data);
 
-        let nes = NES::new(event_bus.clone(), video_out.clone(), audio_out.clone(), rom);
+        let nes = NES::new(
+            event_bus.clone(),
+            video_out.clone(),
+            audio_out.clone(),
+            rom,
+            &Bindings::new(),
+        );
 
         Emulator {
             nes,
             event_bus,
             video_out,
             audio_out,
+            scaler: Scaler::None,
+            display: Display::new(),
         }
     }
 
@@ -40,15 +59,174 @@ impl Emulator {
         self.nes.tick_multi(ticks)
     }
 
+    // The frame as RGB, frame_width() x frame_height() once scaled and cropped.
     pub fn get_frame(&self) -> Vec<u8> {
-        let mut buf = [0; 256 * 240 * 3];
-        self.video_out.borrow().do_render(|frame| {
-            for (tgt, src) in buf.iter_mut().zip(frame.iter()) {
-                *tgt = *src;
-            }
+        let mut scaled = vec![];
+        let scaler = self.scaler;
+        self.video_out.borrow().do_render(|frame| match scaler {
+            Scaler::None => scaled.extend_from_slice(frame),
+            _ => scaler.apply(frame, 256, 240, &mut scaled),
         });
 
-        return buf.to_vec();
+        let (width, height) = self.scaler.output_size(256, 240);
+        let mut buf = vec![];
+        self.display.crop_frame(&scaled, width, height, &mut buf);
+        buf
+    }
+
+    pub fn frame_width(&self) -> usize {
+        let (width, height) = self.scaler.output_size(256, 240);
+        self.display.crop(width, height).2
+    }
+
+    pub fn frame_height(&self) -> usize {
+        let (width, height) = self.scaler.output_size(256, 240);
+        self.display.crop(width, height).3
+    }
+
+    // The size to draw the frame at 1x, with aspect ratio correction.
+    pub fn display_width(&self) -> u32 {
+        self.display.display_size().0.round() as u32
+    }
+
+    pub fn display_height(&self) -> u32 {
+        self.display.display_size().1.round() as u32
+    }
+
+    // Where to draw the frame on a canvas of the given size, as [x, y, width, height].
+    pub fn fit_display(&self, width: u32, height: u32) -> Vec<u32> {
+        let (x, y, w, h) = self.display.fit(width, height);
+        vec![x, y, w, h]
+    }
+
+    // Converts a position on a canvas of the given size to NES pixels, as [x, y], for pointer
+    // events.
+    pub fn to_screen(&self, width: u32, height: u32, x: i32, y: i32) -> Vec<i32> {
+        let (x, y) = self.display.to_screen(width, height, x, y);
+        vec![x, y]
+    }
+
+    pub fn set_overscan(
+        &mut self,
+        top: usize,
+        bottom: usize,
+        left: usize,
+        right: usize,
+    ) -> Result<(), JsValue> {
+        let overscan = format!("{},{},{},{}", top, bottom, left, right);
+        self.display.overscan = Overscan::parse(&overscan).map_err(|e| JsValue::from_str(&e))?;
+        Ok(())
+    }
+
+    // One of "square", "8:7" or "4:3".
+    pub fn set_aspect_ratio(&mut self, name: &str) -> Result<(), JsValue> {
+        self.display.aspect_ratio = AspectRatio::from_name(name)
+            .ok_or_else(|| JsValue::from_str(&format!("Unrecognised aspect ratio: {}", name)))?;
+        Ok(())
+    }
+
+    pub fn set_integer_scale(&mut self, integer_scale: bool) {
+        self.display.integer_scale = integer_scale;
+    }
+
+    // One of "none", "nearest2x", "nearest3x", "nearest4x", "scale2x", "scale3x", "smooth2x",
+    // "smooth3x", "xbr2x" or "crt".
+    pub fn set_scaler(&mut self, name: &str) -> Result<(), JsValue> {
+        self.scaler = Scaler::from_name(name)
+            .ok_or_else(|| JsValue::from_str(&format!("Unrecognised scaler: {}", name)))?;
+        Ok(())
+    }
+
+    // Accepts the contents of a 192 or 1536 byte .pal file.
+    pub fn set_palette(&self, data: Vec<u8>) -> Result<(), JsValue> {
+        let palette = Palette::from_bytes(&data).map_err(|e| JsValue::from_str(&e))?;
+        self.video_out.borrow_mut().set_palette(palette);
+        Ok(())
+    }
+
+    pub fn set_default_palette(&self) {
+        self.video_out.borrow_mut().set_palette(Palette::new());
+    }
+
+    pub fn set_ntsc_palette(
+        &self,
+        hue: f32,
+        saturation: f32,
+        contrast: f32,
+        brightness: f32,
+        gamma: f32,
+    ) {
+        let settings = NTSCSettings {
+            hue,
+            saturation,
+            contrast,
+            brightness,
+            gamma,
+        };
+        self.video_out
+            .borrow_mut()
+            .set_palette(Palette::generate(&settings));
+    }
+
+    // The frame as colour indices, with the emphasis bits above the 6 bit colour.
+    pub fn get_frame_indexed(&self) -> Vec<u16> {
+        let mut buf = vec![];
+        self.video_out
+            .borrow()
+            .do_render_indexed(|frame| buf.extend_from_slice(frame));
+        buf
+    }
+
+    // With the limit off, all sprites on a scanline are drawn, rather than the first 8.
+    pub fn set_sprite_limit(&self, enabled: bool) {
+        self.nes.ppu.borrow_mut().set_sprite_limit(enabled);
+    }
+
+    // Rebinds both controllers from JSON, e.g.
+    // {"players": [{"keys": {"Z": "A", ...}, "pad": {"A": "B", ...}}, {"keys": {}, "pad": {}}]}
+    pub fn set_bindings(&mut self, json: &str) -> Result<(), JsValue> {
+        let bindings: Bindings =
+            serde_json::from_str(json).map_err(|e| JsValue::from_str(&e.to_string()))?;
+        self.nes.set_bindings(&bindings);
+        Ok(())
+    }
+
+    // Plugs a Zapper into port 2, aimed with pointer events.
+    pub fn connect_zapper(&mut self) {
+        let zapper = Rc::new(RefCell::new(Zapper::new(
+            self.nes.ppu.clone(),
+            self.video_out.clone(),
+        )));
+        self.event_bus
+            .borrow_mut()
+            .register(Box::new(zapper.clone()));
+        self.nes.connect(Port::Two, Box::new(zapper));
+    }
+
+    // Plugs in an Arkanoid paddle, turned with pointer events. The Famicom version is on the
+    // expansion port.
+    pub fn connect_vaus(&mut self, famicom: bool) {
+        let mode = if famicom {
+            VausMode::Famicom
+        } else {
+            VausMode::NES
+        };
+        let vaus = Rc::new(RefCell::new(Vaus::new(mode)));
+        self.event_bus.borrow_mut().register(Box::new(vaus.clone()));
+        if famicom {
+            self.nes.connect_expansion(Box::new(vaus));
+        } else {
+            self.nes.connect(Port::Two, Box::new(vaus));
+        }
+    }
+
+    // Plugs the Family BASIC keyboard into the expansion port, typed on with key events.
+    pub fn connect_family_keyboard(&mut self) {
+        let keyboard = Rc::new(RefCell::new(FamilyKeyboard::new(host_keymap())));
+        self.event_bus
+            .borrow_mut()
+            .register(Box::new(keyboard.clone()));
+        self.nes.connect_expansion(Box::new(keyboard));
     }
 
     pub fn get_audio(&self, master_cycles: u64, num_samples: u64) -> Vec<f32> {
@@ -61,6 +239,84 @@ impl Emulator {
         return buf;
     }
 
+    pub fn is_channel_muted(&self, channel: apu::Channel) -> bool {
+        let channel = apu::convert_wasm_channel_to_internal(channel);
+        self.nes.apu.borrow().is_channel_muted(channel)
+    }
+
+    pub fn set_channel_muted(&self, channel: apu::Channel, muted: bool) {
+        let channel = apu::convert_wasm_channel_to_internal(channel);
+        self.nes.apu.borrow_mut().set_channel_muted(channel, muted);
+    }
+
+    pub fn is_channel_solo(&self, channel: apu::Channel) -> bool {
+        let channel = apu::convert_wasm_channel_to_internal(channel);
+        self.nes.apu.borrow().is_channel_solo(channel)
+    }
+
+    pub fn set_channel_solo(&self, channel: apu::Channel, solo: bool) {
+        let channel = apu::convert_wasm_channel_to_internal(channel);
+        self.nes.apu.borrow_mut().set_channel_solo(channel, solo);
+    }
+
+    pub fn channel_volume(&self, channel: apu::Channel) -> f32 {
+        let channel = apu::convert_wasm_channel_to_internal(channel);
+        self.nes.apu.borrow().channel_volume(channel)
+    }
+
+    pub fn set_channel_volume(&self, channel: apu::Channel, volume: f32) {
+        let channel = apu::convert_wasm_channel_to_internal(channel);
+        self.nes
+            .apu
+            .borrow_mut()
+            .set_channel_volume(channel, volume);
+    }
+
+    pub fn channel_pan(&self, channel: apu::Channel) -> f32 {
+        let channel = apu::convert_wasm_channel_to_internal(channel);
+        self.nes.apu.borrow().channel_pan(channel)
+    }
+
+    pub fn set_channel_pan(&self, channel: apu::Channel, pan: f32) {
+        let channel = apu::convert_wasm_channel_to_internal(channel);
+        self.nes.apu.borrow_mut().set_channel_pan(channel, pan);
+    }
+
+    // Turning stereo off centres the channels again.
+    pub fn set_audio_mono(&self) {
+        let mut apu = self.nes.apu.borrow_mut();
+        apu.set_stereo(false);
+        apu.reset_panning();
+        self.audio_out.borrow_mut().set_mode(io::OutputMode::Mono);
+    }
+
+    // Channels start centred unless famicom_panning is set, so set_channel_pan should be called
+    // afterwards for any other panning.
+    pub fn set_audio_stereo(&self, famicom_panning: bool) {
+        let mut apu = self.nes.apu.borrow_mut();
+        apu.set_stereo(true);
+        if famicom_panning {
+            apu.set_famicom_panning();
+        } else {
+            apu.reset_panning();
+        }
+        self.audio_out.borrow_mut().set_mode(io::OutputMode::Stereo);
+    }
+
+    pub fn set_audio_pseudo_stereo(&self, delay_ms: f32) {
+        let mut apu = self.nes.apu.borrow_mut();
+        apu.set_stereo(false);
+        apu.reset_panning();
+        self.audio_out
+            .borrow_mut()
+            .set_mode(io::OutputMode::PseudoStereo { delay_ms });
+    }
+
+    // Audio returned by get_audio is interleaved when there's more than one channel.
+    pub fn audio_channels(&self) -> usize {
+        self.audio_out.borrow().channels()
+    }
+
     pub fn broadcast(&self, e: event::Event) {
         let internal_event = event::convert_wasm_event_to_internal(e);
         println!("{:?}", internal_event);